@@ -0,0 +1,1110 @@
+use extendr_api::prelude::*;
+use gif::{ColorOutput, DecodeOptions, DisposalMethod};
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::BufReader;
+use std::path::{Path, PathBuf};
+
+use crate::error::TinyImgRError;
+use crate::metrics::LabConverter;
+use crate::quantize::{Color, Ditherer, OrderedDither};
+use crate::optimize::{
+    color_key, find_truncate_index, merge_palette_colors, png_options, LossyThreshold, VerboseMessage,
+    DEFAULT_PNG_LEVEL,
+};
+use crate::{console_println, process_files, read_png_rgba, validate_io, write_png_rgba};
+
+// ---------------------------------------------------------------------------
+// Animated GIF decoding
+// ---------------------------------------------------------------------------
+
+/// A fully composited animation frame.
+struct Frame<'a> {
+    /// 0-based frame index.
+    index: usize,
+    /// RGBA pixels of the whole canvas (4 bytes per pixel, row-major).
+    rgba: &'a [u8],
+    width: usize,
+    height: usize,
+    /// Frame delay in seconds.
+    delay: f64,
+}
+
+/// Decoded frames of an animation, ready to be encoded.
+struct Animation {
+    /// RGBA pixels of each frame (whole canvas).
+    frames: Vec<Vec<u8>>,
+    width: usize,
+    height: usize,
+    /// Frame delays in seconds.
+    delays: Vec<f64>,
+}
+
+/// Decode a GIF and composite its frames onto a full RGBA canvas, honoring
+/// each frame's disposal method, and pass each frame to `on_frame`.  Decoding
+/// stops after frame `last` (0-based) when given.  Returns the number of
+/// frames read.
+fn read_gif<F>(input: &Path, last: Option<usize>, mut on_frame: F) -> Result<usize>
+where
+    F: FnMut(&Frame) -> Result<()>,
+{
+    let file = File::open(input)
+        .map_err(|e| TinyImgRError::io(format!("Failed to read {}: {}", input.display(), e)))?;
+    let mut options = DecodeOptions::new();
+    options.set_color_output(ColorOutput::Indexed);
+    let mut decoder = options.read_info(BufReader::new(file))
+        .map_err(|e| TinyImgRError::decode(format!("Failed to decode GIF {}: {}", input.display(), e)))?;
+    let width  = decoder.width() as usize;
+    let height = decoder.height() as usize;
+    let global_palette = decoder.global_palette().map(|p| p.to_vec()).unwrap_or_default();
+
+    // Areas not covered by any frame (or cleared by disposal) are transparent.
+    let mut canvas = vec![0u8; width * height * 4];
+    let mut index = 0;
+    while let Some(frame) = decoder.read_next_frame()
+        .map_err(|e| TinyImgRError::decode(format!("Failed to decode GIF {}: {}", input.display(), e)))?
+    {
+        let saved = (frame.dispose == DisposalMethod::Previous).then(|| canvas.clone());
+        let palette = frame.palette.as_deref().unwrap_or(&global_palette);
+        let (left, top) = (frame.left as usize, frame.top as usize);
+        let (fw, fh) = (frame.width as usize, frame.height as usize);
+        // Frames may extend beyond the logical screen; clip them to the canvas.
+        let x_end = (left + fw).min(width);
+        let y_end = (top + fh).min(height);
+        for y in top..y_end {
+            for x in left..x_end {
+                let idx = frame.buffer[(y - top) * fw + (x - left)];
+                if Some(idx) == frame.transparent {
+                    continue;
+                }
+                let p = 3 * idx as usize;
+                if let Some(rgb) = palette.get(p..p + 3) {
+                    let c = 4 * (y * width + x);
+                    canvas[c..c + 3].copy_from_slice(rgb);
+                    canvas[c + 3] = 255;
+                }
+            }
+        }
+
+        on_frame(&Frame {
+            index, rgba: &canvas, width, height, delay: frame.delay as f64 / 100.0,
+        })?;
+
+        match frame.dispose {
+            DisposalMethod::Background => {
+                for y in top..y_end {
+                    canvas[4 * (y * width + left)..4 * (y * width + x_end)].fill(0);
+                }
+            }
+            DisposalMethod::Previous => {
+                if let Some(saved) = saved {
+                    canvas = saved;
+                }
+            }
+            _ => {}
+        }
+        let done = Some(index) == last;
+        index += 1;
+        if done {
+            break;
+        }
+    }
+    Ok(index)
+}
+
+/// Extract frames of an animated GIF as optimized PNG files
+///
+/// @param input Path to a GIF file
+/// @param outdir Output directory for the PNG files
+/// @param frames 1-based indices of frames to extract (all frames if empty)
+/// @return A list with the frame indices, PNG paths, and frame delays in seconds
+/// @export
+#[extendr]
+fn gif_frames_impl(input: &str, outdir: &str, frames: Integers) -> Result<List> {
+    let input_path = PathBuf::from(input);
+    if !input_path.exists() {
+        return Err(TinyImgRError::io(format!("Input file does not exist: {}", input)).into());
+    }
+    let mut wanted: Vec<usize> = Vec::with_capacity(frames.len());
+    for f in frames.iter() {
+        if f.is_na() || f.inner() < 1 {
+            return Err(TinyImgRError::argument("Frame indices must be positive integers").into());
+        }
+        wanted.push(f.inner() as usize - 1);
+    }
+    wanted.sort_unstable();
+    wanted.dedup();
+    let last = wanted.last().copied();
+
+    std::fs::create_dir_all(outdir)
+        .map_err(|e| TinyImgRError::io(format!("Failed to create directory {}: {}", outdir, e)))?;
+    let stem = input_path.file_stem().map(|s| s.to_string_lossy().to_string())
+        .unwrap_or_else(|| "frame".to_string());
+    let digits = last.map_or(0, |n| (n + 1).to_string().len()).max(4);
+
+    let mut indices: Vec<i32> = Vec::new();
+    let mut paths: Vec<String> = Vec::new();
+    let mut delays: Vec<f64> = Vec::new();
+    let n_frames = read_gif(&input_path, last, |frame| {
+        if !wanted.is_empty() && wanted.binary_search(&frame.index).is_err() {
+            return Ok(());
+        }
+        let name = format!("{}-{:0width$}.png", stem, frame.index + 1, width = digits);
+        let path = Path::new(outdir).join(name);
+        write_png_rgba(&path, frame.rgba, frame.width, frame.height)?;
+        indices.push(frame.index as i32 + 1);
+        paths.push(path.to_string_lossy().to_string());
+        delays.push(frame.delay);
+        Ok(())
+    })?;
+    if let Some(n) = last {
+        if n >= n_frames {
+            return Err(TinyImgRError::argument(format!(
+                "Frame {} requested but {} has only {} frames", n + 1, input, n_frames
+            )).into());
+        }
+    }
+    Ok(list!(frame = indices, path = paths, delay = delays))
+}
+
+/// Convert single-frame GIFs to optimized PNGs
+///
+/// Multi-frame GIFs are left alone.  The frame is composited onto the canvas
+/// as in `gif_frames_impl()`, so pixels of the transparent color index (and
+/// areas not covered by the frame) become transparent.
+///
+/// @param input Paths to GIF files
+/// @param output Paths to the PNG files (same length as `input`)
+/// @param level Optimization level (0-6)
+/// @param verbose Whether to print the size change of each converted file
+/// @return A list with whether each GIF was converted (`converted`, i.e.,
+///   whether it has a single frame), and the sizes of the GIF and PNG files
+///   (`input_size` and `output_size`, `NA` for the GIFs not converted)
+/// @export
+#[extendr]
+fn gif_to_png_impl(input: Strings, output: Strings, level: i32, verbose: bool) -> Result<List> {
+    if input.len() != output.len() {
+        return Err(TinyImgRError::argument("Input and output vectors must have the same length").into());
+    }
+    let level = level.clamp(0, 6) as u8;
+    let inputs: Vec<String> = input.iter().map(|s| s.to_string()).collect();
+    let outputs: Vec<String> = output.iter().map(|s| s.to_string()).collect();
+    let (input_trunc, output_trunc) = (find_truncate_index(&inputs), find_truncate_index(&outputs));
+    let mut converted = Vec::with_capacity(inputs.len());
+    let (mut input_sizes, mut output_sizes) = (Vec::new(), Vec::new());
+    for (input, output) in inputs.iter().zip(&outputs) {
+        let input_path = Path::new(input);
+        let input_size = std::fs::metadata(input_path)
+            .map_err(|_| TinyImgRError::io(format!("Input file does not exist: {}", input)))?.len();
+        // Read at most two frames to tell if the GIF is animated.
+        let mut first = None;
+        let n = read_gif(input_path, Some(1), |frame| {
+            if frame.index == 0 {
+                first = Some((frame.rgba.to_vec(), frame.width, frame.height));
+            }
+            Ok(())
+        })?;
+        let Some((rgba, width, height)) = first.filter(|_| n == 1) else {
+            converted.push(false);
+            input_sizes.push(Rfloat::na());
+            output_sizes.push(Rfloat::na());
+            continue;
+        };
+        let png = lodepng::encode32(&rgba, width, height)
+            .map_err(|e| TinyImgRError::encode(format!("Failed to encode PNG {}: {}", output, e)))?;
+        let png = oxipng::optimize_from_memory(&png, &png_options(level))
+            .map_err(|e| TinyImgRError::encode(format!("Failed to optimize {}: {}", output, e)))?;
+        if let Some(parent) = Path::new(output).parent().filter(|p| !p.as_os_str().is_empty()) {
+            std::fs::create_dir_all(parent)
+                .map_err(|e| TinyImgRError::io(format!("Failed to create directory {}: {}", parent.display(), e)))?;
+        }
+        std::fs::write(output, &png)
+            .map_err(|e| TinyImgRError::io(format!("Failed to write {}: {}", output, e)))?;
+        if verbose {
+            if let Some(line) = VerboseMessage::new(input, output, (input_size, png.len() as u64))
+                .summary(input_trunc, output_trunc)
+            {
+                console_println(&line);
+            }
+        }
+        converted.push(true);
+        input_sizes.push(Rfloat::from(input_size as f64));
+        output_sizes.push(Rfloat::from(png.len() as f64));
+    }
+    Ok(list!(converted = converted, input_size = input_sizes, output_size = output_sizes))
+}
+
+// ---------------------------------------------------------------------------
+// GIF optimization
+// ---------------------------------------------------------------------------
+//
+// A GIF is re-encoded from its composited frames.  Each frame only stores
+// the rectangle that changed since the previous frame was displayed, and
+// pixels in the rectangle that did not change can be transparent, which
+// gives LZW long runs of a single index.  Frames are kept on the canvas
+// after display, unless the next frame makes some of their pixels
+// transparent (which drawing on top cannot do): such a frame covers the
+// whole canvas and is cleared after display.
+
+/// Read the loop count of a GIF (`Repeat::Finite(0)` if it has no loop
+/// extension, i.e., it is played once).
+fn gif_repeat(input: &Path) -> Result<gif::Repeat> {
+    let file = File::open(input)
+        .map_err(|e| TinyImgRError::io(format!("Failed to read {}: {}", input.display(), e)))?;
+    let mut decoder = DecodeOptions::new().read_info(BufReader::new(file))
+        .map_err(|e| TinyImgRError::decode(format!("Failed to decode GIF {}: {}", input.display(), e)))?;
+    // The loop extension usually comes before the first frame but may come
+    // after any frame.
+    while decoder.next_frame_info()
+        .map_err(|e| TinyImgRError::decode(format!("Failed to decode GIF {}: {}", input.display(), e)))?
+        .is_some() {}
+    Ok(decoder.repeat())
+}
+
+/// The rectangle of a frame to draw on the canvas, with its pixels (`None`
+/// where the canvas shows through), and whether it is cleared after display.
+struct GifPatch {
+    rect: (usize, usize, usize, usize),
+    pixels: Vec<Option<Color>>,
+    clear: bool,
+}
+
+impl GifPatch {
+    /// The patch that turns the canvas `base` into `cur` in the rectangle
+    /// `rect`.  Transparent pixels of `cur` are left transparent (`base` is
+    /// transparent there, too), and with `delta`, so are unchanged pixels.
+    fn new(
+        base: &[u8], cur: &[u8], width: usize, rect: (usize, usize, usize, usize), delta: bool, clear: bool,
+    ) -> Self {
+        let (x0, y0, w, h) = rect;
+        let mut pixels = Vec::with_capacity(w * h);
+        for y in y0..y0 + h {
+            for x in x0..x0 + w {
+                let c = 4 * (y * width + x);
+                let (b, p) = (&base[c..c + 4], &cur[c..c + 4]);
+                let skip = p[3] < 128 || (delta && b == p);
+                pixels.push((!skip).then(|| Color::new(p[0], p[1], p[2], 255)));
+            }
+        }
+        GifPatch { rect, pixels, clear }
+    }
+
+    fn has_transparency(&self) -> bool {
+        self.pixels.iter().any(Option::is_none)
+    }
+
+    /// Quantize the colors of the patch if there are too many for a palette
+    /// (with one index reserved for transparency if needed).
+    fn fit_palette(&mut self) {
+        let max = if self.has_transparency() { 255 } else { 256 };
+        let mut palette = GifPalette::default();
+        if palette.extend(&self.pixels, max) {
+            return;
+        }
+        let colors: Vec<Color> = self.pixels.iter().map(|p| p.unwrap_or(Color::new(0, 0, 0, 255))).collect();
+        let (palette, indices) = OrderedDither.to_indexed(&colors, self.rect.2, max);
+        for (p, i) in self.pixels.iter_mut().zip(indices) {
+            if p.is_some() {
+                *p = Some(palette[i as usize]);
+            }
+        }
+    }
+
+    /// An LZW-compressed frame of the patch, with the colors of `palette`,
+    /// written as a local palette if `local` is true.
+    fn frame(&self, palette: &GifPalette, delay: u16, local: bool) -> gif::Frame<'static> {
+        let transparent = self.has_transparency().then_some(palette.len() as u8);
+        let buffer = self.pixels.iter()
+            .map(|p| p.map_or(transparent.unwrap_or(0), |c| palette.index[&color_key(c)]))
+            .collect();
+        let (left, top, width, height) = self.rect;
+        let mut frame = gif::Frame {
+            left: left as u16,
+            top: top as u16,
+            width: width as u16,
+            height: height as u16,
+            buffer: Cow::Owned(buffer),
+            palette: local.then(|| palette.rgb(transparent.is_some())),
+            delay,
+            dispose: if self.clear { DisposalMethod::Background } else { DisposalMethod::Keep },
+            transparent,
+            ..gif::Frame::default()
+        };
+        frame.make_lzw_pre_encoded();
+        frame
+    }
+}
+
+/// A GIF palette: colors in the order of their first appearance, and their
+/// indices.
+#[derive(Default)]
+struct GifPalette {
+    colors: Vec<Color>,
+    index: HashMap<u32, u8>,
+}
+
+impl GifPalette {
+    /// A palette of the colors of a patch (which must fit in a palette).
+    fn of(patch: &GifPatch) -> Self {
+        let mut palette = GifPalette::default();
+        palette.extend(&patch.pixels, 256);
+        palette
+    }
+
+    fn len(&self) -> usize {
+        self.colors.len()
+    }
+
+    /// Add the colors of `pixels` to the palette.  Returns false if the
+    /// palette would have more than `max` colors.
+    fn extend(&mut self, pixels: &[Option<Color>], max: usize) -> bool {
+        for &c in pixels.iter().flatten() {
+            let key = color_key(c);
+            if !self.index.contains_key(&key) {
+                if self.colors.len() >= max {
+                    return false;
+                }
+                self.index.insert(key, self.colors.len() as u8);
+                self.colors.push(c);
+            }
+        }
+        true
+    }
+
+    /// The RGB bytes of the palette, with an extra (black) entry for the
+    /// transparent index if needed.
+    fn rgb(&self, transparent: bool) -> Vec<u8> {
+        let extra = transparent.then_some(Color::new(0, 0, 0, 255));
+        self.colors.iter().chain(extra.iter()).flat_map(|c| [c.r, c.g, c.b]).collect()
+    }
+}
+
+/// Write GIF frames with local palettes, or with the global `palette`.
+fn write_gif_patches(
+    patches: &[GifPatch], delays: &[u16], (width, height): (usize, usize), repeat: gif::Repeat,
+    palette: Option<&GifPalette>,
+) -> Result<Vec<u8>> {
+    let err = |e: gif::EncodingError| TinyImgRError::encode(format!("Failed to encode GIF: {}", e));
+    let global = palette.map_or(Vec::new(), |p| p.rgb(patches.iter().any(GifPatch::has_transparency)));
+    let mut out = Vec::new();
+    let mut encoder = gif::Encoder::new(&mut out, width as u16, height as u16, &global).map_err(err)?;
+    if repeat != gif::Repeat::Finite(0) {
+        encoder.set_repeat(repeat).map_err(err)?;
+    }
+    for (patch, &delay) in patches.iter().zip(delays) {
+        let frame = match palette {
+            Some(p) => patch.frame(p, delay, false),
+            None => patch.frame(&GifPalette::of(patch), delay, true),
+        };
+        encoder.write_lzw_pre_encoded_frame(&frame).map_err(err)?;
+    }
+    drop(encoder);
+    Ok(out)
+}
+
+/// Re-encode a GIF (see above) and return its data.  With `lossy > 0`,
+/// similar colors of all frames are first merged within the CIE76 Delta E
+/// threshold.  At `level` 0, only the changed rectangles are stored; at level
+/// 1, unchanged pixels in them are also transparent; at level 2 or higher,
+/// the smaller of the two is used for each frame.
+fn optimize_gif(input: &Path, level: u8, lossy: f64) -> Result<Vec<u8>> {
+    let mut anim = Animation { frames: Vec::new(), width: 0, height: 0, delays: Vec::new() };
+    read_gif(input, None, |frame| {
+        (anim.width, anim.height) = (frame.width, frame.height);
+        anim.frames.push(frame.rgba.to_vec());
+        anim.delays.push(frame.delay);
+        Ok(())
+    })?;
+    if anim.frames.is_empty() {
+        return Err(TinyImgRError::decode(format!("No frames found in {}", input.display())).into());
+    }
+    let repeat = gif_repeat(input)?;
+    let Animation { mut frames, width, height, delays } = anim;
+    if lossy > 0.0 {
+        // Merge colors of all frames together, so that unchanged areas stay
+        // unchanged from frame to frame.
+        let mut pixels: Vec<Color> = frames.iter()
+            .flat_map(|f| f.chunks_exact(4).map(|p| Color::new(p[0], p[1], p[2], p[3])))
+            .collect();
+        let lab = LabConverter::new("d65").expect("d65 is a known white point");
+        merge_palette_colors(&mut pixels, LossyThreshold::DeltaE(lossy), &lab);
+        for (frame, colors) in frames.iter_mut().zip(pixels.chunks(width * height)) {
+            for (p, c) in frame.chunks_exact_mut(4).zip(colors) {
+                p[..3].copy_from_slice(&[c.r, c.g, c.b]);
+            }
+        }
+    }
+
+    let n = frames.len();
+    let clear: Vec<bool> = (0..n).map(|i| i + 1 < n && frames[i].chunks_exact(4)
+        .zip(frames[i + 1].chunks_exact(4)).any(|(a, b)| a[3] >= 128 && b[3] < 128)
+    ).collect();
+    let blank = vec![0u8; width * height * 4];
+    let variants: &[bool] = match level { 0 => &[false], 1 => &[true], _ => &[false, true] };
+    let patches: Vec<GifPatch> = (0..n).map(|i| {
+        let base = if i == 0 || clear[i - 1] { &blank } else { &frames[i - 1] };
+        let rect = if clear[i] { (0, 0, width, height) } else { changed_rect(base, &frames[i], width, height) };
+        variants.iter().map(|&delta| {
+            let mut patch = GifPatch::new(base, &frames[i], width, rect, delta, clear[i]);
+            patch.fit_palette();
+            let palette = GifPalette::of(&patch);
+            let size = patch.frame(&palette, 0, true).buffer.len() + 3 * palette.len();
+            (size, patch)
+        }).min_by_key(|(size, _)| *size).unwrap().1
+    }).collect();
+
+    let delays = gif_delays(&delays);
+    let data = write_gif_patches(&patches, &delays, (width, height), repeat, None)?;
+    // One global palette saves the local palettes if all colors fit in it.
+    let max = if patches.iter().any(GifPatch::has_transparency) { 255 } else { 256 };
+    let mut global = GifPalette::default();
+    if n > 1 && patches.iter().all(|p| global.extend(&p.pixels, max)) {
+        let data_global = write_gif_patches(&patches, &delays, (width, height), repeat, Some(&global))?;
+        if data_global.len() < data.len() {
+            return Ok(data_global);
+        }
+    }
+    Ok(data)
+}
+
+/// Optimize GIF files
+///
+/// Frames are re-encoded with only the changed areas, exact (or, with
+/// `lossy > 0`, merged) colors, and fresh LZW compression.  Frame delays and
+/// the loop count are kept, and a GIF that would not become smaller is
+/// copied as is.
+///
+/// @param input Paths to GIF files
+/// @param output Paths to the output GIF files (same length as `input`)
+/// @param level Optimization level (0-6; see `optimize_gif()`)
+/// @param lossy Maximum CIE76 Delta E threshold for merging colors (`<= 0`
+///   to keep the colors)
+/// @param verbose Whether to print the size change of each file
+/// @export
+#[extendr]
+fn tinygif_impl(input: Strings, output: Strings, level: i32, lossy: f64, verbose: bool) -> Result<()> {
+    let level = level.clamp(0, 6) as u8;
+    let inputs: Vec<String> = input.iter().map(|s| s.to_string()).collect();
+    let outputs: Vec<String> = output.iter().map(|s| s.to_string()).collect();
+    validate_io(&inputs, &outputs)?;
+    process_files(&inputs, &outputs, verbose, |input_path, output_path| {
+        let input_size = std::fs::metadata(input_path)
+            .map_err(|e| TinyImgRError::io(format!("Failed to read {}: {}", input_path.display(), e)))?.len();
+        let data = optimize_gif(input_path, level, lossy)?;
+        if data.len() as u64 >= input_size {
+            if input_path != output_path {
+                std::fs::copy(input_path, output_path)
+                    .map_err(|e| TinyImgRError::io(format!("Failed to copy {}: {}", input_path.display(), e)))?;
+            }
+            return Ok((input_size, input_size));
+        }
+        std::fs::write(output_path, &data)
+            .map_err(|e| TinyImgRError::io(format!("Failed to write {}: {}", output_path.display(), e)))?;
+        Ok((input_size, data.len() as u64))
+    })
+}
+
+// ---------------------------------------------------------------------------
+// Animated PNG / WebP assembly
+// ---------------------------------------------------------------------------
+//
+// An APNG is a regular PNG whose IDAT holds the first frame, plus an acTL
+// chunk (frame count and loop count) and, per frame, an fcTL chunk (region,
+// delay, disposal and blending) followed by the frame data (IDAT for the
+// first frame, fdAT for the rest).  All frames share the IHDR color type and
+// bit depth, so frame data is compressed as 8-bit RGBA with oxipng's color
+// reductions disabled.
+
+const PNG_SIGNATURE: [u8; 8] = [137, 80, 78, 71, 13, 10, 26, 10];
+
+fn read_u16(b: &[u8], pos: usize) -> u16 {
+    u16::from_be_bytes([b[pos], b[pos + 1]])
+}
+
+fn read_u32(b: &[u8], pos: usize) -> u32 {
+    u32::from_be_bytes([b[pos], b[pos + 1], b[pos + 2], b[pos + 3]])
+}
+
+/// Append a PNG chunk (length, type, data, CRC) to `out`.
+fn write_chunk(out: &mut Vec<u8>, kind: &[u8; 4], data: &[u8]) {
+    out.extend_from_slice(&(data.len() as u32).to_be_bytes());
+    out.extend_from_slice(kind);
+    out.extend_from_slice(data);
+    let mut crc = crc32fast::Hasher::new();
+    crc.update(kind);
+    crc.update(data);
+    out.extend_from_slice(&crc.finalize().to_be_bytes());
+}
+
+/// A PNG chunk as (type, data).
+type Chunk<'a> = ([u8; 4], &'a [u8]);
+
+/// Split a PNG file into its chunks.
+fn png_chunks(png: &[u8]) -> std::result::Result<Vec<Chunk<'_>>, String> {
+    if !png.starts_with(&PNG_SIGNATURE) {
+        return Err("not a PNG file".to_string());
+    }
+    let mut chunks = Vec::new();
+    let mut pos = PNG_SIGNATURE.len();
+    while pos + 12 <= png.len() {
+        let len = read_u32(png, pos) as usize;
+        let end = pos + 12 + len;
+        if end > png.len() {
+            return Err("truncated chunk".to_string());
+        }
+        let kind: [u8; 4] = png[pos + 4..pos + 8].try_into().unwrap();
+        chunks.push((kind, &png[pos + 8..pos + 8 + len]));
+        pos = end;
+        if &kind == b"IEND" {
+            break;
+        }
+    }
+    Ok(chunks)
+}
+
+/// Compress RGBA pixels as an 8-bit RGBA PNG with oxipng and return the
+/// concatenated IDAT data.
+fn rgba_to_idat(rgba: &[u8], width: usize, height: usize) -> Result<Vec<u8>> {
+    let mut encoder = lodepng::Encoder::new();
+    encoder.set_auto_convert(false);
+    let png = encoder.encode(rgba, width, height)
+        .map_err(|e| TinyImgRError::encode(format!("Failed to encode PNG frame: {}", e)))?;
+    let mut opts = png_options(DEFAULT_PNG_LEVEL);
+    opts.bit_depth_reduction = false;
+    opts.color_type_reduction = false;
+    opts.palette_reduction = false;
+    opts.grayscale_reduction = false;
+    let png = oxipng::optimize_from_memory(&png, &opts)
+        .map_err(|e| TinyImgRError::encode(format!("Failed to optimize PNG frame: {}", e)))?;
+    let chunks = png_chunks(&png)?;
+    Ok(chunks.iter().filter(|(k, _)| k == b"IDAT").flat_map(|(_, d)| d.iter().copied()).collect())
+}
+
+/// The smallest rectangle (x, y, width, height) containing all pixels that
+/// differ between two canvases, or a 1x1 rectangle if they are identical.
+fn changed_rect(prev: &[u8], cur: &[u8], width: usize, height: usize) -> (usize, usize, usize, usize) {
+    let (mut x0, mut y0, mut x1, mut y1) = (width, height, 0, 0);
+    for y in 0..height {
+        for x in 0..width {
+            let i = 4 * (y * width + x);
+            if prev[i..i + 4] != cur[i..i + 4] {
+                x0 = x0.min(x);
+                y0 = y0.min(y);
+                x1 = x1.max(x + 1);
+                y1 = y1.max(y + 1);
+            }
+        }
+    }
+    if x0 >= x1 { (0, 0, 1, 1) } else { (x0, y0, x1 - x0, y1 - y0) }
+}
+
+/// Copy the rectangle (x, y, w, h) out of an RGBA canvas.
+fn crop(rgba: &[u8], width: usize, (x, y, w, h): (usize, usize, usize, usize)) -> Vec<u8> {
+    let mut out = Vec::with_capacity(4 * w * h);
+    for row in y..y + h {
+        out.extend_from_slice(&rgba[4 * (row * width + x)..4 * (row * width + x + w)]);
+    }
+    out
+}
+
+/// Convert a delay in seconds to an APNG delay fraction (numerator, denominator).
+fn apng_delay(delay: f64) -> (u16, u16) {
+    let ms = (delay * 1000.0).round();
+    if ms <= u16::MAX as f64 {
+        (ms.max(0.0) as u16, 1000)
+    } else {
+        ((delay * 10.0).round().min(u16::MAX as f64) as u16, 10)
+    }
+}
+
+/// Assemble RGBA frames into an APNG.  Each frame after the first only
+/// stores the rectangle that changed since the previous frame, drawn with
+/// `APNG_DISPOSE_OP_NONE` and `APNG_BLEND_OP_SOURCE`.
+fn encode_apng(anim: &Animation, loops: u32) -> Result<Vec<u8>> {
+    let Animation { frames, width, height, delays } = anim;
+    let (width, height) = (*width, *height);
+    let mut out = PNG_SIGNATURE.to_vec();
+    let mut ihdr = Vec::with_capacity(13);
+    ihdr.extend_from_slice(&(width as u32).to_be_bytes());
+    ihdr.extend_from_slice(&(height as u32).to_be_bytes());
+    ihdr.extend_from_slice(&[8, 6, 0, 0, 0]);
+    write_chunk(&mut out, b"IHDR", &ihdr);
+    let mut actl = (frames.len() as u32).to_be_bytes().to_vec();
+    actl.extend_from_slice(&loops.to_be_bytes());
+    write_chunk(&mut out, b"acTL", &actl);
+
+    let mut seq = 0u32;
+    for (i, rgba) in frames.iter().enumerate() {
+        let rect = if i == 0 {
+            (0, 0, width, height)
+        } else {
+            changed_rect(&frames[i - 1], rgba, width, height)
+        };
+        let (x, y, w, h) = rect;
+        let (num, den) = apng_delay(delays[i]);
+        let mut fctl = seq.to_be_bytes().to_vec();
+        for v in [w, h, x, y] {
+            fctl.extend_from_slice(&(v as u32).to_be_bytes());
+        }
+        fctl.extend_from_slice(&num.to_be_bytes());
+        fctl.extend_from_slice(&den.to_be_bytes());
+        fctl.extend_from_slice(&[0, 0]);
+        write_chunk(&mut out, b"fcTL", &fctl);
+        seq += 1;
+
+        let idat = rgba_to_idat(&crop(rgba, width, rect), w, h)?;
+        if i == 0 {
+            write_chunk(&mut out, b"IDAT", &idat);
+        } else {
+            let mut fdat = seq.to_be_bytes().to_vec();
+            fdat.extend_from_slice(&idat);
+            write_chunk(&mut out, b"fdAT", &fdat);
+            seq += 1;
+        }
+    }
+    write_chunk(&mut out, b"IEND", &[]);
+    Ok(out)
+}
+
+/// Assemble RGBA frames into a lossless animated WebP.  Consecutive identical
+/// frames are merged by libwebp.
+fn encode_webp_anim(anim: &Animation, loops: u32) -> Result<Vec<u8>> {
+    let Animation { frames, width, height, delays } = anim;
+    let (width, height) = (*width, *height);
+    let mut config = webp::WebPConfig::new()
+        .map_err(|_| "Failed to initialize the WebP encoder".to_string())?;
+    config.lossless = 1;
+    let mut encoder = webp::AnimEncoder::new(width as u32, height as u32, &config);
+    encoder.set_loop_count(loops as i32);
+    let mut timestamp = 0;
+    for (rgba, delay) in frames.iter().zip(delays) {
+        encoder.add_frame(webp::AnimFrame::from_rgba(rgba, width as u32, height as u32, timestamp));
+        timestamp += (delay * 1000.0).round() as i32;
+    }
+    let mut data = encoder.try_encode()
+        .map_err(|e| TinyImgRError::encode(format!("Failed to encode animated WebP: {:?}", e)))?
+        .to_vec();
+    // The encoder does not know when the last frame ends and gives it the
+    // average duration of the other frames, so fix it in the last ANMF chunk.
+    let mut pos = 12;
+    let (mut last, mut start) = (None, 0);
+    while pos + 8 <= data.len() {
+        let len = u32::from_le_bytes(data[pos + 4..pos + 8].try_into().unwrap()) as usize;
+        if &data[pos..pos + 4] == b"ANMF" && len >= 16 {
+            if let Some(p) = last {
+                start += webp_duration(&data, p);
+            }
+            last = Some(pos + 20);
+        }
+        pos += 8 + len + len % 2;
+    }
+    if let Some(p) = last {
+        let duration = (timestamp - start as i32).clamp(0, 0xffffff) as u32;
+        data[p..p + 3].copy_from_slice(&duration.to_le_bytes()[..3]);
+    }
+    Ok(data)
+}
+
+/// Read the 24-bit little-endian frame duration at `pos` in a WebP file.
+fn webp_duration(data: &[u8], pos: usize) -> u32 {
+    u32::from_le_bytes([data[pos], data[pos + 1], data[pos + 2], 0])
+}
+
+/// Convert delays in seconds to GIF delays in centiseconds.  Each frame's
+/// end time is rounded instead of its delay, so rounding errors do not
+/// accumulate and the total duration is preserved.
+fn gif_delays(delays: &[f64]) -> Vec<u16> {
+    let mut end = 0.0;
+    let mut prev = 0i64;
+    delays.iter().map(|d| {
+        end += d;
+        let cs = (end * 100.0).round() as i64;
+        let delay = (cs - prev).clamp(0, u16::MAX as i64) as u16;
+        prev = cs;
+        delay
+    }).collect()
+}
+
+/// Assemble RGBA frames into a GIF, quantizing each frame to its own palette
+/// of at most 256 colors (or all frames to one global palette).  Pixels with
+/// alpha below 128 become transparent.  Each frame covers the whole canvas
+/// and is cleared after display, so transparent areas never show earlier
+/// frames.
+fn encode_gif(anim: &Animation, loops: u32, global_palette: bool) -> Result<Vec<u8>> {
+    let Animation { frames, width, height, delays } = anim;
+    let (width, height) = (*width, *height);
+    if width > u16::MAX as usize || height > u16::MAX as usize {
+        return Err(TinyImgRError::encode(format!("Canvas {}x{} is too large for GIF", width, height)).into());
+    }
+    // Index 255 is reserved for transparent pixels when there are any.
+    let is_transparent = |px: &[u8]| px[3] < 128;
+    let has_alpha = |rgba: &[u8]| rgba.chunks_exact(4).any(is_transparent);
+    let to_colors = |rgba: &[u8]| -> Vec<Color> {
+        rgba.chunks_exact(4).map(|p| Color::new(p[0], p[1], p[2], 255)).collect()
+    };
+    let index = |pixels: &[Color], n: usize| -> (Vec<u8>, Vec<u8>) {
+        let (palette, indices) = OrderedDither.to_indexed(pixels, width, n);
+        (palette.iter().flat_map(|c| [c.r, c.g, c.b]).collect(), indices)
+    };
+
+    let mut palettes: Vec<Vec<u8>> = Vec::with_capacity(frames.len());
+    let mut indices: Vec<Vec<u8>> = Vec::with_capacity(frames.len());
+    let transparent = if global_palette {
+        let alpha = frames.iter().any(|f| has_alpha(f));
+        let pixels: Vec<Color> = frames.iter().flat_map(|f| to_colors(f)).collect();
+        let (palette, all) = index(&pixels, if alpha { 255 } else { 256 });
+        indices.extend(all.chunks(width * height).map(|c| c.to_vec()));
+        palettes.push(palette);
+        alpha
+    } else {
+        let mut alpha = false;
+        for rgba in frames {
+            alpha |= has_alpha(rgba);
+            let (palette, idx) = index(&to_colors(rgba), if has_alpha(rgba) { 255 } else { 256 });
+            palettes.push(palette);
+            indices.push(idx);
+        }
+        alpha
+    };
+
+    let global: &[u8] = if global_palette { &palettes[0] } else { &[] };
+    let mut out = Vec::new();
+    let mut encoder = gif::Encoder::new(&mut out, width as u16, height as u16, global)
+        .map_err(|e| TinyImgRError::encode(format!("Failed to encode GIF: {}", e)))?;
+    let repeat = if loops == 0 { gif::Repeat::Infinite } else { gif::Repeat::Finite(loops as u16) };
+    encoder.set_repeat(repeat)
+        .map_err(|e| TinyImgRError::encode(format!("Failed to encode GIF: {}", e)))?;
+    for (i, (rgba, delay)) in frames.iter().zip(gif_delays(delays)).enumerate() {
+        let mut buffer = std::mem::take(&mut indices[i]);
+        if transparent {
+            for (b, px) in buffer.iter_mut().zip(rgba.chunks_exact(4)) {
+                if is_transparent(px) {
+                    *b = 255;
+                }
+            }
+        }
+        let mut palette = None;
+        if !global_palette {
+            // Pad the local palette so that the transparent index exists.
+            let mut p = std::mem::take(&mut palettes[i]);
+            if transparent {
+                p.resize(256 * 3, 0);
+            }
+            palette = Some(p);
+        }
+        let frame = gif::Frame {
+            width: width as u16,
+            height: height as u16,
+            buffer: buffer.into(),
+            palette,
+            delay,
+            dispose: DisposalMethod::Background,
+            transparent: transparent.then_some(255),
+            ..gif::Frame::default()
+        };
+        encoder.write_frame(&frame)
+            .map_err(|e| TinyImgRError::encode(format!("Failed to encode GIF frame {}: {}", i + 1, e)))?;
+    }
+    drop(encoder);
+    Ok(out)
+}
+
+/// Decode an APNG and composite its frames onto a full RGBA canvas, honoring
+/// each frame's disposal and blending operations, and pass each frame to
+/// `on_frame`.  Returns the number of frames read.
+fn read_apng<F>(input: &Path, mut on_frame: F) -> Result<usize>
+where
+    F: FnMut(&Frame) -> Result<()>,
+{
+    let bytes = std::fs::read(input)
+        .map_err(|e| TinyImgRError::io(format!("Failed to read {}: {}", input.display(), e)))?;
+    let err = |e: String| TinyImgRError::decode(format!("Failed to decode APNG {}: {}", input.display(), e));
+    let chunks = png_chunks(&bytes).map_err(err)?;
+    let ihdr = match chunks.first() {
+        Some((k, d)) if k == b"IHDR" && d.len() == 13 => *d,
+        _ => return Err(err("missing IHDR".to_string()).into()),
+    };
+    let width = read_u32(ihdr, 0) as usize;
+    let height = read_u32(ihdr, 4) as usize;
+    // Chunks that every frame needs to be decoded on its own (e.g. PLTE, tRNS).
+    let shared: Vec<&Chunk> = chunks.iter()
+        .take_while(|(k, _)| k != b"IDAT")
+        .filter(|(k, _)| !matches!(k, b"IHDR" | b"acTL" | b"fcTL"))
+        .collect();
+
+    // Group the frame data: each fcTL is followed by its IDAT or fdAT chunks.
+    let mut frames: Vec<(&[u8], Vec<u8>)> = Vec::new();
+    for (kind, data) in &chunks {
+        match kind {
+            b"fcTL" if data.len() >= 26 => frames.push((data, Vec::new())),
+            b"IDAT" => if let Some((_, d)) = frames.last_mut() { d.extend_from_slice(data) },
+            b"fdAT" if data.len() >= 4 => {
+                if let Some((_, d)) = frames.last_mut() { d.extend_from_slice(&data[4..]) }
+            }
+            _ => {}
+        }
+    }
+
+    let mut canvas = vec![0u8; width * height * 4];
+    for (index, (fctl, data)) in frames.iter().enumerate() {
+        let (fw, fh) = (read_u32(fctl, 4) as usize, read_u32(fctl, 8) as usize);
+        let (left, top) = (read_u32(fctl, 12) as usize, read_u32(fctl, 16) as usize);
+        let (num, den) = (read_u16(fctl, 20), read_u16(fctl, 22));
+        let (dispose, blend) = (fctl[24], fctl[25]);
+        if left + fw > width || top + fh > height {
+            return Err(err(format!("frame {} lies outside the canvas", index + 1)).into());
+        }
+
+        let mut png = PNG_SIGNATURE.to_vec();
+        let mut header = ihdr.to_vec();
+        header[0..4].copy_from_slice(&(fw as u32).to_be_bytes());
+        header[4..8].copy_from_slice(&(fh as u32).to_be_bytes());
+        write_chunk(&mut png, b"IHDR", &header);
+        for (k, d) in &shared {
+            write_chunk(&mut png, k, d);
+        }
+        write_chunk(&mut png, b"IDAT", data);
+        write_chunk(&mut png, b"IEND", &[]);
+        let img = lodepng::decode32(&png).map_err(|e| err(e.to_string()))?;
+
+        // APNG_DISPOSE_OP_PREVIOUS on the first frame is treated as BACKGROUND.
+        let saved = (dispose == 2 && index > 0).then(|| canvas.clone());
+        for y in 0..fh {
+            for x in 0..fw {
+                let src = img.buffer[y * fw + x];
+                let c = 4 * ((top + y) * width + left + x);
+                let dst = &mut canvas[c..c + 4];
+                if blend == 0 || src.a == 255 {
+                    dst.copy_from_slice(&[src.r, src.g, src.b, src.a]);
+                } else if src.a > 0 {
+                    // APNG_BLEND_OP_OVER: alpha-composite the frame over the canvas.
+                    let sa = src.a as f64 / 255.0;
+                    let da = dst[3] as f64 / 255.0 * (1.0 - sa);
+                    let a = sa + da;
+                    for (d, s) in dst.iter_mut().zip([src.r, src.g, src.b]) {
+                        *d = ((s as f64 * sa + *d as f64 * da) / a).round() as u8;
+                    }
+                    dst[3] = (a * 255.0).round() as u8;
+                }
+            }
+        }
+
+        let delay = if den == 0 { num as f64 / 100.0 } else { num as f64 / den as f64 };
+        on_frame(&Frame { index, rgba: &canvas, width, height, delay })?;
+
+        match (dispose, saved) {
+            (2, Some(saved)) => canvas = saved,
+            (1, _) | (2, None) => {
+                for y in top..top + fh {
+                    canvas[4 * (y * width + left)..4 * (y * width + left + fw)].fill(0);
+                }
+            }
+            _ => {}
+        }
+    }
+    Ok(frames.len())
+}
+
+/// Decode an animated WebP and pass each (composited) frame to `on_frame`.
+/// Returns the number of frames read.
+fn read_webp_anim<F>(input: &Path, mut on_frame: F) -> Result<usize>
+where
+    F: FnMut(&Frame) -> Result<()>,
+{
+    let bytes = std::fs::read(input)
+        .map_err(|e| TinyImgRError::io(format!("Failed to read {}: {}", input.display(), e)))?;
+    let anim = webp::AnimDecoder::new(&bytes).decode()
+        .map_err(|e| TinyImgRError::decode(format!("Failed to decode WebP {}: {}", input.display(), e)))?;
+    // Timestamps mark the end of each frame, so delays are their differences.
+    let mut start = 0;
+    for index in 0..anim.len() {
+        let frame = anim.get_frame(index).unwrap();
+        let end = frame.get_time_ms();
+        on_frame(&Frame {
+            index,
+            rgba: frame.get_image(),
+            width: frame.width() as usize,
+            height: frame.height() as usize,
+            delay: (end - start) as f64 / 1000.0,
+        })?;
+        start = end;
+    }
+    Ok(anim.len())
+}
+
+/// Decode an animated GIF, PNG, or WebP file (detected from its signature)
+/// and pass each composited frame to `on_frame`.  Returns the number of
+/// frames read.
+fn read_anim<F>(input: &Path, on_frame: F) -> Result<usize>
+where
+    F: FnMut(&Frame) -> Result<()>,
+{
+    let mut head = [0u8; 12];
+    let n = File::open(input)
+        .and_then(|mut f| std::io::Read::read(&mut f, &mut head))
+        .map_err(|e| TinyImgRError::io(format!("Failed to read {}: {}", input.display(), e)))?;
+    let head = &head[..n];
+    if head.starts_with(b"GIF8") {
+        read_gif(input, None, on_frame)
+    } else if head.starts_with(&PNG_SIGNATURE) {
+        read_apng(input, on_frame)
+    } else if head.len() == 12 && &head[0..4] == b"RIFF" && &head[8..12] == b"WEBP" {
+        read_webp_anim(input, on_frame)
+    } else {
+        Err(TinyImgRError::argument(format!("Unsupported animation format: {}", input.display())).into())
+    }
+}
+
+/// Decode all frames of an animated GIF, PNG, or WebP file
+///
+/// @param input Path to an animation file
+/// @return A list with the canvas width and height, frame delays in seconds,
+///   and a list of RGBA pixel data (raw vectors) of the composited frames
+#[extendr]
+fn anim_frames_impl(input: &str) -> Result<List> {
+    let (mut width, mut height) = (0, 0);
+    let mut delays: Vec<f64> = Vec::new();
+    let mut pixels: Vec<Robj> = Vec::new();
+    read_anim(Path::new(input), |frame| {
+        (width, height) = (frame.width as i32, frame.height as i32);
+        delays.push(frame.delay);
+        pixels.push(Raw::from_bytes(frame.rgba).into());
+        Ok(())
+    })?;
+    Ok(list!(width = width, height = height, delay = delays, rgba = List::from_values(pixels)))
+}
+
+/// Encode RGBA frames in the given format and write them to `output`.
+fn write_anim(
+    output: &str, format: &str, anim: &Animation, loops: u32, global_palette: bool,
+) -> Result<()> {
+    let data = match format {
+        "apng" => encode_apng(anim, loops)?,
+        "webp" => encode_webp_anim(anim, loops)?,
+        "gif" => encode_gif(anim, loops, global_palette)?,
+        _ => return Err(TinyImgRError::argument(format!("Unsupported animation format: {}", format)).into()),
+    };
+    if let Some(parent) = Path::new(output).parent() {
+        std::fs::create_dir_all(parent)
+            .map_err(|e| TinyImgRError::io(format!("Failed to create directory {}: {}", parent.display(), e)))?;
+    }
+    std::fs::write(output, &data)
+        .map_err(|e| TinyImgRError::io(format!("Failed to write {}: {}", output, e)).into())
+}
+
+/// Assemble PNG frames into an animated PNG, WebP, or GIF
+///
+/// @param input Vector of input PNG file paths (one per frame)
+/// @param output Path to the output file
+/// @param delay Frame delays in seconds (recycled to the number of frames)
+/// @param format Output format (`"apng"`, `"webp"`, or `"gif"`)
+/// @param loops Number of loops (0 means infinite)
+/// @export
+#[extendr]
+fn tinyanim_impl(input: Strings, output: &str, delay: Doubles, format: &str, loops: i32) -> Result<()> {
+    if input.is_empty() {
+        return Err(TinyImgRError::argument("No input frames").into());
+    }
+    if delay.is_empty() || delay.iter().any(|d| d.is_na() || d.inner() < 0.0) {
+        return Err(TinyImgRError::argument("Frame delays must be non-negative numbers").into());
+    }
+    if loops < 0 {
+        return Err(TinyImgRError::argument("The number of loops must be non-negative").into());
+    }
+    let mut frames = Vec::with_capacity(input.len());
+    let mut size = None;
+    for f in input.iter() {
+        let path = f.as_str();
+        let (rgba, fw, fh) = read_png_rgba(Path::new(path))?;
+        let (w, h) = *size.get_or_insert((fw, fh));
+        if (fw, fh) != (w, h) {
+            return Err(TinyImgRError::argument(format!(
+                "Frame {} is {}x{} but the first frame is {}x{}", path, fw, fh, w, h
+            )).into());
+        }
+        frames.push(rgba);
+    }
+    let (width, height) = size.unwrap();
+    let delays: Vec<f64> = (0..frames.len()).map(|i| delay.elt(i % delay.len()).inner()).collect();
+    let anim = Animation { frames, width, height, delays };
+    write_anim(output, format, &anim, loops as u32, false)
+}
+
+/// Convert an animation between the GIF, APNG, and WebP formats
+///
+/// @param input Path to an animated GIF, PNG, or WebP file
+/// @param output Path to the output file
+/// @param format Output format (`"gif"`, `"apng"`, or `"webp"`)
+/// @param loops Number of loops (0 means infinite)
+/// @param global_palette Use one palette for all frames in GIF output
+/// @export
+#[extendr]
+fn anim_convert_impl(
+    input: &str, output: &str, format: &str, loops: i32, global_palette: bool,
+) -> Result<()> {
+    if loops < 0 {
+        return Err(TinyImgRError::argument("The number of loops must be non-negative").into());
+    }
+    let mut anim = Animation { frames: Vec::new(), width: 0, height: 0, delays: Vec::new() };
+    read_anim(Path::new(input), |frame| {
+        (anim.width, anim.height) = (frame.width, frame.height);
+        anim.frames.push(frame.rgba.to_vec());
+        anim.delays.push(frame.delay);
+        Ok(())
+    })?;
+    if anim.frames.is_empty() {
+        return Err(TinyImgRError::decode(format!("No frames found in {}", input)).into());
+    }
+    write_anim(output, format, &anim, loops as u32, global_palette)
+}
+
+/// Remove consecutive duplicate frames from an APNG file
+///
+/// @param input Path to the input APNG file
+/// @param output Path to the output APNG file
+/// @return The numbers of frames before and after removing duplicates
+/// @export
+#[extendr]
+fn tinypng_strip_duplicate_frames_impl(input: &str, output: &str) -> Result<Integers> {
+    let path = Path::new(input);
+    let bytes = std::fs::read(path)
+        .map_err(|e| TinyImgRError::io(format!("Failed to read {}: {}", input, e)))?;
+    let chunks = png_chunks(&bytes)
+        .map_err(|e| TinyImgRError::decode(format!("Failed to decode APNG {}: {}", input, e)))?;
+    let loops = chunks.iter()
+        .find(|(k, d)| k == b"acTL" && d.len() >= 8)
+        .map_or(0, |(_, d)| read_u32(d, 4));
+    let mut anim = Animation { frames: Vec::new(), width: 0, height: 0, delays: Vec::new() };
+    // Frames are composited onto the whole canvas, so a duplicate is a frame
+    // whose pixels are identical to the previous one; its duration is added
+    // to the previous frame.  The frames are compared byte by byte, which is
+    // as cheap as hashing them and cannot produce false positives.
+    let n = read_apng(path, |frame| {
+        (anim.width, anim.height) = (frame.width, frame.height);
+        match (anim.frames.last(), anim.delays.last_mut()) {
+            (Some(prev), Some(delay)) if prev[..] == frame.rgba[..] => *delay += frame.delay,
+            _ => {
+                anim.frames.push(frame.rgba.to_vec());
+                anim.delays.push(frame.delay);
+            }
+        }
+        Ok(())
+    })?;
+    if n == 0 {
+        return Err(TinyImgRError::decode(format!("No frames found in {} (not an APNG file?)", input)).into());
+    }
+    write_anim(output, "apng", &anim, loops, false)?;
+    Ok(Integers::from_values([n as i32, anim.frames.len() as i32]))
+}
+
+extendr_module! {
+    mod anim;
+    fn gif_frames_impl;
+    fn gif_to_png_impl;
+    fn tinygif_impl;
+    fn tinyanim_impl;
+    fn anim_frames_impl;
+    fn anim_convert_impl;
+    fn tinypng_strip_duplicate_frames_impl;
+}
@@ -0,0 +1,132 @@
+use extendr_api::prelude::*;
+use std::path::Path;
+
+use crate::error::TinyImgRError;
+use crate::quantize::{Quantizer, NEAREST};
+use crate::optimize::{
+    count_unique_colors, decode_png_colors, encode_png_colors, find_png_files, png_options, sample_indices,
+};
+
+// ---------------------------------------------------------------------------
+// Auditing directories of PNG files
+// ---------------------------------------------------------------------------
+//
+// The audit estimates how much each PNG file would shrink, without writing
+// anything: files are only read, and all trial optimizations are done in
+// memory.
+
+/// Number of colors of the quick lossy probe for truecolor images.
+const PROBE_COLORS: usize = 64;
+
+/// Maximum number of pixels sampled to estimate the number of unique colors.
+const COLOR_SAMPLES: usize = 50_000;
+
+/// Names of the PNG color types (indexed by the color type in IHDR).
+fn color_type_name(color_type: u8) -> Option<&'static str> {
+    match color_type {
+        0 => Some("gray"),
+        2 => Some("rgb"),
+        3 => Some("palette"),
+        4 => Some("gray_alpha"),
+        6 => Some("rgba"),
+        _ => None,
+    }
+}
+
+/// Audit results of a single PNG file.
+struct Audit {
+    width: u32,
+    height: u32,
+    color_type: &'static str,
+    colors: usize,
+    lossless_size: usize,
+    lossy_size: Option<usize>,
+}
+
+/// Read the dimensions and color type of a PNG file from its IHDR chunk, and
+/// estimate its optimized size with an in-memory pass at `level`, plus a
+/// quantization to `PROBE_COLORS` colors for truecolor images if `lossy`.
+fn audit_png(png: &[u8], input: &Path, level: u8, lossy: bool) -> std::result::Result<Audit, String> {
+    if png.len() < 33 || &png[12..16] != b"IHDR" {
+        return Err("missing IHDR".to_string());
+    }
+    let read_u32 = |i: usize| u32::from_be_bytes(png[i..i + 4].try_into().unwrap());
+    let color_type = color_type_name(png[25]).ok_or("invalid color type")?;
+    let opts = png_options(level);
+    let lossless = oxipng::optimize_from_memory(png, &opts).map_err(|e| e.to_string())?;
+    let (pixels, width, height) = decode_png_colors(png, input).map_err(|e| e.to_string())?;
+    let samples: Vec<_> = sample_indices(pixels.len(), COLOR_SAMPLES).iter().map(|&i| pixels[i]).collect();
+    let colors = count_unique_colors(&samples);
+    // Images with fewer colors than the probe would not lose anything.
+    let lossy_size = if lossy && matches!(color_type, "rgb" | "rgba") && colors > PROBE_COLORS {
+        let q = NEAREST.quantize(&pixels, width, PROBE_COLORS);
+        drop(pixels);
+        let png = encode_png_colors(&q, width, height)?;
+        Some(oxipng::optimize_from_memory(&png, &opts).map_err(|e| e.to_string())?.len())
+    } else {
+        None
+    };
+    Ok(Audit {
+        width: read_u32(16),
+        height: read_u32(20),
+        color_type,
+        colors,
+        lossless_size: lossless.len(),
+        lossy_size,
+    })
+}
+
+/// Estimate the savings of optimizing the PNG files in a directory
+///
+/// @param dir Path to the directory
+/// @param recursive Whether to include subdirectories
+/// @param sample_level Optimization level (0-6) of the trial optimization
+/// @param lossy Whether to probe a 64-color quantization of truecolor images
+/// @return A list of the file paths, sizes, dimensions, color types, estimated
+///   numbers of unique colors, and estimated lossless and lossy sizes (`NA`
+///   for the fields that could not be determined)
+/// @export
+#[extendr]
+fn tinyaudit_impl(dir: &str, recursive: bool, sample_level: i32, lossy: bool) -> Result<List> {
+    if !(0..=6).contains(&sample_level) {
+        return Err(TinyImgRError::argument("The sample level must be between 0 and 6").into());
+    }
+    let files = find_png_files(Path::new(dir), recursive)
+        .map_err(|e| TinyImgRError::io(format!("Failed to read directory {}: {}", dir, e)))?;
+    let n = files.len();
+    let (mut paths, mut sizes, mut widths, mut heights) =
+        (Vec::with_capacity(n), Vec::with_capacity(n), Vec::with_capacity(n), Vec::with_capacity(n));
+    let (mut types, mut colors, mut lossless, mut lossy_sizes) =
+        (Vec::with_capacity(n), Vec::with_capacity(n), Vec::with_capacity(n), Vec::with_capacity(n));
+    for path in files {
+        let png = std::fs::read(&path)
+            .map_err(|e| TinyImgRError::io(format!("Failed to read {}: {}", path.display(), e)))?;
+        // Broken files are reported with NA instead of failing the audit.
+        let audit = audit_png(&png, &path, sample_level as u8, lossy).ok();
+        let int = |f: fn(&Audit) -> usize| Rint::from(audit.as_ref().map(|a| f(a) as i32));
+        let size = |v: Option<usize>| v.map_or(Rfloat::na(), |v| Rfloat::from(v as f64));
+        paths.push(path.to_string_lossy().into_owned());
+        sizes.push(png.len() as f64);
+        widths.push(int(|a| a.width as usize));
+        heights.push(int(|a| a.height as usize));
+        types.push(audit.as_ref().map_or(Rstr::na(), |a| Rstr::from(a.color_type)));
+        colors.push(int(|a| a.colors));
+        lossless.push(size(audit.as_ref().map(|a| a.lossless_size)));
+        lossy_sizes.push(size(audit.as_ref().and_then(|a| a.lossy_size)));
+    }
+    Ok(list!(
+        file = paths,
+        size = sizes,
+        width = Integers::from_values(widths),
+        height = Integers::from_values(heights),
+        color_type = Strings::from_values(types),
+        colors = Integers::from_values(colors),
+        lossless_size = Doubles::from_values(lossless),
+        lossy_size = Doubles::from_values(lossy_sizes)
+    ))
+}
+
+extendr_module! {
+    mod audit;
+    fn tinyaudit_impl;
+}
@@ -0,0 +1,328 @@
+// A command-line interface to the PNG optimization of the tinyimg R package,
+// sharing its pipeline (see src/optimize.rs), so that files optimized with the
+// same settings are identical to those from `tinypng()`.
+
+use oxipng::StripChunks;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
+
+use tinyimg::optimize::{
+    find_png_files, find_truncate_index, optimize_batch, BatchOptions, ColorType, FileResult, LossySpeed,
+    PngSettings, VerboseMessage,
+};
+
+const USAGE: &str = "\
+Usage: tinyimg-cli [OPTIONS] <INPUT>...
+
+Optimize PNG files (or the PNG files in directories) with oxipng, optionally
+with lossy palette reduction, like tinypng() in the R package tinyimg.
+
+Options:
+  -o, --output <PATH>    Output file for a single input file, or output directory
+                         for a single input directory (by default, files are
+                         optimized in place, or written to <name>_l<lossy>.png
+                         with --lossy)
+  -l, --level <LEVEL>    Optimization level from 0 to 6 [default: 2]
+      --lossy <DELTA_E>  Maximum CIE76 Delta E of lossy palette reduction
+                         [default: 0, i.e., lossless]
+      --lossy-speed <SPEED>
+                         Speed of lossy palette reduction from 1 (slowest, most
+                         exact) to 10 (fastest, coarsest) [default: 4]
+      --alpha            Optimize transparent pixels (changes their colors)
+      --strip <MODE>     Metadata chunks to strip: all, safe, or none [default: all]
+      --convert-profile  Convert images with color profiles (iCCP, or gAMA/cHRM
+                         incompatible with sRGB) to sRGB before stripping the
+                         profiles (unsupported profiles are kept with a warning)
+      --metadata-backup <DIR|.SUFFIX>
+                         Save the stripped metadata chunks of each file to a
+                         sidecar file <DIR>/<name>.meta, or <output>.SUFFIX
+                         (e.g., .meta), to restore them later with
+                         png_restore_meta() in R
+      --color-type <TYPE>
+                         Write all outputs in a color type: rgba, rgb, palette,
+                         gray, or gray_alpha (images that cannot be represented
+                         in it fail; use --lossy to reduce them to a palette)
+      --reproducible     Make the output bytes depend only on the input and the
+                         options (strip time-varying chunks such as tIME and
+                         text chunks, even with --strip none or safe)
+  -j, --threads <N>      Number of files to optimize at the same time (0 for the
+                         number of available CPUs) [default: 1]
+      --no-preserve      Do not preserve file permissions and timestamps
+      --no-recursive     Do not search subdirectories of input directories
+      --json <PATH>      Write a JSON report of the results to a file (- for stdout)
+  -q, --quiet            Do not print the size changes of files
+  -h, --help             Print this help
+
+Exit status: 0 if all files were optimized, 1 if any file failed, and 2 for
+invalid arguments.";
+
+/// Parsed command-line arguments.
+struct Args {
+    inputs: Vec<PathBuf>,
+    output: Option<PathBuf>,
+    level: i32,
+    lossy: f64,
+    lossy_speed: LossySpeed,
+    alpha: bool,
+    strip: StripChunks,
+    convert_profile: bool,
+    metadata_backup: Option<String>,
+    color_type: Option<ColorType>,
+    reproducible: bool,
+    preserve: bool,
+    recursive: bool,
+    threads: usize,
+    json: Option<String>,
+    quiet: bool,
+}
+
+/// Parse the command-line arguments, or return `None` if help was requested.
+fn parse_args(args: impl IntoIterator<Item = String>) -> Result<Option<Args>, String> {
+    let mut parsed = Args {
+        inputs: Vec::new(),
+        output: None,
+        level: 2,
+        lossy: 0.0,
+        lossy_speed: LossySpeed::default(),
+        alpha: false,
+        strip: StripChunks::All,
+        convert_profile: false,
+        metadata_backup: None,
+        color_type: None,
+        reproducible: false,
+        preserve: true,
+        recursive: true,
+        threads: 1,
+        json: None,
+        quiet: false,
+    };
+    let mut args = args.into_iter();
+    let mut positional_only = false;
+    while let Some(arg) = args.next() {
+        if positional_only || !arg.starts_with('-') || arg == "-" {
+            parsed.inputs.push(PathBuf::from(arg));
+            continue;
+        }
+        // Options can be given as `--name value` or `--name=value`.
+        let (name, inline) = match arg.split_once('=') {
+            Some((name, value)) if name.starts_with("--") => (name.to_string(), Some(value.to_string())),
+            _ => (arg.clone(), None),
+        };
+        let mut value = || {
+            inline.clone().or_else(|| args.next()).ok_or(format!("Missing value for {}", name))
+        };
+        match name.as_str() {
+            "--" => positional_only = true,
+            "-h" | "--help" => return Ok(None),
+            "-o" | "--output" => parsed.output = Some(PathBuf::from(value()?)),
+            "-l" | "--level" => {
+                let v = value()?;
+                parsed.level = match v.parse() {
+                    Ok(level) if (0..=6).contains(&level) => level,
+                    _ => return Err(format!("The level must be an integer from 0 to 6: {}", v)),
+                };
+            }
+            "--lossy" => {
+                let v = value()?;
+                parsed.lossy = match v.parse::<f64>() {
+                    Ok(lossy) if lossy >= 0.0 && lossy.is_finite() => lossy,
+                    _ => return Err(format!("The lossy threshold must be a non-negative number: {}", v)),
+                };
+            }
+            "--lossy-speed" => {
+                let v = value()?;
+                parsed.lossy_speed = v.parse().ok().and_then(|speed| LossySpeed::new(speed).ok())
+                    .ok_or(format!("The lossy speed must be an integer from 1 to 10: {}", v))?;
+            }
+            "--strip" => {
+                parsed.strip = match value()?.as_str() {
+                    "all" => StripChunks::All,
+                    "safe" => StripChunks::Safe,
+                    "none" => StripChunks::None,
+                    v => return Err(format!("Unknown strip mode (expected all, safe, or none): {}", v)),
+                };
+            }
+            "-j" | "--threads" => {
+                let v = value()?;
+                parsed.threads = v.parse()
+                    .map_err(|_| format!("The number of threads must be a non-negative integer: {}", v))?;
+            }
+            "--json" => parsed.json = Some(value()?),
+            "--metadata-backup" => parsed.metadata_backup = Some(value()?),
+            "--color-type" => {
+                parsed.color_type = Some(ColorType::from_name(&value()?).map_err(|e| e.to_string())?);
+            }
+            "--alpha" | "--convert-profile" | "--reproducible" | "--no-preserve" | "--no-recursive"
+            | "-q" | "--quiet" if inline.is_some() => {
+                return Err(format!("The flag {} does not take a value", name));
+            }
+            "--alpha" => parsed.alpha = true,
+            "--convert-profile" => parsed.convert_profile = true,
+            "--reproducible" => parsed.reproducible = true,
+            "--no-preserve" => parsed.preserve = false,
+            "--no-recursive" => parsed.recursive = false,
+            "-q" | "--quiet" => parsed.quiet = true,
+            _ => return Err(format!("Unknown option: {}", arg)),
+        }
+    }
+    if parsed.inputs.is_empty() {
+        return Err("No input files or directories".to_string());
+    }
+    if parsed.output.is_some() && parsed.inputs.len() > 1 {
+        return Err("--output can only be used with a single input".to_string());
+    }
+    Ok(Some(parsed))
+}
+
+/// The default output path of an input file, as `tiny_output()` in R: the
+/// file itself, or the file with the suffix `_l<lossy>` for lossy reduction.
+fn default_output(input: &Path, lossy: f64) -> PathBuf {
+    match (lossy > 0.0, input.file_stem(), input.extension()) {
+        (true, Some(stem), Some(ext)) => {
+            let mut name = stem.to_os_string();
+            name.push(format!("_l{}.", lossy));
+            name.push(ext);
+            input.with_file_name(name)
+        }
+        _ => input.to_path_buf(),
+    }
+}
+
+/// Resolve the input and output paths of all PNG files.
+fn resolve_files(args: &Args) -> Result<Vec<(PathBuf, PathBuf)>, String> {
+    let mut files = Vec::new();
+    for input in &args.inputs {
+        if input.is_dir() {
+            let found = find_png_files(input, args.recursive)
+                .map_err(|e| format!("Failed to read directory {}: {}", input.display(), e))?;
+            for file in found {
+                let output = match &args.output {
+                    // The directory structure is mirrored in the output directory.
+                    Some(dir) => dir.join(file.strip_prefix(input).unwrap_or(&file)),
+                    None => default_output(&file, args.lossy),
+                };
+                files.push((file, output));
+            }
+        } else if input.is_file() {
+            let output = args.output.clone().unwrap_or_else(|| default_output(input, args.lossy));
+            files.push((input.clone(), output));
+        } else {
+            return Err(format!("Input file does not exist: {}", input.display()));
+        }
+    }
+    Ok(files)
+}
+
+/// Quote a string as a JSON string.
+fn json_string(s: &str) -> String {
+    let mut out = String::with_capacity(s.len() + 2);
+    out.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c if (c as u32) < 0x20 => out.push_str(&format!("\\u{:04x}", c as u32)),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    out
+}
+
+/// A JSON array of the results, with the input and output paths and sizes,
+/// the removed chunk types, and the error message (`null` for success) of
+/// each file.
+fn json_report(results: &[FileResult]) -> String {
+    let items: Vec<String> = results.iter().map(|res| {
+        let (sizes, error) = match &res.sizes {
+            Ok((input_size, output_size)) => (
+                format!("\"input_size\": {}, \"output_size\": {}", input_size, output_size),
+                "null".to_string(),
+            ),
+            Err(e) => ("\"input_size\": null, \"output_size\": null".to_string(), json_string(&e.to_string())),
+        };
+        let removed: Vec<String> = res.removed.iter().map(|name| json_string(name)).collect();
+        format!(
+            "  {{\"input\": {}, \"output\": {}, {}, \"removed\": [{}], \"error\": {}}}",
+            json_string(&res.input.to_string_lossy()), json_string(&res.output.to_string_lossy()), sizes,
+            removed.join(", "), error
+        )
+    }).collect();
+    if items.is_empty() { "[]\n".to_string() } else { format!("[\n{}\n]\n", items.join(",\n")) }
+}
+
+fn run(args: Args) -> Result<bool, String> {
+    let files = resolve_files(&args)?;
+    let settings = PngSettings::new(args.level, args.alpha, args.preserve, args.lossy, [0.0; 3], "d65")
+        .map_err(|e| e.to_string())?
+        .with_strip(args.strip)
+        .with_reproducible(args.reproducible)
+        .with_profile_conversion(args.convert_profile)
+        .with_metadata_backup(args.metadata_backup.clone())
+        .with_color_type(args.color_type)
+        .with_lossy_speed(args.lossy_speed);
+    for (_, output) in &files {
+        if let Some(parent) = output.parent().filter(|p| !p.as_os_str().is_empty()) {
+            std::fs::create_dir_all(parent)
+                .map_err(|e| format!("Failed to create directory {}: {}", parent.display(), e))?;
+        }
+    }
+    let paths = |k: usize| -> Vec<String> {
+        files.iter().map(|f| [&f.0, &f.1][k].to_string_lossy().into_owned()).collect()
+    };
+    let (input_trunc, output_trunc) = (find_truncate_index(&paths(0)), find_truncate_index(&paths(1)));
+    // Keep stdout clean for the JSON report when it is written there.
+    let json_stdout = args.json.as_deref() == Some("-");
+    let batch = BatchOptions { settings, files, keep_going: true, threads: args.threads };
+    let results = optimize_batch(&batch, |res| {
+        if let Some(warning) = &res.warning {
+            eprintln!("tinyimg-cli: warning: {}", warning);
+        }
+        match &res.sizes {
+            Ok(sizes) if !args.quiet => {
+                let msg = VerboseMessage::new(&res.input.to_string_lossy(), &res.output.to_string_lossy(), *sizes);
+                if let Some(line) = msg.summary(input_trunc, output_trunc) {
+                    if json_stdout { eprintln!("{}", line) } else { println!("{}", line) }
+                }
+            }
+            Ok(_) => {}
+            Err(e) => eprintln!("tinyimg-cli: {}", e),
+        }
+        Ok(())
+    });
+    if let Some(json) = &args.json {
+        let report = json_report(&results);
+        if json_stdout {
+            std::io::stdout().write_all(report.as_bytes())
+        } else {
+            std::fs::write(json, report)
+        }.map_err(|e| format!("Failed to write the JSON report {}: {}", json, e))?;
+    }
+    Ok(results.iter().all(|res| res.sizes.is_ok()))
+}
+
+fn main() -> ExitCode {
+    let args = match parse_args(std::env::args().skip(1)) {
+        Ok(Some(args)) => args,
+        Ok(None) => {
+            println!("{}", USAGE);
+            return ExitCode::SUCCESS;
+        }
+        Err(e) => {
+            eprintln!("tinyimg-cli: {}\n\n{}", e, USAGE);
+            return ExitCode::from(2);
+        }
+    };
+    match run(args) {
+        Ok(true) => ExitCode::SUCCESS,
+        Ok(false) => ExitCode::from(1),
+        Err(e) => {
+            eprintln!("tinyimg-cli: {}", e);
+            ExitCode::from(1)
+        }
+    }
+}
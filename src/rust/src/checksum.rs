@@ -0,0 +1,71 @@
+use extendr_api::prelude::*;
+use std::path::{Path, PathBuf};
+
+use crate::error::TinyImgRError;
+use crate::optimize::sha256_hex;
+
+// ---------------------------------------------------------------------------
+// SHA-256 checksum manifests
+// ---------------------------------------------------------------------------
+//
+// A checksum manifest lists the SHA-256 hash of each output file in the format
+// of `sha256sum` (the hash in hex, two spaces, and the path relative to the
+// directory of the manifest), so that it can be checked with `sha256sum -c`
+// as well as `tinyverify()`.
+
+/// The path of a file relative to a directory, or its absolute path if it is
+/// not under the directory.
+fn relative_path(path: &Path, dir: &Path) -> String {
+    let path = std::path::absolute(path).unwrap_or_else(|_| path.to_path_buf());
+    let dir = std::path::absolute(dir).unwrap_or_else(|_| dir.to_path_buf());
+    path.strip_prefix(&dir).unwrap_or(&path).to_string_lossy().into_owned()
+}
+
+/// Write a checksum manifest of files with their hashes atomically (to a
+/// temporary file that is renamed), with paths relative to its directory.
+pub(crate) fn write_checksums(manifest: &Path, files: &[(PathBuf, String)]) -> std::result::Result<(), TinyImgRError> {
+    let dir = manifest.parent().filter(|p| !p.as_os_str().is_empty()).unwrap_or(Path::new("."));
+    let content: String = files.iter()
+        .map(|(path, hash)| format!("{}  {}\n", hash, relative_path(path, dir)))
+        .collect();
+    let mut tmp = manifest.as_os_str().to_owned();
+    tmp.push(".tmp");
+    std::fs::write(&tmp, content)
+        .and_then(|_| std::fs::rename(&tmp, manifest))
+        .map_err(|e| TinyImgRError::io(format!("Failed to write checksums {}: {}", manifest.display(), e)))
+}
+
+/// Verify files against a SHA-256 checksum manifest
+///
+/// @param manifest Path to the manifest file
+/// @param root Directory that the paths in the manifest are relative to
+/// @return A list of the paths in the manifest (`file`), whether each file
+///   matches its hash (`ok`), and its status (`"ok"`, `"mismatch"`, or
+///   `"missing"`)
+/// @export
+#[extendr]
+fn tinyverify_impl(manifest: &str, root: &str) -> Result<List> {
+    let content = std::fs::read_to_string(manifest)
+        .map_err(|e| TinyImgRError::io(format!("Failed to read {}: {}", manifest, e)))?;
+    let (mut files, mut ok, mut status) = (Vec::new(), Vec::new(), Vec::new());
+    for (i, line) in content.lines().enumerate().filter(|(_, l)| !l.trim().is_empty()) {
+        // The path may follow the hash with a `*` for binary mode.
+        let Some((hash, path)) = line.split_once("  ").or_else(|| line.split_once(" *")) else {
+            return Err(TinyImgRError::decode(format!("{}:{}: not a sha256sum line: {}", manifest, i + 1, line)).into());
+        };
+        let s = match std::fs::read(Path::new(root).join(path)) {
+            Ok(bytes) if sha256_hex(&bytes).eq_ignore_ascii_case(hash) => "ok",
+            Ok(_) => "mismatch",
+            Err(_) => "missing",
+        };
+        files.push(path.to_string());
+        ok.push(s == "ok");
+        status.push(s);
+    }
+    Ok(list!(file = files, ok = ok, status = status))
+}
+
+extendr_module! {
+    mod checksum;
+    fn tinyverify_impl;
+}
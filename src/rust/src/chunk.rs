@@ -0,0 +1,181 @@
+use extendr_api::prelude::*;
+use std::io::{BufReader, Read};
+use std::path::Path;
+
+use crate::error::TinyImgRError;
+use crate::optimize::repair_crc;
+use crate::sidecar::{pixel_hash, read_sidecar, restore_chunks};
+use crate::validate_io;
+
+// ---------------------------------------------------------------------------
+// PNG chunk-level repairs
+// ---------------------------------------------------------------------------
+//
+// These functions patch PNG files at the byte level: the chunk layout (length,
+// type, data, CRC) is walked directly, and pixel data is never decoded.
+
+const PNG_SIGNATURE: [u8; 8] = [137, 80, 78, 71, 13, 10, 26, 10];
+
+/// Reads the chunk headers of a PNG file one after another without loading
+/// the file: after the header of a chunk at `offset`, the next chunk starts at
+/// `offset + 12 + length` (the length, type, data, and CRC), so the data and
+/// CRC are skipped with a seek.  Memory use does not depend on the file size.
+struct ChunkReader {
+    reader: BufReader<std::fs::File>,
+    /// Bytes to skip to reach the next chunk header.
+    skip: i64,
+}
+
+impl ChunkReader {
+    /// Open a PNG file and check its signature.
+    fn open(input: &Path) -> std::result::Result<Self, String> {
+        let file = std::fs::File::open(input).map_err(|e| e.to_string())?;
+        let mut reader = BufReader::new(file);
+        let mut signature = [0u8; 8];
+        reader.read_exact(&mut signature).map_err(|_| "not a PNG file".to_string())?;
+        if signature != PNG_SIGNATURE {
+            return Err("not a PNG file".to_string());
+        }
+        Ok(Self { reader, skip: 0 })
+    }
+}
+
+impl Iterator for ChunkReader {
+    /// The type and data length of a chunk.
+    type Item = std::result::Result<([u8; 4], u32), String>;
+
+    /// The next chunk header, or `None` at the end of the file (including a
+    /// truncated header).
+    fn next(&mut self) -> Option<Self::Item> {
+        if let Err(e) = self.reader.seek_relative(self.skip) {
+            return Some(Err(e.to_string()));
+        }
+        let mut header = [0u8; 8];
+        self.reader.read_exact(&mut header).ok()?;
+        let len = u32::from_be_bytes(header[..4].try_into().unwrap());
+        self.skip = len as i64 + 4;
+        Some(Ok((header[4..8].try_into().unwrap(), len)))
+    }
+}
+
+/// Check whether a PNG file is animated, i.e., whether an `acTL` chunk appears
+/// before the first `IDAT`.  Only the chunk headers are read (see
+/// `ChunkReader`), so the image is never loaded into memory.
+fn is_apng(input: &Path) -> std::result::Result<bool, String> {
+    // A missing IDAT/IEND is not our business here: the file is simply not
+    // animated as far as the chunks we could read tell.
+    for chunk in ChunkReader::open(input)? {
+        match &chunk?.0 {
+            b"acTL" => return Ok(true),
+            b"IDAT" | b"IEND" => return Ok(false),
+            _ => {}
+        }
+    }
+    Ok(false)
+}
+
+/// Detect animated PNG (APNG) files
+///
+/// @param input Vector of PNG file paths
+/// @return A logical vector indicating which files are APNGs
+/// @export
+#[extendr]
+fn tinypng_detect_animated_impl(input: Strings) -> Result<Logicals> {
+    let mut res = Vec::with_capacity(input.len());
+    for path in input.iter() {
+        let animated = is_apng(Path::new(path.as_str()))
+            .map_err(|e| TinyImgRError::io(format!("Failed to read {}: {}", path.as_str(), e)))?;
+        res.push(Rbool::from(animated));
+    }
+    Ok(Logicals::from_values(res))
+}
+
+/// Detect PNG files that a strict decoder rejects
+///
+/// @param input Vector of PNG file paths
+/// @return A list of whether each file decodes (`lodepng_ok`), and the lodepng
+///   error code (`lodepng_error_code`) and its description
+///   (`error_description`) for the files that do not (`NA` otherwise)
+/// @export
+#[extendr]
+fn tinypng_detect_corrupt_impl(input: Strings) -> List {
+    let errors: Vec<Option<lodepng::Error>> = input.iter()
+        .map(|path| lodepng::decode32_file(path.as_str()).err())
+        .collect();
+    list!(
+        lodepng_ok = Logicals::from_values(errors.iter().map(|e| Rbool::from(e.is_none()))),
+        lodepng_error_code = Integers::from_values(
+            errors.iter().map(|e| e.map_or(Rint::na(), |e| Rint::from(e.0 as i32)))
+        ),
+        // The descriptions are C strings with a trailing NUL.
+        error_description = Strings::from_values(
+            errors.iter().map(|e| e.map_or(Rstr::na(), |e| Rstr::from(e.as_str().trim_end_matches('\0'))))
+        )
+    )
+}
+
+/// Repair the CRCs of PNG chunks
+///
+/// @param input Vector of input PNG file paths
+/// @param output Vector of output PNG file paths (same length as input)
+/// @return The number of corrected chunks in each file
+/// @export
+#[extendr]
+fn tinypng_crc_repair_impl(input: Strings, output: Strings) -> Result<Integers> {
+    let inputs: Vec<String>  = input.iter().map(|s| s.to_string()).collect();
+    let outputs: Vec<String> = output.iter().map(|s| s.to_string()).collect();
+    validate_io(&inputs, &outputs)?;
+    let mut counts = Vec::with_capacity(inputs.len());
+    for (input, output) in inputs.iter().zip(&outputs) {
+        let mut png = std::fs::read(Path::new(input))
+            .map_err(|e| TinyImgRError::io(format!("Failed to read {}: {}", input, e)))?;
+        let fixed = repair_crc(&mut png)
+            .map_err(|e| TinyImgRError::encode(format!("Failed to repair {}: {}", input, e)))?;
+        std::fs::write(Path::new(output), &png)
+            .map_err(|e| TinyImgRError::io(format!("Failed to write {}: {}", output, e)))?;
+        counts.push(fixed as i32);
+    }
+    Ok(Integers::from_values(counts))
+}
+
+// ---------------------------------------------------------------------------
+// Metadata restore
+// ---------------------------------------------------------------------------
+//
+// Chunks backed up to a sidecar file by `tinypng(metadata_backup = )` (see
+// sidecar.rs) are put back into the image.  Only to check that the sidecar
+// belongs to the image are its pixels decoded.
+
+/// Restore metadata chunks from a sidecar file
+///
+/// @param image Path to the PNG file
+/// @param sidecar Path to the sidecar file
+/// @param output Path to the output PNG file
+/// @return A list of the number of restored chunks (`chunks`) and a warning
+///   if the sidecar was made for a different image (`warning`, or `NULL`)
+/// @export
+#[extendr]
+fn png_restore_meta_impl(image: &str, sidecar: &str, output: &str) -> Result<List> {
+    let png = std::fs::read(image)
+        .map_err(|e| TinyImgRError::io(format!("Failed to read {}: {}", image, e)))?;
+    let bytes = std::fs::read(sidecar)
+        .map_err(|e| TinyImgRError::io(format!("Failed to read {}: {}", sidecar, e)))?;
+    let (hash, saved) = read_sidecar(&bytes)
+        .map_err(|e| TinyImgRError::decode(format!("Failed to read {}: {}", sidecar, e)))?;
+    let warning = (pixel_hash(&png, Path::new(image))? != hash).then(|| format!(
+        "The metadata in {} was saved from a different image than {} (the pixels differ)", sidecar, image
+    ));
+    let (restored, n) = restore_chunks(&png, &saved)
+        .map_err(|e| TinyImgRError::encode(format!("Failed to restore metadata to {}: {}", image, e)))?;
+    std::fs::write(output, restored)
+        .map_err(|e| TinyImgRError::io(format!("Failed to write {}: {}", output, e)))?;
+    Ok(list!(chunks = n as i32, warning = warning))
+}
+
+extendr_module! {
+    mod chunk;
+    fn tinypng_crc_repair_impl;
+    fn tinypng_detect_animated_impl;
+    fn tinypng_detect_corrupt_impl;
+    fn png_restore_meta_impl;
+}
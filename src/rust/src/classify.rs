@@ -0,0 +1,50 @@
+use extendr_api::prelude::*;
+use std::path::Path;
+
+use crate::optimize::{classify_rgba, ImageClass};
+use crate::read_png_rgba;
+
+// ---------------------------------------------------------------------------
+// Image content classification
+// ---------------------------------------------------------------------------
+//
+// The classifier itself (`classify_rgba()`) is in the shared optimization
+// pipeline, where it chooses the thresholds of `lossy = "auto"`.
+
+impl ImageClass {
+    /// The suggested file format for this kind of content.
+    fn format(self) -> &'static str {
+        match self {
+            Self::Photo => "jpeg/webp lossy",
+            _ => "png",
+        }
+    }
+}
+
+/// Classify a PNG file.
+pub(crate) fn classify_png(input: &Path) -> Result<ImageClass> {
+    let (rgba, w, h) = read_png_rgba(input)?;
+    Ok(classify_rgba(&rgba, w, h))
+}
+
+/// Classify PNG images as graphics or photographs
+///
+/// @param paths Vector of PNG file paths
+/// @return A list with the classes (`"graphic"`, `"photo"`, or `"mixed"`) and
+///   the suggested formats of the images
+/// @export
+#[extendr]
+fn png_classify_impl(paths: Strings) -> Result<List> {
+    let mut classes = Vec::with_capacity(paths.len());
+    for s in paths.iter() {
+        classes.push(classify_png(Path::new(s.as_str()))?);
+    }
+    let class: Vec<&str> = classes.iter().map(|c| c.name()).collect();
+    let format: Vec<&str> = classes.iter().map(|c| c.format()).collect();
+    Ok(list!(class = class, format = format))
+}
+
+extendr_module! {
+    mod classify;
+    fn png_classify_impl;
+}
@@ -0,0 +1,250 @@
+use extendr_api::prelude::*;
+use std::path::{Path, PathBuf};
+
+use crate::error::TinyImgRError;
+use crate::metrics::{mat_vec, srgb_to_linear, LabConverter, SRGB_TO_XYZ};
+use crate::quantize::Color;
+use crate::{read_png_rgba, validate_io, write_png_rgba};
+
+// ---------------------------------------------------------------------------
+// Color space transforms
+// ---------------------------------------------------------------------------
+//
+// The RGB channels of a PNG can hold the components of another color space,
+// each scaled to 0-255: hue, saturation, and lightness/value for HSL/HSV
+// (hue 0-360 degrees maps to 0-255), and full-range BT.601 Y, Cb, Cr (as in
+// JPEG/JFIF) for YCbCr.  Pixels are decoded to sRGB in [0, 1] and encoded in
+// the target space; alpha is kept as is.
+
+#[derive(Clone, Copy)]
+enum ColorSpace {
+    Srgb,
+    Hsl,
+    Hsv,
+    YCbCr,
+}
+
+impl ColorSpace {
+    fn parse(name: &str) -> Result<Self> {
+        match name.to_ascii_lowercase().as_str() {
+            "srgb" => Ok(Self::Srgb),
+            "hsl" => Ok(Self::Hsl),
+            "hsv" => Ok(Self::Hsv),
+            "ycbcr" => Ok(Self::YCbCr),
+            _ => Err(TinyImgRError::argument(format!(
+                "Unknown color space '{}' (expected one of: srgb, hsl, hsv, ycbcr)", name
+            )).into()),
+        }
+    }
+
+    /// Decode 8-bit components into sRGB in [0, 1].
+    fn decode(self, c: [u8; 3]) -> [f64; 3] {
+        let [a, b, c] = c.map(|v| v as f64 / 255.0);
+        match self {
+            Self::Srgb => [a, b, c],
+            Self::Hsl => {
+                let chroma = (1.0 - (2.0 * c - 1.0).abs()) * b;
+                hue_to_rgb(a, chroma, c - chroma / 2.0)
+            }
+            Self::Hsv => {
+                let chroma = c * b;
+                hue_to_rgb(a, chroma, c - chroma)
+            }
+            Self::YCbCr => {
+                let (cb, cr) = (b - 0.5, c - 0.5);
+                [a + 1.402 * cr, a - 0.344136 * cb - 0.714136 * cr, a + 1.772 * cb]
+            }
+        }
+    }
+
+    /// Encode sRGB in [0, 1] as 8-bit components.
+    fn encode(self, rgb: [f64; 3]) -> [u8; 3] {
+        let [r, g, b] = rgb;
+        let (max, min) = (r.max(g).max(b), r.min(g).min(b));
+        let chroma = max - min;
+        let v = match self {
+            Self::Srgb => rgb,
+            Self::Hsl => {
+                let l = (max + min) / 2.0;
+                let s = if chroma == 0.0 { 0.0 } else { chroma / (1.0 - (2.0 * l - 1.0).abs()) };
+                [rgb_hue(rgb), s, l]
+            }
+            Self::Hsv => {
+                let s = if max == 0.0 { 0.0 } else { chroma / max };
+                [rgb_hue(rgb), s, max]
+            }
+            Self::YCbCr => {
+                let y = 0.299 * r + 0.587 * g + 0.114 * b;
+                [y, 0.5 + (b - y) / 1.772, 0.5 + (r - y) / 1.402]
+            }
+        };
+        v.map(|x| (x * 255.0).round().clamp(0.0, 255.0) as u8)
+    }
+}
+
+/// Hue in [0, 1) of an sRGB color (0 for grays).
+fn rgb_hue([r, g, b]: [f64; 3]) -> f64 {
+    let (max, min) = (r.max(g).max(b), r.min(g).min(b));
+    let chroma = max - min;
+    if chroma == 0.0 {
+        return 0.0;
+    }
+    let h = if max == r {
+        ((g - b) / chroma).rem_euclid(6.0)
+    } else if max == g {
+        (b - r) / chroma + 2.0
+    } else {
+        (r - g) / chroma + 4.0
+    };
+    h / 6.0
+}
+
+/// sRGB from a hue in [0, 1], a chroma, and the offset added to all channels.
+fn hue_to_rgb(hue: f64, chroma: f64, m: f64) -> [f64; 3] {
+    let h = (hue * 6.0) % 6.0;
+    let x = chroma * (1.0 - (h % 2.0 - 1.0).abs());
+    let (r, g, b) = match h as u32 {
+        0 => (chroma, x, 0.0),
+        1 => (x, chroma, 0.0),
+        2 => (0.0, chroma, x),
+        3 => (0.0, x, chroma),
+        4 => (x, 0.0, chroma),
+        _ => (chroma, 0.0, x),
+    };
+    [r + m, g + m, b + m]
+}
+
+/// Convert PNG pixels between color spaces
+///
+/// @param input Vector of input PNG file paths
+/// @param output Vector of output PNG file paths (same length as input)
+/// @param from_space Color space of the input pixels (`"srgb"`, `"hsl"`,
+///   `"hsv"`, or `"ycbcr"`)
+/// @param to_space Color space of the output pixels
+/// @export
+#[extendr]
+fn tinypng_colorspace_convert_impl(
+    input: Strings, output: Strings, from_space: &str, to_space: &str,
+) -> Result<()> {
+    let (from, to) = (ColorSpace::parse(from_space)?, ColorSpace::parse(to_space)?);
+    let inputs: Vec<String>  = input.iter().map(|s| s.to_string()).collect();
+    let outputs: Vec<String> = output.iter().map(|s| s.to_string()).collect();
+    validate_io(&inputs, &outputs)?;
+    for (input, output) in inputs.iter().zip(&outputs) {
+        let (mut rgba, w, h) = read_png_rgba(Path::new(input))?;
+        for p in rgba.chunks_exact_mut(4) {
+            let c = to.encode(from.decode([p[0], p[1], p[2]]));
+            p[..3].copy_from_slice(&c);
+        }
+        write_png_rgba(&PathBuf::from(output), &rgba, w, h)?;
+    }
+    Ok(())
+}
+
+// ---------------------------------------------------------------------------
+// Color analysis
+// ---------------------------------------------------------------------------
+//
+// Summaries of the colors of visible (not fully transparent) pixels:
+// lightness and chroma from CIE L*a*b* (D65), the hue from the HSV hue of the
+// sRGB components, and the correlated color temperature from the mean XYZ.
+// Hue statistics only count chromatic pixels, since grays have no hue.
+
+/// L*a*b* chroma below which a pixel counts as gray (no hue).
+const ACHROMATIC_CHROMA: f64 = 5.0;
+
+/// Number of hue bins (of 10 degrees) to find the dominant hue.
+const HUE_BINS: usize = 36;
+
+/// Color summary of an image (`None` where undefined, e.g., the hue of an
+/// image without chromatic pixels).
+struct ColorAnalysis {
+    mean_lightness: Option<f64>,
+    mean_chroma: Option<f64>,
+    dominant_hue: Option<f64>,
+    warm_cool_ratio: Option<f64>,
+    temperature: Option<f64>,
+}
+
+fn analyze_colors(rgba: &[u8]) -> ColorAnalysis {
+    let lab = LabConverter::new("d65").expect("d65 is a known white point");
+    let (mut n, mut lightness, mut chroma, mut xyz) = (0usize, 0.0, 0.0, [0.0; 3]);
+    let (mut warm, mut cool) = (0usize, 0usize);
+    // The count and the summed unit vectors of the hues in each bin.
+    let mut bins = [(0usize, 0.0, 0.0); HUE_BINS];
+    for p in rgba.chunks_exact(4).filter(|p| p[3] > 0) {
+        let [l, a, b] = lab.to_lab(Color { r: p[0], g: p[1], b: p[2], a: 255 });
+        let c = a.hypot(b);
+        n += 1;
+        lightness += l;
+        chroma += c;
+        let v = mat_vec(&SRGB_TO_XYZ, [p[0], p[1], p[2]].map(|v| srgb_to_linear(v as f64 / 255.0)));
+        (0..3).for_each(|i| xyz[i] += v[i]);
+        if c < ACHROMATIC_CHROMA {
+            continue;
+        }
+        let hue = rgb_hue([p[0], p[1], p[2]].map(|v| v as f64 / 255.0)) * 360.0;
+        if !(60.0..300.0).contains(&hue) {
+            warm += 1;
+        } else if (120.0..=240.0).contains(&hue) {
+            cool += 1;
+        }
+        let bin = &mut bins[(hue / 360.0 * HUE_BINS as f64) as usize % HUE_BINS];
+        bin.0 += 1;
+        bin.1 += hue.to_radians().cos();
+        bin.2 += hue.to_radians().sin();
+    }
+    // The mean hue in the most populated bin.
+    let dominant_hue = bins.iter().max_by_key(|b| b.0).filter(|b| b.0 > 0)
+        .map(|b| b.2.atan2(b.1).to_degrees().rem_euclid(360.0));
+    // McCamy's approximation of the correlated color temperature from the
+    // CIE 1931 xy chromaticity.
+    let sum = xyz[0] + xyz[1] + xyz[2];
+    let temperature = (sum > 0.0).then(|| {
+        let (x, y) = (xyz[0] / sum, xyz[1] / sum);
+        let k = (x - 0.3320) / (0.1858 - y);
+        449.0 * k.powi(3) + 3525.0 * k.powi(2) + 6823.3 * k + 5520.33
+    });
+    let mean = |v: f64| (n > 0).then(|| v / n as f64);
+    ColorAnalysis {
+        mean_lightness: mean(lightness),
+        mean_chroma: mean(chroma),
+        dominant_hue,
+        warm_cool_ratio: (warm + cool > 0).then(|| warm as f64 / (warm + cool) as f64),
+        temperature,
+    }
+}
+
+/// Analyze the colors of PNG images
+///
+/// @param input Vector of PNG file paths
+/// @return A list with the mean L* (`mean_lightness`), the mean L*a*b* chroma
+///   (`mean_chroma`), the dominant hue in degrees (`dominant_hue_deg`), the
+///   fraction of warm pixels among warm and cool pixels (`warm_cool_ratio`),
+///   and the approximate correlated color temperature in Kelvin
+///   (`color_temperature_k`) of the images, with `NA` where undefined
+/// @export
+#[extendr]
+fn tinypng_color_analysis_impl(input: Strings) -> Result<List> {
+    let mut results = Vec::with_capacity(input.len());
+    for s in input.iter() {
+        let (rgba, ..) = read_png_rgba(Path::new(s.as_str()))?;
+        results.push(analyze_colors(&rgba));
+    }
+    let column = |f: fn(&ColorAnalysis) -> Option<f64>| {
+        Doubles::from_values(results.iter().map(|r| f(r).map_or(Rfloat::na(), Rfloat::from)))
+    };
+    Ok(list!(
+        mean_lightness = column(|r| r.mean_lightness),
+        mean_chroma = column(|r| r.mean_chroma),
+        dominant_hue_deg = column(|r| r.dominant_hue),
+        warm_cool_ratio = column(|r| r.warm_cool_ratio),
+        color_temperature_k = column(|r| r.temperature)
+    ))
+}
+
+extendr_module! {
+    mod colorspace;
+    fn tinypng_colorspace_convert_impl;
+    fn tinypng_color_analysis_impl;
+}
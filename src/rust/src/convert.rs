@@ -0,0 +1,193 @@
+use extendr_api::prelude::*;
+use mozjpeg::ColorSpace;
+use std::path::{Path, PathBuf};
+
+use crate::edit::write_png_gray;
+use crate::error::TinyImgRError;
+use crate::metrics::LabConverter;
+use crate::quantize::colors_into_bytes;
+use crate::optimize::{quantize_png, LossyThreshold};
+use crate::{encode_jpeg, process_files, read_png_rgba, validate_io};
+
+// ---------------------------------------------------------------------------
+// Conversion from PNG to other formats
+// ---------------------------------------------------------------------------
+
+/// Decode a PNG file into a flat RGBA byte buffer, applying lossy palette
+/// reduction first when `lossy > 0`.  Lossless oxipng optimization never
+/// changes the decoded pixels, so it is skipped here.
+fn png_rgba(input: &PathBuf, lossy: f64) -> Result<(Vec<u8>, usize, usize)> {
+    if lossy > 0.0 {
+        let lab = LabConverter::new("d65").expect("d65 is a known white point");
+        let (pixels, width, height) = quantize_png(input, LossyThreshold::DeltaE(lossy), &lab)?;
+        Ok((colors_into_bytes(pixels), width, height))
+    } else {
+        read_png_rgba(input)
+    }
+}
+
+/// Convert PNG files to the QOI format
+///
+/// @param input Vector of input PNG file paths
+/// @param output Vector of output QOI file paths (same length as input)
+/// @param lossy Maximum CIE76 Delta E threshold for palette reduction before
+///   conversion (`<= 0` to disable)
+/// @return A list of input and output file sizes in bytes
+/// @export
+#[extendr]
+fn tinypng_png_to_qoi_impl(input: Strings, output: Strings, lossy: f64) -> Result<List> {
+    let inputs: Vec<String>  = input.iter().map(|s| s.to_string()).collect();
+    let outputs: Vec<String> = output.iter().map(|s| s.to_string()).collect();
+    validate_io(&inputs, &outputs)?;
+    let mut input_sizes  = Vec::with_capacity(inputs.len());
+    let mut output_sizes = Vec::with_capacity(inputs.len());
+    for (input_str, output_str) in inputs.iter().zip(outputs.iter()) {
+        let input_path  = PathBuf::from(input_str);
+        let output_path = PathBuf::from(output_str);
+        let (rgba, width, height) = png_rgba(&input_path, lossy)?;
+        // QOI supports RGB, but we always write the alpha channel.
+        let data = qoi::encode_to_vec(&rgba, width as u32, height as u32)
+            .map_err(|e| TinyImgRError::encode(format!("Failed to encode QOI for {}: {}", input_path.display(), e)))?;
+        std::fs::write(&output_path, &data)
+            .map_err(|e| TinyImgRError::io(format!("Failed to write {}: {}", output_path.display(), e)))?;
+        input_sizes.push(std::fs::metadata(&input_path).map(|m| m.len()).unwrap_or(0) as f64);
+        output_sizes.push(data.len() as f64);
+    }
+    Ok(list!(input_size = input_sizes, output_size = output_sizes))
+}
+
+/// Encode RGBA pixels as a still WebP image.  In lossless mode, `quality` is
+/// the compression effort, and the colors of fully transparent pixels are
+/// kept, so that the decoded pixels are exactly the input pixels.
+fn encode_webp(rgba: &[u8], width: usize, height: usize, quality: f32, lossless: bool) -> Result<Vec<u8>> {
+    let mut config = webp::WebPConfig::new()
+        .map_err(|_| "Failed to initialize the WebP encoder".to_string())?;
+    config.lossless = lossless as i32;
+    config.exact = lossless as i32;
+    config.quality = quality;
+    let data = webp::Encoder::from_rgba(rgba, width as u32, height as u32).encode_advanced(&config)
+        .map_err(|e| TinyImgRError::encode(format!("Failed to encode WebP: {:?}", e)))?;
+    Ok(data.to_vec())
+}
+
+/// Convert PNG files to the WebP format
+///
+/// @param input Vector of input PNG file paths
+/// @param output Vector of output WebP file paths (same length as input)
+/// @param quality WebP quality (0-100); the compression effort for lossless
+///   WebP
+/// @param lossless Write lossless (`TRUE`) or lossy WebP
+/// @param verbose Print file size reduction info
+/// @export
+#[extendr]
+fn tinywebp_impl(input: Strings, output: Strings, quality: f64, lossless: bool, verbose: bool) -> Result<()> {
+    if !(0.0..=100.0).contains(&quality) {
+        return Err(TinyImgRError::argument("The WebP quality must be between 0 and 100").into());
+    }
+    let inputs: Vec<String>  = input.iter().map(|s| s.to_string()).collect();
+    let outputs: Vec<String> = output.iter().map(|s| s.to_string()).collect();
+    validate_io(&inputs, &outputs)?;
+    process_files(&inputs, &outputs, verbose, |input_path, output_path| {
+        let (rgba, width, height) = read_png_rgba(input_path)?;
+        let data = encode_webp(&rgba, width, height, quality as f32, lossless)?;
+        std::fs::write(output_path, &data)
+            .map_err(|e| TinyImgRError::io(format!("Failed to write {}: {}", output_path.display(), e)))?;
+        let input_size = std::fs::metadata(input_path).map(|m| m.len()).unwrap_or(0);
+        Ok((input_size, data.len() as u64))
+    })
+}
+
+/// Encode RGBA pixels as an AVIF image with ravif, keeping the alpha channel
+/// (images without translucent pixels are encoded without it).  lodepng and
+/// ravif share the pixel type of the `rgb` crate, so decoded pixels are
+/// passed as is.
+fn encode_avif(pixels: &[lodepng::RGBA], width: usize, height: usize, quality: f32, speed: u8) -> Result<Vec<u8>> {
+    let encoded = ravif::Encoder::new()
+        .with_quality(quality)
+        .with_alpha_quality(quality)
+        .with_speed(speed)
+        .encode_rgba(ravif::Img::new(pixels, width, height))
+        .map_err(|e| TinyImgRError::encode(format!("Failed to encode AVIF: {}", e)))?;
+    Ok(encoded.avif_file)
+}
+
+/// Convert PNG files to the AVIF format
+///
+/// @param input Vector of input PNG file paths
+/// @param output Vector of output AVIF file paths (same length as input)
+/// @param quality AVIF quality (1-100) of each file (same length as input)
+/// @param speed The encoding speed from 1 (slowest, smallest files) to 10
+///   (fastest), i.e., the effort of the AV1 encoder
+/// @param verbose Print file size reduction info
+/// @export
+#[extendr]
+fn tinyavif_impl(input: Strings, output: Strings, quality: Doubles, speed: i32, verbose: bool) -> Result<()> {
+    let quality: Vec<f64> = quality.iter().map(|q| q.inner()).collect();
+    if quality.len() != input.len() {
+        return Err(TinyImgRError::argument(
+            "The AVIF quality must be a number or a vector of the same length as the input"
+        ).into());
+    }
+    if !quality.iter().all(|q| (1.0..=100.0).contains(q)) {
+        return Err(TinyImgRError::argument("The AVIF quality must be between 1 and 100").into());
+    }
+    let speed = u8::try_from(speed).ok().filter(|s| (1..=10).contains(s)).ok_or_else(|| {
+        TinyImgRError::argument(format!("The AVIF speed must be an integer from 1 to 10 (got {})", speed))
+    })?;
+    let inputs: Vec<String>  = input.iter().map(|s| s.to_string()).collect();
+    let outputs: Vec<String> = output.iter().map(|s| s.to_string()).collect();
+    validate_io(&inputs, &outputs)?;
+    // The files are processed in order, so the quality of each is the next one.
+    let next = std::cell::Cell::new(0);
+    process_files(&inputs, &outputs, verbose, |input_path, output_path| {
+        let quality = quality[next.replace(next.get() + 1)];
+        let data = std::fs::read(input_path)
+            .map_err(|e| TinyImgRError::io(format!("Failed to read {}: {}", input_path.display(), e)))?;
+        let image = lodepng::decode32(&data)
+            .map_err(|e| TinyImgRError::decode(format!("Failed to read PNG {}: {}", input_path.display(), e)))?;
+        let avif = encode_avif(&image.buffer, image.width, image.height, quality as f32, speed)?;
+        std::fs::write(output_path, &avif)
+            .map_err(|e| TinyImgRError::io(format!("Failed to write {}: {}", output_path.display(), e)))?;
+        Ok((data.len() as u64, avif.len() as u64))
+    })
+}
+
+/// Split PNG files into RGB JPEG files and grayscale PNG alpha masks
+///
+/// @param input Vector of input PNG file paths
+/// @param jpeg_output Vector of output JPEG file paths (same length as input)
+/// @param alpha_output Vector of output PNG file paths for the alpha channels
+///   (same length as input)
+/// @param jpeg_quality JPEG quality (0-100)
+/// @export
+#[extendr]
+fn tinypng_split_channels_to_jpeg_impl(
+    input: Strings, jpeg_output: Strings, alpha_output: Strings, jpeg_quality: i32,
+) -> Result<()> {
+    let inputs: Vec<String> = input.iter().map(|s| s.to_string()).collect();
+    let jpegs: Vec<String>  = jpeg_output.iter().map(|s| s.to_string()).collect();
+    let alphas: Vec<String> = alpha_output.iter().map(|s| s.to_string()).collect();
+    validate_io(&inputs, &jpegs)?;
+    validate_io(&inputs, &alphas)?;
+    for ((input, jpeg), alpha) in inputs.iter().zip(&jpegs).zip(&alphas) {
+        let (rgba, width, height) = read_png_rgba(Path::new(input))?;
+        let (rgb, a): (Vec<[u8; 3]>, Vec<u8>) =
+            rgba.chunks_exact(4).map(|p| ([p[0], p[1], p[2]], p[3])).unzip();
+        let jpeg_path = PathBuf::from(jpeg);
+        let data = encode_jpeg(
+            rgb.as_flattened(), width, height, ColorSpace::JCS_RGB, jpeg_quality as f32, true, &jpeg_path,
+        )?;
+        std::fs::write(&jpeg_path, data)
+            .map_err(|e| TinyImgRError::io(format!("Failed to write {}: {}", jpeg, e)))?;
+        write_png_gray(alpha, &a, width, height)?;
+    }
+    Ok(())
+}
+
+extendr_module! {
+    mod convert;
+    fn tinypng_png_to_qoi_impl;
+    fn tinypng_split_channels_to_jpeg_impl;
+    fn tinyavif_impl;
+    fn tinywebp_impl;
+}
@@ -0,0 +1,89 @@
+use std::path::{Path, PathBuf};
+
+// ---------------------------------------------------------------------------
+// CPU limits
+// ---------------------------------------------------------------------------
+//
+// In containers, `std::thread::available_parallelism()` may count all CPUs of
+// the host, although the container can only use a few of them.  The default
+// numbers of worker threads are therefore also limited by the CPU quota of the
+// cgroup (v2 `cpu.max`, or v1 `cpu.cfs_quota_us` / `cpu.cfs_period_us`) and
+// the CPU affinity mask of the process (`Cpus_allowed_list` in
+// `/proc/self/status`).  The cgroup root can be changed by the environment
+// variable `TINYIMG_CGROUP_ROOT` (e.g., for a non-standard mount point).
+
+/// The default cgroup mount point on Linux.
+const CGROUP_ROOT: &str = "/sys/fs/cgroup";
+
+/// Parse a cgroup v2 `cpu.max` file (`"<quota> <period>"`, where the quota
+/// may be `max`) into the number of CPUs it allows, or `None` for no limit.
+pub fn parse_cpu_max(content: &str) -> Option<f64> {
+    let mut fields = content.split_whitespace();
+    let quota = fields.next()?;
+    let period = fields.next().unwrap_or("100000");
+    cpu_quota(quota.parse().ok()?, period.parse().ok()?)
+}
+
+/// Parse the cgroup v1 files `cpu.cfs_quota_us` and `cpu.cfs_period_us` into
+/// the number of CPUs they allow, or `None` for no limit (a quota of `-1`).
+pub fn parse_cfs_quota(quota: &str, period: &str) -> Option<f64> {
+    cpu_quota(quota.trim().parse().ok()?, period.trim().parse().ok()?)
+}
+
+fn cpu_quota(quota: f64, period: f64) -> Option<f64> {
+    (quota > 0.0 && period > 0.0).then(|| quota / period)
+}
+
+/// Count the CPUs in a CPU list like `"0-3,8,10-11"` (as in
+/// `Cpus_allowed_list`), or `None` if it is malformed.
+pub fn parse_cpu_list(list: &str) -> Option<usize> {
+    let mut n = 0;
+    for range in list.trim().split(',').filter(|r| !r.is_empty()) {
+        n += match range.split_once('-') {
+            Some((a, b)) => b.trim().parse::<usize>().ok()?.checked_sub(a.trim().parse().ok()?)? + 1,
+            None => range.trim().parse::<usize>().map(|_| 1).ok()?,
+        };
+    }
+    (n > 0).then_some(n)
+}
+
+/// The number of CPUs in the affinity mask from the content of
+/// `/proc/self/status`.
+pub fn parse_affinity(status: &str) -> Option<usize> {
+    status.lines().find_map(|l| l.strip_prefix("Cpus_allowed_list:")).and_then(parse_cpu_list)
+}
+
+/// The CPU quota of the cgroup mounted at `root` (v2 first, then v1).
+fn cgroup_quota(root: &Path) -> Option<f64> {
+    let read = |file: &str| std::fs::read_to_string(root.join(file)).ok();
+    if let Some(max) = read("cpu.max") {
+        return parse_cpu_max(&max);
+    }
+    ["cpu", "cpu,cpuacct", "cpuacct,cpu"].iter().find_map(|dir| {
+        let quota = read(&format!("{}/cpu.cfs_quota_us", dir))?;
+        parse_cfs_quota(&quota, &read(&format!("{}/cpu.cfs_period_us", dir))?)
+    })
+}
+
+/// The number of CPUs available to this process, given the cgroup root and
+/// the content of `/proc/self/status` (if any): the smallest of the available
+/// parallelism, the number of CPUs in the affinity mask, and the cgroup CPU
+/// quota (rounded up), and at least 1.
+pub fn detect_cpus_in(cgroup_root: &Path, proc_status: Option<&str>) -> usize {
+    let mut n = std::thread::available_parallelism().map_or(1, |n| n.get());
+    if let Some(k) = proc_status.and_then(parse_affinity) {
+        n = n.min(k);
+    }
+    if let Some(q) = cgroup_quota(cgroup_root) {
+        n = n.min(q.ceil() as usize);
+    }
+    n.max(1)
+}
+
+/// The number of CPUs available to this process (see `detect_cpus_in()`), to
+/// be used for all default numbers of worker threads.
+pub fn detect_cpus() -> usize {
+    let root = std::env::var_os("TINYIMG_CGROUP_ROOT").map_or_else(|| PathBuf::from(CGROUP_ROOT), PathBuf::from);
+    let status = std::fs::read_to_string("/proc/self/status").ok();
+    detect_cpus_in(&root, status.as_deref())
+}
@@ -0,0 +1,673 @@
+use extendr_api::prelude::*;
+use std::collections::{HashMap, VecDeque};
+use std::path::{Path, PathBuf};
+
+use crate::error::TinyImgRError;
+use crate::metrics::{delta_e, LabConverter};
+use crate::quantize::Color;
+use crate::optimize::{png_options, DEFAULT_PNG_LEVEL};
+use crate::{read_png_rgba, validate_io, write_png_rgba};
+
+// ---------------------------------------------------------------------------
+// PNG editing
+// ---------------------------------------------------------------------------
+//
+// Simple pixel-level edits of PNG images.  Each edit decodes the input to
+// RGBA, transforms the pixels, and writes the result through the regular
+// PNG optimizer, which also picks the smallest color type for the output
+// (e.g., adding an alpha channel only when transparent pixels appear).
+
+/// Parse a hex color (`#RGB`, `#RGBA`, `#RRGGBB`, or `#RRGGBBAA`) into RGBA.
+pub(crate) fn parse_color(color: &str) -> Result<[u8; 4]> {
+    let hex = color.strip_prefix('#').unwrap_or(color);
+    let digits: Vec<u8> = hex.chars()
+        .map(|c| c.to_digit(16).map(|d| d as u8))
+        .collect::<Option<_>>()
+        .ok_or_else(|| TinyImgRError::argument(format!("Invalid hex color: {}", color)))?;
+    let rgba = match digits.len() {
+        3 | 4 => {
+            let mut c = [255u8; 4];
+            for (i, d) in digits.iter().enumerate() {
+                c[i] = d * 17;
+            }
+            c
+        }
+        6 | 8 => {
+            let mut c = [255u8; 4];
+            for (i, d) in digits.chunks(2).enumerate() {
+                c[i] = d[0] * 16 + d[1];
+            }
+            c
+        }
+        _ => return Err(TinyImgRError::argument(format!("Invalid hex color: {}", color)).into()),
+    };
+    Ok(rgba)
+}
+
+/// Add margins around a PNG image
+///
+/// @param input Path to the input PNG file
+/// @param output Path to the output PNG file
+/// @param top,right,bottom,left Margin sizes in pixels
+/// @param color Hex color of the margins (may include alpha)
+/// @export
+#[extendr]
+fn png_pad_impl(
+    input: &str, output: &str, top: i32, right: i32, bottom: i32, left: i32, color: &str,
+) -> Result<()> {
+    if [top, right, bottom, left].iter().any(|&m| m < 0) {
+        return Err(TinyImgRError::argument("Margins must be non-negative").into());
+    }
+    let fill = parse_color(color)?;
+    let (rgba, w, h) = read_png_rgba(Path::new(input))?;
+    let (top, right, bottom, left) = (top as usize, right as usize, bottom as usize, left as usize);
+    let (pw, ph) = (left + w + right, top + h + bottom);
+    let mut out: Vec<u8> = fill.iter().copied().cycle().take(pw * ph * 4).collect();
+    for y in 0..h {
+        let dst = 4 * ((top + y) * pw + left);
+        out[dst..dst + 4 * w].copy_from_slice(&rgba[4 * y * w..4 * (y + 1) * w]);
+    }
+    write_png_rgba(&PathBuf::from(output), &out, pw, ph)
+}
+
+/// Anchor offsets of `size` within `extent` for the horizontal or vertical
+/// part of a gravity: start (west/north), center, or end (east/south).  The
+/// offset `d` moves the overlay inwards from the anchored edge.
+fn anchor(start: bool, end: bool, extent: usize, size: usize, d: i64) -> i64 {
+    let free = extent as i64 - size as i64;
+    match (start, end) {
+        (true, _) => d,
+        (_, true) => free - d,
+        _ => free / 2 + d,
+    }
+}
+
+/// Top-left position of the overlay on the base image for a gravity such as
+/// `"northwest"`, `"center"`, or `"south"`.
+fn gravity_position(
+    gravity: &str, (bw, bh): (usize, usize), (ow, oh): (usize, usize), x: i64, y: i64,
+) -> Result<(i64, i64)> {
+    let g = gravity.to_ascii_lowercase();
+    let (v, h) = match g.as_str() {
+        "center" | "centre" => ("", ""),
+        "north" | "south" => (g.as_str(), ""),
+        "east" | "west" => ("", g.as_str()),
+        "northwest" | "northeast" | "southwest" | "southeast" => g.split_at(5),
+        _ => return Err(TinyImgRError::argument(format!("Unknown gravity: {}", gravity)).into()),
+    };
+    let px = anchor(h == "west", h == "east", bw, ow, x);
+    let py = anchor(v == "north", v == "south", bh, oh, y);
+    Ok((px, py))
+}
+
+/// Composite `src` over `dst` (both non-premultiplied RGBA) with the source
+/// alpha scaled by `opacity`.
+fn blend(dst: &mut [u8], src: &[u8], opacity: f64) {
+    let sa = src[3] as f64 / 255.0 * opacity;
+    let da = dst[3] as f64 / 255.0;
+    let oa = sa + da * (1.0 - sa);
+    for i in 0..3 {
+        dst[i] = if oa > 0.0 {
+            ((src[i] as f64 * sa + dst[i] as f64 * da * (1.0 - sa)) / oa).round() as u8
+        } else {
+            0
+        };
+    }
+    dst[3] = (oa * 255.0).round() as u8;
+}
+
+/// Composite a PNG image over another PNG image
+///
+/// @param input Path to the base PNG file
+/// @param overlay Path to the overlay PNG file
+/// @param output Path to the output PNG file
+/// @param x,y Offsets of the overlay in pixels from the edge given by `gravity`
+/// @param opacity Opacity of the overlay (0--1)
+/// @param gravity Anchor of the overlay, e.g., `"northwest"` or `"center"`
+/// @param clip Allow the overlay to be larger than the base image
+/// @export
+#[extendr]
+#[allow(clippy::too_many_arguments)]
+fn png_overlay_impl(
+    input: &str, overlay: &str, output: &str, x: i32, y: i32, opacity: f64, gravity: &str,
+    clip: bool,
+) -> Result<()> {
+    if !(0.0..=1.0).contains(&opacity) {
+        return Err(TinyImgRError::argument("Opacity must be between 0 and 1").into());
+    }
+    let (mut base, bw, bh) = read_png_rgba(Path::new(input))?;
+    let (top, ow, oh) = read_png_rgba(Path::new(overlay))?;
+    if !clip && (ow > bw || oh > bh) {
+        return Err(TinyImgRError::argument(format!(
+            "The overlay ({}x{}) is larger than the base image ({}x{}); use clip = TRUE to crop it",
+            ow, oh, bw, bh
+        )).into());
+    }
+    let (px, py) = gravity_position(gravity, (bw, bh), (ow, oh), x as i64, y as i64)?;
+    // Only the part of the overlay that falls on the base image is drawn.
+    for oy in 0..oh {
+        let by = py + oy as i64;
+        if by < 0 || by >= bh as i64 {
+            continue;
+        }
+        for ox in 0..ow {
+            let bx = px + ox as i64;
+            if bx < 0 || bx >= bw as i64 {
+                continue;
+            }
+            let d = 4 * (by as usize * bw + bx as usize);
+            let s = 4 * (oy * ow + ox);
+            blend(&mut base[d..d + 4], &top[s..s + 4], opacity);
+        }
+    }
+    write_png_rgba(&PathBuf::from(output), &base, bw, bh)
+}
+
+/// Append PNG images side by side or stacked
+///
+/// @param inputs Paths to the input PNG files (at least two)
+/// @param output Path to the output PNG file
+/// @param direction `"horizontal"` or `"vertical"`
+/// @param align Alignment across the direction: `"start"`, `"center"`, or `"end"`
+/// @param background Hex color of the gaps and slack (may include alpha)
+/// @param gap Gap between images in pixels
+/// @export
+#[extendr]
+fn png_append_impl(
+    inputs: Strings, output: &str, direction: &str, align: &str, background: &str, gap: i32,
+) -> Result<()> {
+    if inputs.len() < 2 {
+        return Err(TinyImgRError::argument("At least two images are required").into());
+    }
+    if gap < 0 {
+        return Err(TinyImgRError::argument("The gap must be non-negative").into());
+    }
+    let horizontal = match direction {
+        "horizontal" => true,
+        "vertical" => false,
+        _ => return Err(TinyImgRError::argument(format!("Unknown direction: {}", direction)).into()),
+    };
+    if !matches!(align, "start" | "center" | "end") {
+        return Err(TinyImgRError::argument(format!("Unknown alignment: {}", align)).into());
+    }
+    let fill = parse_color(background)?;
+    let images = inputs
+        .iter()
+        .map(|s| read_png_rgba(Path::new(s.as_str())))
+        .collect::<Result<Vec<_>>>()?;
+
+    // Images are laid out along the main axis; `across` is the extent of the
+    // other axis, and smaller images are aligned within it.
+    let gap = gap as usize;
+    let main = |w: usize, h: usize| if horizontal { w } else { h };
+    let across = images.iter().map(|(_, w, h)| main(*h, *w)).max().unwrap_or(0);
+    let length = images.iter().map(|(_, w, h)| main(*w, *h)).sum::<usize>() + gap * (images.len() - 1);
+    let (pw, ph) = if horizontal { (length, across) } else { (across, length) };
+    let mut out: Vec<u8> = fill.iter().copied().cycle().take(pw * ph * 4).collect();
+    let mut pos = 0;
+    for (rgba, w, h) in &images {
+        let slack = across - main(*h, *w);
+        let offset = match align {
+            "start" => 0,
+            "center" => slack / 2,
+            _ => slack,
+        };
+        let (left, top) = if horizontal { (pos, offset) } else { (offset, pos) };
+        for y in 0..*h {
+            let dst = 4 * ((top + y) * pw + left);
+            out[dst..dst + 4 * w].copy_from_slice(&rgba[4 * y * w..4 * (y + 1) * w]);
+        }
+        pos += main(*w, *h) + gap;
+    }
+    write_png_rgba(&PathBuf::from(output), &out, pw, ph)
+}
+
+/// Replace a rectangular region of a PNG image with another PNG image
+///
+/// @param base Path to the base PNG file
+/// @param patch Path to the patch PNG file
+/// @param output Path to the output PNG file
+/// @param x,y Offsets of the patch from the top-left corner in pixels
+/// @export
+#[extendr]
+fn tinypng_patch_impl(base: &str, patch: &str, output: &str, x: i32, y: i32) -> Result<()> {
+    let (mut rgba, w, h) = read_png_rgba(Path::new(base))?;
+    let (src, pw, ph) = read_png_rgba(Path::new(patch))?;
+    if x < 0 || y < 0 || x as usize + pw > w || y as usize + ph > h {
+        return Err(TinyImgRError::argument(format!(
+            "The patch ({}x{}) at ({}, {}) does not fit in the base image ({}x{})",
+            pw, ph, x, y, w, h
+        )).into());
+    }
+    let (x, y) = (x as usize, y as usize);
+    // Pixels are copied as is (including alpha), without blending.
+    for row in 0..ph {
+        let dst = 4 * ((y + row) * w + x);
+        rgba[dst..dst + 4 * pw].copy_from_slice(&src[4 * row * pw..4 * (row + 1) * pw]);
+    }
+    write_png_rgba(&PathBuf::from(output), &rgba, w, h)
+}
+
+/// Crop a PNG image to a rectangle
+///
+/// @param input Path to the input PNG file
+/// @param output Path to the output PNG file
+/// @param x,y Offsets (0-based) of the top-left corner of the rectangle
+/// @param width,height Size of the rectangle in pixels
+/// @export
+#[extendr]
+fn png_crop_impl(input: &str, output: &str, x: i32, y: i32, width: i32, height: i32) -> Result<()> {
+    let (rgba, w, h) = read_png_rgba(Path::new(input))?;
+    if x < 0 || y < 0 || width <= 0 || height <= 0
+        || x as usize + width as usize > w || y as usize + height as usize > h {
+        return Err(TinyImgRError::argument(format!(
+            "The rectangle {}x{}+{}+{} is not within the image ({}x{})",
+            width, height, x, y, w, h
+        )).into());
+    }
+    let (x, y, cw, ch) = (x as usize, y as usize, width as usize, height as usize);
+    let mut out = Vec::with_capacity(cw * ch * 4);
+    for row in y..y + ch {
+        out.extend_from_slice(&rgba[4 * (row * w + x)..4 * (row * w + x + cw)]);
+    }
+    write_png_rgba(&PathBuf::from(output), &out, cw, ch)
+}
+
+/// Find the bounding box `(x, y, width, height)` of pixels with nonzero alpha,
+/// or `None` if all pixels are fully transparent.
+fn content_bbox(rgba: &[u8], width: usize) -> Option<(usize, usize, usize, usize)> {
+    let (mut x0, mut y0, mut x1, mut y1) = (usize::MAX, usize::MAX, 0, 0);
+    for (i, px) in rgba.chunks_exact(4).enumerate() {
+        if px[3] > 0 {
+            let (x, y) = (i % width, i / width);
+            x0 = x0.min(x); x1 = x1.max(x);
+            y0 = y0.min(y); y1 = y1.max(y);
+        }
+    }
+    (x0 != usize::MAX).then(|| (x0, y0, x1 - x0 + 1, y1 - y0 + 1))
+}
+
+/// Find the bounding boxes of non-transparent content in PNG images
+///
+/// @param input Vector of PNG file paths
+/// @param padding Pixels added to each side of the boxes (clamped to the
+///   image bounds)
+/// @return A list of the (0-based) offsets `x` and `y`, the `width` and
+///   `height` of the boxes (`NA` for fully transparent images), and the
+///   `original_width` and `original_height` of the images
+/// @export
+#[extendr]
+fn tinypng_optimal_crop_impl(input: Strings, padding: i32) -> Result<List> {
+    let pad = padding.max(0) as usize;
+    let n = input.len();
+    let (mut xs, mut ys, mut ws, mut hs) = (Vec::with_capacity(n), Vec::with_capacity(n), Vec::with_capacity(n), Vec::with_capacity(n));
+    let (mut orig_w, mut orig_h) = (Vec::with_capacity(n), Vec::with_capacity(n));
+    for path in input.iter() {
+        let (rgba, w, h) = read_png_rgba(Path::new(path.as_str()))?;
+        let bbox = content_bbox(&rgba, w).map(|(x, y, bw, bh)| {
+            let (x0, y0) = (x.saturating_sub(pad), y.saturating_sub(pad));
+            let (x1, y1) = ((x + bw + pad).min(w), (y + bh + pad).min(h));
+            [x0, y0, x1 - x0, y1 - y0].map(|v| v as i32)
+        });
+        let get = |k: usize| Rint::from(bbox.map(|b| b[k]));
+        xs.push(get(0));
+        ys.push(get(1));
+        ws.push(get(2));
+        hs.push(get(3));
+        orig_w.push(w as i32);
+        orig_h.push(h as i32);
+    }
+    Ok(list!(
+        x = Integers::from_values(xs),
+        y = Integers::from_values(ys),
+        width = Integers::from_values(ws),
+        height = Integers::from_values(hs),
+        original_width = orig_w,
+        original_height = orig_h
+    ))
+}
+
+/// Encode 8-bit grayscale pixels as a PNG, optimize it with the default
+/// preset, and write it to `output`.
+pub(crate) fn write_png_gray(output: &str, gray: &[u8], width: usize, height: usize) -> Result<()> {
+    let png = lodepng::encode_memory(gray, width, height, lodepng::ColorType::GREY, 8)
+        .map_err(|e| TinyImgRError::encode(format!("Failed to encode PNG {}: {}", output, e)))?;
+    let optimized = oxipng::optimize_from_memory(&png, &png_options(DEFAULT_PNG_LEVEL))
+        .map_err(|e| TinyImgRError::encode(format!("Failed to optimize {}: {}", output, e)))?;
+    std::fs::write(output, optimized)
+        .map_err(|e| TinyImgRError::io(format!("Failed to write {}: {}", output, e)).into())
+}
+
+/// Extract the alpha channel of a PNG image as a grayscale image
+///
+/// @param input Path to the input PNG file
+/// @param output Path to the output PNG file
+/// @export
+#[extendr]
+fn png_alpha_extract_impl(input: &str, output: &str) -> Result<()> {
+    // Images without an alpha channel are decoded with opaque (255) alpha.
+    let (rgba, w, h) = read_png_rgba(Path::new(input))?;
+    let alpha: Vec<u8> = rgba.chunks_exact(4).map(|p| p[3]).collect();
+    write_png_gray(output, &alpha, w, h)
+}
+
+/// Set the alpha channel of a PNG image from the luminance of a mask image
+///
+/// @param input Path to the input PNG file
+/// @param mask Path to the mask PNG file (same dimensions as `input`)
+/// @param output Path to the output PNG file
+/// @export
+#[extendr]
+fn png_alpha_apply_impl(input: &str, mask: &str, output: &str) -> Result<()> {
+    let (mut rgba, w, h) = read_png_rgba(Path::new(input))?;
+    let (m, mw, mh) = read_png_rgba(Path::new(mask))?;
+    if (mw, mh) != (w, h) {
+        return Err(TinyImgRError::argument(format!(
+            "The mask is {}x{} but the image is {}x{}", mw, mh, w, h
+        )).into());
+    }
+    for (p, q) in rgba.chunks_exact_mut(4).zip(m.chunks_exact(4)) {
+        // Rec. 601 luma in integers, so gray masks map to their exact values.
+        let luma = 299 * q[0] as u32 + 587 * q[1] as u32 + 114 * q[2] as u32;
+        p[3] = ((luma + 500) / 1000) as u8;
+    }
+    write_png_rgba(&PathBuf::from(output), &rgba, w, h)
+}
+
+/// Set the RGB values of fully transparent pixels to the colors of their
+/// nearest non-transparent pixels (by a breadth-first search over the four
+/// neighbors), keeping their alpha at 0.  Returns `false` if the image has no
+/// non-transparent pixels, in which case it is left unchanged.
+fn alpha_fill(rgba: &mut [u8], width: usize, height: usize) -> bool {
+    let mut queue: VecDeque<usize> = (0..width * height).filter(|&i| rgba[4 * i + 3] > 0).collect();
+    if queue.is_empty() {
+        return false;
+    }
+    let mut seen = vec![false; width * height];
+    queue.iter().for_each(|&i| seen[i] = true);
+    while let Some(i) = queue.pop_front() {
+        let (x, y) = (i % width, i / width);
+        let neighbors = [
+            (x > 0).then(|| i - 1),
+            (x + 1 < width).then(|| i + 1),
+            (y > 0).then(|| i - width),
+            (y + 1 < height).then(|| i + width),
+        ];
+        for j in neighbors.into_iter().flatten() {
+            if !seen[j] {
+                seen[j] = true;
+                rgba.copy_within(4 * i..4 * i + 3, 4 * j);
+                queue.push_back(j);
+            }
+        }
+    }
+    true
+}
+
+/// Fill the colors of transparent pixels in PNG images
+///
+/// @param input Vector of input PNG file paths
+/// @param output Vector of output PNG file paths (same length as input)
+/// @return Whether each image has non-transparent pixels to fill from
+/// @export
+#[extendr]
+fn tinypng_alpha_fill_impl(input: Strings, output: Strings) -> Result<Logicals> {
+    let inputs: Vec<String>  = input.iter().map(|s| s.to_string()).collect();
+    let outputs: Vec<String> = output.iter().map(|s| s.to_string()).collect();
+    validate_io(&inputs, &outputs)?;
+    let mut res = Vec::with_capacity(inputs.len());
+    for (input, output) in inputs.iter().zip(&outputs) {
+        let (mut rgba, w, h) = read_png_rgba(Path::new(input))?;
+        let filled = alpha_fill(&mut rgba, w, h);
+        write_png_rgba(&PathBuf::from(output), &rgba, w, h)?;
+        res.push(Rbool::from(filled));
+    }
+    Ok(Logicals::from_values(res))
+}
+
+/// Replace a color in a PNG image
+///
+/// @param input Path to the input PNG file
+/// @param output Path to the output PNG file
+/// @param from Hex color to be replaced (alpha is ignored)
+/// @param to Hex color of the replacement (alpha is ignored)
+/// @param tolerance Maximum CIE76 Delta E from `from` of replaced pixels
+/// @param feather Shift colors proportionally to their distance from `from`
+/// @export
+#[extendr]
+fn png_recolor_impl(
+    input: &str, output: &str, from: &str, to: &str, tolerance: f64, feather: bool,
+) -> Result<()> {
+    if tolerance.is_nan() || tolerance < 0.0 {
+        return Err(TinyImgRError::argument("The tolerance must be non-negative").into());
+    }
+    let (from, to) = (parse_color(from)?, parse_color(to)?);
+    let (mut rgba, w, h) = read_png_rgba(Path::new(input))?;
+    let lab = LabConverter::new("d65").expect("d65 is a known white point");
+    let from_lab = lab.to_lab(Color::new(from[0], from[1], from[2], 255));
+    // Weight of the replacement for each distinct color (0 = keep, 1 = `to`).
+    let mut weights: HashMap<[u8; 3], f64> = HashMap::new();
+    for p in rgba.chunks_exact_mut(4) {
+        let rgb = [p[0], p[1], p[2]];
+        let weight = *weights.entry(rgb).or_insert_with(|| {
+            if rgb == from[..3] {
+                return 1.0;
+            }
+            let d = delta_e(from_lab, lab.to_lab(Color::new(rgb[0], rgb[1], rgb[2], 255)));
+            match (d <= tolerance, feather) {
+                (false, _) => 0.0,
+                (true, false) => 1.0,
+                (true, true) => 1.0 - d / tolerance,
+            }
+        });
+        if weight == 1.0 {
+            p[..3].copy_from_slice(&to[..3]);
+        } else if weight > 0.0 {
+            // Shift the color by the same (weighted) offset as `from` -> `to`,
+            // so anti-aliased edges keep their gradient toward the new color.
+            for i in 0..3 {
+                let v = p[i] as f64 + weight * (to[i] as f64 - from[i] as f64);
+                p[i] = v.round().clamp(0.0, 255.0) as u8;
+            }
+        }
+    }
+    write_png_rgba(&PathBuf::from(output), &rgba, w, h)
+}
+
+/// Images with more unique colors than this are considered photographic and
+/// skipped by `png_darkmode_impl()` unless forced.
+const DARKMODE_MAX_COLORS: usize = 4096;
+
+/// Create a dark-mode variant of a PNG image
+///
+/// @param input Path to the input PNG file
+/// @param output Path to the output PNG file
+/// @param mode `"invert_luminance"` (flip L* in L*a*b*) or `"invert_rgb"`
+/// @param force Also process images that look photographic
+/// @return Whether the image was processed (`FALSE` if it was skipped as a
+///   photographic image)
+/// @export
+#[extendr]
+fn png_darkmode_impl(input: &str, output: &str, mode: &str, force: bool) -> Result<Rbool> {
+    let luminance = match mode {
+        "invert_luminance" => true,
+        "invert_rgb" => false,
+        _ => return Err(TinyImgRError::argument(format!("Unknown mode: {}", mode)).into()),
+    };
+    let (mut rgba, w, h) = read_png_rgba(Path::new(input))?;
+    let mut colors: HashMap<[u8; 3], [u8; 3]> = HashMap::new();
+    for p in rgba.chunks_exact(4) {
+        colors.insert([p[0], p[1], p[2]], [0; 3]);
+    }
+    if !force && colors.len() > DARKMODE_MAX_COLORS {
+        return Ok(Rbool::from(false));
+    }
+    // Map each unique color once; alpha is kept as is.
+    let lab = LabConverter::new("d65").expect("d65 is a known white point");
+    for (c, v) in colors.iter_mut() {
+        *v = if luminance {
+            let [l, a, b] = lab.to_lab(Color::new(c[0], c[1], c[2], 255));
+            lab.to_rgb([100.0 - l, a, b])
+        } else {
+            c.map(|x| 255 - x)
+        };
+    }
+    for p in rgba.chunks_exact_mut(4) {
+        let c = colors[&[p[0], p[1], p[2]]];
+        p[..3].copy_from_slice(&c);
+    }
+    write_png_rgba(&PathBuf::from(output), &rgba, w, h)?;
+    Ok(Rbool::from(true))
+}
+
+/// Subsamples per axis used to anti-alias rounded corners.
+const ROUND_SUPERSAMPLE: usize = 4;
+
+/// Round the corners of a PNG image
+///
+/// @param input Path to the input PNG file
+/// @param output Path to the output PNG file
+/// @param radius Corner radius in pixels, or a fraction of the width and
+///   height if `relative = TRUE` (0.5 gives a circle or ellipse)
+/// @param relative Whether `radius` is relative to the image size
+/// @export
+#[extendr]
+fn png_round_impl(input: &str, output: &str, radius: f64, relative: bool) -> Result<()> {
+    if radius.is_nan() || radius < 0.0 {
+        return Err(TinyImgRError::argument("The radius must be non-negative").into());
+    }
+    let (mut rgba, w, h) = read_png_rgba(Path::new(input))?;
+    // Horizontal and vertical radii, at most half of the width and height.
+    let (rx, ry) = if relative { (radius * w as f64, radius * h as f64) } else { (radius, radius) };
+    let (rx, ry) = (rx.min(w as f64 / 2.0), ry.min(h as f64 / 2.0));
+    if rx <= 0.0 || ry <= 0.0 {
+        return write_png_rgba(&PathBuf::from(output), &rgba, w, h);
+    }
+    // Whether a point lies in the rounded rectangle, given that it lies in the
+    // top-left corner box after mirroring.
+    let inside = |px: f64, py: f64| {
+        let (dx, dy) = ((px - rx) / rx, (py - ry) / ry);
+        px >= rx || py >= ry || dx * dx + dy * dy <= 1.0
+    };
+    let n = ROUND_SUPERSAMPLE;
+    let (cw, ch) = (rx.ceil() as usize, ry.ceil() as usize);
+    // Coverage of the pixels in the top-left corner box; the other corners
+    // are mirror images of it.
+    let mut coverage = vec![0.0; cw * ch];
+    for y in 0..ch {
+        for x in 0..cw {
+            let mut count = 0;
+            for j in 0..n {
+                for i in 0..n {
+                    let px = x as f64 + (i as f64 + 0.5) / n as f64;
+                    let py = y as f64 + (j as f64 + 0.5) / n as f64;
+                    if inside(px, py) { count += 1; }
+                }
+            }
+            coverage[y * cw + x] = count as f64 / (n * n) as f64;
+        }
+    }
+    for y in 0..ch {
+        for x in 0..cw {
+            let c = coverage[y * cw + x];
+            if c == 1.0 { continue; }
+            // The middle row/column of an odd-sized image is its own mirror.
+            let mut xs = vec![x, w - 1 - x];
+            let mut ys = vec![y, h - 1 - y];
+            xs.dedup();
+            ys.dedup();
+            for &py in &ys {
+                for &px in &xs {
+                    let a = &mut rgba[4 * (py * w + px) + 3];
+                    *a = (*a as f64 * c).round() as u8;
+                }
+            }
+        }
+    }
+    write_png_rgba(&PathBuf::from(output), &rgba, w, h)
+}
+
+/// Mean absolute difference (in 0-255 levels, over all channels) between the
+/// left and right edges and between the top and bottom edges of an image,
+/// i.e., how visible the seams are where its copies meet when tiled.
+fn edge_asymmetry(rgba: &[u8], w: usize, h: usize) -> f64 {
+    let px = |x: usize, y: usize| &rgba[4 * (y * w + x)..4 * (y * w + x) + 4];
+    let diff = |a: &[u8], b: &[u8]| a.iter().zip(b).map(|(&a, &b)| a.abs_diff(b) as u64).sum::<u64>();
+    let cols: u64 = (0..h).map(|y| diff(px(0, y), px(w - 1, y))).sum();
+    let rows: u64 = (0..w).map(|x| diff(px(x, 0), px(x, h - 1))).sum();
+    (cols + rows) as f64 / (4 * (w + h)) as f64
+}
+
+/// Edge asymmetry above which the seams of an offset image are reported.
+const TILEABLE_ASYMMETRY: f64 = 8.0;
+
+/// Make a PNG image seamlessly tileable
+///
+/// @param input Path to the input PNG file
+/// @param output Path to the output PNG file
+/// @param method `"offset"` (shift by half the width and height, so that the
+///   seams move to the center) or `"mirror"` (append mirrored copies to double
+///   the width and height)
+/// @return A list of the method used, the edge asymmetry of the input (see
+///   `edge_asymmetry()`), and a warning about the seams (empty if none)
+/// @export
+#[extendr]
+fn tinypng_make_tileable_impl(input: &str, output: &str, method: &str) -> Result<List> {
+    let (rgba, w, h) = read_png_rgba(Path::new(input))?;
+    let asymmetry = edge_asymmetry(&rgba, w, h);
+    let mut warning = Vec::new();
+    let (out, ow, oh) = match method {
+        "offset" => {
+            let mut out = vec![0u8; rgba.len()];
+            for y in 0..h {
+                for x in 0..w {
+                    let i = 4 * (((y + h / 2) % h) * w + (x + w / 2) % w);
+                    out[4 * (y * w + x)..4 * (y * w + x) + 4].copy_from_slice(&rgba[i..i + 4]);
+                }
+            }
+            if asymmetry > TILEABLE_ASYMMETRY {
+                warning.push(format!(
+                    "The opposite edges of {} differ (by {:.1} levels on average), so seams are \
+                     visible in the center of the output and may need to be painted over",
+                    input, asymmetry
+                ));
+            }
+            (out, w, h)
+        }
+        "mirror" => {
+            // The copies in the other quadrants are flipped horizontally,
+            // vertically, or both, so all edges match their neighbors.
+            let (ow, oh) = (2 * w, 2 * h);
+            let mut out = vec![0u8; 4 * ow * oh];
+            for y in 0..oh {
+                let sy = if y < h { y } else { oh - 1 - y };
+                for x in 0..ow {
+                    let sx = if x < w { x } else { ow - 1 - x };
+                    let i = 4 * (sy * w + sx);
+                    out[4 * (y * ow + x)..4 * (y * ow + x) + 4].copy_from_slice(&rgba[i..i + 4]);
+                }
+            }
+            (out, ow, oh)
+        }
+        _ => return Err(TinyImgRError::argument(format!("Unknown method: {}", method)).into()),
+    };
+    write_png_rgba(&PathBuf::from(output), &out, ow, oh)?;
+    Ok(list!(method = method, asymmetry = asymmetry, warning = warning))
+}
+
+extendr_module! {
+    mod edit;
+    fn png_pad_impl;
+    fn png_overlay_impl;
+    fn png_append_impl;
+    fn tinypng_patch_impl;
+    fn png_crop_impl;
+    fn tinypng_optimal_crop_impl;
+    fn png_alpha_extract_impl;
+    fn png_alpha_apply_impl;
+    fn tinypng_alpha_fill_impl;
+    fn png_recolor_impl;
+    fn png_darkmode_impl;
+    fn png_round_impl;
+    fn tinypng_make_tileable_impl;
+}
@@ -0,0 +1,82 @@
+// ---------------------------------------------------------------------------
+// Classed errors
+// ---------------------------------------------------------------------------
+//
+// extendr turns an `Err` into a plain R error with only a message, so the kind
+// of error is encoded as a `[class]` prefix of the message, which the R side
+// (`tinyimg_condition()` in R/error.R) turns into the condition classes
+// `c(class, "tinyimg_error", "error", "condition")`.  Errors built from plain
+// strings only get the class `tinyimg_error`.
+
+/// The kinds of errors, each mapped to an R condition subclass.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ErrorKind {
+    /// Reading or writing files failed (or an input file does not exist).
+    Io,
+    /// An input image could not be decoded.
+    Decode,
+    /// An image could not be encoded or optimized.
+    Encode,
+    /// An argument is invalid.
+    Argument,
+}
+
+impl ErrorKind {
+    /// The R condition class of the error kind.
+    pub fn class(self) -> &'static str {
+        match self {
+            Self::Io => "tinyimg_io_error",
+            Self::Decode => "tinyimg_decode_error",
+            Self::Encode => "tinyimg_encode_error",
+            Self::Argument => "tinyimg_argument_error",
+        }
+    }
+}
+
+/// An error message with its kind.
+#[derive(Debug)]
+pub struct TinyImgRError {
+    kind: ErrorKind,
+    message: String,
+}
+
+impl TinyImgRError {
+    pub(crate) fn new(kind: ErrorKind, message: impl Into<String>) -> Self {
+        Self { kind, message: message.into() }
+    }
+
+    pub(crate) fn io(message: impl Into<String>) -> Self {
+        Self::new(ErrorKind::Io, message)
+    }
+
+    pub(crate) fn decode(message: impl Into<String>) -> Self {
+        Self::new(ErrorKind::Decode, message)
+    }
+
+    pub(crate) fn encode(message: impl Into<String>) -> Self {
+        Self::new(ErrorKind::Encode, message)
+    }
+
+    pub(crate) fn argument(message: impl Into<String>) -> Self {
+        Self::new(ErrorKind::Argument, message)
+    }
+
+    pub fn kind(&self) -> ErrorKind {
+        self.kind
+    }
+}
+
+impl std::fmt::Display for TinyImgRError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.message)
+    }
+}
+
+impl std::error::Error for TinyImgRError {}
+
+#[cfg(feature = "r")]
+impl From<TinyImgRError> for extendr_api::Error {
+    fn from(e: TinyImgRError) -> Self {
+        extendr_api::Error::Other(format!("[{}] {}", e.kind.class(), e.message))
+    }
+}
@@ -0,0 +1,268 @@
+use crate::metrics::{bradford, mat_inv, mat_mul, mat_vec, SRGB_TO_XYZ, WHITE_POINTS};
+
+// ---------------------------------------------------------------------------
+// Color profiles
+// ---------------------------------------------------------------------------
+//
+// A PNG can describe its color space with an ICC profile (iCCP), with a gamma
+// and chromaticities (gAMA and cHRM), or with coding-independent code points
+// (cICP).  Stripping these chunks makes viewers assume sRGB, which visibly
+// shifts the colors of, e.g., Display P3 screenshots.  The pixels can instead
+// be converted to sRGB before the chunks are stripped.  Only the common
+// matrix/TRC RGB profiles (three colorants and three tone curves) are
+// handled here; LUT-based and non-RGB profiles are left alone.
+
+/// Chunks that describe the color space of a PNG image.
+pub(crate) const COLOR_CHUNKS: [[u8; 4]; 5] = [*b"iCCP", *b"gAMA", *b"cHRM", *b"sRGB", *b"cICP"];
+
+/// The color chunks before the image data of a PNG file, as their types, data,
+/// and complete bytes (length, type, data, and CRC).
+pub(crate) fn color_chunks(png: &[u8]) -> Vec<([u8; 4], &[u8], &[u8])> {
+    let mut chunks = Vec::new();
+    let mut pos = 8;  // after the signature
+    while pos + 12 <= png.len() {
+        let len = u32::from_be_bytes(png[pos..pos + 4].try_into().unwrap()) as usize;
+        let end = pos + 12 + len;
+        let name: [u8; 4] = png[pos + 4..pos + 8].try_into().unwrap();
+        if end > png.len() || &name == b"IDAT" {
+            break;
+        }
+        if COLOR_CHUNKS.contains(&name) {
+            chunks.push((name, &png[pos + 8..end - 4], &png[pos..end]));
+        }
+        pos = end;
+    }
+    chunks
+}
+
+/// A tone curve that maps an encoded component in [0, 1] to linear light.
+enum Curve {
+    Gamma(f64),
+    /// Samples at equal steps, linearly interpolated.
+    Table(Vec<f64>),
+    /// An ICC parametric curve: the function type and its parameters
+    /// `[g, a, b, c, d, e, f]` (unused ones are zero).
+    Parametric(u16, [f64; 7]),
+}
+
+impl Curve {
+    fn eval(&self, x: f64) -> f64 {
+        match self {
+            Self::Gamma(g) => x.powf(*g),
+            Self::Table(t) => {
+                let pos = x * (t.len() - 1) as f64;
+                let i = (pos.floor() as usize).min(t.len() - 2);
+                t[i] + (t[i + 1] - t[i]) * (pos - i as f64)
+            }
+            Self::Parametric(kind, [g, a, b, c, d, e, f]) => {
+                let pow = |x: f64| if x > 0.0 { x.powf(*g) } else { 0.0 };
+                match kind {
+                    0 => pow(x),
+                    1 => if x >= -b / a { pow(a * x + b) } else { 0.0 },
+                    2 => if x >= -b / a { pow(a * x + b) + c } else { *c },
+                    3 => if x >= *d { pow(a * x + b) } else { c * x },
+                    _ => if x >= *d { pow(a * x + b) + e } else { c * x + f },
+                }
+            }
+        }
+    }
+}
+
+/// An RGB color space given by the XYZ (D50) colors of its primaries and the
+/// tone curves of its channels.
+pub(crate) struct RgbProfile {
+    to_xyz: [[f64; 3]; 3],
+    curves: [Curve; 3],
+}
+
+/// The color space of a PNG image as described by its color chunks.
+pub(crate) enum PngProfile {
+    /// sRGB, or no color chunks at all.
+    Srgb,
+    /// An RGB color space other than sRGB.
+    Rgb(Box<RgbProfile>),
+    /// Color chunks that cannot be converted, with the reason.
+    Unsupported(String),
+}
+
+/// Decompress the ICC profile of an iCCP chunk (a profile name of 1-79 bytes,
+/// a null separator, the compression method 0, and zlib data).
+fn inflate_iccp(data: &[u8]) -> Option<Vec<u8>> {
+    let start = data.iter().take(80).position(|&b| b == 0)? + 2;
+    let zlib = data.get(start..).filter(|_| data[start - 1] == 0)?;
+    let mut decompressor = libdeflater::Decompressor::new();
+    // The size is unknown in advance, so grow the buffer until it fits.
+    let mut size = (zlib.len() * 4).max(1024);
+    while size <= 1 << 26 {
+        let mut icc = vec![0; size];
+        match decompressor.zlib_decompress(zlib, &mut icc) {
+            Ok(n) => {
+                icc.truncate(n);
+                return Some(icc);
+            }
+            Err(libdeflater::DecompressionError::InsufficientSpace) => size *= 4,
+            Err(_) => return None,
+        }
+    }
+    None
+}
+
+/// Determine the color space of a PNG image from its color chunks (see
+/// `color_chunks()`), in the order of precedence of the PNG specification:
+/// cICP, iCCP, sRGB, and gAMA/cHRM.
+pub(crate) fn png_profile(chunks: &[([u8; 4], &[u8], &[u8])]) -> PngProfile {
+    let find = |name: &[u8; 4]| chunks.iter().find(|(n, ..)| n == name).map(|(_, data, _)| *data);
+    if let Some(cicp) = find(b"cICP") {
+        // BT.709 primaries with the sRGB transfer function are plain sRGB.
+        return if cicp.starts_with(&[1, 13]) {
+            PngProfile::Srgb
+        } else {
+            PngProfile::Unsupported("cICP color spaces other than sRGB are not supported".to_string())
+        };
+    }
+    if let Some(iccp) = find(b"iCCP") {
+        let icc = inflate_iccp(iccp).ok_or("the ICC profile cannot be decompressed".to_string());
+        return match icc.and_then(|icc| parse_icc(&icc)) {
+            Ok(profile) if profile.is_srgb() => PngProfile::Srgb,
+            Ok(profile) => PngProfile::Rgb(Box::new(profile)),
+            Err(e) => PngProfile::Unsupported(e),
+        };
+    }
+    if find(b"sRGB").is_some() {
+        return PngProfile::Srgb;
+    }
+    let u32_at = |data: &[u8], i: usize| data.get(i..i + 4).map(|b| u32::from_be_bytes(b.try_into().unwrap()) as f64 / 1e5);
+    // Like libpng, treat a gamma of about 1/2.2 as the sRGB tone curve.
+    let gamma = find(b"gAMA").and_then(|d| u32_at(d, 0)).filter(|g| !(0.45..=0.46).contains(g));
+    let xy = find(b"cHRM").and_then(|d| (0..8).map(|i| u32_at(d, 4 * i)).collect::<Option<Vec<_>>>());
+    const SRGB_XY: [f64; 8] = [0.3127, 0.329, 0.64, 0.33, 0.3, 0.6, 0.15, 0.06];
+    let xy = xy.filter(|xy| xy.iter().zip(SRGB_XY).any(|(a, b)| (a - b).abs() > 0.001));
+    if gamma.is_none() && xy.is_none() {
+        return PngProfile::Srgb;
+    }
+    let to_xyz = match &xy {
+        Some(xy) if xy[1] > 0.0 && xy[3] > 0.0 && xy[5] > 0.0 && xy[7] > 0.0 => primaries_to_xyz(xy),
+        Some(_) => return PngProfile::Unsupported("invalid cHRM chunk".to_string()),
+        None => mat_mul(&bradford(WHITE_POINTS[0].1, WHITE_POINTS[1].1), &SRGB_TO_XYZ),
+    };
+    if gamma == Some(0.0) {
+        return PngProfile::Unsupported("invalid gAMA chunk".to_string());
+    }
+    // gAMA stores the encoding gamma, so decoding raises to its reciprocal.
+    let curves = [0, 1, 2].map(|_| gamma.map_or(SRGB_CURVE, |g| Curve::Gamma(1.0 / g)));
+    PngProfile::Rgb(Box::new(RgbProfile { to_xyz, curves }))
+}
+
+/// The sRGB tone curve as an ICC parametric curve.
+const SRGB_CURVE: Curve = Curve::Parametric(3, [2.4, 1.0 / 1.055, 0.055 / 1.055, 1.0 / 12.92, 0.04045, 0.0, 0.0]);
+
+/// The RGB -> XYZ (D50) matrix of the chromaticities of the white point and
+/// the red, green, and blue primaries (`[wx, wy, rx, ry, gx, gy, bx, by]`).
+fn primaries_to_xyz(xy: &[f64]) -> [[f64; 3]; 3] {
+    let xyz = |x: f64, y: f64| [x / y, 1.0, (1.0 - x - y) / y];
+    let (r, g, b) = (xyz(xy[2], xy[3]), xyz(xy[4], xy[5]), xyz(xy[6], xy[7]));
+    let p = [0, 1, 2].map(|i| [r[i], g[i], b[i]]);
+    let white = xyz(xy[0], xy[1]);
+    // Scale the primaries so that they add up to the white point.
+    let s = mat_vec(&mat_inv(&p), white);
+    let m = [0, 1, 2].map(|i| [0, 1, 2].map(|j| p[i][j] * s[j]));
+    mat_mul(&bradford(white, WHITE_POINTS[1].1), &m)
+}
+
+/// Parse a matrix/TRC RGB ICC profile.
+fn parse_icc(icc: &[u8]) -> std::result::Result<RgbProfile, String> {
+    if icc.len() < 132 || &icc[36..40] != b"acsp" {
+        return Err("invalid ICC profile".to_string());
+    }
+    let space = String::from_utf8_lossy(&icc[16..20]).trim().to_string();
+    if space != "RGB" {
+        return Err(format!("{} ICC profiles are not supported", space));
+    }
+    if &icc[20..24] != b"XYZ " {
+        return Err("ICC profiles with a Lab connection space are not supported".to_string());
+    }
+    let be32 = |i: usize| icc.get(i..i + 4).map(|b| u32::from_be_bytes(b.try_into().unwrap()));
+    let n = be32(128).unwrap_or(0) as usize;
+    let tag = |sig: &[u8; 4]| -> Option<&[u8]> {
+        (0..n).map(|k| 132 + 12 * k).find(|&e| icc.get(e..e + 4) == Some(&sig[..])).and_then(|e| {
+            let (offset, size) = (be32(e + 4)? as usize, be32(e + 8)? as usize);
+            icc.get(offset..offset.checked_add(size)?)
+        })
+    };
+    let s15 = |data: &[u8], i: usize| data.get(i..i + 4).map(|b| i32::from_be_bytes(b.try_into().unwrap()) as f64 / 65536.0);
+    let mut to_xyz = [[0.0; 3]; 3];
+    let mut curves = Vec::with_capacity(3);
+    for (j, (xyz_sig, trc_sig)) in [(b"rXYZ", b"rTRC"), (b"gXYZ", b"gTRC"), (b"bXYZ", b"bTRC")].into_iter().enumerate() {
+        let (Some(xyz), Some(trc)) = (tag(xyz_sig), tag(trc_sig)) else {
+            return Err("ICC profiles without RGB colorants and tone curves (e.g., LUT-based ones) are not supported".to_string());
+        };
+        for (i, row) in to_xyz.iter_mut().enumerate() {
+            row[j] = s15(xyz, 8 + 4 * i).filter(|_| &xyz[..4] == b"XYZ ").ok_or("invalid colorant in the ICC profile")?;
+        }
+        curves.push(parse_curve(trc).ok_or("invalid tone curve in the ICC profile")?);
+    }
+    let curves: [Curve; 3] = curves.try_into().ok().unwrap();
+    Ok(RgbProfile { to_xyz, curves })
+}
+
+/// Parse an ICC `curv` or `para` tone curve.
+fn parse_curve(trc: &[u8]) -> Option<Curve> {
+    let be16 = |i: usize| trc.get(i..i + 2).map(|b| u16::from_be_bytes(b.try_into().unwrap()));
+    match trc.get(..4)? {
+        b"curv" => {
+            let n = u32::from_be_bytes(trc.get(8..12)?.try_into().unwrap()) as usize;
+            match n {
+                0 => Some(Curve::Gamma(1.0)),
+                1 => Some(Curve::Gamma(be16(12)? as f64 / 256.0)),
+                _ => (0..n).map(|k| be16(12 + 2 * k).map(|v| v as f64 / 65535.0))
+                    .collect::<Option<Vec<_>>>().map(Curve::Table),
+            }
+        }
+        b"para" => {
+            let kind = be16(8)?;
+            let count = *[1, 3, 4, 5, 7].get(kind as usize)?;
+            let mut params = [0.0; 7];
+            for (k, p) in params.iter_mut().take(count).enumerate() {
+                *p = i32::from_be_bytes(trc.get(12 + 4 * k..16 + 4 * k)?.try_into().unwrap()) as f64 / 65536.0;
+            }
+            Some(Curve::Parametric(kind, params))
+        }
+        _ => None,
+    }
+}
+
+/// Converts 8-bit components of an RGB profile to sRGB.
+pub(crate) struct SrgbConverter {
+    /// Linear values of the 256 levels of each channel.
+    linear: [[f64; 256]; 3],
+    /// Linear RGB -> linear sRGB.
+    matrix: [[f64; 3]; 3],
+}
+
+impl RgbProfile {
+    pub(crate) fn to_srgb(&self) -> SrgbConverter {
+        let linear = [0, 1, 2].map(|c| std::array::from_fn(|v| self.curves[c].eval(v as f64 / 255.0)));
+        let srgb_to_xyz = mat_mul(&bradford(WHITE_POINTS[0].1, WHITE_POINTS[1].1), &SRGB_TO_XYZ);
+        SrgbConverter { linear, matrix: mat_mul(&mat_inv(&srgb_to_xyz), &self.to_xyz) }
+    }
+
+    /// Whether the profile is sRGB as far as 8-bit colors can tell, i.e.,
+    /// converting the ramps of gray and of each primary changes nothing.
+    fn is_srgb(&self) -> bool {
+        let conv = self.to_srgb();
+        (0..=255u8).all(|v| {
+            [[v, v, v], [v, 0, 0], [0, v, 0], [0, 0, v]].iter().all(|&c| conv.convert(c) == c)
+        })
+    }
+}
+
+impl SrgbConverter {
+    pub(crate) fn convert(&self, [r, g, b]: [u8; 3]) -> [u8; 3] {
+        let lin = [self.linear[0][r as usize], self.linear[1][g as usize], self.linear[2][b as usize]];
+        mat_vec(&self.matrix, lin).map(|u| {
+            let u = u.clamp(0.0, 1.0);
+            let v = if u <= 0.0031308 { 12.92 * u } else { 1.055 * u.powf(1.0 / 2.4) - 0.055 };
+            (v * 255.0).round() as u8
+        })
+    }
+}
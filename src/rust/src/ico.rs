@@ -0,0 +1,213 @@
+use extendr_api::prelude::*;
+use std::path::PathBuf;
+
+use crate::error::TinyImgRError;
+use crate::optimize::{png_options, DEFAULT_PNG_LEVEL};
+use crate::{process_files, validate_io};
+
+// ---------------------------------------------------------------------------
+// ICO/CUR optimisation
+// ---------------------------------------------------------------------------
+//
+// An ICO (or CUR) file is a 6-byte header followed by one 16-byte directory
+// entry per image and then the image payloads.  Each payload is either a
+// complete PNG file or a headerless BMP (a DIB with an AND transparency mask
+// appended).  We recompress PNG payloads with oxipng, convert large BMP
+// payloads to PNG, and then lay the payloads out again in entry order.
+
+const PNG_SIGNATURE: [u8; 8] = [137, 80, 78, 71, 13, 10, 26, 10];
+
+/// BMP payloads at least this wide or tall are converted to PNG.
+const BMP_TO_PNG_MIN_SIZE: u32 = 64;
+
+struct IconEntry {
+    /// The first 8 bytes of the directory entry (dimensions, color count,
+    /// planes/bit count for ICO or hotspot coordinates for CUR), kept verbatim.
+    header: [u8; 8],
+    data: Vec<u8>,
+}
+
+fn read_u16(b: &[u8], pos: usize) -> u16 {
+    u16::from_le_bytes([b[pos], b[pos + 1]])
+}
+
+fn read_u32(b: &[u8], pos: usize) -> u32 {
+    u32::from_le_bytes([b[pos], b[pos + 1], b[pos + 2], b[pos + 3]])
+}
+
+/// Parse the header and directory of an ICO/CUR file into its resource type
+/// (1 = icon, 2 = cursor) and entries.
+fn parse_ico(bytes: &[u8]) -> std::result::Result<(u16, Vec<IconEntry>), String> {
+    if bytes.len() < 6 || read_u16(bytes, 0) != 0 {
+        return Err("not an ICO/CUR file".to_string());
+    }
+    let kind = read_u16(bytes, 2);
+    if kind != 1 && kind != 2 {
+        return Err(format!("unknown resource type {}", kind));
+    }
+    let count = read_u16(bytes, 4) as usize;
+    if bytes.len() < 6 + 16 * count {
+        return Err("truncated image directory".to_string());
+    }
+    let mut entries = Vec::with_capacity(count);
+    for i in 0..count {
+        let pos = 6 + 16 * i;
+        let size = read_u32(bytes, pos + 8) as usize;
+        let offset = read_u32(bytes, pos + 12) as usize;
+        let end = offset.checked_add(size).filter(|&e| e <= bytes.len())
+            .ok_or_else(|| format!("entry {} points outside the file", i + 1))?;
+        let mut header = [0u8; 8];
+        header.copy_from_slice(&bytes[pos..pos + 8]);
+        entries.push(IconEntry { header, data: bytes[offset..end].to_vec() });
+    }
+    Ok((kind, entries))
+}
+
+/// Serialize entries back into an ICO/CUR file with payloads stored
+/// contiguously in entry order.
+fn write_ico(kind: u16, entries: &[IconEntry]) -> Vec<u8> {
+    let mut out = Vec::new();
+    out.extend_from_slice(&0u16.to_le_bytes());
+    out.extend_from_slice(&kind.to_le_bytes());
+    out.extend_from_slice(&(entries.len() as u16).to_le_bytes());
+    let mut offset = 6 + 16 * entries.len();
+    for e in entries {
+        out.extend_from_slice(&e.header);
+        out.extend_from_slice(&(e.data.len() as u32).to_le_bytes());
+        out.extend_from_slice(&(offset as u32).to_le_bytes());
+        offset += e.data.len();
+    }
+    for e in entries {
+        out.extend_from_slice(&e.data);
+    }
+    out
+}
+
+/// Decode a BMP payload (BITMAPINFOHEADER + pixels + AND mask) into RGBA
+/// pixels.  Returns `None` for layouts we do not handle (RLE, 16-bit, ...),
+/// in which case the payload is kept as is.
+fn decode_dib(data: &[u8]) -> Option<(Vec<lodepng::RGBA>, usize, usize)> {
+    if data.len() < 40 {
+        return None;
+    }
+    let header_size = read_u32(data, 0) as usize;
+    let width = i32::from_le_bytes(data[4..8].try_into().ok()?);
+    // The height in an icon DIB covers both the XOR bitmap and the AND mask.
+    let height = i32::from_le_bytes(data[8..12].try_into().ok()?) / 2;
+    let bpp = read_u16(data, 14) as usize;
+    let compression = read_u32(data, 16);
+    let colors_used = read_u32(data, 32) as usize;
+    if header_size < 40 || width <= 0 || height <= 0 || compression != 0 {
+        return None;
+    }
+    let (w, h) = (width as usize, height as usize);
+
+    let palette_len = if bpp <= 8 {
+        if colors_used > 0 { colors_used } else { 1 << bpp }
+    } else {
+        0
+    };
+    let palette_pos = header_size;
+    let pixels_pos = palette_pos + 4 * palette_len;
+    let xor_stride = (w * bpp).div_ceil(32) * 4;
+    let and_stride = w.div_ceil(32) * 4;
+    let and_pos = pixels_pos + xor_stride * h;
+    if !matches!(bpp, 1 | 4 | 8 | 24 | 32) || data.len() < and_pos {
+        return None;
+    }
+    let has_mask = data.len() >= and_pos + and_stride * h;
+    let palette = |i: usize| -> Option<lodepng::RGBA> {
+        if i >= palette_len {
+            return None;
+        }
+        let p = palette_pos + 4 * i;
+        Some(lodepng::RGBA::new(data[p + 2], data[p + 1], data[p], 255))
+    };
+
+    let mut pixels = vec![lodepng::RGBA::new(0, 0, 0, 0); w * h];
+    for y in 0..h {
+        // DIB rows are stored bottom-up.
+        let row = &data[pixels_pos + (h - 1 - y) * xor_stride..];
+        for x in 0..w {
+            pixels[y * w + x] = match bpp {
+                32 => lodepng::RGBA::new(row[4 * x + 2], row[4 * x + 1], row[4 * x], row[4 * x + 3]),
+                24 => lodepng::RGBA::new(row[3 * x + 2], row[3 * x + 1], row[3 * x], 255),
+                8 => palette(row[x] as usize)?,
+                4 => palette(((row[x / 2] >> (4 * (1 - x % 2))) & 0x0f) as usize)?,
+                _ => palette(((row[x / 8] >> (7 - x % 8)) & 1) as usize)?,
+            };
+        }
+    }
+
+    // 32-bit payloads carry their own alpha unless it is entirely zero (an
+    // old-style icon that relies on the AND mask); everything else uses the mask.
+    let use_mask = bpp != 32 || pixels.iter().all(|p| p.a == 0);
+    if use_mask {
+        if !has_mask {
+            return None;
+        }
+        for y in 0..h {
+            let row = &data[and_pos + (h - 1 - y) * and_stride..];
+            for x in 0..w {
+                let transparent = (row[x / 8] >> (7 - x % 8)) & 1 == 1;
+                pixels[y * w + x].a = if transparent { 0 } else { 255 };
+            }
+        }
+    }
+    Some((pixels, w, h))
+}
+
+/// Return the smaller of `data` and its optimized replacement.
+fn optimize_entry(data: Vec<u8>, opts: &oxipng::Options) -> Vec<u8> {
+    let candidate = if data.starts_with(&PNG_SIGNATURE) {
+        oxipng::optimize_from_memory(&data, opts).ok()
+    } else {
+        decode_dib(&data)
+            .filter(|(_, w, h)| *w as u32 >= BMP_TO_PNG_MIN_SIZE || *h as u32 >= BMP_TO_PNG_MIN_SIZE)
+            .and_then(|(pixels, w, h)| lodepng::encode32(&pixels, w, h).ok())
+            .and_then(|png| oxipng::optimize_from_memory(&png, opts).ok())
+    };
+    match candidate {
+        Some(c) if c.len() < data.len() => c,
+        _ => data,
+    }
+}
+
+/// Returns the sizes of the input and output data.
+fn optimize_ico(input: &PathBuf, output: &PathBuf) -> Result<(u64, u64)> {
+    let bytes = std::fs::read(input)
+        .map_err(|e| TinyImgRError::io(format!("Failed to read {}: {}", input.display(), e)))?;
+    let (kind, entries) = parse_ico(&bytes)
+        .map_err(|e| TinyImgRError::decode(format!("Failed to parse ICO {}: {}", input.display(), e)))?;
+    let opts = png_options(DEFAULT_PNG_LEVEL);
+    let entries: Vec<IconEntry> = entries
+        .into_iter()
+        .map(|e| IconEntry { header: e.header, data: optimize_entry(e.data, &opts) })
+        .collect();
+    let optimized = write_ico(kind, &entries);
+    let data = if optimized.len() < bytes.len() { &optimized } else { &bytes };
+    std::fs::write(output, data)
+        .map_err(|e| TinyImgRError::io(format!("Failed to write {}: {}", output.display(), e)))?;
+    Ok((bytes.len() as u64, data.len() as u64))
+}
+
+/// Optimize ICO and CUR files
+///
+/// @param input Vector of input ICO/CUR file paths
+/// @param output Vector of output ICO/CUR file paths (same length as input)
+/// @param verbose Print file size reduction info
+/// @export
+#[extendr]
+fn tinyico_impl(input: Strings, output: Strings, verbose: bool) -> Result<()> {
+    let inputs: Vec<String>  = input.iter().map(|s| s.to_string()).collect();
+    let outputs: Vec<String> = output.iter().map(|s| s.to_string()).collect();
+    validate_io(&inputs, &outputs)?;
+    process_files(&inputs, &outputs, verbose, |input_path, output_path| {
+        optimize_ico(input_path, output_path)
+    })
+}
+
+extendr_module! {
+    mod ico;
+    fn tinyico_impl;
+}
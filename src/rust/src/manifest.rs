@@ -0,0 +1,195 @@
+use extendr_api::prelude::*;
+use std::io::{BufRead, BufReader, BufWriter, Write};
+use std::path::PathBuf;
+
+use crate::error::{ErrorKind, TinyImgRError};
+use crate::optimize::{PngSettings, QualityReport, VerboseMessage, QUALITY_BREAKS};
+
+// ---------------------------------------------------------------------------
+// Manifest files for large batches
+// ---------------------------------------------------------------------------
+//
+// A manifest lists one `input<TAB>output` pair per line (UTF-8, with blank
+// lines and `#` comments ignored).  It is read and processed line by line, so
+// the full list of paths never has to be held in memory, neither in R nor
+// here.
+
+/// Parse a manifest line into an input and an output path, or `None` for
+/// blank and comment lines.
+fn parse_line(line: &str) -> std::result::Result<Option<(&str, &str)>, String> {
+    let line = line.strip_suffix('\r').unwrap_or(line);
+    if line.trim().is_empty() || line.starts_with('#') {
+        return Ok(None);
+    }
+    let mut fields = line.split('\t');
+    match (fields.next(), fields.next(), fields.next()) {
+        (Some(input), Some(output), None) if !input.is_empty() && !output.is_empty() => {
+            Ok(Some((input, output)))
+        }
+        _ => Err("expected an input and an output path separated by a tab".to_string()),
+    }
+}
+
+/// Optimize PNG files listed in a manifest file
+///
+/// @param manifest Path to the manifest file
+/// @param stats_output Path to a file to write the file sizes to (tab-separated
+///   columns `input`, `output`, `input_size`, `output_size`, and `removed`),
+///   or `""` to return them
+/// @param verbose Verbosity (`0`, `1`, or `2`) as in `tinypng_impl()`
+/// @param level,alpha,preserve,lossy,lossy_l,lossy_a,lossy_b,white_point,lossy_background,auto_thresholds,auto_level_breaks,auto_level_values
+///   Same as in `tinypng_impl()`
+/// @param quality_samples The maximum number of pixels to compare between
+///   each output and its input for a quality report (0 for no report)
+/// @return A list of the input and output paths and sizes, the removed chunk
+///   types (a list of character vectors), the classes and lossy thresholds
+///   with automatic thresholds, the levels with automatic levels, and the
+///   quality reports (the number of sampled pixels, the Delta E histograms as
+///   a list of integer vectors, and the Delta E quantiles), or `NULL` if they
+///   were written to `stats_output`
+/// @export
+#[extendr]
+#[allow(clippy::too_many_arguments)]
+fn tinypng_manifest_impl(
+    manifest: &str,
+    stats_output: &str,
+    level: i32,
+    alpha: bool,
+    preserve: bool,
+    verbose: i32,
+    lossy: f64,
+    lossy_l: f64,
+    lossy_a: f64,
+    lossy_b: f64,
+    white_point: &str,
+    lossy_background: &str,
+    auto_thresholds: Doubles,
+    auto_level_breaks: Doubles,
+    auto_level_values: Integers,
+    quality_samples: i32,
+) -> Result<Robj> {
+    let auto = crate::parse_auto_thresholds(&auto_thresholds)?;
+    let auto_levels = crate::parse_auto_levels(&auto_level_breaks, &auto_level_values)?;
+    let has_levels = auto_levels.is_some();
+    let settings = PngSettings::new(level, alpha, preserve, lossy, [lossy_l, lossy_a, lossy_b], white_point)?
+        .with_background(crate::parse_background(lossy_background)?)
+        .with_auto_thresholds(auto)
+        .with_auto_levels(auto_levels)
+        .with_quality_report(quality_samples.max(0) as usize);
+    let has_quality = quality_samples > 0;
+    let file = std::fs::File::open(manifest)
+        .map_err(|e| TinyImgRError::io(format!("Failed to read {}: {}", manifest, e)))?;
+    let mut stats = if stats_output.is_empty() {
+        None
+    } else {
+        let f = std::fs::File::create(stats_output)
+            .map_err(|e| TinyImgRError::io(format!("Failed to write {}: {}", stats_output, e)))?;
+        let mut w = BufWriter::new(f);
+        let header = format!(
+            "{}{}{}", if auto.is_some() { "\tclass\tlossy" } else { "" }, if has_levels { "\tlevel" } else { "" },
+            if has_quality { "\tde_samples\tde_hist\tde_p50\tde_p95\tde_p99\tde_max" } else { "" }
+        );
+        writeln!(w, "input\toutput\tinput_size\toutput_size\tremoved{}", header)
+            .map_err(|e| TinyImgRError::io(format!("Failed to write {}: {}", stats_output, e)))?;
+        Some(w)
+    };
+    let (mut inputs, mut outputs, mut input_sizes, mut output_sizes) =
+        (Vec::new(), Vec::new(), Vec::new(), Vec::new());
+    let (mut removed, mut classes, mut lossy_values, mut levels) = (Vec::new(), Vec::new(), Vec::new(), Vec::new());
+    let mut reports: Vec<QualityReport> = Vec::new();
+    for (i, line) in BufReader::new(file).lines().enumerate() {
+        let fail = |kind: ErrorKind, e: String| {
+            TinyImgRError::new(kind, format!("Line {} of {}: {}", i + 1, manifest, e))
+        };
+        let line = line.map_err(|e| fail(ErrorKind::Io, e.to_string()))?;
+        let Some((input, output)) = parse_line(&line).map_err(|e| fail(ErrorKind::Argument, e))? else { continue };
+        let (input_path, output_path) = (PathBuf::from(input), PathBuf::from(output));
+        if !input_path.exists() {
+            return Err(fail(ErrorKind::Io, format!("Input file does not exist: {}", input)).into());
+        }
+        if let Some(parent) = output_path.parent().filter(|p| !p.as_os_str().is_empty()) {
+            std::fs::create_dir_all(parent).map_err(|e| fail(ErrorKind::Io, format!(
+                "Failed to create directory {}: {}", parent.display(), e
+            )))?;
+        }
+        let (sizes, notes) = settings.optimize_auto(&input_path, &output_path)
+            .map_err(|e| fail(e.kind(), e.to_string()))?;
+        let chosen = notes.auto;
+        if verbose > 0 {
+            VerboseMessage::new(input, output, sizes)
+                .with_auto(chosen)
+                .with_level(notes.level)
+                .with_removed(if verbose > 1 { &notes.removed } else { &[] })
+                .print(0, 0);
+        }
+        match &mut stats {
+            Some(w) => {
+                let mut extra = chosen.map_or(String::new(), |a| format!("\t{}\t{}", a.class.name(), a.lossy));
+                if let Some(level) = notes.level {
+                    extra.push_str(&format!("\t{}", level));
+                }
+                if let Some(q) = &notes.quality {
+                    let counts: Vec<String> = q.counts.iter().map(|n| n.to_string()).collect();
+                    let [p50, p95, p99, max] = q.quantiles;
+                    extra.push_str(&format!("\t{}\t{}\t{}\t{}\t{}\t{}", q.samples, counts.join(","), p50, p95, p99, max));
+                }
+                writeln!(w, "{}\t{}\t{}\t{}\t{}{}", input, output, sizes.0, sizes.1, notes.removed.join(","), extra)
+                    .map_err(|e| TinyImgRError::io(format!("Failed to write {}: {}", stats_output, e)))?
+            }
+            None => {
+                inputs.push(input.to_string());
+                outputs.push(output.to_string());
+                input_sizes.push(sizes.0 as f64);
+                output_sizes.push(sizes.1 as f64);
+                removed.push(Strings::from_values(&notes.removed));
+                if let Some(a) = chosen {
+                    classes.push(a.class.name());
+                    lossy_values.push(a.lossy);
+                }
+                levels.extend(notes.level.map(i32::from));
+                reports.extend(notes.quality);
+            }
+        }
+    }
+    match stats {
+        Some(mut w) => {
+            w.flush().map_err(|e| TinyImgRError::io(format!("Failed to write {}: {}", stats_output, e)))?;
+            Ok(().into())
+        }
+        None => {
+            let mut columns: Vec<(&str, Robj)> = vec![
+                ("input", inputs.into()), ("output", outputs.into()), ("input_size", input_sizes.into()),
+                ("output_size", output_sizes.into()), ("removed", List::from_values(removed).into()),
+            ];
+            if auto.is_some() {
+                columns.extend([("class", classes.into()), ("lossy", lossy_values.into())]);
+            }
+            if has_levels {
+                columns.push(("level", levels.into()));
+            }
+            if has_quality {
+                let names: Vec<String> = QUALITY_BREAKS.iter()
+                    .map(|b| if b.is_finite() { b.to_string() } else { "Inf".to_string() }).collect();
+                let hist = reports.iter().map(|q| -> Result<Robj> {
+                    let mut counts: Robj = q.counts.iter().map(|&n| n as i32).collect::<Integers>().into();
+                    counts.set_names(&names)?;
+                    Ok(counts)
+                }).collect::<Result<Vec<_>>>()?;
+                let quantile = |k: usize| reports.iter().map(|q| q.quantiles[k]).collect::<Vec<_>>();
+                columns.extend([
+                    ("de_samples", reports.iter().map(|q| q.samples as i32).collect::<Vec<_>>().into()),
+                    ("de_hist", List::from_values(hist).into()),
+                    ("de_p50", quantile(0).into()), ("de_p95", quantile(1).into()),
+                    ("de_p99", quantile(2).into()), ("de_max", quantile(3).into()),
+                ]);
+            }
+            let (names, values): (Vec<_>, Vec<_>) = columns.into_iter().unzip();
+            Ok(List::from_names_and_values(names, values)?.into())
+        }
+    }
+}
+
+extendr_module! {
+    mod manifest;
+    fn tinypng_manifest_impl;
+}
@@ -0,0 +1,182 @@
+use crate::quantize::Color;
+
+// ---------------------------------------------------------------------------
+// Color metrics
+// ---------------------------------------------------------------------------
+//
+// sRGB colors are converted to CIE L*a*b* for perceptual color differences.
+// sRGB is defined relative to the D65 white point; for other reference
+// whites, XYZ values are first adapted to the target white with the Bradford
+// transform, i.e., the sRGB-to-XYZ matrix is replaced by the adapted one.
+
+/// Reference white points in XYZ (CIE 1931 2° observer, Y = 1).
+pub(crate) const WHITE_POINTS: [(&str, [f64; 3]); 4] = [
+    ("d65", [0.95047, 1.0, 1.08883]),
+    ("d50", [0.96422, 1.0, 0.82521]),
+    ("d55", [0.95682, 1.0, 0.92149]),
+    ("e",   [1.0, 1.0, 1.0]),
+];
+
+/// sRGB (linear) -> XYZ matrix under the D65 white point.
+pub(crate) const SRGB_TO_XYZ: [[f64; 3]; 3] = [
+    [0.4124564, 0.3575761, 0.1804375],
+    [0.2126729, 0.7151522, 0.0721750],
+    [0.0193339, 0.1191920, 0.9503041],
+];
+
+/// Bradford cone response matrix and its inverse.
+const BRADFORD: [[f64; 3]; 3] = [
+    [0.8951, 0.2664, -0.1614],
+    [-0.7502, 1.7135, 0.0367],
+    [0.0389, -0.0685, 1.0296],
+];
+const BRADFORD_INV: [[f64; 3]; 3] = [
+    [0.9869929, -0.1470543, 0.1599627],
+    [0.4323053, 0.5183603, 0.0492912],
+    [-0.0085287, 0.0400428, 0.9684867],
+];
+
+pub(crate) fn mat_mul(a: &[[f64; 3]; 3], b: &[[f64; 3]; 3]) -> [[f64; 3]; 3] {
+    let mut m = [[0.0; 3]; 3];
+    for (i, row) in m.iter_mut().enumerate() {
+        for (j, v) in row.iter_mut().enumerate() {
+            *v = (0..3).map(|k| a[i][k] * b[k][j]).sum();
+        }
+    }
+    m
+}
+
+/// Inverse of a (non-singular) 3x3 matrix via the adjugate.
+pub(crate) fn mat_inv(m: &[[f64; 3]; 3]) -> [[f64; 3]; 3] {
+    let c = |i: usize, j: usize| {
+        let (r0, r1) = ((i + 1) % 3, (i + 2) % 3);
+        let (c0, c1) = ((j + 1) % 3, (j + 2) % 3);
+        m[r0][c0] * m[r1][c1] - m[r0][c1] * m[r1][c0]
+    };
+    let det = m[0][0] * c(0, 0) + m[0][1] * c(0, 1) + m[0][2] * c(0, 2);
+    let mut inv = [[0.0; 3]; 3];
+    for (i, row) in inv.iter_mut().enumerate() {
+        for (j, v) in row.iter_mut().enumerate() {
+            *v = c(j, i) / det;
+        }
+    }
+    inv
+}
+
+pub(crate) fn mat_vec(m: &[[f64; 3]; 3], v: [f64; 3]) -> [f64; 3] {
+    [0, 1, 2].map(|i| m[i][0] * v[0] + m[i][1] * v[1] + m[i][2] * v[2])
+}
+
+/// Bradford adaptation of XYZ values from one white point to another: scale
+/// the cone responses by target / source white.
+pub(crate) fn bradford(src: [f64; 3], dst: [f64; 3]) -> [[f64; 3]; 3] {
+    let (src, dst) = (mat_vec(&BRADFORD, src), mat_vec(&BRADFORD, dst));
+    let mut scale = [[0.0; 3]; 3];
+    for i in 0..3 {
+        scale[i][i] = dst[i] / src[i];
+    }
+    mat_mul(&BRADFORD_INV, &mat_mul(&scale, &BRADFORD))
+}
+
+/// The sRGB transfer function (IEC 61966-2-1): an encoded component in [0, 1]
+/// to linear light.
+pub(crate) fn srgb_to_linear(u: f64) -> f64 {
+    if u > 0.04045 { ((u + 0.055) / 1.055).powf(2.4) } else { u / 12.92 }
+}
+
+/// Converts sRGB colors to CIE L*a*b* relative to a reference white.
+pub(crate) struct LabConverter {
+    rgb_to_xyz: [[f64; 3]; 3],
+    #[cfg(feature = "r")]
+    xyz_to_rgb: [[f64; 3]; 3],
+    white: [f64; 3],
+    /// Background over which translucent colors are seen (see `perceived()`).
+    background: Option<[f64; 3]>,
+}
+
+impl LabConverter {
+    /// Look up a white point by name (`"d65"`, `"d50"`, `"d55"`, or `"e"`).
+    pub(crate) fn new(white_point: &str) -> std::result::Result<Self, String> {
+        let name = white_point.to_ascii_lowercase();
+        let white = WHITE_POINTS.iter().find(|(n, _)| *n == name).map(|(_, w)| *w)
+            .ok_or_else(|| format!(
+                "Unknown white point '{}' (expected one of: d65, d50, d55, e)", white_point
+            ))?;
+        let d65 = WHITE_POINTS[0].1;
+        let rgb_to_xyz = if white == d65 {
+            SRGB_TO_XYZ
+        } else {
+            mat_mul(&bradford(d65, white), &SRGB_TO_XYZ)
+        };
+        Ok(Self {
+            rgb_to_xyz,
+            #[cfg(feature = "r")]
+            xyz_to_rgb: mat_inv(&rgb_to_xyz),
+            white,
+            background: None,
+        })
+    }
+
+    /// Composite translucent colors over an sRGB background in `perceived()`.
+    pub(crate) fn with_background(mut self, background: Option<[u8; 3]>) -> Self {
+        self.background = background.map(|b| b.map(f64::from));
+        self
+    }
+
+    pub(crate) fn to_lab(&self, c: Color) -> [f64; 3] {
+        self.rgb_to_lab([c.r, c.g, c.b].map(f64::from))
+    }
+
+    /// L*a*b* of a color as it is seen: composited over the background (if
+    /// any), otherwise its raw RGB values (as `to_lab()`).  Opaque colors are
+    /// not affected by the background.
+    pub(crate) fn perceived(&self, c: Color) -> [f64; 3] {
+        match self.background {
+            Some(bg) if c.a < 255 => {
+                let a = c.a as f64 / 255.0;
+                let rgb = [c.r, c.g, c.b].map(f64::from);
+                self.rgb_to_lab([0, 1, 2].map(|i| rgb[i] * a + bg[i] * (1.0 - a)))
+            }
+            _ => self.to_lab(c),
+        }
+    }
+
+    /// Convert sRGB values (0-255, possibly fractional) to L*a*b*.
+    fn rgb_to_lab(&self, rgb: [f64; 3]) -> [f64; 3] {
+        // CIE Lab piecewise transform constants (epsilon, kappa).
+        fn f(t: f64) -> f64 {
+            if t > 0.008856 { t.powf(1.0 / 3.0) } else { (903.3 * t + 16.0) / 116.0 }
+        }
+        let rgb = rgb.map(|v| srgb_to_linear(v / 255.0));
+        // sRGB -> XYZ, then white-point normalization.
+        let xyz = mat_vec(&self.rgb_to_xyz, rgb);
+        let fx = f(xyz[0] / self.white[0]);
+        let fy = f(xyz[1] / self.white[1]);
+        let fz = f(xyz[2] / self.white[2]);
+        [116.0 * fy - 16.0, 500.0 * (fx - fy), 200.0 * (fy - fz)]
+    }
+
+    /// Convert L*a*b* back to sRGB (the inverse of `to_lab()`), clipping
+    /// out-of-gamut colors to the sRGB cube.
+    #[cfg(feature = "r")]
+    pub(crate) fn to_rgb(&self, lab: [f64; 3]) -> [u8; 3] {
+        fn gamma(u: f64) -> f64 {
+            if u > 0.0031308 { 1.055 * u.powf(1.0 / 2.4) - 0.055 } else { 12.92 * u }
+        }
+        fn f_inv(t: f64) -> f64 {
+            if t * t * t > 0.008856 { t * t * t } else { (116.0 * t - 16.0) / 903.3 }
+        }
+        let fy = (lab[0] + 16.0) / 116.0;
+        let f = [fy + lab[1] / 500.0, fy, fy - lab[2] / 200.0];
+        let xyz = [0, 1, 2].map(|i| f_inv(f[i]) * self.white[i]);
+        mat_vec(&self.xyz_to_rgb, xyz).map(|u| (gamma(u.clamp(0.0, 1.0)) * 255.0).round() as u8)
+    }
+}
+
+/// CIE76 color difference (Euclidean distance in L*a*b*).
+pub(crate) fn delta_e(a: [f64; 3], b: [f64; 3]) -> f64 {
+    let dl = a[0] - b[0];
+    let da = a[1] - b[1];
+    let db = a[2] - b[2];
+    (dl * dl + da * da + db * db).sqrt()
+}
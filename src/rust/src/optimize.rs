@@ -0,0 +1,1968 @@
+use oxipng::{Deflaters, IndexSet, Interlacing, Options, RowFilter, StripChunks};
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::path::{Path, PathBuf};
+
+use crate::error::TinyImgRError;
+use crate::icc::{color_chunks, png_profile, PngProfile, COLOR_CHUNKS};
+use crate::metrics::{delta_e, LabConverter};
+use crate::quantize::{color_bytes, colors_from_rgba, remap, Color, Palettes};
+use crate::sidecar::{ancillary_chunks_raw, pixel_hash, restore_chunks, sidecar_path, write_sidecar, SavedChunk};
+
+// ---------------------------------------------------------------------------
+// PNG optimization pipeline
+// ---------------------------------------------------------------------------
+//
+// The optimization of PNG files does not depend on R, so that it can be shared
+// by `tinypng()` and the command-line tool `tinyimg-cli`.
+
+type Result<T> = std::result::Result<T, TinyImgRError>;
+
+/// Preset level for PNG files written by functions without a `level` argument
+/// (same as the `tinypng()` default).
+#[cfg(feature = "r")]
+pub(crate) const DEFAULT_PNG_LEVEL: u8 = 2;
+
+/// oxipng options for a preset level with all metadata chunks stripped.
+pub(crate) fn png_options(level: u8) -> Options {
+    let mut opts = Options::from_preset(level);
+    opts.strip = StripChunks::All;
+    opts
+}
+
+/// Copy the permissions and access/modification times of a file (given by
+/// its metadata) to another file.
+pub(crate) fn copy_attrs(metadata: &std::fs::Metadata, output: &Path) -> std::io::Result<()> {
+    // Set the times first: the copied permissions may not allow writing.
+    let times = std::fs::FileTimes::new()
+        .set_accessed(metadata.accessed()?)
+        .set_modified(metadata.modified()?);
+    std::fs::File::options().write(true).open(output)?.set_times(times)?;
+    std::fs::set_permissions(output, metadata.permissions())
+}
+
+/// The settings of `tinypng()` for optimizing a single PNG file.
+pub struct PngSettings {
+    opts: Options,
+    threshold: Option<LossyThreshold>,
+    channels: [f64; 3],
+    auto: Option<AutoThresholds>,
+    auto_levels: Option<AutoLevels>,
+    lab: LabConverter,
+    preserve: bool,
+    reproducible: bool,
+    convert_profile: bool,
+    metadata_backup: Option<String>,
+    color_type: Option<ColorType>,
+    quality_samples: usize,
+    speed: LossySpeed,
+    zopfli: Option<std::num::NonZeroU8>,
+    filters: Option<IndexSet<RowFilter>>,
+    timeout: Option<std::time::Duration>,
+    colors: usize,
+    dither: Option<Dither>,
+    fix_errors: bool,
+    dry_run: bool,
+    no_grow: bool,
+    checksum: bool,
+    classify: Option<f64>,
+}
+
+/// Chunks whose contents vary between runs: the modification time, and text
+/// chunks, which commonly record creation or modification dates (e.g.,
+/// `Creation Time` or ImageMagick's `date:create`).
+const TIME_VARYING_CHUNKS: [[u8; 4]; 4] = [*b"tIME", *b"tEXt", *b"zTXt", *b"iTXt"];
+
+impl PngSettings {
+    pub fn new(
+        level: i32, alpha: bool, preserve: bool, lossy: f64, channels: [f64; 3], white_point: &str,
+    ) -> Result<Self> {
+        let mut opts = png_options(level as u8);
+        opts.optimize_alpha = alpha;
+        let threshold = LossyThreshold::new(lossy, channels);
+        let lab = LabConverter::new(white_point).map_err(TinyImgRError::argument)?;
+        Ok(Self {
+            opts, threshold, channels, auto: None, auto_levels: None, lab, preserve, reproducible: false,
+            convert_profile: false, metadata_backup: None, color_type: None,
+            quality_samples: 0, speed: LossySpeed::default(), zopfli: None, filters: None, timeout: None, colors: 0,
+            dither: None, fix_errors: false, dry_run: false, no_grow: true, checksum: false, classify: None,
+        })
+    }
+
+    /// Save the ancillary chunks stripped from each file to a sidecar file
+    /// (see `sidecar_path()` for how `backup` names it), so that they can be
+    /// restored later.
+    pub fn with_metadata_backup(mut self, backup: Option<String>) -> Self {
+        self.metadata_backup = backup;
+        self
+    }
+
+    /// Optimize each file in memory only, without writing the output, its
+    /// attributes, or the metadata sidecar, to report what would be saved.
+    pub fn with_dry_run(mut self, dry_run: bool) -> Self {
+        self.dry_run = dry_run;
+        self
+    }
+
+    /// Whether to write the optimized data even if it is larger than the
+    /// source (by default, the source is copied to the output instead).
+    pub fn with_no_grow(mut self, no_grow: bool) -> Self {
+        self.no_grow = no_grow;
+        self
+    }
+
+    /// Compute the SHA-256 hash of the optimized data of each file (see
+    /// `FileNotes::sha256`).
+    pub fn with_checksum(mut self, checksum: bool) -> Self {
+        self.checksum = checksum;
+        self
+    }
+
+    /// Classify the content of each image (see `FileNotes::class`) from the
+    /// pixels decoded to optimize it.  Images optimized without decoding their
+    /// pixels are only decoded for it (from the input data in memory) when
+    /// their size is reduced by less than the fraction `max_saving`.
+    pub fn with_classify(mut self, max_saving: Option<f64>) -> Self {
+        self.classify = max_saving;
+        self
+    }
+
+    /// Choose the lossy threshold of each image by its class (overriding the
+    /// `lossy` value of `new()`).
+    pub fn with_auto_thresholds(mut self, auto: Option<AutoThresholds>) -> Self {
+        self.auto = auto;
+        self
+    }
+
+    /// Choose the optimization level of each file by its size (overriding the
+    /// `level` of `new()`).
+    pub fn with_auto_levels(mut self, levels: Option<AutoLevels>) -> Self {
+        self.auto_levels = levels;
+        self
+    }
+
+    /// Write all outputs in a color type, instead of letting oxipng choose the
+    /// smallest one (see `encode_color_type()`).
+    pub fn with_color_type(mut self, color_type: Option<ColorType>) -> Self {
+        self.color_type = color_type;
+        self
+    }
+
+    /// Compare the pixels of each output with those of its input on at most
+    /// `samples` pixels (0 for no comparison), and report the error in
+    /// `FileNotes::quality`.
+    pub fn with_quality_report(mut self, samples: usize) -> Self {
+        self.quality_samples = samples;
+        self
+    }
+
+    /// Trade the quality of lossy palette reduction for speed (see
+    /// `LossySpeed`).
+    pub fn with_lossy_speed(mut self, speed: LossySpeed) -> Self {
+        self.speed = speed;
+        self
+    }
+
+    /// Measure the lossy error of translucent pixels as composited over an
+    /// sRGB background (by default, their raw RGB values are compared).
+    pub fn with_background(mut self, background: Option<[u8; 3]>) -> Self {
+        self.lab = self.lab.with_background(background);
+        self
+    }
+
+    /// Compress the image data with Zopfli (with a number of iterations)
+    /// instead of the deflater of the preset, which usually makes files a few
+    /// percent smaller, but is often 10 to 100 times slower: oxipng
+    /// compresses the best row filter (chosen with a fast deflater) with
+    /// Zopfli up to level 2, and every row filter it tries at higher levels.
+    pub fn with_zopfli(mut self, iterations: Option<std::num::NonZeroU8>) -> Self {
+        self.zopfli = iterations;
+        if let Some(iterations) = iterations {
+            self.opts.deflate = Deflaters::Zopfli { iterations };
+        }
+        self
+    }
+
+    /// Set the row filters to try (`None` for those of the preset level).
+    pub fn with_filters(mut self, filters: Option<IndexSet<RowFilter>>) -> Self {
+        if let Some(f) = &filters {
+            self.opts.filter = f.clone();
+        }
+        self.filters = filters;
+        self
+    }
+
+    /// Enable or disable the lossless reductions of oxipng (all enabled by
+    /// default): of the bit depth, the color type (including to grayscale),
+    /// and the palette (removing unused and duplicate entries).
+    pub fn with_reductions(mut self, bit_depth: bool, color_type: bool, palette: bool) -> Self {
+        self.opts.bit_depth_reduction = bit_depth;
+        (self.opts.color_type_reduction, self.opts.grayscale_reduction) = (color_type, color_type);
+        self.opts.palette_reduction = palette;
+        self
+    }
+
+    /// Scale 16-bit images down to 8 bits, which is lossy (by default, they are
+    /// only reduced if no precision is lost).
+    pub fn with_scale_16(mut self, scale_16: bool) -> Self {
+        self.opts.scale_16 = scale_16;
+        self
+    }
+
+    /// Limit the time spent on each file (`None` for no limit): the lossy
+    /// palette reduction stops bisecting the palette size (keeping the
+    /// smallest size found to be within the threshold so far), and oxipng
+    /// stops trying (keeping its best result so far) once it is reached.  The
+    /// limit is ignored if the output is reproducible (see
+    /// `with_reproducible()`), whichever is set first.
+    pub fn with_timeout(mut self, timeout: Option<std::time::Duration>) -> Self {
+        self.timeout = timeout;
+        self.pin_reproducible();
+        self
+    }
+
+    /// Reduce each image to a palette of `colors` colors (from 1 to 256; 0 to
+    /// choose the smallest size within the lossy threshold), instead of
+    /// searching for the smallest size within the threshold.
+    pub fn with_colors(mut self, colors: usize) -> Self {
+        self.colors = colors;
+        self
+    }
+
+    /// Dither the pixels reduced to a palette with an algorithm (`None` for
+    /// the dithering of the lossy speed).  The palette size is still chosen
+    /// by the error of the pixels without dithering, so the 95th percentile
+    /// error of the dithered pixels can exceed the lossy threshold.
+    pub fn with_dither(mut self, dither: Option<Dither>) -> Self {
+        self.dither = dither;
+        self
+    }
+
+    /// Always write the result of the optimization, even if it is larger than
+    /// the input or the input is already optimal (like `oxipng --force`).
+    pub fn with_force(mut self, force: bool) -> Self {
+        self.opts.force = force;
+        self
+    }
+
+    /// Salvage slightly corrupt files: chunks with wrong CRCs are accepted
+    /// (and written with correct ones), and files that cannot be decoded for
+    /// the palette reduction (e.g., with a `tRNS` chunk longer than the
+    /// palette) are rewritten by oxipng first.
+    pub fn with_fix_errors(mut self, fix_errors: bool) -> Self {
+        self.fix_errors = fix_errors;
+        self.opts.fix_errors = fix_errors;
+        self
+    }
+
+    /// Set the interlacing of the outputs (`None` to keep that of each input;
+    /// oxipng removes it by default).  Unlike oxipng, which only changes the
+    /// interlacing if that makes the file smaller, the setting is enforced.
+    pub fn with_interlace(mut self, interlace: Option<Interlacing>) -> Self {
+        self.opts.interlace = interlace;
+        self
+    }
+
+    /// Set the metadata chunks to strip (all of them by default).
+    pub fn with_strip(mut self, strip: StripChunks) -> Self {
+        self.opts.strip = strip;
+        self.pin_reproducible();
+        self
+    }
+
+    /// Convert the pixels of images with a color profile (or an sRGB-incompatible
+    /// gamma or chromaticities) to sRGB, so that they look the same once the
+    /// color chunks are stripped.  Profiles that cannot be converted are kept
+    /// (see `FileNotes::warning`).
+    pub fn with_profile_conversion(mut self, convert: bool) -> Self {
+        self.convert_profile = convert;
+        self
+    }
+
+    /// Make the output bytes depend only on the input bytes and the settings:
+    /// time-varying chunks are stripped whatever the strip setting is, and no
+    /// time limit is set on the optimization.  The rest of the pipeline is
+    /// already deterministic: the palette quantization uses no random numbers,
+    /// and oxipng breaks ties between trials in a fixed order, regardless of
+    /// the number of threads.
+    pub fn with_reproducible(mut self, reproducible: bool) -> Self {
+        self.reproducible = reproducible;
+        self.pin_reproducible();
+        self
+    }
+
+    fn pin_reproducible(&mut self) {
+        if !self.reproducible {
+            return;
+        }
+        self.timeout = None;
+        self.opts.timeout = None;
+        self.opts.strip = match std::mem::replace(&mut self.opts.strip, StripChunks::All) {
+            StripChunks::None => StripChunks::Strip(TIME_VARYING_CHUNKS.into_iter().collect()),
+            StripChunks::Strip(mut names) => {
+                names.extend(TIME_VARYING_CHUNKS);
+                StripChunks::Strip(names)
+            }
+            StripChunks::Keep(mut names) => {
+                names.retain(|name| !TIME_VARYING_CHUNKS.contains(name));
+                StripChunks::Keep(names)
+            }
+            // Neither keeps any of the time-varying chunks.
+            strip @ (StripChunks::Safe | StripChunks::All) => strip,
+        };
+    }
+
+    /// Optimize a PNG file, returning the sizes of the input and output data.
+    pub fn optimize(&self, input_path: &PathBuf, output_path: &PathBuf) -> Result<(u64, u64)> {
+        self.optimize_auto(input_path, output_path).map(|(sizes, _)| sizes)
+    }
+
+    /// Optimize a PNG file as `optimize()`, also returning what was decided
+    /// for it (see `FileNotes`).
+    pub fn optimize_auto(
+        &self, input_path: &PathBuf, output_path: &PathBuf,
+    ) -> Result<((u64, u64), FileNotes)> {
+        let mut opts = std::borrow::Cow::Borrowed(&self.opts);
+        // Metadata is read before the input, so its access time is preserved.
+        let metadata = if self.preserve {
+            Some(std::fs::metadata(input_path)
+                .map_err(|e| TinyImgRError::io(format!("Failed to read metadata of {}: {}", input_path.display(), e)))?)
+        } else {
+            None
+        };
+        let mut data = std::fs::read(input_path)
+            .map_err(|e| TinyImgRError::io(format!("Failed to read {}: {}", input_path.display(), e)))?;
+        let input_size = data.len() as u64;
+        let deadline = self.timeout.map(|t| std::time::Instant::now() + t);
+        let dither = self.dither.unwrap_or(self.speed.ditherer());
+        // A repaired file is always written, even if it is not smaller.
+        let mut repaired = false;
+        if self.fix_errors {
+            repaired = repair_crc(&mut data).is_ok_and(|n| n > 0);
+            let decode = self.threshold.is_some() || self.colors > 0 || self.auto.is_some() || self.color_type.is_some()
+                || self.convert_profile;
+            if decode && lodepng::decode32(&data).is_err() {
+                let clean = Options {
+                    fix_errors: true, strip: StripChunks::None, interlace: None, ..Options::from_preset(0)
+                };
+                data = oxipng::optimize_from_memory(&data, &clean).map_err(|e| optimize_error(input_path, e))?;
+                repaired = true;
+            }
+        }
+        let input_chunks = ancillary_chunks(&data);
+        // The interlace method is the last byte of IHDR.
+        let adam7 = |png: &[u8]| png.get(28) == Some(&1);
+        let input_adam7 = adam7(&data);
+        let saved = self.metadata_backup.as_ref().map(|_| ancillary_chunks_raw(&data));
+        let mut notes = FileNotes::default();
+        // Only the row filters and the compression of the preset depend on the
+        // level; the other options (e.g., stripping) are kept.
+        if let Some(levels) = &self.auto_levels {
+            let level = levels.get(input_size);
+            let preset = Options::from_preset(level);
+            let o = opts.to_mut();
+            o.fast_evaluation = preset.fast_evaluation;
+            if self.zopfli.is_none() {
+                o.deflate = preset.deflate;
+            }
+            o.filter = self.filters.clone().unwrap_or(preset.filter);
+            notes.level = Some(level);
+        }
+        // Pixels converted to sRGB replace the input data; the color chunks of
+        // profiles that cannot be converted are kept.
+        let (mut decoded, mut converted) = (None, false);
+        let chunks = if self.convert_profile { color_chunks(&data) } else { Vec::new() };
+        if !chunks.is_empty() {
+            match png_profile(&chunks) {
+                PngProfile::Srgb => {}
+                PngProfile::Rgb(profile) => {
+                    let (pixels, width, height) = decode_png_colors(&data, input_path)?;
+                    let conv = profile.to_srgb();
+                    let pixels = pixels.into_iter().map(|c| {
+                        let [r, g, b] = conv.convert([c.r, c.g, c.b]);
+                        Color { r, g, b, a: c.a }
+                    }).collect();
+                    (decoded, converted) = (Some((pixels, width, height)), true);
+                }
+                PngProfile::Unsupported(reason) => {
+                    notes.warning = Some(format!(
+                        "Kept the color profile of {} because it cannot be converted to sRGB: {}",
+                        input_path.display(), reason
+                    ));
+                    opts.to_mut().strip = keep_color_chunks(&self.opts.strip);
+                }
+            }
+        }
+        drop(chunks);
+        // PNGs re-encoded from the pixels (e.g., by the palette reduction) only
+        // have the chunks of their pixels, so the chunks of the input that are
+        // not stripped are carried over to them (except the chunks that
+        // describe the input encoding, and the color chunks of converted
+        // pixels).
+        let carried: Vec<SavedChunk> = if matches!(opts.strip, StripChunks::All) { Vec::new() } else {
+            ancillary_chunks_raw(&data).into_iter().filter(|c| {
+                strip_keeps(&opts.strip, &c.name) && !ENCODING_CHUNKS.contains(&c.name)
+                    && !(converted && COLOR_CHUNKS.contains(&c.name))
+            }).collect()
+        };
+        let optimize = |png: &[u8], opts: &Options| -> std::result::Result<Vec<u8>, oxipng::PngError> {
+            // oxipng gets the time left of the file, and only changes the
+            // interlacing if the file gets smaller.
+            let timed;
+            let opts = match deadline {
+                Some(deadline) => {
+                    timed = Options {
+                        timeout: Some(deadline.saturating_duration_since(std::time::Instant::now())), ..opts.clone()
+                    };
+                    &timed
+                }
+                None => opts,
+            };
+            let forced;
+            let opts = match opts.interlace {
+                Some(interlace) if (interlace == Interlacing::Adam7) != adam7(png) => {
+                    forced = Options { force: true, ..opts.clone() };
+                    &forced
+                }
+                _ => opts,
+            };
+            oxipng::optimize_from_memory(png, opts)
+        };
+        let carry = |png: Vec<u8>| -> Result<Vec<u8>> {
+            if carried.is_empty() { return Ok(png); }
+            restore_chunks(&png, &carried).map(|(png, _)| png).map_err(|e| TinyImgRError::encode(
+                format!("Failed to keep the metadata chunks of {}: {}", input_path.display(), e)
+            ))
+        };
+        // With automatic thresholds, the decoded pixels are classified first,
+        // and kept for the palette reduction if the threshold is positive.
+        let mut threshold = self.threshold;
+        if let Some(thresholds) = &self.auto {
+            let (pixels, width, height) = match decoded.take() {
+                Some(decoded) => decoded,
+                None => decode_png_colors(&data, input_path)?,
+            };
+            let class = classify_rgba(color_bytes(&pixels), width, height);
+            let lossy = thresholds.get(class);
+            threshold = LossyThreshold::new(lossy, self.channels);
+            notes.auto = Some(AutoLossy { class, lossy });
+            if threshold.is_some() || self.colors > 0 || converted || self.color_type.is_some() {
+                decoded = Some((pixels, width, height));
+            }
+        }
+        // A fixed palette size is not searched for, so the threshold (only
+        // used to measure the error of palettes) does not matter.
+        if self.colors > 0 && threshold.is_none() {
+            threshold = Some(LossyThreshold::DeltaE(0.0));
+        }
+        // The image is classified from the pixels decoded for it anyway (they
+        // are decoded here instead of in the branches below).
+        if self.classify.is_some() {
+            if notes.auto.is_none() && decoded.is_none() && (threshold.is_some() || self.color_type.is_some()) {
+                decoded = Some(decode_png_colors(&data, input_path)?);
+            }
+            notes.class = match (&notes.auto, &decoded) {
+                (Some(auto), _) => Some(auto.class),
+                (None, Some((pixels, width, height))) => Some(classify_rgba(color_bytes(pixels), *width, *height)),
+                (None, None) => None,
+            };
+        }
+        // Pixels are re-encoded without interlacing, so keeping the interlacing
+        // means restoring that of the input.
+        if opts.interlace.is_none() && (self.color_type.is_some() || threshold.is_some() || decoded.is_some()) {
+            opts.to_mut().interlace = Some(if input_adam7 { Interlacing::Adam7 } else { Interlacing::None });
+        }
+        let optimized = if let Some(color_type) = self.color_type {
+            // The pixels (reduced to a palette if lossy) are encoded in the
+            // color type, which oxipng must not change.
+            let (pixels, width, height) = match decoded {
+                Some(decoded) => decoded,
+                None => decode_png_colors(&data, input_path)?,
+            };
+            let pixels = match threshold {
+                Some(threshold) => {
+                    let (pixels, n) = quantize_colors(
+                        pixels, width, threshold, self.colors, dither, &self.lab, &self.speed, deadline,
+                    );
+                    notes.colors = Some(n);
+                    pixels
+                }
+                None => pixels,
+            };
+            let png = encode_color_type(&pixels, width, height, color_type).map_err(|e| TinyImgRError::encode(
+                format!("Cannot write {} as {}: {}", input_path.display(), color_type.name(), e)
+            ))?;
+            drop(pixels);
+            let png = carry(png)?;
+            let o = opts.to_mut();
+            (o.color_type_reduction, o.grayscale_reduction) = (false, false);
+            optimize(&png, &opts)
+        } else if let Some(threshold) = threshold.filter(|_| self.colors == 0 && is_indexed_with_trns(&data)) {
+            // Quantizing an indexed image with transparent palette entries
+            // (e.g., a GIF converted to PNG) as RGBA can blend transparent and
+            // opaque colors, and make the file larger than its compact input.
+            // Its opaque colors are merged within its own palette instead, and
+            // the result is written as an indexed image directly (unless the
+            // palette size is fixed).
+            let (mut pixels, width, height) = match decoded {
+                Some(decoded) => decoded,
+                None => decode_png_colors(&data, input_path)?,
+            };
+            merge_palette_colors(&mut pixels, threshold, &self.lab);
+            let (png, palette) = encode_palette_png(&pixels, width, height).map_err(TinyImgRError::encode)?;
+            notes.colors = Some(palette.len());
+            drop(pixels);
+            let png = carry(png)?;
+            optimize(&png, &opts).and_then(|lossy| {
+                // Never make the file larger (unless its pixels were converted).
+                if converted || lossy.len() < data.len() { Ok(lossy) } else { optimize(&data, &opts) }
+            })
+        } else if let Some(threshold) = threshold {
+            // The source and quantized pixels are gone once the intermediate
+            // PNG is encoded, and the intermediate PNG is dropped as soon as
+            // oxipng has consumed it.
+            let (pixels, width, height) = match decoded {
+                Some(decoded) => decoded,
+                None => decode_png_colors(&data, input_path)?,
+            };
+            let (lossy_data, n) = apply_lossy_colors(
+                pixels, width, height, threshold, self.colors, dither, &self.lab, &self.speed, deadline,
+            )?;
+            notes.colors = Some(n);
+            optimize(&carry(lossy_data)?, &opts)
+        } else if let Some((pixels, width, height)) = decoded {
+            let png = carry(encode_png_colors(&pixels, width, height).map_err(TinyImgRError::encode)?)?;
+            drop(pixels);
+            optimize(&png, &opts)
+        } else {
+            optimize(&data, &opts)
+        }.map_err(|e| optimize_error(input_path, e))?;
+        // Neither oxipng nor re-encoding the pixels guarantees a smaller file,
+        // so the source is kept if the result is larger (unless its pixels
+        // were converted, or its color type or interlacing changed, as the
+        // user asked for, or it was repaired or forced).
+        let interlaced = self.opts.interlace.is_some_and(|i| (i == Interlacing::Adam7) != input_adam7);
+        let grown = self.no_grow && !self.opts.force && !converted && !repaired && !interlaced && self.color_type.is_none()
+            && optimized.len() as u64 > input_size;
+        // The input data is kept in memory (it is far smaller than the decoded
+        // pixels), so the input file is not read again to be copied or compared.
+        let optimized: &[u8] = if grown { &data } else { &optimized };
+        let output_size = optimized.len() as u64;
+        notes.timed_out = deadline.is_some_and(|d| std::time::Instant::now() >= d);
+        // Without `force`, oxipng also returns the input data when it cannot
+        // be made smaller.
+        notes.copied = grown || (!repaired && optimized == data);
+        // Images optimized without decoding their pixels are only decoded to
+        // be classified when they are not made much smaller.
+        if let (Some(max_saving), None) = (self.classify, notes.class) {
+            if output_size as f64 > input_size as f64 * (1.0 - max_saving) {
+                notes.class = decode_png_colors(&data, input_path).ok()
+                    .map(|(pixels, width, height)| classify_rgba(color_bytes(&pixels), width, height));
+            }
+        }
+        // The report is made from the input data and the final bytes in memory.
+        if self.quality_samples > 0 {
+            let (original, ..) = decode_png_colors(&data, input_path)?;
+            let (final_pixels, ..) = decode_png_colors(optimized, output_path)?;
+            notes.quality = Some(QualityReport::new(&original, &final_pixels, &self.lab, self.quality_samples));
+        }
+        let output_chunks = ancillary_chunks(optimized);
+        notes.removed = input_chunks.into_iter().filter(|name| !output_chunks.contains(name))
+            .map(|name| String::from_utf8_lossy(&name).into_owned()).collect();
+        // The removed chunks are backed up with the hash of the output pixels.
+        let backup = match (&self.metadata_backup, saved) {
+            (Some(backup), Some(saved)) if !notes.removed.is_empty() => {
+                let saved: Vec<_> = saved.into_iter().filter(|c| !output_chunks.contains(&c.name)).collect();
+                Some((sidecar_path(output_path, backup), pixel_hash(optimized, output_path)?, saved))
+            }
+            _ => None,
+        };
+        // oxipng returns the original data if it cannot be optimized further;
+        // like oxipng::optimize(), leave such files untouched in place.
+        let changed = repaired || interlaced || (!grown && (threshold.is_some() || converted || self.color_type.is_some()));
+        let write = self.opts.force || !(!changed && input_path == output_path && output_size == input_size);
+        // The hash is of the data in the output file after this, without
+        // reading it back.
+        notes.sha256 = self.checksum.then(|| sha256_hex(if write { optimized } else { &data }));
+        if self.dry_run {
+            return Ok(((input_size, output_size), notes));
+        }
+        if write {
+            std::fs::write(output_path, optimized)
+                .map_err(|e| TinyImgRError::io(format!("Failed to write {}: {}", output_path.display(), e)))?;
+            if let Some(metadata) = &metadata {
+                copy_attrs(metadata, output_path)
+                    .map_err(|e| TinyImgRError::io(format!("Failed to preserve attributes of {}: {}", output_path.display(), e)))?;
+            }
+        }
+        if let Some((path, hash, saved)) = backup {
+            write_sidecar(&path, hash, &saved)?;
+        }
+        Ok(((input_size, output_size), notes))
+    }
+}
+
+/// The error of a failed oxipng optimization of a file, with the causes that
+/// users can act on spelled out.  Interlaced PNGs are supported at all levels,
+/// so only an invalid interlace method in the header fails.
+pub(crate) fn optimize_error(path: &Path, e: oxipng::PngError) -> TinyImgRError {
+    use oxipng::PngError;
+    let reason = match e {
+        PngError::C2PAMetadataPreventsChanges => "it contains C2PA content credentials (a caBX \
+            chunk), which any change to the file would invalidate; strip all metadata chunks to \
+            optimize it anyway".to_string(),
+        PngError::APNGOutOfOrder => "the frames of the animated PNG (APNG) are out of order \
+            (the sequence numbers of its fcTL/fdAT chunks are wrong), so the file is malformed".to_string(),
+        PngError::APNGNotSupported => "this animated PNG (APNG) is not supported".to_string(),
+        PngError::NotPNG => "not a PNG file (the PNG signature is missing)".to_string(),
+        PngError::TruncatedData | PngError::ChunkMissing(_) => {
+            format!("{}; the file is incomplete (e.g., an interrupted download or copy)", e)
+        }
+        PngError::TimedOut => "the optimization timed out".to_string(),
+        PngError::Other(ref msg) if &**msg == "Unexpected interlacing in header" => {
+            "invalid interlace method in the header (only 0 for none and 1 for Adam7 exist)".to_string()
+        }
+        e => e.to_string(),
+    };
+    TinyImgRError::encode(format!("Failed to optimize {}: {}", path.display(), reason))
+}
+
+/// The ancillary chunks kept by `StripChunks::Safe` (as listed by oxipng).
+const SAFE_CHUNKS: [[u8; 4]; 7] = [*b"cICP", *b"iCCP", *b"sRGB", *b"pHYs", *b"acTL", *b"fcTL", *b"fdAT"];
+
+/// Ancillary chunks that describe how the pixels of a file are encoded (its
+/// transparency, background, and histogram refer to its color type or
+/// palette, and the animation chunks to its frames), so they are never
+/// carried over to a PNG re-encoded from the pixels.
+const ENCODING_CHUNKS: [[u8; 4]; 7] = [*b"tRNS", *b"bKGD", *b"hIST", *b"sBIT", *b"acTL", *b"fcTL", *b"fdAT"];
+
+/// Whether oxipng keeps a chunk with a strip setting (like its private
+/// `StripChunks::keep()`).
+fn strip_keeps(strip: &StripChunks, name: &[u8; 4]) -> bool {
+    match strip {
+        StripChunks::None => true,
+        StripChunks::Keep(names) => names.contains(name),
+        StripChunks::Strip(names) => !names.contains(name),
+        StripChunks::Safe => SAFE_CHUNKS.contains(name),
+        StripChunks::All => false,
+    }
+}
+
+/// Parse the metadata chunks to strip: `all`, `safe` (keep the chunks that
+/// affect how the image is displayed), `none`, or the names of the chunks to
+/// keep (all other ancillary chunks are stripped).
+pub fn parse_strip(x: &[&str]) -> Result<StripChunks> {
+    match x {
+        ["all"] => return Ok(StripChunks::All),
+        ["safe"] => return Ok(StripChunks::Safe),
+        ["none"] => return Ok(StripChunks::None),
+        _ => {}
+    }
+    let mut names = oxipng::IndexSet::new();
+    for name in x {
+        let bytes: [u8; 4] = name.as_bytes().try_into().ok().filter(|b: &[u8; 4]| b.iter().all(u8::is_ascii_alphabetic))
+            .ok_or_else(|| TinyImgRError::argument(format!(
+                "Invalid chunk name '{}' to keep (expected \"all\", \"safe\", \"none\", or chunk names of four letters like \"iCCP\")", name
+            )))?;
+        names.insert(bytes);
+    }
+    Ok(StripChunks::Keep(names))
+}
+
+/// Parse the row filters for oxipng to try, given by names (case-insensitive,
+/// e.g., `Paeth` or `minsum`) or numbers (0 to 9); no filters means those of
+/// the preset level.
+pub fn parse_filters(x: &[&str]) -> Result<Option<IndexSet<RowFilter>>> {
+    if x.is_empty() {
+        return Ok(None);
+    }
+    let all: Vec<RowFilter> = (0..=RowFilter::LAST).filter_map(|i| RowFilter::try_from(i).ok()).collect();
+    x.iter().map(|name| {
+        let found = match name.parse::<u8>() {
+            Ok(i) => RowFilter::try_from(i).ok(),
+            Err(_) => all.iter().find(|f| f.to_string().eq_ignore_ascii_case(name)).copied(),
+        };
+        found.ok_or_else(|| TinyImgRError::argument(format!(
+            "Unknown row filter '{}' (expected one of {}, or 0 to {})", name,
+            all.iter().map(|f| format!("\"{}\"", f)).collect::<Vec<_>>().join(", "), RowFilter::LAST
+        )))
+    }).collect::<Result<IndexSet<_>>>().map(Some)
+}
+
+/// Parse the interlacing of outputs: `keep` (`None`), `remove`, or `adam7`.
+pub fn parse_interlace(x: &str) -> Result<Option<Interlacing>> {
+    match x {
+        "keep" => Ok(None),
+        "remove" => Ok(Some(Interlacing::None)),
+        "adam7" => Ok(Some(Interlacing::Adam7)),
+        _ => Err(TinyImgRError::argument(format!(
+            "Unknown interlacing '{}' (expected \"keep\", \"remove\", or \"adam7\")", x
+        ))),
+    }
+}
+
+/// A strip setting that also keeps the color chunks (see `COLOR_CHUNKS`).
+fn keep_color_chunks(strip: &StripChunks) -> StripChunks {
+    match strip {
+        StripChunks::None => StripChunks::None,
+        StripChunks::Strip(names) => {
+            StripChunks::Strip(names.iter().filter(|n| !COLOR_CHUNKS.contains(n)).copied().collect())
+        }
+        StripChunks::Keep(names) => StripChunks::Keep(names.iter().copied().chain(COLOR_CHUNKS).collect()),
+        // The chunks kept by `Safe` plus the color chunks.
+        StripChunks::Safe => StripChunks::Keep(SAFE_CHUNKS.into_iter().chain(COLOR_CHUNKS).collect()),
+        StripChunks::All => StripChunks::Keep(COLOR_CHUNKS.into_iter().collect()),
+    }
+}
+
+const PNG_SIGNATURE: [u8; 8] = [137, 80, 78, 71, 13, 10, 26, 10];
+
+/// Recompute the CRC of every chunk in a PNG file and fix the wrong ones in
+/// place.  Returns the number of corrected chunks.  Bytes after `IEND` are
+/// left untouched.
+pub(crate) fn repair_crc(png: &mut [u8]) -> std::result::Result<usize, String> {
+    if !png.starts_with(&PNG_SIGNATURE) {
+        return Err("not a PNG file".to_string());
+    }
+    let mut fixed = 0;
+    let mut pos = PNG_SIGNATURE.len();
+    while pos + 12 <= png.len() {
+        let len = u32::from_be_bytes(png[pos..pos + 4].try_into().unwrap()) as usize;
+        let end = pos + 12 + len;
+        if end > png.len() {
+            return Err(format!("truncated chunk at byte {}", pos));
+        }
+        // The CRC covers the chunk type and data, but not the length.
+        let crc = crc32fast::hash(&png[pos + 4..end - 4]).to_be_bytes();
+        if png[end - 4..end] != crc {
+            png[end - 4..end].copy_from_slice(&crc);
+            fixed += 1;
+        }
+        let iend = &png[pos + 4..pos + 8] == b"IEND";
+        pos = end;
+        if iend {
+            break;
+        }
+    }
+    Ok(fixed)
+}
+
+/// The distinct types of the ancillary chunks in a PNG file (those with a
+/// lowercase first letter), in the order they first appear.  Only the chunk
+/// headers are read, so this is cheap even for large files.
+fn ancillary_chunks(png: &[u8]) -> Vec<[u8; 4]> {
+    let mut names = Vec::new();
+    let mut pos = 8;  // after the signature
+    while pos + 12 <= png.len() {
+        let len = u32::from_be_bytes(png[pos..pos + 4].try_into().unwrap()) as usize;
+        let name: [u8; 4] = png[pos + 4..pos + 8].try_into().unwrap();
+        if name[0].is_ascii_lowercase() && !names.contains(&name) {
+            names.push(name);
+        }
+        pos = pos.saturating_add(12 + len);
+    }
+    names
+}
+
+/// What was decided for a file while optimizing it.
+#[derive(Default)]
+pub struct FileNotes {
+    /// The class and lossy threshold chosen with automatic thresholds.
+    pub auto: Option<AutoLossy>,
+    /// The optimization level chosen by the input size with automatic levels.
+    pub level: Option<u8>,
+    /// A problem that did not stop the optimization (e.g., a color profile
+    /// that could not be converted to sRGB).
+    pub warning: Option<String>,
+    /// The types of the ancillary chunks in the input that are not in the
+    /// output (e.g., metadata that was stripped).
+    pub removed: Vec<String>,
+    /// The distribution of the error of the output pixels (see
+    /// `PngSettings::with_quality_report()`).
+    pub quality: Option<QualityReport>,
+    /// Whether the output is a copy of the input data (which could not be
+    /// made smaller) instead of a fresh compression.
+    pub copied: bool,
+    /// Whether the time limit of the file was reached (see
+    /// `PngSettings::with_timeout()`), so the best result so far was kept.
+    pub timed_out: bool,
+    /// The palette size of lossy palette reduction (`None` if the file was
+    /// not reduced to a palette).
+    pub colors: Option<usize>,
+    /// The SHA-256 hash (in hex) of the output data (see
+    /// `PngSettings::with_checksum()`).
+    pub sha256: Option<String>,
+    /// The class of the image content (see `PngSettings::with_classify()`).
+    pub class: Option<ImageClass>,
+}
+
+/// The SHA-256 hash of bytes in lowercase hex.
+pub fn sha256_hex(data: &[u8]) -> String {
+    use sha2::{Digest, Sha256};
+    Sha256::digest(data).iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+/// The upper bounds of the bins of the Delta E histograms of quality reports.
+pub const QUALITY_BREAKS: [f64; 6] = [0.5, 1.0, 2.0, 4.0, 8.0, f64::INFINITY];
+
+/// The probabilities of the Delta E quantiles of quality reports.
+pub const QUALITY_PROBS: [f64; 4] = [0.5, 0.95, 0.99, 1.0];
+
+/// The distribution of the CIE76 Delta E between the pixels of an output and
+/// its input, on a sample of the pixels.
+#[derive(Clone, Debug)]
+pub struct QualityReport {
+    /// The number of sampled pixels.
+    pub samples: usize,
+    /// The numbers of pixels in the bins with upper bounds `QUALITY_BREAKS`
+    /// (each bin includes its upper bound and excludes the lower one).
+    pub counts: [usize; 6],
+    /// The Delta E quantiles at `QUALITY_PROBS` (the median, 95th and 99th
+    /// percentiles, and the maximum).
+    pub quantiles: [f64; 4],
+}
+
+impl QualityReport {
+    /// Compare two images of the same dimensions on about `max_samples`
+    /// evenly spaced pixels.
+    pub(crate) fn new(original: &[Color], output: &[Color], lab: &LabConverter, max_samples: usize) -> Self {
+        let idx = sample_indices(original.len().min(output.len()), max_samples);
+        let mut des: Vec<f64> = idx.iter().map(|&i| delta_e(lab.to_lab(original[i]), lab.to_lab(output[i]))).collect();
+        des.sort_by(|a, b| a.total_cmp(b));
+        let mut counts = [0; 6];
+        for &de in &des {
+            counts[QUALITY_BREAKS.iter().position(|&b| de <= b).unwrap_or(5)] += 1;
+        }
+        let quantiles = QUALITY_PROBS.map(|p| {
+            let k = ((des.len() as f64 * p).ceil() as usize).saturating_sub(1);
+            des.get(k).copied().unwrap_or(0.0)
+        });
+        Self { samples: des.len(), counts, quantiles }
+    }
+}
+
+/// Optimization levels chosen by the input file size for `level = "auto"`:
+/// `values[i]` for sizes from `breaks[i - 1]` (inclusive) to `breaks[i]`
+/// (exclusive) in bytes, so there is one more value than breaks.
+#[derive(Debug)]
+pub struct AutoLevels {
+    breaks: Vec<u64>,
+    values: Vec<u8>,
+}
+
+impl AutoLevels {
+    pub fn new(breaks: Vec<u64>, values: Vec<u8>) -> Result<Self> {
+        if values.len() != breaks.len() + 1 {
+            return Err(TinyImgRError::argument("There must be one more automatic level than size breaks"));
+        }
+        if breaks.windows(2).any(|w| w[0] >= w[1]) {
+            return Err(TinyImgRError::argument("The size breaks of automatic levels must be increasing"));
+        }
+        if values.iter().any(|&v| v > 6) {
+            return Err(TinyImgRError::argument("The automatic levels must be between 0 and 6"));
+        }
+        Ok(Self { breaks, values })
+    }
+
+    fn get(&self, size: u64) -> u8 {
+        self.values[self.breaks.iter().take_while(|&&b| size >= b).count()]
+    }
+}
+
+/// The color types that outputs can be forced to.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ColorType {
+    Rgba,
+    Rgb,
+    Palette,
+    Gray,
+    GrayAlpha,
+}
+
+impl ColorType {
+    pub const ALL: [ColorType; 5] = [Self::Rgba, Self::Rgb, Self::Palette, Self::Gray, Self::GrayAlpha];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Rgba => "rgba",
+            Self::Rgb => "rgb",
+            Self::Palette => "palette",
+            Self::Gray => "gray",
+            Self::GrayAlpha => "gray_alpha",
+        }
+    }
+
+    /// The color type of a name (see `name()`), or an error listing the names.
+    pub fn from_name(name: &str) -> Result<Self> {
+        Self::ALL.into_iter().find(|c| c.name() == name).ok_or_else(|| TinyImgRError::argument(format!(
+            "Unknown color type '{}' (expected one of {})", name, Self::ALL.map(|c| c.name()).join(", ")
+        )))
+    }
+
+    /// The color type code in the IHDR chunk.
+    pub fn code(self) -> u8 {
+        match self {
+            Self::Gray => 0,
+            Self::Rgb => 2,
+            Self::Palette => 3,
+            Self::GrayAlpha => 4,
+            Self::Rgba => 6,
+        }
+    }
+}
+
+/// Lossy thresholds chosen by the image class for `lossy = "auto"`.
+#[derive(Clone, Copy)]
+pub struct AutoThresholds {
+    pub graphic: f64,
+    pub mixed: f64,
+    pub photo: f64,
+}
+
+impl AutoThresholds {
+    fn get(&self, class: ImageClass) -> f64 {
+        match class {
+            ImageClass::Graphic => self.graphic,
+            ImageClass::Mixed => self.mixed,
+            ImageClass::Photo => self.photo,
+        }
+    }
+}
+
+/// The class of an image and the lossy threshold chosen for it.
+#[derive(Clone, Copy)]
+pub struct AutoLossy {
+    pub class: ImageClass,
+    pub lossy: f64,
+}
+
+/// The outcome of optimizing a file in a batch.
+pub struct FileResult {
+    pub input: PathBuf,
+    pub output: PathBuf,
+    /// The sizes of the input and output data, or the error.
+    pub sizes: Result<(u64, u64)>,
+    /// The class and lossy threshold chosen with automatic thresholds.
+    pub auto: Option<AutoLossy>,
+    /// See `FileNotes::level`.
+    pub level: Option<u8>,
+    /// See `FileNotes::warning`.
+    pub warning: Option<String>,
+    /// See `FileNotes::removed`.
+    pub removed: Vec<String>,
+    /// See `FileNotes::copied`.
+    pub copied: bool,
+    /// See `FileNotes::timed_out`.
+    pub timed_out: bool,
+    /// See `FileNotes::colors`.
+    pub colors: Option<usize>,
+    /// See `FileNotes::sha256`.
+    pub sha256: Option<String>,
+    /// See `FileNotes::class`.
+    pub class: Option<ImageClass>,
+}
+
+/// A batch of PNG files to optimize with the same settings.
+pub struct BatchOptions {
+    pub settings: PngSettings,
+    /// Pairs of input and output paths.
+    pub files: Vec<(PathBuf, PathBuf)>,
+    /// Continue with the remaining files after a file failed (otherwise stop
+    /// at the first failure).
+    pub keep_going: bool,
+    /// The number of files optimized at the same time (0 for the number of
+    /// available CPUs, and 1 to optimize them one by one).
+    pub threads: usize,
+}
+
+/// Optimize a batch of PNG files, calling `on_result` after each file (e.g.,
+/// to report progress) in the order of the files, and always from the calling
+/// thread.  An error returned by `on_result` is recorded as the error of the
+/// file.  Returns the results of the files processed.
+///
+/// With more than one thread, the files are optimized by worker threads that
+/// take the next file as soon as they are done with one.  After a failure
+/// (unless `keep_going`), no more files are started, and the files that were
+/// in progress are finished and reported, so every output is either written
+/// completely or not touched.
+pub fn optimize_batch<F>(options: &BatchOptions, on_result: F) -> Vec<FileResult>
+where
+    F: FnMut(&FileResult) -> Result<()>,
+{
+    let threads = match options.threads {
+        0 => crate::cpus::detect_cpus(),
+        n => n,
+    };
+    if threads.min(options.files.len()) > 1 {
+        optimize_parallel(options, threads, on_result)
+    } else {
+        optimize_sequential(options, on_result)
+    }
+}
+
+fn optimize_file(settings: &PngSettings, input: &Path, output: &Path) -> FileResult {
+    let (input, output) = (input.to_path_buf(), output.to_path_buf());
+    // Inputs are only checked up front if a failure stops the batch.
+    if !input.exists() {
+        let e = TinyImgRError::io(format!("Input file does not exist: {}", input.display()));
+        return FileResult { input, output, sizes: Err(e), auto: None, level: None, warning: None, removed: Vec::new(),
+            copied: false, timed_out: false, colors: None, sha256: None, class: None,
+        };
+    }
+    let (sizes, notes) = match settings.optimize_auto(&input, &output) {
+        Ok((sizes, notes)) => (Ok(sizes), notes),
+        Err(e) => (Err(e), FileNotes::default()),
+    };
+    FileResult {
+        input, output, sizes, auto: notes.auto, level: notes.level, warning: notes.warning, removed: notes.removed,
+        copied: notes.copied, timed_out: notes.timed_out, colors: notes.colors, sha256: notes.sha256,
+        class: notes.class,
+    }
+}
+
+fn optimize_sequential<F>(options: &BatchOptions, mut on_result: F) -> Vec<FileResult>
+where
+    F: FnMut(&FileResult) -> Result<()>,
+{
+    let mut results = Vec::with_capacity(options.files.len());
+    for (input, output) in &options.files {
+        let mut res = optimize_file(&options.settings, input, output);
+        if let Err(e) = on_result(&res) {
+            res.sizes = Err(e);
+        }
+        let failed = res.sizes.is_err();
+        results.push(res);
+        if failed && !options.keep_going {
+            break;
+        }
+    }
+    results
+}
+
+fn optimize_parallel<F>(options: &BatchOptions, threads: usize, mut on_result: F) -> Vec<FileResult>
+where
+    F: FnMut(&FileResult) -> Result<()>,
+{
+    use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+    let (next, stop) = (AtomicUsize::new(0), AtomicBool::new(false));
+    let (tx, rx) = std::sync::mpsc::channel::<(usize, FileResult)>();
+    std::thread::scope(|s| {
+        for _ in 0..threads.min(options.files.len()) {
+            let (tx, next, stop) = (tx.clone(), &next, &stop);
+            s.spawn(move || {
+                while !stop.load(Ordering::Relaxed) {
+                    let i = next.fetch_add(1, Ordering::Relaxed);
+                    let Some((input, output)) = options.files.get(i) else { break };
+                    if tx.send((i, optimize_file(&options.settings, input, output))).is_err() {
+                        break;
+                    }
+                }
+            });
+        }
+        drop(tx);
+        // Results arrive in the order the files finish; they are held back
+        // until all files before them have been reported.
+        let mut results = Vec::with_capacity(options.files.len());
+        let mut pending = BTreeMap::new();
+        for (i, res) in rx {
+            pending.insert(i, res);
+            while let Some(mut res) = pending.remove(&results.len()) {
+                if let Err(e) = on_result(&res) {
+                    res.sizes = Err(e);
+                }
+                if res.sizes.is_err() && !options.keep_going {
+                    stop.store(true, Ordering::Relaxed);
+                }
+                results.push(res);
+            }
+        }
+        results
+    })
+}
+
+/// Choose `k` of the indices `0..n` uniformly at random (all of them if `k >=
+/// n`), in increasing order.  The choice only depends on `seed` (it uses a
+/// SplitMix64 generator and a partial Fisher-Yates shuffle), so the same
+/// subset of a batch is chosen in every run.
+pub fn random_sample(n: usize, k: usize, seed: u64) -> Vec<usize> {
+    let mut state = seed;
+    let mut next = || {
+        state = state.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        let mut z = state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        z ^ (z >> 31)
+    };
+    let mut idx: Vec<usize> = (0..n).collect();
+    let k = k.min(n);
+    for i in 0..k {
+        // The modulo bias is negligible for the sizes of batches.
+        let j = i + (next() % (n - i) as u64) as usize;
+        idx.swap(i, j);
+    }
+    idx.truncate(k);
+    idx.sort_unstable();
+    idx
+}
+
+/// The sizes of a batch estimated from a random sample of its files: the
+/// output size of the batch is extrapolated from the ratio of the output and
+/// input sizes of the sample.
+#[derive(Clone, Debug, PartialEq)]
+pub struct SampleEstimate {
+    /// The number of files in the batch.
+    pub files: usize,
+    /// The number of sampled files (that were processed successfully).
+    pub sampled: usize,
+    /// The total input size of the batch.
+    pub input_size: u64,
+    /// The total input and output sizes of the sampled files.
+    pub sample_input_size: u64,
+    pub sample_output_size: u64,
+}
+
+impl SampleEstimate {
+    /// The estimated output size of the batch.
+    pub fn output_size(&self) -> u64 {
+        if self.sample_input_size == 0 { return self.input_size; }
+        (self.input_size as f64 * self.sample_output_size as f64 / self.sample_input_size as f64).round() as u64
+    }
+
+    /// A one-line summary of the estimate.
+    pub fn summary(&self) -> String {
+        let (input_size, output_size) = (self.input_size, self.output_size());
+        let change = if input_size == 0 { 0.0 } else {
+            (output_size as f64 - input_size as f64) / input_size as f64 * 100.0
+        };
+        format!(
+            "Estimated for all {} files (from {} sampled): {} -> {} ({:+.1}%, saving about {})",
+            self.files, self.sampled, format_bytes(input_size), format_bytes(output_size), change,
+            format_bytes(input_size.saturating_sub(output_size))
+        )
+    }
+}
+
+/// Find the PNG files (by the extension `.png` or `.apng`, ignoring case) in a
+/// directory, sorted by their paths.
+pub fn find_png_files(dir: &Path, recursive: bool) -> std::io::Result<Vec<PathBuf>> {
+    let mut files = Vec::new();
+    let mut entries = std::fs::read_dir(dir)?
+        .map(|e| e.map(|e| e.path()))
+        .collect::<std::io::Result<Vec<_>>>()?;
+    entries.sort();
+    for path in entries {
+        if path.is_dir() {
+            if recursive {
+                files.extend(find_png_files(&path, recursive)?);
+            }
+        } else if path.extension().and_then(|e| e.to_str())
+            .is_some_and(|e| e.eq_ignore_ascii_case("png") || e.eq_ignore_ascii_case("apng"))
+        {
+            files.push(path);
+        }
+    }
+    Ok(files)
+}
+
+/// Size-change summary of a processed file.  Messages are created wherever a
+/// file is processed (possibly a worker thread) and sent to the main thread,
+/// which is the only thread allowed to print to the R console.
+pub struct VerboseMessage {
+    input: String,
+    output: String,
+    input_size: u64,
+    output_size: u64,
+    auto: Option<AutoLossy>,
+    level: Option<u8>,
+    removed: Vec<String>,
+    timed_out: bool,
+    colors: Option<usize>,
+}
+
+impl VerboseMessage {
+    pub fn new(input: &str, output: &str, (input_size, output_size): (u64, u64)) -> Self {
+        Self {
+            input: input.to_string(), output: output.to_string(), input_size, output_size,
+            auto: None, level: None, removed: Vec::new(), timed_out: false, colors: None,
+        }
+    }
+
+    /// Also show the class and lossy threshold chosen for the file.
+    pub fn with_auto(mut self, auto: Option<AutoLossy>) -> Self {
+        self.auto = auto;
+        self
+    }
+
+    /// Also show the optimization level chosen for the file.
+    pub fn with_level(mut self, level: Option<u8>) -> Self {
+        self.level = level;
+        self
+    }
+
+    /// Also report the palette size of lossy palette reduction.
+    pub fn with_colors(mut self, colors: Option<usize>) -> Self {
+        self.colors = colors;
+        self
+    }
+
+    /// Also note that the time limit of the file was reached.
+    pub fn with_timed_out(mut self, timed_out: bool) -> Self {
+        self.timed_out = timed_out;
+        self
+    }
+
+    /// Also list the chunk types removed from the file (on a second line).
+    pub fn with_removed(mut self, removed: &[String]) -> Self {
+        self.removed = removed.to_vec();
+        self
+    }
+
+    /// The line listing the removed chunk types, if any were set.
+    pub fn removed_line(&self) -> Option<String> {
+        (!self.removed.is_empty()).then(|| format!("  removed chunks: {}", self.removed.join(", ")))
+    }
+
+    /// A one-line size-change summary, with the first characters of the paths
+    /// truncated (see `find_truncate_index()`).
+    pub fn summary(&self, input_truncate_index: usize, output_truncate_index: usize) -> Option<String> {
+        let (input_size, output_size) = (self.input_size, self.output_size);
+        if input_size == 0 { return None; }  // 0-byte input: nothing to report
+        let reduction =
+            ((input_size as f64 - output_size as f64) / input_size as f64) * 100.0;
+        let sign = match output_size.cmp(&input_size) {
+            std::cmp::Ordering::Less => "-",
+            std::cmp::Ordering::Equal => "",
+            std::cmp::Ordering::Greater => "+",
+        };
+        let display_input  = truncate_path(&self.input,  input_truncate_index);
+        let display_output = truncate_path(&self.output, output_truncate_index);
+        let path_display = if self.input == self.output {
+            display_output
+        } else {
+            format!("{} -> {}", display_input, display_output)
+        };
+        let mut auto = self.auto.map_or(String::new(), |a| format!(" | {}, lossy = {}", a.class.name(), a.lossy));
+        if let Some(level) = self.level {
+            auto.push_str(&format!(" | level = {}", level));
+        }
+        if let Some(colors) = self.colors {
+            auto.push_str(&format!(" | colors = {}", colors));
+        }
+        if self.timed_out {
+            auto.push_str(" | timed out");
+        }
+        Some(format!(
+            "{} | {} -> {} ({}{:.1}%){}",
+            path_display,
+            format_bytes(input_size),
+            format_bytes(output_size),
+            sign,
+            reduction.abs(),
+            auto
+        ))
+    }
+}
+
+/// The total sizes of the files of a batch under a directory.
+#[derive(Clone, Debug, PartialEq)]
+pub struct DirGroup {
+    pub dir: String,
+    pub files: usize,
+    pub input_size: u64,
+    pub output_size: u64,
+}
+
+/// Group the sizes `(path, input_size, output_size)` of the files of a batch
+/// by their first `depth` directories after the common prefix of the paths
+/// (`truncate_index`, see `find_truncate_index()`).  Files directly under the
+/// prefix, and all files if there is no common prefix, are in the group `.`.
+/// Groups are sorted by their names.
+pub fn group_sizes_by_dir(sizes: &[(String, u64, u64)], truncate_index: usize, depth: usize) -> Vec<DirGroup> {
+    let mut groups: BTreeMap<String, DirGroup> = BTreeMap::new();
+    for (path, input_size, output_size) in sizes {
+        let rel = if truncate_index == 0 { "" } else { path.get(truncate_index..).unwrap_or("") };
+        let dirs: Vec<&str> = rel.split(['/', '\\']).collect();
+        let dirs = &dirs[..dirs.len() - 1];
+        let dir = if dirs.is_empty() { ".".to_string() } else { dirs[..depth.min(dirs.len())].join("/") };
+        let group = groups.entry(dir.clone()).or_insert(DirGroup { dir, files: 0, input_size: 0, output_size: 0 });
+        group.files += 1;
+        group.input_size += input_size;
+        group.output_size += output_size;
+    }
+    groups.into_values().collect()
+}
+
+/// A table of the sizes of directory groups (with a total row if there is
+/// more than one group), one line per row.
+pub fn format_dir_groups(groups: &[DirGroup]) -> Vec<String> {
+    let total = DirGroup {
+        dir: "(total)".to_string(),
+        files: groups.iter().map(|g| g.files).sum(),
+        input_size: groups.iter().map(|g| g.input_size).sum(),
+        output_size: groups.iter().map(|g| g.output_size).sum(),
+    };
+    let rows: Vec<&DirGroup> = groups.iter().chain((groups.len() > 1).then_some(&total)).collect();
+    let cells: Vec<[String; 5]> = rows.iter().map(|g| {
+        let change = if g.input_size == 0 { 0.0 } else {
+            (g.output_size as f64 - g.input_size as f64) / g.input_size as f64 * 100.0
+        };
+        [
+            g.dir.clone(), g.files.to_string(), format_bytes(g.input_size),
+            format_bytes(g.output_size), format!("{:+.1}%", change),
+        ]
+    }).collect();
+    let header = ["directory", "files", "before", "after", "change"].map(String::from);
+    let width = |j: usize| cells.iter().chain([&header]).map(|c| c[j].chars().count()).max().unwrap_or(0);
+    let widths: Vec<usize> = (0..5).map(width).collect();
+    std::iter::once(&header).chain(&cells).map(|c| {
+        let mut line = format!("{:<w$}", c[0], w = widths[0]);
+        for j in 1..5 {
+            line.push_str(&format!("  {:>w$}", c[j], w = widths[j]));
+        }
+        line
+    }).collect()
+}
+
+/// Find the index position to truncate paths
+/// Returns the position after the last common '/' or '\', or 0 if no truncation needed
+///
+/// The index is a byte offset.  Separators are ASCII, so the byte after one is
+/// always a character boundary, even in paths with multibyte characters (e.g.,
+/// `fig/日本語/plot.png`).
+pub fn find_truncate_index(paths: &[String]) -> usize {
+    if paths.is_empty() {
+        return 0;
+    }
+
+    let is_sep = |b: &u8| *b == b'/' || *b == b'\\';
+
+    if paths.len() == 1 {
+        // For single path, find the last '/' or '\'
+        return paths[0].as_bytes().iter().rposition(is_sep).map_or(0, |pos| pos + 1);
+    }
+
+    // The largest common prefix (of bytes) ending at a separator
+    let first_path = paths[0].as_bytes();
+    let mut truncate_idx = 0;
+
+    for (pos, b) in first_path.iter().enumerate() {
+        if !paths.iter().all(|p| p.as_bytes().get(pos) == Some(b)) {
+            // Found a mismatch, return the last valid truncate index
+            break;
+        }
+        if is_sep(b) {
+            truncate_idx = pos + 1;
+        }
+    }
+
+    truncate_idx
+}
+
+/// Truncate a path by removing the first `index` bytes (the path is kept
+/// whole if the index is not at a character boundary)
+pub fn truncate_path(path: &str, index: usize) -> String {
+    if index == 0 || index >= path.len() {
+        return path.to_string();
+    }
+    path.get(index..).unwrap_or(path).to_string()
+}
+
+/// Format bytes in human-readable form (similar to xfun::format_bytes)
+pub(crate) fn format_bytes(bytes: u64) -> String {
+    let units = ["B", "KB", "MB", "GB", "TB", "PB"];
+    if bytes == 0 { return "0 B".to_string(); }
+
+    let i = (bytes as f64).log(1024.0).floor() as usize;
+    let p = 1024_f64.powi(i as i32);
+    let s = (bytes as f64) / p;
+
+    format!("{:.1} {}", s, units[i])
+}
+
+/// Encode exoquant colors as a PNG with lodepng.
+pub(crate) fn encode_png_colors(pixels: &[Color], width: usize, height: usize) -> std::result::Result<Vec<u8>, String> {
+    lodepng::encode32(color_bytes(pixels), width, height).map_err(|e| format!("Failed to encode PNG data: {}", e))
+}
+
+/// Quantize decoded pixels and encode the result with lodepng, also returning
+/// the palette size.  lodepng picks an indexed color type for at most 256
+/// colors, so the intermediate PNG handed to oxipng is far smaller than the
+/// RGBA pixels (passing the pixels to oxipng as a raw image would keep them
+/// alive during the whole optimization).
+#[allow(clippy::too_many_arguments)]
+pub(crate) fn apply_lossy_colors(
+    pixels: Vec<Color>, width: usize, height: usize, threshold: LossyThreshold, colors: usize, dither: Dither,
+    lab: &LabConverter, speed: &LossySpeed, deadline: Option<std::time::Instant>,
+) -> Result<(Vec<u8>, usize)> {
+    let (quantized, n) = quantize_colors(pixels, width, threshold, colors, dither, lab, speed, deadline);
+    let png = lodepng::encode32(color_bytes(&quantized), width, height)
+        .map_err(|e| TinyImgRError::encode(format!("Failed to encode quantized PNG data: {}", e)))?;
+    Ok((png, n))
+}
+
+/// The quantization stage of lossy palette reduction, without encoding the
+/// pixels or optimizing them with oxipng: the quantized pixels, the palette
+/// size passed to the quantizer, and the error of the pixels (as measured
+/// by `palette_p95_error()`).
+#[cfg(feature = "r")]
+pub(crate) struct Quantized {
+    pub pixels: Vec<Color>,
+    pub n_colors: usize,
+    pub metric: [f64; 3],
+}
+
+/// Quantize decoded pixels to `colors` colors, or (with `colors == 0`) to the
+/// smallest palette size within `threshold` as in `quantize_colors()`, with
+/// or without dithering, and measure the error of the result (on the samples
+/// of the lossy speed).
+#[cfg(feature = "r")]
+pub(crate) fn quantize_stage(
+    pixels: &[Color], width: usize, threshold: LossyThreshold, colors: usize, dither: Dither, lab: &LabConverter,
+    speed: &LossySpeed,
+) -> Quantized {
+    let samples = LabSamples::with_max(pixels, lab, speed.samples);
+    let palettes = speed.palettes(pixels);
+    let n_colors = if colors > 0 {
+        colors
+    } else {
+        palette_size(pixels, &palettes, &samples, threshold, lab, speed, None)
+    };
+    let quantized = speed.quantize(pixels, width, &palettes, n_colors, dither);
+    let metric = palette_p95_error(&samples, &quantized, threshold, lab, &mut HashMap::new());
+    Quantized { pixels: quantized, n_colors, metric }
+}
+
+/// Encode pixels with at most 256 colors as an 8-bit indexed PNG (without
+/// lodepng's automatic choice of the color type, so the PLTE chunk always
+/// lists the palette), returning the PNG and the palette in PLTE order (the
+/// order in which the colors first appear).
+pub(crate) fn encode_palette_png(
+    pixels: &[Color], width: usize, height: usize,
+) -> std::result::Result<(Vec<u8>, Vec<Color>), String> {
+    let mut palette: Vec<Color> = Vec::new();
+    let mut index: HashMap<u32, u8> = HashMap::new();
+    let mut indices = Vec::with_capacity(pixels.len());
+    for &c in pixels {
+        let i = match index.get(&color_key(c)) {
+            Some(&i) => i,
+            None if palette.len() < 256 => {
+                index.insert(color_key(c), palette.len() as u8);
+                palette.push(c);
+                (palette.len() - 1) as u8
+            }
+            None => return Err("The image has more than 256 colors".to_string()),
+        };
+        indices.push(i);
+    }
+    let mut mode = lodepng::ColorMode::new();
+    mode.colortype = lodepng::ColorType::PALETTE;
+    mode.set_bitdepth(8);
+    for c in &palette {
+        mode.palette_add(lodepng::RGBA::new(c.r, c.g, c.b, c.a))
+            .map_err(|e| format!("Failed to add palette colors: {}", e))?;
+    }
+    let mut encoder = lodepng::Encoder::new();
+    encoder.set_auto_convert(false);
+    *encoder.info_raw_mut() = mode.clone();
+    encoder.info_png_mut().color = mode;
+    let png = encoder.encode(&indices, width, height)
+        .map_err(|e| format!("Failed to encode PNG data: {}", e))?;
+    Ok((png, palette))
+}
+
+/// Encode pixels as an 8-bit PNG of a color type (bypassing lodepng's
+/// automatic choice), or explain why the pixels cannot be represented in it
+/// without loss: translucent pixels need an alpha channel, colored pixels
+/// cannot be gray, and a palette holds at most 256 colors.
+pub(crate) fn encode_color_type(
+    pixels: &[Color], width: usize, height: usize, color_type: ColorType,
+) -> std::result::Result<Vec<u8>, String> {
+    let gray = matches!(color_type, ColorType::Gray | ColorType::GrayAlpha);
+    let alpha = matches!(color_type, ColorType::GrayAlpha | ColorType::Rgba | ColorType::Palette);
+    if gray && pixels.iter().any(|c| c.r != c.g || c.g != c.b) {
+        return Err("the image has colored (non-gray) pixels".to_string());
+    }
+    if !alpha && pixels.iter().any(|c| c.a != 255) {
+        return Err("the image has transparent or translucent pixels".to_string());
+    }
+    if color_type == ColorType::Palette {
+        if count_unique_colors(pixels) > 256 {
+            return Err("the image has more than 256 colors (use a positive `lossy` threshold to reduce \
+                them)".to_string());
+        }
+        return encode_palette_png(pixels, width, height).map(|(png, _)| png);
+    }
+    let (mode, bytes): (lodepng::ColorType, Vec<u8>) = match color_type {
+        ColorType::Gray => (lodepng::ColorType::GREY, pixels.iter().map(|c| c.r).collect()),
+        ColorType::GrayAlpha => (lodepng::ColorType::GREY_ALPHA, pixels.iter().flat_map(|c| [c.r, c.a]).collect()),
+        ColorType::Rgb => (lodepng::ColorType::RGB, pixels.iter().flat_map(|c| [c.r, c.g, c.b]).collect()),
+        _ => (lodepng::ColorType::RGBA, color_bytes(pixels).to_vec()),
+    };
+    let mut encoder = lodepng::Encoder::new();
+    encoder.set_auto_convert(false);
+    encoder.info_raw_mut().colortype = mode;
+    encoder.info_png_mut().color.colortype = mode;
+    encoder.encode(&bytes, width, height).map_err(|e| format!("Failed to encode PNG data: {}", e))
+}
+
+/// Perceptual error thresholds for lossy palette reduction.
+#[derive(Clone, Copy)]
+pub(crate) enum LossyThreshold {
+    /// Maximum CIE76 Delta E.
+    DeltaE(f64),
+    /// Independent maxima of |ΔL*|, |Δa*|, and |Δb*|; channels with a
+    /// non-positive threshold are unconstrained.
+    PerChannel([f64; 3]),
+}
+
+impl LossyThreshold {
+    /// Build the threshold from the global `lossy` value and per-channel
+    /// overrides (`<= 0` to use `lossy`).  Returns `None` when lossy
+    /// reduction is disabled.
+    pub(crate) fn new(lossy: f64, channels: [f64; 3]) -> Option<Self> {
+        if channels.iter().any(|&t| t > 0.0) {
+            Some(Self::PerChannel(channels.map(|t| if t > 0.0 { t } else { lossy })))
+        } else if lossy > 0.0 {
+            Some(Self::DeltaE(lossy))
+        } else {
+            None
+        }
+    }
+
+    /// Error components between two Lab colors: the Delta E (in the first
+    /// component only) or the absolute per-channel differences.
+    fn errors(&self, a: [f64; 3], b: [f64; 3]) -> [f64; 3] {
+        match self {
+            Self::DeltaE(_) => [delta_e(a, b), 0.0, 0.0],
+            Self::PerChannel(_) => [(a[0] - b[0]).abs(), (a[1] - b[1]).abs(), (a[2] - b[2]).abs()],
+        }
+    }
+
+    /// Whether all error components are within their thresholds.
+    fn accepts(&self, errors: [f64; 3]) -> bool {
+        match self {
+            Self::DeltaE(t) => errors[0] <= *t,
+            Self::PerChannel(t) => t.iter().zip(errors).all(|(&t, e)| t <= 0.0 || e <= t),
+        }
+    }
+}
+
+/// The internal settings of lossy palette reduction bundled by a speed from 1
+/// (slowest) to 10 (fastest), like the `--speed` of pngquant.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct LossySpeed {
+    /// The maximum number of pixels counted in the histogram that palettes
+    /// are generated from (`usize::MAX` for all pixels).
+    pub palette_samples: usize,
+    /// The maximum number of pixels sampled to measure the error of a
+    /// palette (`usize::MAX` for all pixels).
+    pub samples: usize,
+    /// The bisection of the palette size stops once the smallest accepted
+    /// size is known within this many colors, and takes the upper bound.
+    pub tolerance: usize,
+    /// The K-means iterations of the palette optimizer (0 for none).
+    pub kmeans_iterations: usize,
+    /// Whether the final palette is dithered.
+    pub dither: bool,
+}
+
+/// The settings of the speeds 1 to 10 (in this order).  Speed 4 is the
+/// default, and the settings before speeds were introduced.
+pub const LOSSY_SPEEDS: [LossySpeed; 10] = {
+    const ALL: usize = usize::MAX;
+    const fn speed(
+        palette_samples: usize, samples: usize, tolerance: usize, kmeans_iterations: usize, dither: bool,
+    ) -> LossySpeed {
+        LossySpeed { palette_samples, samples, tolerance, kmeans_iterations, dither }
+    }
+    [
+        speed(ALL, ALL, 0, 16, true),
+        speed(ALL, 200_000, 0, 12, true),
+        speed(ALL, 100_000, 0, 10, true),
+        speed(ALL, 50_000, 0, 8, true),
+        speed(1_000_000, 40_000, 1, 6, true),
+        speed(500_000, 30_000, 2, 4, true),
+        speed(250_000, 20_000, 2, 3, true),
+        speed(100_000, 10_000, 4, 2, true),
+        speed(50_000, 5_000, 6, 1, true),
+        speed(20_000, 2_000, 8, 0, false),
+    ]
+};
+
+/// The default speed of lossy palette reduction.
+pub const DEFAULT_LOSSY_SPEED: u8 = 4;
+
+impl LossySpeed {
+    /// The settings of a speed from 1 to 10.
+    pub fn new(speed: i32) -> Result<Self> {
+        usize::try_from(speed).ok().filter(|s| (1..=10).contains(s)).map(|s| LOSSY_SPEEDS[s - 1]).ok_or_else(|| {
+            TinyImgRError::argument(format!("The lossy speed must be an integer from 1 to 10 (got {})", speed))
+        })
+    }
+
+    /// The histogram of (a sample of) `pixels` to generate palettes from.
+    pub(crate) fn palettes(&self, pixels: &[Color]) -> Palettes {
+        if pixels.len() <= self.palette_samples {
+            return Palettes::new(pixels);
+        }
+        let sampled: Vec<Color> = sample_indices(pixels.len(), self.palette_samples).iter().map(|&i| pixels[i]).collect();
+        Palettes::new(&sampled)
+    }
+
+    /// Quantize `pixels` (rows of `width` pixels) to the palette of `n` colors
+    /// from `palettes`, with or without dithering.  At the default speed, this
+    /// is the same as `DITHERED` or `NEAREST` with ordered or no dithering.
+    pub(crate) fn quantize(
+        &self, pixels: &[Color], width: usize, palettes: &Palettes, n: usize, dither: Dither,
+    ) -> Vec<Color> {
+        remap(pixels, width, &palettes.generate(n.clamp(1, 256), self.kmeans_iterations), dither)
+    }
+
+    /// The dithering of the speed: ordered dithering or none.
+    pub fn ditherer(&self) -> Dither {
+        if self.dither { Dither::Ordered } else { Dither::None }
+    }
+}
+
+/// The dithering algorithms of pixels reduced to a palette.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Dither {
+    /// Nearest-color mapping, best for flat fills (e.g., screenshots).
+    None,
+    /// Ordered (Bayer matrix) dithering, which can show cross-hatch patterns
+    /// on smooth gradients.
+    Ordered,
+    /// Floyd-Steinberg error diffusion, which looks better on photos.
+    FloydSteinberg,
+}
+
+impl Dither {
+    pub const ALL: [Dither; 3] = [Self::None, Self::Ordered, Self::FloydSteinberg];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::None => "none",
+            Self::Ordered => "ordered",
+            Self::FloydSteinberg => "floyd-steinberg",
+        }
+    }
+
+    /// The dithering of a name (see `name()`), or an error listing the names.
+    pub fn from_name(name: &str) -> Result<Self> {
+        Self::ALL.into_iter().find(|d| d.name() == name).ok_or_else(|| TinyImgRError::argument(format!(
+            "Unknown dithering '{}' (expected one of {})", name, Self::ALL.map(|d| d.name()).join(", ")
+        )))
+    }
+}
+
+impl Default for LossySpeed {
+    fn default() -> Self {
+        LOSSY_SPEEDS[DEFAULT_LOSSY_SPEED as usize - 1]
+    }
+}
+
+/// Decode a PNG file into exoquant colors and its dimensions.
+#[cfg(feature = "r")]
+pub(crate) fn read_png_colors(input: &PathBuf) -> Result<(Vec<Color>, usize, usize)> {
+    let image = lodepng::decode32_file(input)
+        .map_err(|e| TinyImgRError::decode(format!("Failed to read PNG {}: {}", input.display(), e)))?;
+    Ok((colors_from_rgba(image.buffer), image.width, image.height))
+}
+
+/// Decode PNG data (read from `input`) into exoquant colors and dimensions.
+pub(crate) fn decode_png_colors(png: &[u8], input: &Path) -> Result<(Vec<Color>, usize, usize)> {
+    let image = lodepng::decode32(png)
+        .map_err(|e| TinyImgRError::decode(format!("Failed to read PNG {}: {}", input.display(), e)))?;
+    Ok((colors_from_rgba(image.buffer), image.width, image.height))
+}
+
+/// Sampled source pixels for perceptual error evaluation: their indices,
+/// L*a*b* values, and RGBA keys.  They depend only on the source image, so
+/// they can be shared by several palette reductions of the same image.
+pub(crate) struct LabSamples {
+    idx: Vec<usize>,
+    lab: Vec<[f64; 3]>,
+    keys: Vec<u32>,
+}
+
+impl LabSamples {
+    #[cfg(feature = "r")]
+    pub(crate) fn new(pixels: &[Color], lab: &LabConverter) -> Self {
+        Self::with_max(pixels, lab, LossySpeed::default().samples)
+    }
+
+    /// Sample at most `max_samples` pixels for perceptual error evaluation.
+    pub(crate) fn with_max(pixels: &[Color], lab: &LabConverter, max_samples: usize) -> Self {
+        let idx = sample_indices(pixels.len(), max_samples);
+        let lab = idx.iter().map(|&i| lab.perceived(pixels[i])).collect();
+        // Pre-compute RGBA keys for sampled pixels once; reused in every bisection step.
+        let keys = idx.iter().map(|&i| color_key(pixels[i])).collect();
+        Self { idx, lab, keys }
+    }
+}
+
+/// Reduce the palette of a PNG file to the smallest size whose perceptual
+/// error stays within `threshold` (measured in L*a*b* by `lab`), returning the
+/// quantized pixels and dimensions.
+#[cfg(feature = "r")]
+pub(crate) fn quantize_png(
+    input: &PathBuf, threshold: LossyThreshold, lab: &LabConverter,
+) -> Result<(Vec<Color>, usize, usize)> {
+    // Decode source image into RGBA pixels used as the ground truth.
+    let (pixels, width, height) = read_png_colors(input)?;
+    let speed = LossySpeed::default();
+    let (quantized, _) = quantize_colors(pixels, width, threshold, 0, speed.ditherer(), lab, &speed, None);
+    Ok((quantized, width, height))
+}
+
+/// Whether PNG data is an indexed image (color type 3) with a tRNS chunk,
+/// i.e., with translucent or transparent palette entries.
+pub(crate) fn is_indexed_with_trns(png: &[u8]) -> bool {
+    png.get(25) == Some(&3) && ancillary_chunks(png).contains(b"tRNS")
+}
+
+/// Reduce the opaque colors of an indexed image to a subset of its own
+/// palette: colors are visited from the most to the least frequent, and each
+/// is replaced by the closest color kept so far if that is within the
+/// threshold (otherwise it is kept).  Translucent colors are never merged, so
+/// the transparency of the image stays exact.
+pub(crate) fn merge_palette_colors(pixels: &mut [Color], threshold: LossyThreshold, lab: &LabConverter) {
+    let mut counts: HashMap<u32, (Color, usize)> = HashMap::new();
+    for &c in pixels.iter().filter(|c| c.a == 255) {
+        counts.entry(color_key(c)).or_insert((c, 0)).1 += 1;
+    }
+    let mut colors: Vec<(u32, Color, usize)> = counts.into_iter().map(|(k, (c, n))| (k, c, n)).collect();
+    colors.sort_by(|a, b| b.2.cmp(&a.2).then(a.0.cmp(&b.0)));
+    let mut kept: Vec<(Color, [f64; 3])> = Vec::new();
+    let mut map: HashMap<u32, Color> = HashMap::new();
+    for (key, c, _) in colors {
+        let c_lab = lab.perceived(c);
+        let closest = kept.iter()
+            .map(|(k, k_lab)| (*k, threshold.errors(c_lab, *k_lab)))
+            .filter(|(_, e)| threshold.accepts(*e))
+            .min_by(|a, b| {
+                let norm = |e: &[f64; 3]| e.iter().map(|x| x * x).sum::<f64>();
+                norm(&a.1).total_cmp(&norm(&b.1))
+            });
+        match closest {
+            Some((k, _)) => { map.insert(key, k); }
+            None => kept.push((c, c_lab)),
+        }
+    }
+    for c in pixels.iter_mut() {
+        if let Some(&k) = map.get(&color_key(*c)) {
+            *c = k;
+        }
+    }
+}
+
+/// Quantize decoded pixels as in `quantize_png()`, consuming them, with the
+/// settings of a lossy speed (and a deadline for choosing the palette size),
+/// or to `colors` colors if it is positive, returning the palette size too.
+/// The pixels are dithered with `dither` in the end.
+#[allow(clippy::too_many_arguments)]
+pub(crate) fn quantize_colors(
+    pixels: Vec<Color>, width: usize, threshold: LossyThreshold, colors: usize, dither: Dither,
+    lab: &LabConverter, speed: &LossySpeed, deadline: Option<std::time::Instant>,
+) -> (Vec<Color>, usize) {
+    // The Lab samples (and the per-color error map) are only needed to choose
+    // the palette size, so they are freed before the final quantization, and
+    // the source pixels are freed before the caller encodes the result.
+    let palettes = speed.palettes(&pixels);
+    let n = if colors > 0 { colors.min(256) } else {
+        let samples = LabSamples::with_max(&pixels, lab, speed.samples);
+        palette_size(&pixels, &palettes, &samples, threshold, lab, speed, deadline)
+    };
+    (speed.quantize(&pixels, width, &palettes, n, dither), n)
+}
+
+/// Reduce the palette of decoded pixels to the smallest size whose perceptual
+/// error on `samples` stays within `threshold`.
+#[cfg(feature = "r")]
+pub(crate) fn quantize_pixels(
+    pixels: &[Color], width: usize, samples: &LabSamples, threshold: LossyThreshold,
+    lab: &LabConverter,
+) -> Vec<Color> {
+    let speed = LossySpeed::default();
+    let palettes = speed.palettes(pixels);
+    let n = palette_size(pixels, &palettes, samples, threshold, lab, &speed, None);
+    speed.quantize(pixels, width, &palettes, n, Dither::Ordered)
+}
+
+/// Find the smallest palette size whose perceptual error on `samples` stays
+/// within `threshold` (bisecting between 1 and 256 colors), or a size at most
+/// `speed.tolerance` colors above it.  After the `deadline`, the bisection
+/// stops with the smallest size found to be within `threshold` so far.
+pub(crate) fn palette_size(
+    pixels: &[Color], palettes: &Palettes, samples: &LabSamples, threshold: LossyThreshold, lab: &LabConverter,
+    speed: &LossySpeed, deadline: Option<std::time::Instant>,
+) -> usize {
+    // Pre-allocate the per-color map; cleared and refilled in each evaluation.
+    let mut color_max_de: HashMap<u32, [f64; 3]> = HashMap::new();
+    // Without dithering, each pixel is mapped to its nearest palette color on
+    // its own, so only the sampled pixels need to be mapped to measure the
+    // error of a palette.
+    let sampled: Vec<Color> = samples.idx.iter().map(|&i| pixels[i]).collect();
+    let mut metric = |palette: &[Color]| {
+        let quantized = remap(&sampled, sampled.len(), palette, Dither::None);
+        sample_p95_error(samples, |j| quantized[j], threshold, lab, &mut color_max_de)
+    };
+
+    // Quantize at 256 colors first to establish an upper bound for the bisection.
+    // If even 256 colors exceeds the threshold, use 256 (best possible quality).
+    // Otherwise the number of distinct colors actually used in the 256-quantized
+    // image is a tighter upper bound: there is no benefit searching above it.
+    // Only the color count of the 256-color image is needed, so it is dropped
+    // right away.
+    let palette256 = palettes.generate(256, speed.kmeans_iterations);
+    if !threshold.accepts(metric(&palette256)) {
+        256
+    } else {
+        let mut lo = 1usize;
+        let mut hi = count_unique_colors(&remap(pixels, pixels.len(), &palette256, Dither::None)).min(256);
+        while hi - lo > speed.tolerance && deadline.is_none_or(|d| std::time::Instant::now() < d) {
+            let mid = (lo + hi) / 2;
+            if threshold.accepts(metric(&palettes.generate(mid, speed.kmeans_iterations))) {
+                hi = mid;
+            } else {
+                lo = mid + 1;
+            }
+        }
+        hi
+    }
+}
+
+pub(crate) fn sample_indices(len: usize, max_samples: usize) -> Vec<usize> {
+    if len == 0 {
+        return Vec::new();
+    }
+    let step = (len / max_samples).max(1);
+    (0..len).step_by(step).collect()
+}
+
+#[inline]
+pub(crate) fn color_key(c: Color) -> u32 {
+    ((c.r as u32) << 24) | ((c.g as u32) << 16) | ((c.b as u32) << 8) | c.a as u32
+}
+
+pub(crate) fn count_unique_colors(pixels: &[Color]) -> usize {
+    pixels.iter().map(|&c| color_key(c)).collect::<HashSet<u32>>().len()
+}
+
+/// Compute the 95th percentile of per-unique-color max error (DeltaE, or
+/// each Lab channel difference for per-channel thresholds).
+/// Pixels are grouped by their original RGBA color so that a dominant
+/// background color gets only a single vote.  Within each group the
+/// worst-case error is kept; then p95 is taken over those group-level values
+/// (independently for each error component).
+///
+/// `samples` must be pre-computed from the original pixels.  `color_max_de`
+/// is a caller-owned map that is cleared and refilled on each call, avoiding
+/// a heap allocation per bisection step.
+#[cfg(feature = "r")]
+pub(crate) fn palette_p95_error(
+    samples: &LabSamples,
+    quantized: &[Color],
+    threshold: LossyThreshold,
+    lab: &LabConverter,
+    color_max_de: &mut HashMap<u32, [f64; 3]>,
+) -> [f64; 3] {
+    sample_p95_error(samples, |j| quantized[samples.idx[j]], threshold, lab, color_max_de)
+}
+
+/// `palette_p95_error()` with the quantized color of the `j`-th sample given
+/// by `quantized(j)`.
+fn sample_p95_error(
+    samples: &LabSamples,
+    quantized: impl Fn(usize) -> Color,
+    threshold: LossyThreshold,
+    lab: &LabConverter,
+    color_max_de: &mut HashMap<u32, [f64; 3]>,
+) -> [f64; 3] {
+    color_max_de.clear();
+    for j in 0..samples.idx.len() {
+        let errors = threshold.errors(samples.lab[j], lab.perceived(quantized(j)));
+        let entry = color_max_de.entry(samples.keys[j]).or_insert([0.0_f64; 3]);
+        for (m, e) in entry.iter_mut().zip(errors) {
+            if e > *m { *m = e; }
+        }
+    }
+    let mut p95 = [0.0_f64; 3];
+    if color_max_de.is_empty() { return p95; }
+    for (k, out) in p95.iter_mut().enumerate() {
+        let mut des: Vec<f64> = color_max_de.values().map(|e| e[k]).collect();
+        des.sort_by(|a, b| a.partial_cmp(b).unwrap_or(std::cmp::Ordering::Equal));
+        let p = ((des.len() as f64 * 0.95).ceil() as usize).saturating_sub(1);
+        *out = des[p.min(des.len() - 1)];
+    }
+    p95
+}
+
+// ---------------------------------------------------------------------------
+// Image content classification
+// ---------------------------------------------------------------------------
+//
+// Graphics (plots, diagrams, screenshots) have few colors and large flat
+// areas, which PNG compresses well.  Photographs have many colors and smooth
+// gradients with noise, so almost no two neighbouring pixels are identical;
+// a lossy format like JPEG or WebP suits them much better.  The features are
+// computed on (at most) `CLASSIFY_SAMPLES` pixels and their right neighbours.
+
+const CLASSIFY_SAMPLES: usize = 50_000;
+
+/// Image content class.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ImageClass {
+    Graphic,
+    Photo,
+    Mixed,
+}
+
+impl ImageClass {
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Graphic => "graphic",
+            Self::Photo => "photo",
+            Self::Mixed => "mixed",
+        }
+    }
+}
+
+/// Classify RGBA pixels (4 bytes per pixel, row-major) from three features:
+/// the ratio of unique colors among the sampled pixels, the fraction of
+/// sampled pixels identical to their right neighbours (flat areas), and the
+/// fraction of the remaining neighbour differences that are small (smooth
+/// gradients rather than sharp edges).
+pub(crate) fn classify_rgba(rgba: &[u8], width: usize, height: usize) -> ImageClass {
+    let n = width * height;
+    if n == 0 || width < 2 {
+        return ImageClass::Graphic;
+    }
+    let idx = sample_indices(n, CLASSIFY_SAMPLES);
+    let mut colors = HashSet::new();
+    let (mut pairs, mut flat, mut smooth) = (0usize, 0usize, 0usize);
+    for &i in &idx {
+        let p = &rgba[4 * i..4 * i + 4];
+        colors.insert([p[0], p[1], p[2], p[3]]);
+        if i % width == width - 1 {
+            continue;
+        }
+        let q = &rgba[4 * (i + 1)..4 * (i + 2)];
+        pairs += 1;
+        let diff = (0..4).map(|k| (p[k] as i32 - q[k] as i32).abs()).max().unwrap_or(0);
+        if diff == 0 {
+            flat += 1;
+        } else if diff <= 16 {
+            smooth += 1;
+        }
+    }
+    let unique = colors.len() as f64 / idx.len() as f64;
+    let smooth = smooth as f64 / (pairs - flat).max(1) as f64;
+    let flat = flat as f64 / pairs.max(1) as f64;
+    if flat >= 0.6 && unique <= 0.05 {
+        ImageClass::Graphic
+    } else if flat <= 0.3 && smooth >= 0.5 && unique >= 0.1 {
+        ImageClass::Photo
+    } else {
+        ImageClass::Mixed
+    }
+}
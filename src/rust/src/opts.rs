@@ -0,0 +1,298 @@
+use extendr_api::prelude::*;
+
+use crate::error::TinyImgRError;
+use crate::optimize::{parse_filters, parse_interlace, parse_strip, ColorType, Dither, LossySpeed, PngSettings};
+
+// ---------------------------------------------------------------------------
+// Settings files
+// ---------------------------------------------------------------------------
+//
+// The settings of `tinypng()` (from which its `oxipng::Options` are built) can
+// be saved to a TOML file and loaded again.  Only flat `key = value` pairs of
+// the keys in `OPT_KEYS` are written and read: booleans, numbers, basic
+// strings, and arrays of basic strings, with `#` comments, which is all the
+// TOML these files need, so no TOML crate is used.
+
+/// The types of the values of settings.
+#[derive(Clone, Copy, PartialEq)]
+enum OptType {
+    /// An integer within a range.
+    Int(i64, i64),
+    Float,
+    Bool,
+    Str,
+    /// An array of strings (or a single string).
+    StrList,
+}
+
+/// The keys of settings files (the argument names of `tinypng()`) and the
+/// types of their values, in the order they are written.  The other arguments
+/// of `tinypng()` (the files, the bookkeeping files of a batch, and how it is
+/// run and reported) describe a run rather than the settings of the outputs.
+const OPT_KEYS: [(&str, OptType); 27] = [
+    ("level", OptType::Int(0, 6)),
+    ("alpha", OptType::Bool),
+    ("preserve", OptType::Bool),
+    ("lossy", OptType::Float),
+    ("lossy_l", OptType::Float),
+    ("lossy_a", OptType::Float),
+    ("lossy_b", OptType::Float),
+    ("white_point", OptType::Str),
+    ("lossy_background", OptType::Str),
+    ("reproducible", OptType::Bool),
+    ("strip", OptType::StrList),
+    ("strip_profile", OptType::Str),
+    ("force_color_type", OptType::Str),
+    ("lossy_speed", OptType::Int(1, 10)),
+    ("no_grow", OptType::Bool),
+    ("zopfli", OptType::Int(0, 255)),
+    ("fix_errors", OptType::Bool),
+    ("interlace", OptType::Str),
+    ("filters", OptType::StrList),
+    ("reduce_bit_depth", OptType::Bool),
+    ("reduce_color_type", OptType::Bool),
+    ("reduce_palette", OptType::Bool),
+    ("scale_16", OptType::Bool),
+    ("force", OptType::Bool),
+    ("timeout", OptType::Float),
+    ("colors", OptType::Int(1, 256)),
+    ("dither", OptType::Str),
+];
+
+/// A value read from a settings file.
+enum OptValue {
+    Int(i64),
+    Float(f64),
+    Bool(bool),
+    Str(String),
+    List(Vec<String>),
+}
+
+/// Parse a TOML basic string (starting with `"`) and return it with the rest
+/// of the line after the closing quote.
+fn parse_toml_string(s: &str) -> Option<(String, &str)> {
+    let mut out = String::new();
+    let mut chars = s.strip_prefix('"')?.char_indices();
+    while let Some((i, c)) = chars.next() {
+        match c {
+            '"' => return Some((out, &s[i + 2..])),
+            '\\' => out.push(match chars.next()?.1 {
+                'n' => '\n',
+                't' => '\t',
+                c @ ('"' | '\\') => c,
+                _ => return None,
+            }),
+            c => out.push(c),
+        }
+    }
+    None
+}
+
+/// Parse the value of a `key = value` line (with an optional comment).
+fn parse_toml_value(s: &str) -> Option<OptValue> {
+    let s = s.trim_start();
+    if s.starts_with('"') {
+        let (value, rest) = parse_toml_string(s)?;
+        let rest = rest.trim_start();
+        return (rest.is_empty() || rest.starts_with('#')).then_some(OptValue::Str(value));
+    }
+    if let Some(mut rest) = s.strip_prefix('[') {
+        let mut values = Vec::new();
+        loop {
+            rest = rest.trim_start();
+            if let Some(r) = rest.strip_prefix(']') {
+                rest = r.trim_start();
+                break;
+            }
+            let (value, r) = parse_toml_string(rest)?;
+            values.push(value);
+            rest = r.trim_start();
+            match rest.strip_prefix(',') {
+                Some(r) => rest = r,
+                None if rest.starts_with(']') => {}
+                None => return None,
+            }
+        }
+        return (rest.is_empty() || rest.starts_with('#')).then_some(OptValue::List(values));
+    }
+    let s = s.split('#').next().unwrap_or("").trim().replace('_', "");
+    match s.as_str() {
+        "true" => Some(OptValue::Bool(true)),
+        "false" => Some(OptValue::Bool(false)),
+        _ => s.parse().map(OptValue::Int).ok()
+            .or_else(|| s.parse::<f64>().ok().filter(|x| x.is_finite()).map(OptValue::Float)),
+    }
+}
+
+/// Quote a string for TOML.
+fn toml_string(s: &str) -> String {
+    format!("\"{}\"", s.replace('\\', "\\\\").replace('"', "\\\"").replace('\n', "\\n").replace('\t', "\\t"))
+}
+
+/// Write strings as a TOML array.
+fn toml_array(x: &[&str]) -> String {
+    format!("[{}]", x.iter().map(|s| toml_string(s)).collect::<Vec<_>>().join(", "))
+}
+
+/// Save the settings of tinypng() to a TOML file
+///
+/// @param output_path Path to the TOML file
+/// @param level Optimization level (0-6)
+/// @param alpha Whether to optimize transparent pixels
+/// @param preserve Whether to preserve file permissions and timestamps
+/// @param lossy Maximum CIE76 Delta E threshold
+/// @param lossy_l,lossy_a,lossy_b Per-channel thresholds
+/// @param white_point Reference white for L*a*b*
+/// @param lossy_background Hex color of the background of translucent pixels
+///   (`""` for none)
+/// @param reproducible Whether to make the output reproducible
+/// @param strip The metadata chunks to strip (see `parse_strip()`)
+/// @param strip_profile `"strip"` or `"convert"` the color profiles
+/// @param force_color_type The color type of the outputs (`""` for none)
+/// @param lossy_speed The speed of lossy reduction from `1` to `10`
+/// @param no_grow Whether to copy files that cannot be made smaller
+/// @param zopfli The number of Zopfli iterations (`0` for no Zopfli)
+/// @param fix_errors Whether to salvage slightly corrupt files
+/// @param interlace `"remove"`, `"keep"`, or `"adam7"`
+/// @param filters The row filters to try (empty for those of the level)
+/// @param reduce_bit_depth,reduce_color_type,reduce_palette Whether to allow
+///   the reductions of oxipng
+/// @param scale_16 Whether to scale 16-bit images to 8 bits
+/// @param force Whether to always write the outputs
+/// @param timeout The time limit of each file in seconds (`0` for none)
+/// @param colors The palette size of lossy reduction (`0` for none)
+/// @param dither The dithering of lossy reduction (`""` for that of the speed)
+/// @return The path of the TOML file
+/// @export
+#[extendr]
+#[allow(clippy::too_many_arguments)]
+fn tinypng_save_opts_impl(
+    output_path: &str, level: i32, alpha: bool, preserve: bool, lossy: f64, lossy_l: f64, lossy_a: f64,
+    lossy_b: f64, white_point: &str, lossy_background: &str, reproducible: bool, strip: Strings,
+    strip_profile: &str, force_color_type: &str, lossy_speed: i32, no_grow: bool, zopfli: i32,
+    fix_errors: bool, interlace: &str, filters: Strings, reduce_bit_depth: bool, reduce_color_type: bool,
+    reduce_palette: bool, scale_16: bool, force: bool, timeout: f64, colors: i32, dither: &str,
+) -> Result<String> {
+    crate::check_level(level)?;
+    let thresholds = [lossy, lossy_l, lossy_a, lossy_b];
+    if thresholds.iter().any(|x| !x.is_finite()) {
+        return Err(TinyImgRError::argument("The lossy thresholds must be finite numbers").into());
+    }
+    if !matches!(strip_profile, "strip" | "convert") {
+        return Err(TinyImgRError::argument(format!(
+            "The profile handling must be \"strip\" or \"convert\": {}", strip_profile
+        )).into());
+    }
+    if !(0..=255).contains(&zopfli) {
+        return Err(TinyImgRError::argument(
+            "The number of Zopfli iterations must be an integer from 0 (no Zopfli) to 255"
+        ).into());
+    }
+    if !(timeout.is_finite() && timeout >= 0.0) {
+        return Err(TinyImgRError::argument("The timeout must be a non-negative number of seconds").into());
+    }
+    if !(0..=256).contains(&colors) {
+        return Err(TinyImgRError::argument("The number of colors must be between 1 and 256 (or 0)").into());
+    }
+    if colors > 0 && thresholds.iter().any(|&t| t > 0.0) {
+        return Err(TinyImgRError::argument("The number of colors cannot be used with a positive lossy threshold").into());
+    }
+    // Check the other settings as tinypng() does (e.g., the white point).
+    PngSettings::new(level, alpha, preserve, lossy, [lossy_l, lossy_a, lossy_b], white_point)?;
+    crate::parse_background(lossy_background)?;
+    let strip: Vec<&str> = strip.iter().map(|s| s.as_str()).collect();
+    let filters: Vec<&str> = filters.iter().map(|s| s.as_str()).collect();
+    parse_strip(&strip)?;
+    parse_filters(&filters)?;
+    parse_interlace(interlace)?;
+    LossySpeed::new(lossy_speed)?;
+    if !force_color_type.is_empty() {
+        ColorType::from_name(force_color_type)?;
+    }
+    if !dither.is_empty() {
+        Dither::from_name(dither)?;
+    }
+    // Settings that are not set (e.g., no timeout) are left out of the file.
+    let optional = |set: bool, value: String| set.then_some(value);
+    let values = [
+        Some(level.to_string()), Some(alpha.to_string()), Some(preserve.to_string()), Some(format!("{:?}", lossy)),
+        Some(format!("{:?}", lossy_l)), Some(format!("{:?}", lossy_a)), Some(format!("{:?}", lossy_b)),
+        Some(toml_string(white_point)), optional(!lossy_background.is_empty(), toml_string(lossy_background)),
+        Some(reproducible.to_string()), Some(toml_array(&strip)), Some(toml_string(strip_profile)),
+        optional(!force_color_type.is_empty(), toml_string(force_color_type)), Some(lossy_speed.to_string()),
+        Some(no_grow.to_string()), Some(zopfli.to_string()), Some(fix_errors.to_string()),
+        Some(toml_string(interlace)), optional(!filters.is_empty(), toml_array(&filters)),
+        Some(reduce_bit_depth.to_string()), Some(reduce_color_type.to_string()), Some(reduce_palette.to_string()),
+        Some(scale_16.to_string()), Some(force.to_string()), optional(timeout > 0.0, format!("{:?}", timeout)),
+        optional(colors > 0, colors.to_string()), optional(!dither.is_empty(), toml_string(dither)),
+    ];
+    let mut toml = String::from("# Settings of tinypng() written by tinypng_save_opts()\n");
+    for ((key, _), value) in OPT_KEYS.iter().zip(values) {
+        if let Some(value) = value {
+            toml.push_str(&format!("{} = {}\n", key, value));
+        }
+    }
+    std::fs::write(output_path, toml)
+        .map_err(|e| TinyImgRError::io(format!("Failed to write {}: {}", output_path, e)))?;
+    Ok(output_path.to_string())
+}
+
+/// Load the settings of tinypng() from a TOML file
+///
+/// @param input_path Path to the TOML file
+/// @return A named list of the settings in the file
+/// @export
+#[extendr]
+fn tinypng_load_opts_impl(input_path: &str) -> Result<List> {
+    let content = std::fs::read_to_string(input_path)
+        .map_err(|e| TinyImgRError::io(format!("Failed to read {}: {}", input_path, e)))?;
+    let (mut names, mut values): (Vec<&str>, Vec<Robj>) = (Vec::new(), Vec::new());
+    for (i, line) in content.lines().enumerate() {
+        let err = |msg: String| TinyImgRError::argument(format!("{}:{}: {}", input_path, i + 1, msg));
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let Some((key, value)) = line.split_once('=') else {
+            return Err(err(format!("expected `key = value`: {}", line)).into());
+        };
+        let key = key.trim().trim_matches('"');
+        let Some(&(key, ty)) = OPT_KEYS.iter().find(|(k, _)| *k == key) else {
+            return Err(err(format!(
+                "unknown setting '{}' (expected one of {})", key,
+                OPT_KEYS.map(|(k, _)| k).join(", ")
+            )).into());
+        };
+        if names.contains(&key) {
+            return Err(err(format!("duplicate setting '{}'", key)).into());
+        }
+        let value: Robj = match (ty, parse_toml_value(value)) {
+            (OptType::Int(min, max), Some(OptValue::Int(x))) if (min..=max).contains(&x) => (x as i32).into(),
+            (OptType::Float, Some(OptValue::Float(x))) => x.into(),
+            (OptType::Float, Some(OptValue::Int(x))) => (x as f64).into(),
+            (OptType::Bool, Some(OptValue::Bool(x))) => x.into(),
+            (OptType::Str, Some(OptValue::Str(x))) => x.into(),
+            (OptType::StrList, Some(OptValue::Str(x))) => vec![x].into(),
+            (OptType::StrList, Some(OptValue::List(x))) => x.into(),
+            _ => {
+                let expected = match ty {
+                    OptType::Int(min, max) => format!("an integer from {} to {}", min, max),
+                    OptType::Float => "a number".to_string(),
+                    OptType::Bool => "true or false".to_string(),
+                    OptType::Str => "a quoted string".to_string(),
+                    OptType::StrList => "a quoted string or an array of them".to_string(),
+                };
+                return Err(err(format!("the value of '{}' must be {}", key, expected)).into());
+            }
+        };
+        names.push(key);
+        values.push(value);
+    }
+    List::from_names_and_values(names, values)
+}
+
+extendr_module! {
+    mod opts;
+    fn tinypng_save_opts_impl;
+    fn tinypng_load_opts_impl;
+}
@@ -0,0 +1,224 @@
+use extendr_api::prelude::*;
+use std::path::{Path, PathBuf};
+
+use crate::error::TinyImgRError;
+use crate::metrics::{delta_e, LabConverter};
+use crate::optimize::{read_png_colors, sample_indices};
+use crate::quantize::{colors_from_rgba, Color, QUANTIZERS};
+
+// ---------------------------------------------------------------------------
+// Spatial quality maps
+// ---------------------------------------------------------------------------
+//
+// A single quality score (such as the 95th percentile Delta E used by lossy
+// palette reduction) does not tell where an image lost quality.  The maps here
+// split the image into square blocks and score each block separately.
+
+/// Mean CIE76 Delta E between two images of the same dimensions in blocks of
+/// `block` x `block` pixels, in row-major order of the blocks.  Blocks on the
+/// right and bottom edges may be smaller.
+fn block_delta_e(
+    a: &[Color], b: &[Color], width: usize, height: usize, block: usize, lab: &LabConverter,
+) -> (Vec<f64>, usize, usize) {
+    let (rows, cols) = (height.div_ceil(block), width.div_ceil(block));
+    let mut sums = vec![0.0_f64; rows * cols];
+    let mut counts = vec![0_usize; rows * cols];
+    for y in 0..height {
+        for x in 0..width {
+            let i = y * width + x;
+            let k = (y / block) * cols + x / block;
+            sums[k] += delta_e(lab.to_lab(a[i]), lab.to_lab(b[i]));
+            counts[k] += 1;
+        }
+    }
+    let means = sums.iter().zip(&counts).map(|(&s, &n)| s / n as f64).collect();
+    (means, rows, cols)
+}
+
+/// Compute a block-level Delta E map between two PNG images
+///
+/// @param original Path to the original PNG file
+/// @param quantized Path to the quantized (or otherwise processed) PNG file
+/// @param block_size Width and height of the blocks in pixels
+/// @return A numeric matrix of the mean Delta E of each block, with
+///   `ceiling(height / block_size)` rows and `ceiling(width / block_size)`
+///   columns
+/// @export
+#[extendr]
+fn tinypng_spatial_quality_impl(original: &str, quantized: &str, block_size: i32) -> Result<Robj> {
+    if block_size <= 0 {
+        return Err(TinyImgRError::argument("The block size must be positive").into());
+    }
+    let (a, width, height) = read_png_colors(&PathBuf::from(original))?;
+    let (b, width2, height2) = read_png_colors(&PathBuf::from(quantized))?;
+    if (width, height) != (width2, height2) {
+        return Err(TinyImgRError::argument(format!(
+            "The images have different dimensions: {}x{} ({}) and {}x{} ({})",
+            width, height, original, width2, height2, quantized
+        )).into());
+    }
+    let lab = LabConverter::new("d65").expect("d65 is a known white point");
+    let (means, rows, cols) = block_delta_e(&a, &b, width, height, block_size as usize, &lab);
+    Ok(RMatrix::new_matrix(rows, cols, |r, c| means[r * cols + c]).into())
+}
+
+// ---------------------------------------------------------------------------
+// Quantizer benchmark
+// ---------------------------------------------------------------------------
+
+/// The 95th percentile of the CIE76 Delta E between the pixels of two images
+/// of the same dimensions.
+fn p95_delta_e(a: &[Color], b: &[Color], lab: &LabConverter) -> f64 {
+    let mut des: Vec<f64> = a.iter().zip(b).map(|(&x, &y)| delta_e(lab.to_lab(x), lab.to_lab(y))).collect();
+    if des.is_empty() {
+        return 0.0;
+    }
+    let p = ((des.len() as f64 * 0.95).ceil() as usize).saturating_sub(1);
+    *des.select_nth_unstable_by(p, |x, y| x.total_cmp(y)).1
+}
+
+/// Benchmark the palette quantizers on a PNG image
+///
+/// @param input Path to the PNG file
+/// @param n_colors Number of palette colors (1-256)
+/// @return A list of the optimizer and ditherer names, the number of colors,
+///   the 95th percentile Delta E of the quantized pixels, and the elapsed
+///   milliseconds of the quantization, with one element per combination
+/// @export
+#[extendr]
+fn tinypng_benchmark_quantizers_impl(input: &str, n_colors: i32) -> Result<List> {
+    if !(1..=256).contains(&n_colors) {
+        return Err(TinyImgRError::argument("The number of colors must be between 1 and 256").into());
+    }
+    let (pixels, width, _) = read_png_colors(&PathBuf::from(input))?;
+    let lab = LabConverter::new("d65").expect("d65 is a known white point");
+    let (mut p95, mut elapsed_ms) = (Vec::new(), Vec::new());
+    for (_, _, quantizer) in QUANTIZERS {
+        let start = std::time::Instant::now();
+        let quantized = quantizer.quantize(&pixels, width, n_colors as usize);
+        elapsed_ms.push(start.elapsed().as_secs_f64() * 1000.0);
+        p95.push(p95_delta_e(&pixels, &quantized, &lab));
+    }
+    Ok(list!(
+        optimizer = QUANTIZERS.map(|q| q.0).to_vec(),
+        ditherer = QUANTIZERS.map(|q| q.1).to_vec(),
+        n_colors = vec![n_colors; QUANTIZERS.len()],
+        p95_de = p95,
+        elapsed_ms = elapsed_ms
+    ))
+}
+
+// ---------------------------------------------------------------------------
+// Directory comparison
+// ---------------------------------------------------------------------------
+//
+// Two trees of images (e.g., a site before and after a migration) are compared
+// file by file: the files are matched by their relative paths, and the pixels
+// of each pair are compared by their CIE76 Delta E, on at most `max_samples`
+// pixels of large images.  Problems with single files (a missing file, a file
+// that is not an image, or different dimensions) are reported as the status
+// of the file instead of errors.
+
+/// Decode a PNG or JPEG file (recognized by its magic bytes) into colors and
+/// its dimensions.
+fn read_image_colors(path: &Path) -> std::result::Result<(Vec<Color>, usize, usize), String> {
+    let bytes = std::fs::read(path).map_err(|e| e.to_string())?;
+    if bytes.starts_with(b"\x89PNG\r\n\x1a\n") {
+        let image = lodepng::decode32(&bytes).map_err(|e| e.to_string())?;
+        return Ok((colors_from_rgba(image.buffer), image.width, image.height));
+    }
+    if !bytes.starts_with(&[0xFF, 0xD8, 0xFF]) {
+        return Err("not a PNG or JPEG image".to_string());
+    }
+    // libjpeg errors are raised as panics by mozjpeg.
+    std::panic::catch_unwind(|| -> std::io::Result<_> {
+        let mut image = mozjpeg::Decompress::new_mem(&bytes)?.rgb()?;
+        let (width, height) = (image.width(), image.height());
+        let pixels: Vec<[u8; 3]> = image.read_scanlines()?;
+        let colors = pixels.iter().map(|&[r, g, b]| Color { r, g, b, a: 255 }).collect();
+        Ok((colors, width, height))
+    }).map_err(|_| "failed to decode the JPEG data".to_string())?.map_err(|e| e.to_string())
+}
+
+/// The maximum and mean CIE76 Delta E between two images of the same
+/// dimensions, on at most `max_samples` evenly spaced pixels.
+fn compare_colors(a: &[Color], b: &[Color], lab: &LabConverter, max_samples: usize) -> (f64, f64) {
+    let idx = sample_indices(a.len(), max_samples);
+    let (mut max, mut sum) = (0.0_f64, 0.0);
+    for &i in &idx {
+        let de = delta_e(lab.to_lab(a[i]), lab.to_lab(b[i]));
+        max = max.max(de);
+        sum += de;
+    }
+    (max, if idx.is_empty() { 0.0 } else { sum / idx.len() as f64 })
+}
+
+/// Compare the images of the same relative paths in two directories
+///
+/// @param dir_a,dir_b Paths to the directories
+/// @param files Relative paths of the files to compare (in either directory)
+/// @param threshold Maximum Delta E for a pair of images to pass
+/// @param max_samples Maximum number of pixels to compare in each pair
+/// @return A list of the relative paths (`file`), the status of each file
+///   (`"pass"`, `"fail"`, `"dimensions"`, `"only_a"`, `"only_b"`, or
+///   `"error"`), the maximum and mean Delta E (`max_de` and `mean_de`, `NA`
+///   unless both images were compared), the dimensions of the images (`dim_a`
+///   and `dim_b` as `"WxH"`), and error messages (`message`)
+/// @export
+#[extendr]
+fn tinycompare_impl(dir_a: &str, dir_b: &str, files: Strings, threshold: f64, max_samples: i32) -> Result<List> {
+    if max_samples <= 0 {
+        return Err(TinyImgRError::argument("The maximum number of samples must be positive").into());
+    }
+    let lab = LabConverter::new("d65").expect("d65 is a known white point");
+    let n = files.len();
+    let (mut status, mut max_de, mut mean_de) = (Vec::with_capacity(n), vec![None; n], vec![None; n]);
+    let (mut dim_a, mut dim_b, mut message) = (vec![None; n], vec![None; n], vec![None; n]);
+    for (i, file) in files.iter().enumerate() {
+        let (a, b) = (Path::new(dir_a).join(file.as_str()), Path::new(dir_b).join(file.as_str()));
+        let s = match (a.is_file(), b.is_file()) {
+            (true, false) => "only_a",
+            (false, true) => "only_b",
+            (false, false) => {
+                message[i] = Some("the file exists in neither directory".to_string());
+                "error"
+            }
+            (true, true) => match (read_image_colors(&a), read_image_colors(&b)) {
+                (Ok((pa, wa, ha)), Ok((pb, wb, hb))) => {
+                    dim_a[i] = Some(format!("{}x{}", wa, ha));
+                    dim_b[i] = Some(format!("{}x{}", wb, hb));
+                    if (wa, ha) != (wb, hb) {
+                        "dimensions"
+                    } else {
+                        let (max, mean) = compare_colors(&pa, &pb, &lab, max_samples as usize);
+                        (max_de[i], mean_de[i]) = (Some(max), Some(mean));
+                        if max <= threshold { "pass" } else { "fail" }
+                    }
+                }
+                (ra, rb) => {
+                    message[i] = Some([(ra.err(), &a), (rb.err(), &b)].into_iter()
+                        .filter_map(|(e, p)| e.map(|e| format!("{}: {}", p.display(), e)))
+                        .collect::<Vec<_>>().join("; "));
+                    "error"
+                }
+            },
+        };
+        status.push(s);
+    }
+    Ok(list!(
+        file = files,
+        status = status,
+        max_de = max_de.into_iter().map(Rfloat::from).collect::<Doubles>(),
+        mean_de = mean_de.into_iter().map(Rfloat::from).collect::<Doubles>(),
+        dim_a = dim_a.into_iter().map(Rstr::from).collect::<Strings>(),
+        dim_b = dim_b.into_iter().map(Rstr::from).collect::<Strings>(),
+        message = message.into_iter().map(Rstr::from).collect::<Strings>()
+    ))
+}
+
+extendr_module! {
+    mod quality;
+    fn tinypng_spatial_quality_impl;
+    fn tinypng_benchmark_quantizers_impl;
+    fn tinycompare_impl;
+}
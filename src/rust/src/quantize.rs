@@ -0,0 +1,244 @@
+use exoquant::optimizer::Optimizer;
+use exoquant::{ditherer, generate_palette, optimizer, Histogram, Remapper, SimpleColorSpace};
+#[cfg(feature = "r")]
+use exoquant::{convert_to_indexed, ColorMap, ColorSpace, Colorf};
+
+pub(crate) use exoquant::Color;
+
+use crate::optimize::Dither;
+
+// ---------------------------------------------------------------------------
+// Palette quantization
+// ---------------------------------------------------------------------------
+//
+// All uses of `exoquant` go through the `Quantizer` and `Ditherer` traits in
+// this module, so that an API change in a future `exoquant` version only
+// needs to be handled here, not in the lossy PNG bisection logic.
+
+// exoquant's `Color` is not `#[repr(C)]`, so check at compile time that it
+// has the same byte layout as lodepng's `RGBA` (four `u8` fields in RGBA
+// order), which makes the buffer reinterpretations below sound.
+const _: () = {
+    use std::mem::{align_of, offset_of, size_of};
+    assert!(size_of::<Color>() == 4 && align_of::<Color>() == 1);
+    assert!(size_of::<lodepng::RGBA>() == 4 && align_of::<lodepng::RGBA>() == 1);
+    assert!(offset_of!(Color, r) == 0 && offset_of!(lodepng::RGBA, r) == 0);
+    assert!(offset_of!(Color, g) == 1 && offset_of!(lodepng::RGBA, g) == 1);
+    assert!(offset_of!(Color, b) == 2 && offset_of!(lodepng::RGBA, b) == 2);
+    assert!(offset_of!(Color, a) == 3 && offset_of!(lodepng::RGBA, a) == 3);
+};
+
+/// Reuse a decoded lodepng buffer as exoquant colors without copying.
+pub(crate) fn colors_from_rgba(pixels: Vec<lodepng::RGBA>) -> Vec<Color> {
+    let mut pixels = std::mem::ManuallyDrop::new(pixels);
+    let (ptr, len, cap) = (pixels.as_mut_ptr(), pixels.len(), pixels.capacity());
+    // SAFETY: both types have the same size, alignment, and byte layout
+    // (asserted above), so the allocation and its contents stay valid.
+    unsafe { Vec::from_raw_parts(ptr as *mut Color, len, cap) }
+}
+
+/// Turn exoquant colors into RGBA bytes (4 per pixel) without copying.
+#[cfg(feature = "r")]
+pub(crate) fn colors_into_bytes(pixels: Vec<Color>) -> Vec<u8> {
+    let mut pixels = std::mem::ManuallyDrop::new(pixels);
+    let (ptr, len, cap) = (pixels.as_mut_ptr(), pixels.len(), pixels.capacity());
+    // SAFETY: `Color` is four `u8` fields without padding (asserted above),
+    // so the allocation holds `4 * cap` bytes with the same alignment.
+    unsafe { Vec::from_raw_parts(ptr as *mut u8, 4 * len, 4 * cap) }
+}
+
+/// View exoquant colors as RGBA bytes (4 per pixel) without copying.
+pub(crate) fn color_bytes(pixels: &[Color]) -> &[u8] {
+    // SAFETY: `Color` is four `u8` fields without padding (asserted above).
+    unsafe { std::slice::from_raw_parts(pixels.as_ptr() as *const u8, pixels.len() * 4) }
+}
+
+/// Maps pixels onto a generated palette, optionally dithering them.
+#[cfg(feature = "r")]
+pub(crate) trait Ditherer {
+    /// Generate a palette of at most `n` colors (1 to 256) for `pixels` (rows
+    /// of `width` pixels) and return it with the palette index of each pixel.
+    fn to_indexed(&self, pixels: &[Color], width: usize, n: usize) -> (Vec<Color>, Vec<u8>);
+}
+
+/// Reduces an image to a limited number of colors.
+#[cfg(feature = "r")]
+pub(crate) trait Quantizer {
+    /// Quantize `pixels` (rows of `width` pixels) to at most `n` colors and
+    /// return the quantized pixels.
+    fn quantize(&self, pixels: &[Color], width: usize, n: usize) -> Vec<Color>;
+}
+
+/// Nearest-color mapping without dithering.
+#[cfg(feature = "r")]
+pub(crate) struct NoDither;
+
+/// Ordered (Bayer matrix) dithering.
+#[cfg(feature = "r")]
+pub(crate) struct OrderedDither;
+
+#[cfg(feature = "r")]
+impl Ditherer for NoDither {
+    fn to_indexed(&self, pixels: &[Color], width: usize, n: usize) -> (Vec<Color>, Vec<u8>) {
+        convert_to_indexed(pixels, width, n, &optimizer::KMeans, &ditherer::None)
+    }
+}
+
+#[cfg(feature = "r")]
+impl Ditherer for OrderedDither {
+    fn to_indexed(&self, pixels: &[Color], width: usize, n: usize) -> (Vec<Color>, Vec<u8>) {
+        convert_to_indexed(pixels, width, n, &optimizer::KMeans, &ditherer::Ordered)
+    }
+}
+
+/// Ordered dithering whose amplitude follows the local quantization error:
+/// exoquant's 2x2 ordered dithering, scaled in each block of `ADAPTIVE_BLOCK`
+/// x `ADAPTIVE_BLOCK` pixels by the mean error of the nearest palette colors
+/// (relative to half the distance to their nearest neighbours in the
+/// palette, so that the scale does not depend on the palette size).  Flat
+/// blocks (of a single color) are not dithered, and blocks whose colors fall
+/// halfway between palette colors are fully dithered.
+#[cfg(feature = "r")]
+struct AdaptiveOrdered;
+
+/// Block size of `AdaptiveOrdered` (a multiple of the dither matrix size).
+#[cfg(feature = "r")]
+const ADAPTIVE_BLOCK: usize = 8;
+
+/// exoquant's ordered dither matrix and amplitude (`ditherer::Ordered`).
+#[cfg(feature = "r")]
+const ORDERED_MATRIX: [f64; 4] = [-0.375, 0.125, 0.375, -0.125];
+#[cfg(feature = "r")]
+const ORDERED_AMPLITUDE: f64 = 0.75;
+
+#[cfg(feature = "r")]
+impl ditherer::Ditherer for AdaptiveOrdered {
+    fn remap<'a>(
+        &'a self, image: Box<dyn Iterator<Item = Colorf> + 'a>, width: usize, map: &'a ColorMap,
+        _: &'a dyn ColorSpace,
+    ) -> Box<dyn Iterator<Item = usize> + 'a> {
+        // The block strengths need all pixels, so the image is read first.
+        let image: Vec<Colorf> = image.collect();
+        let height = image.len() / width.max(1);
+        let cols = width.div_ceil(ADAPTIVE_BLOCK);
+        let n_blocks = cols * height.div_ceil(ADAPTIVE_BLOCK);
+        let (mut sums, mut counts, mut flat) = (vec![0.0; n_blocks], vec![0usize; n_blocks], vec![true; n_blocks]);
+        let block = move |i: usize| (i / width / ADAPTIVE_BLOCK) * cols + (i % width) / ADAPTIVE_BLOCK;
+        let mut first: Vec<Option<Colorf>> = vec![None; n_blocks];
+        for (i, &c) in image.iter().enumerate() {
+            let k = block(i);
+            let j = map.find_nearest(c);
+            let half = map.neighbor_distance(j) / 2.0;
+            let error = (c - map.float_color(j)).abs();
+            sums[k] += if half > 0.0 { (error / half).min(1.0) } else { 0.0 };
+            counts[k] += 1;
+            match first[k] {
+                None => first[k] = Some(c),
+                Some(f) => flat[k] &= (c - f).abs() == 0.0,
+            }
+        }
+        let strength: Vec<f64> = (0..n_blocks)
+            .map(|k| if flat[k] || counts[k] == 0 { 0.0 } else { sums[k] / counts[k] as f64 })
+            .collect();
+        Box::new(image.into_iter().enumerate().map(move |(i, color)| {
+            let (x, y) = (i % width, i / width);
+            let dither = ORDERED_MATRIX[(x & 1) + (y & 1) * 2];
+            let d = map.neighbor_distance(map.find_nearest(color));
+            map.find_nearest(color + d * dither * ORDERED_AMPLITUDE * strength[block(i)])
+        }))
+    }
+}
+
+/// Remap pixels (rows of `width` pixels) onto a palette of at most 256 colors
+/// with ordered dithering scaled by the local quantization error, so that
+/// flat areas stay flat and only areas with visible banding are dithered.
+#[cfg(feature = "r")]
+pub(crate) fn dither_adaptive(pixels: &[Color], width: usize, palette: &[Color]) -> Vec<Color> {
+    let colorspace = SimpleColorSpace::default();
+    let indexed = Remapper::new(palette, &colorspace, &AdaptiveOrdered).remap(pixels, width);
+    indexed.iter().map(|&i| palette[i as usize]).collect()
+}
+
+/// The color histogram of (a sample of) an image, from which palettes of
+/// several sizes can be generated without counting the colors again.  The
+/// palette of `n` colors from the histogram of all pixels with 8 K-means
+/// iterations is the palette of `KMeans` (before sorting).
+pub(crate) struct Palettes {
+    hist: Histogram,
+    colorspace: SimpleColorSpace,
+}
+
+impl Palettes {
+    pub(crate) fn new(pixels: &[Color]) -> Self {
+        Self { hist: pixels.iter().cloned().collect(), colorspace: SimpleColorSpace::default() }
+    }
+
+    /// A palette of at most `n` colors (1 to 256), optimized with `iterations`
+    /// K-means iterations (0 to skip K-means, also while generating it).
+    pub(crate) fn generate(&self, n: usize, iterations: usize) -> Vec<Color> {
+        let (hist, colorspace) = (&self.hist, &self.colorspace);
+        if iterations == 0 {
+            return generate_palette(hist, colorspace, &optimizer::None, n);
+        }
+        let palette = generate_palette(hist, colorspace, &optimizer::KMeans, n);
+        optimizer::KMeans.optimize_palette(colorspace, &palette, hist, iterations)
+    }
+}
+
+/// Map pixels (rows of `width` pixels) onto a palette with a dithering
+/// algorithm, or to their nearest palette colors.  Without dithering, each
+/// pixel is mapped on its own, so any subset of the pixels of an image can be
+/// mapped.
+pub(crate) fn remap(pixels: &[Color], width: usize, palette: &[Color], dither: Dither) -> Vec<Color> {
+    let colorspace = SimpleColorSpace::default();
+    let indexed = match dither {
+        Dither::None => Remapper::new(palette, &colorspace, &ditherer::None).remap(pixels, width),
+        Dither::Ordered => Remapper::new(palette, &colorspace, &ditherer::Ordered).remap(pixels, width),
+        Dither::FloydSteinberg => {
+            Remapper::new(palette, &colorspace, &ditherer::FloydSteinberg::new()).remap(pixels, width)
+        }
+    };
+    indexed.iter().map(|&i| palette[i as usize]).collect()
+}
+
+/// K-means palette generation followed by remapping with a `Ditherer`.
+#[cfg(feature = "r")]
+pub(crate) struct KMeans<D>(pub(crate) D);
+
+#[cfg(feature = "r")]
+impl<D: Ditherer> Quantizer for KMeans<D> {
+    fn quantize(&self, pixels: &[Color], width: usize, n: usize) -> Vec<Color> {
+        let (palette, indexed) = self.0.to_indexed(pixels, width, n.clamp(1, 256));
+        indexed.iter().map(|&idx| palette[idx as usize]).collect()
+    }
+}
+
+/// Quantizer for evaluating candidate palette sizes: without dithering, the
+/// error of each pixel reflects the palette alone.
+#[cfg(feature = "r")]
+pub(crate) const NEAREST: KMeans<NoDither> = KMeans(NoDither);
+
+/// Quantizer for the final output, where dithering hides banding.
+#[cfg(feature = "r")]
+pub(crate) const DITHERED: KMeans<OrderedDither> = KMeans(OrderedDither);
+
+/// K-means palette generation followed by `dither_adaptive()`.
+#[cfg(feature = "r")]
+pub(crate) struct KMeansAdaptive;
+
+#[cfg(feature = "r")]
+impl Quantizer for KMeansAdaptive {
+    fn quantize(&self, pixels: &[Color], width: usize, n: usize) -> Vec<Color> {
+        let (palette, _) = NoDither.to_indexed(pixels, width, n.clamp(1, 256));
+        dither_adaptive(pixels, width, &palette)
+    }
+}
+
+/// All palette optimizer and ditherer combinations defined in this module, by
+/// the names of the optimizer and the ditherer.
+#[cfg(feature = "r")]
+pub(crate) const QUANTIZERS: [(&str, &str, &dyn Quantizer); 3] = [
+    ("kmeans", "none", &NEAREST),
+    ("kmeans", "ordered", &DITHERED),
+    ("kmeans", "adaptive", &KMeansAdaptive),
+];
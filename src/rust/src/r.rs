@@ -0,0 +1,1658 @@
+use extendr_api::prelude::*;
+use mozjpeg::{ColorSpace, Compress, Decompress};
+use oxipng::{InFile, OutFile, Options};
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::collections::HashSet;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+mod anim;
+mod audit;
+mod checksum;
+mod chunk;
+mod classify;
+mod colorspace;
+mod convert;
+mod edit;
+mod ico;
+mod manifest;
+mod opts;
+mod quality;
+mod svg;
+mod swatch;
+
+use error::TinyImgRError;
+use metrics::LabConverter;
+use optimize::{
+    decode_png_colors, encode_palette_png, encode_png_colors, find_truncate_index, format_dir_groups,
+    group_sizes_by_dir, optimize_batch, optimize_error, png_options, quantize_pixels, quantize_stage, random_sample,
+    parse_filters, parse_interlace, parse_strip, read_png_colors, truncate_path, AutoLevels, AutoThresholds, BatchOptions, ColorType, Dither, ImageClass, LabSamples,
+    LossySpeed, LossyThreshold, PngSettings, SampleEstimate, VerboseMessage, DEFAULT_LOSSY_SPEED, DEFAULT_PNG_LEVEL,
+};
+use quantize::{colors_from_rgba, colors_into_bytes, Color, KMeansAdaptive, Quantizer};
+
+// ---------------------------------------------------------------------------
+// Custom global allocator: panic on OOM instead of calling abort()
+// ---------------------------------------------------------------------------
+//
+// Rust's default allocator calls abort() when an allocation fails, which
+// causes a core dump in the host R process.  By replacing it with this
+// wrapper we turn allocation failures into Rust panics; because the crate
+// uses `panic = "unwind"` and extendr wraps every exported function in
+// `catch_unwind`, the panic is caught and returned to R as a normal error.
+
+struct PanicOnOomAllocator;
+
+unsafe impl GlobalAlloc for PanicOnOomAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let ptr = System.alloc(layout);
+        if ptr.is_null() {
+            panic!("memory allocation of {} bytes failed", layout.size());
+        }
+        ptr
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout)
+    }
+
+    unsafe fn alloc_zeroed(&self, layout: Layout) -> *mut u8 {
+        let ptr = System.alloc_zeroed(layout);
+        if ptr.is_null() {
+            panic!("memory allocation of {} bytes failed", layout.size());
+        }
+        ptr
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        let new_ptr = System.realloc(ptr, layout, new_size);
+        if new_ptr.is_null() {
+            panic!("memory reallocation of {} bytes failed", new_size);
+        }
+        new_ptr
+    }
+}
+
+#[global_allocator]
+static ALLOCATOR: PanicOnOomAllocator = PanicOnOomAllocator;
+
+// ---------------------------------------------------------------------------
+// Shared I/O helpers
+// ---------------------------------------------------------------------------
+
+/// Validate that inputs and outputs have the same length, all input files
+/// exist, and all output parent directories are created as needed.
+fn validate_io(inputs: &[String], outputs: &[String]) -> Result<()> {
+    validate_inputs(inputs, outputs)?;
+    create_output_dirs(outputs)
+}
+
+/// Create the missing parent directories of output files.
+fn create_output_dirs<S: AsRef<str>>(outputs: &[S]) -> Result<()> {
+    for s in outputs {
+        let p = PathBuf::from(s.as_ref());
+        if let Some(parent) = p.parent() {
+            if !parent.exists() {
+                std::fs::create_dir_all(parent).map_err(|e| {
+                    TinyImgRError::io(format!("Failed to create directory {}: {}", parent.display(), e))
+                })?;
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Check that the input and output vectors match and all inputs exist.
+fn validate_inputs(inputs: &[String], outputs: &[String]) -> Result<()> {
+    if inputs.len() != outputs.len() {
+        return Err(TinyImgRError::argument("Input and output vectors must have the same length").into());
+    }
+    for s in inputs {
+        if !PathBuf::from(s).exists() {
+            return Err(TinyImgRError::io(format!("Input file does not exist: {}", s)).into());
+        }
+    }
+    Ok(())
+}
+
+/// Refuse to overwrite existing output files, listing all of them.
+fn check_overwrite(outputs: &[String]) -> Result<()> {
+    let existing: Vec<&str> = outputs.iter().filter(|s| Path::new(s).exists()).map(|s| s.as_str()).collect();
+    if existing.is_empty() {
+        return Ok(());
+    }
+    Err(TinyImgRError::argument(format!(
+        "Output files already exist (set overwrite = TRUE to overwrite them):\n  {}",
+        existing.join("\n  ")
+    )).into())
+}
+
+/// Check an optimization level (0-6) and convert it for `png_options()`.
+fn check_level(level: i32) -> Result<u8> {
+    if !(0..=6).contains(&level) {
+        return Err(TinyImgRError::argument(format!("The optimization level must be between 0 and 6: {}", level)).into());
+    }
+    Ok(level as u8)
+}
+
+/// Print a line on the R console.  `Rprintf()` (behind `rprintln!`) writes
+/// the bytes as they are, so UTF-8 file names come out garbled on consoles
+/// that are not UTF-8 (e.g., RGui or cmd on Windows with a legacy code page).
+/// Lines with non-ASCII characters are printed by R's `cat()` instead, from a
+/// string marked as UTF-8, so R converts it for the console.
+fn console_println(line: &str) {
+    if line.is_ascii() || call!("cat", line, "\n", sep = "").is_err() {
+        rprintln!("{}", line);
+    }
+}
+
+/// Print text without a newline on the R console (see `console_println()`),
+/// and flush the console, so that a line being updated is shown.
+fn console_print(text: &str) {
+    if text.is_ascii() || call!("cat", text, sep = "").is_err() {
+        rprint!("{}", text);
+    }
+    let _ = call!("flush.console");
+}
+
+/// The progress of a batch, reported after each file: by calling an R
+/// function with the index of the file, the number of files, the file path,
+/// and the bytes saved (`NA` if it failed), or by showing a `[ i/n ] file`
+/// line.  On a terminal, the line is updated in place (and erased by
+/// `clear()` before other messages are printed); otherwise, it is printed
+/// about every tenth of the batch, so logs are not flooded.
+enum Progress {
+    None,
+    Callback(Function),
+    Line { updating: bool, width: usize },
+}
+
+struct BatchProgress {
+    progress: Progress,
+    done: usize,
+    total: usize,
+    // The line shows the paths as short as verbose messages do.
+    trunc: usize,
+}
+
+impl BatchProgress {
+    fn new(progress: Robj, inputs: &[String]) -> Self {
+        let (progress, trunc) = match (progress.as_function(), progress.as_bool()) {
+            (Some(f), _) => (Progress::Callback(f), 0),
+            (None, Some(updating)) => (Progress::Line { updating, width: 0 }, find_truncate_index(inputs)),
+            _ => (Progress::None, 0),
+        };
+        Self { progress, done: 0, total: inputs.len(), trunc }
+    }
+
+    /// Erase the updated line, so that the next message starts on a clean line.
+    fn clear(&mut self) {
+        if let Progress::Line { updating: true, width } = &mut self.progress {
+            if *width > 0 {
+                console_print(&format!("\r{}\r", " ".repeat(*width)));
+                *width = 0;
+            }
+        }
+    }
+
+    /// Report that a file has been processed.
+    fn update(&mut self, file: &str, saved: Option<f64>) -> std::result::Result<(), TinyImgRError> {
+        self.done += 1;
+        let (done, total) = (self.done, self.total);
+        match &mut self.progress {
+            Progress::None => {}
+            Progress::Callback(f) => {
+                f.call(pairlist!(done as i32, total as i32, file, saved.map_or(Rfloat::na(), Rfloat::from)))
+                    .map_err(|e| TinyImgRError::argument(format!("The progress function failed: {}", e)))?;
+            }
+            Progress::Line { updating, width } => {
+                let file = truncate_path(file, self.trunc);
+                let line = format!("[{:>w$}/{}] {}", done, total, file, w = total.to_string().len());
+                if *updating {
+                    let n = line.chars().count();
+                    console_print(&format!("\r{}{}", line, " ".repeat(width.saturating_sub(n))));
+                    *width = n;
+                } else if done == total || done * 10 / total != (done - 1) * 10 / total {
+                    console_println(&line);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// End the updated line after the batch, keeping its last state.
+    fn finish(&mut self) {
+        if let Progress::Line { updating: true, width } = &mut self.progress {
+            if *width > 0 {
+                console_print("\n");
+                *width = 0;
+            }
+        }
+    }
+}
+
+impl VerboseMessage {
+    /// Print the size-change summary and the removed chunks (main R thread
+    /// only).
+    fn print(&self, input_truncate_index: usize, output_truncate_index: usize) {
+        if let Some(line) = self.summary(input_truncate_index, output_truncate_index) {
+            console_println(&line);
+        }
+        if let Some(line) = self.removed_line() {
+            console_println(&line);
+        }
+    }
+}
+
+/// Iterate over validated input/output pairs, call `process_fn` on each, and
+/// optionally print verbose size-change summaries.  `process_fn` returns the
+/// sizes of the input and output data it read and wrote, so the summaries do
+/// not depend on the files on disk (which may be changed by other processes).
+///
+/// Each task sends its summary through a channel, and the main thread drains
+/// the channel between tasks, so tasks never call into R themselves and can
+/// be moved to worker threads.
+fn process_files<F>(
+    inputs: &[String],
+    outputs: &[String],
+    verbose: bool,
+    process_fn: F,
+) -> Result<()>
+where
+    F: Fn(&PathBuf, &PathBuf) -> Result<(u64, u64)>,
+{
+    let input_trunc  = if verbose { find_truncate_index(inputs)  } else { 0 };
+    let output_trunc = if verbose { find_truncate_index(outputs) } else { 0 };
+    let (tx, rx) = std::sync::mpsc::channel::<VerboseMessage>();
+    let drain = || for msg in rx.try_iter() { msg.print(input_trunc, output_trunc) };
+    for (input_str, output_str) in inputs.iter().zip(outputs.iter()) {
+        let input_path  = PathBuf::from(input_str);
+        let output_path = PathBuf::from(output_str);
+        let res = process_fn(&input_path, &output_path);
+        if let (true, Ok(sizes)) = (verbose, &res) {
+            // The receiver outlives all senders, so sending cannot fail.
+            let _ = tx.send(VerboseMessage::new(input_str, output_str, *sizes));
+        }
+        drain();
+        res?;
+    }
+    drop(tx);
+    drain();
+    Ok(())
+}
+
+// ---------------------------------------------------------------------------
+// PNG optimisation
+// ---------------------------------------------------------------------------
+
+/// Decode a PNG file into RGBA pixels (4 bytes per pixel, row-major) and its
+/// dimensions.
+fn read_png_rgba(input: &Path) -> Result<(Vec<u8>, usize, usize)> {
+    let image = lodepng::decode32_file(input)
+        .map_err(|e| TinyImgRError::decode(format!("Failed to read PNG {}: {}", input.display(), e)))?;
+    Ok((colors_into_bytes(colors_from_rgba(image.buffer)), image.width, image.height))
+}
+
+/// Encode RGBA pixels (4 bytes per pixel, row-major) as a PNG, optimize it
+/// with the default preset, and write it to `output`.
+fn write_png_rgba(output: &PathBuf, rgba: &[u8], width: usize, height: usize) -> Result<()> {
+    let png = lodepng::encode32(rgba, width, height)
+        .map_err(|e| TinyImgRError::encode(format!("Failed to encode PNG {}: {}", output.display(), e)))?;
+    let optimized = oxipng::optimize_from_memory(&png, &png_options(DEFAULT_PNG_LEVEL))
+        .map_err(|e| TinyImgRError::encode(format!("Failed to optimize {}: {}", output.display(), e)))?;
+    std::fs::write(output, optimized)
+        .map_err(|e| TinyImgRError::io(format!("Failed to write {}: {}", output.display(), e)).into())
+}
+
+/// Minimum relative saving below which `tinypng_impl()` suggests a lossy
+/// format for photographs in verbose mode.
+const PHOTO_HINT_SAVING: f64 = 0.05;
+
+/// A state file that records finished files of a batch, so that an
+/// interrupted batch can be resumed.  The first line holds a hash of the
+/// settings, and each following line an input and an output path separated
+/// by a tab.
+struct StateFile {
+    path: PathBuf,
+    file: std::fs::File,
+    done: HashSet<(String, String)>,
+}
+
+impl StateFile {
+    /// Open a state file, or create it if it does not exist.  If it was
+    /// written with different settings, it is reset, and `true` is returned
+    /// as the second value.
+    fn open(path: &str, settings_hash: &str) -> Result<(Self, bool)> {
+        let path = PathBuf::from(path);
+        let header = format!("# tinyimg state {}", settings_hash);
+        let content = std::fs::read_to_string(&path).unwrap_or_default();
+        let mut lines = content.lines();
+        let reset = match lines.next() {
+            Some(first) => first != header,
+            None => false,
+        };
+        let done = if reset { HashSet::new() } else {
+            lines.filter_map(|l| l.split_once('\t')).map(|(i, o)| (i.to_string(), o.to_string())).collect()
+        };
+        let err = |e: std::io::Error| TinyImgRError::io(format!("Failed to write state file {}: {}", path.display(), e));
+        let file = if content.is_empty() || reset {
+            let mut f = std::fs::File::create(&path).map_err(err)?;
+            writeln!(f, "{}", header).and_then(|_| f.sync_data()).map_err(err)?;
+            f
+        } else {
+            std::fs::OpenOptions::new().append(true).open(&path).map_err(err)?
+        };
+        Ok((Self { path, file, done }, reset))
+    }
+
+    fn is_done(&self, input: &str, output: &str) -> bool {
+        self.done.contains(&(input.to_string(), output.to_string()))
+    }
+
+    /// Record a finished file, syncing the write to disk.
+    fn record(&mut self, input: &str, output: &str) -> std::result::Result<(), TinyImgRError> {
+        writeln!(self.file, "{}\t{}", input, output)
+            .and_then(|_| self.file.sync_data())
+            .map_err(|e| TinyImgRError::io(format!("Failed to write state file {}: {}", self.path.display(), e)))
+    }
+}
+
+/// A checkpoint file that lists the output paths of the files of a batch that
+/// have been processed, one per line.  Unlike a `StateFile`, it is not tied to
+/// the settings, so it can be shared by batches with different settings.
+struct CheckpointFile {
+    path: PathBuf,
+    file: std::fs::File,
+    done: HashSet<String>,
+}
+
+impl CheckpointFile {
+    /// Open a checkpoint file: if `resume`, read the recorded outputs and
+    /// append to the file, otherwise start a new file.
+    fn open(path: &str, resume: bool) -> Result<Self> {
+        let path = PathBuf::from(path);
+        let err = |e: std::io::Error| TinyImgRError::io(format!("Failed to write checkpoint file {}: {}", path.display(), e));
+        let done = if resume {
+            std::fs::read_to_string(&path).unwrap_or_default().lines().map(|l| l.to_string()).collect()
+        } else {
+            HashSet::new()
+        };
+        let file = std::fs::OpenOptions::new()
+            .create(true).append(resume).write(true).truncate(!resume)
+            .open(&path).map_err(err)?;
+        Ok(Self { path, file, done })
+    }
+
+    /// Record the output of a processed file, syncing the write to disk.
+    fn record(&mut self, output: &str) -> std::result::Result<(), TinyImgRError> {
+        writeln!(self.file, "{}", output)
+            .and_then(|_| self.file.sync_data())
+            .map_err(|e| TinyImgRError::io(format!("Failed to write checkpoint file {}: {}", self.path.display(), e)))
+    }
+}
+
+/// A skiplist of the files that failed to be optimized, so that later batches
+/// skip them until their content changes.  Each line holds an input path, the
+/// CRC-32 of its content (in hex), and the error, separated by tabs.
+struct SkipList {
+    path: PathBuf,
+    entries: Vec<(String, String, String)>,
+}
+
+impl SkipList {
+    /// Read a skiplist (a missing file is an empty list).
+    fn open(path: &str) -> Self {
+        let entries = std::fs::read_to_string(path).unwrap_or_default().lines().filter_map(|l| {
+            let mut fields = l.splitn(3, '\t');
+            Some((fields.next()?.to_string(), fields.next()?.to_string(), fields.next().unwrap_or("").to_string()))
+        }).collect();
+        Self { path: PathBuf::from(path), entries }
+    }
+
+    fn content_hash(input: &str) -> std::result::Result<String, TinyImgRError> {
+        let bytes = std::fs::read(input)
+            .map_err(|e| TinyImgRError::io(format!("Failed to read {}: {}", input, e)))?;
+        Ok(format!("{:08x}", crc32fast::hash(&bytes)))
+    }
+
+    /// Whether a file is listed with its current content.
+    fn contains(&self, input: &str) -> std::result::Result<bool, TinyImgRError> {
+        match self.entries.iter().find(|e| e.0 == input) {
+            Some(entry) => Ok(entry.1 == Self::content_hash(input)?),
+            None => Ok(false),
+        }
+    }
+
+    /// Add a failed file to the list (replacing its old entry).
+    fn add(&mut self, input: &str, error: &str) -> std::result::Result<(), TinyImgRError> {
+        let hash = Self::content_hash(input)?;
+        self.entries.retain(|e| e.0 != input);
+        self.entries.push((input.to_string(), hash, error.replace(['\t', '\n', '\r'], " ")));
+        self.save()
+    }
+
+    /// Remove a file that has been optimized successfully from the list.
+    fn remove(&mut self, input: &str) -> std::result::Result<(), TinyImgRError> {
+        let n = self.entries.len();
+        self.entries.retain(|e| e.0 != input);
+        if self.entries.len() == n { Ok(()) } else { self.save() }
+    }
+
+    /// Rewrite the list atomically: write a temporary file next to it and
+    /// rename it, so that an interrupted write never leaves a partial list.
+    fn save(&self) -> std::result::Result<(), TinyImgRError> {
+        let err = |e: std::io::Error| TinyImgRError::io(format!("Failed to write skiplist {}: {}", self.path.display(), e));
+        let mut tmp = self.path.as_os_str().to_owned();
+        tmp.push(".tmp");
+        let content: String = self.entries.iter().map(|(p, h, e)| format!("{}\t{}\t{}\n", p, h, e)).collect();
+        std::fs::write(&tmp, content).and_then(|_| std::fs::rename(&tmp, &self.path)).map_err(err)
+    }
+}
+
+/// Parse the background color for measuring the lossy error of translucent
+/// pixels (an empty string means no background).  Its alpha is ignored.
+fn parse_background(color: &str) -> Result<Option<[u8; 3]>> {
+    if color.is_empty() {
+        return Ok(None);
+    }
+    let [r, g, b, _] = edit::parse_color(color)?;
+    Ok(Some([r, g, b]))
+}
+
+/// Parse the lossy thresholds of graphics, mixed content, and photographs for
+/// `lossy = "auto"` (an empty vector means no automatic thresholds).
+fn parse_auto_thresholds(thresholds: &Doubles) -> Result<Option<AutoThresholds>> {
+    let t: Vec<f64> = thresholds.iter().map(|x| x.inner()).collect();
+    match t[..] {
+        [] => Ok(None),
+        [graphic, mixed, photo] if t.iter().all(|x| x.is_finite()) => {
+            Ok(Some(AutoThresholds { graphic, mixed, photo }))
+        }
+        _ => Err(TinyImgRError::argument(
+            "The automatic lossy thresholds must be three finite numbers (graphic, mixed, and photo)"
+        ).into()),
+    }
+}
+
+/// Parse the size breaks (in bytes) and levels of `level = "auto"` (both empty
+/// for a fixed level).
+fn parse_auto_levels(breaks: &Doubles, values: &Integers) -> Result<Option<AutoLevels>> {
+    if breaks.is_empty() && values.is_empty() {
+        return Ok(None);
+    }
+    if breaks.iter().any(|b| b.is_na() || b.inner() < 0.0) || values.iter().any(|v| v.is_na() || v.inner() < 0) {
+        return Err(TinyImgRError::argument(
+            "The size breaks and automatic levels must be non-negative numbers"
+        ).into());
+    }
+    let breaks = breaks.iter().map(|b| b.inner() as u64).collect();
+    let values = values.iter().map(|v| v.inner().min(u8::MAX as i32) as u8).collect();
+    Ok(Some(AutoLevels::new(breaks, values)?))
+}
+
+/// Optimize PNG files using oxipng
+///
+/// @param input Vector of input PNG file paths
+/// @param output Vector of output PNG file paths (same length as input)
+/// @param level Optimization level (0-6)
+/// @param alpha Optimize transparent pixels (may be lossy but visually lossless)
+/// @param preserve Preserve file permissions and timestamps
+/// @param verbose Verbosity: `0` for none, `1` to print file size reduction
+///   info, and `2` to also list the removed chunk types
+/// @param lossy Maximum CIE76 Delta E threshold
+/// @param lossy_l,lossy_a,lossy_b Per-channel thresholds for |ΔL*|, |Δa*|, and
+///   |Δb*| (`<= 0` to use `lossy`)
+/// @param white_point Reference white for L*a*b* (`"d65"`, `"d50"`, `"d55"`, or `"e"`)
+/// @param lossy_background Hex color to composite translucent pixels over
+///   when measuring the lossy error (`""` for none)
+/// @param auto_thresholds Lossy thresholds for graphics, mixed content, and
+///   photographs to choose the threshold of each image by its class
+///   (overriding `lossy`), or an empty vector
+/// @param auto_level_breaks,auto_level_values File size breaks (in bytes) and
+///   the optimization levels for the sizes below, between, and above them to
+///   choose the level of each file by its size (overriding `level`), or empty
+///   vectors
+/// @param reproducible Make the output bytes depend only on the input and the
+///   settings (see `PngSettings::with_reproducible()`)
+/// @param convert_profile Convert images with color profiles to sRGB instead
+///   of only stripping the profiles
+/// @param metadata_backup A directory or a suffix (starting with `.`) for
+///   sidecar files to save the stripped metadata chunks to (`""` for none)
+/// @param overwrite Whether to overwrite existing output files (if not, an
+///   error lists them before any file is processed)
+/// @param state Path to a state file to resume an interrupted batch (`""` for none)
+/// @param checkpoint_file Path to a file to record the output paths of the
+///   processed files in (`""` for none)
+/// @param skip_checkpointed Whether to skip the files whose output paths are
+///   already in `checkpoint_file` (otherwise the file is started anew)
+/// @param skiplist Path to a file to record the files that failed in, and to
+///   skip them in later runs unless their content changed (`""` for none)
+/// @param retry_failed Whether to retry the files in `skiplist`
+/// @param checksums Path to a SHA-256 checksum manifest to write for the
+///   written outputs (`""` for none)
+/// @param force_color_type The color type to write all outputs in (`"rgba"`,
+///   `"rgb"`, `"palette"`, `"gray"`, or `"gray_alpha"`), or `""` to let
+///   oxipng choose
+/// @param group_by_dir The number of directory levels (after the common
+///   prefix of the input paths) to total the file sizes by, or `0` for no
+///   totals; the totals are printed as a table in verbose mode
+/// @param sample_files The number of files to choose at random from the batch
+///   and process (the others are left alone), or `0` to process all files
+/// @param seed The seed of the random choice of `sample_files`
+/// @param lossy_speed The speed of lossy palette reduction, from `1`
+///   (slowest) to `10` (fastest); see `LossySpeed`
+/// @param threads The number of files to optimize at the same time (`0` for
+///   the number of available CPUs, `1` to optimize them one by one)
+/// @param dry_run Whether to optimize the files in memory only and report
+///   their sizes, without writing any files or creating directories
+/// @param no_grow Whether to copy the input to the output when the optimized
+///   data would be larger
+/// @param stop_on_error Whether to stop at the first file that fails (`FALSE`
+///   to process all files and report the failures in `files$error`)
+/// @param progress `NULL` for no progress report, a function to be called
+///   after each file, or whether to update a progress line in place (`TRUE`)
+///   or print it from time to time (`FALSE`); see `BatchProgress`
+/// @param strip The metadata chunks to strip (see `parse_strip()`)
+/// @param zopfli The number of iterations to also try Zopfli with (`0` not to
+///   try it)
+/// @param fix_errors Whether to salvage files with wrong CRCs or a malformed
+///   `tRNS` chunk (see `PngSettings::with_fix_errors()`)
+/// @param interlace The interlacing of the outputs: `"keep"`, `"remove"`, or
+///   `"adam7"`
+/// @param filters The row filters to try (see `parse_filters()`; empty for
+///   those of the preset level)
+/// @param reduce_bit_depth,reduce_color_type,reduce_palette Whether to enable
+///   the lossless reductions of oxipng (see `PngSettings::with_reductions()`)
+/// @param scale_16 Whether to scale 16-bit images down to 8 bits
+/// @param force Whether to always write the optimized files, even if they are
+///   larger than the inputs
+/// @param timeout The time limit of each file in seconds (0 for none), after
+///   which the best result so far is kept (see `PngSettings::with_timeout()`)
+/// @param colors The palette size of lossy palette reduction (0 to choose the
+///   smallest size within `lossy`; see `PngSettings::with_colors()`)
+/// @param dither The dithering of lossy palette reduction (see `Dither`; `""`
+///   for that of `lossy_speed`)
+/// @return A list of whether the state file was reset because the settings
+///   changed (`reset`), the warnings of the files (`warnings`), the totals by
+///   directory (`groups`, a list of `dir`, `files`, `input_size`, and
+///   `output_size`, or `NULL`), the indices of the sampled files (`sampled`,
+///   or `NULL`), and the sizes of the batch estimated from the sample
+///   (`estimate`, a list of `files`, `sampled`, `input_size`,
+///   `sample_input_size`, `sample_output_size`, and `output_size`, or `NULL`),
+///   and the results of the processed files (`files`, a list of `input`,
+///   `output`, `input_size`, `output_size`, and the percent `reduction`, which
+///   are `NA` for failed files, whether the output is a `copied` input (which
+///   could not be made smaller) instead of a fresh compression, whether the
+///   file `timed_out`, the palette size of lossy files (`colors`, `NA` for
+///   the other files), and the `error` message, which is `NA` for the other
+///   files)
+/// @export
+#[extendr]
+#[allow(clippy::too_many_arguments)]
+fn tinypng_impl(
+    input: Strings,
+    output: Strings,
+    level: i32,
+    alpha: bool,
+    preserve: bool,
+    verbose: i32,
+    lossy: f64,
+    lossy_l: f64,
+    lossy_a: f64,
+    lossy_b: f64,
+    white_point: &str,
+    lossy_background: &str,
+    auto_thresholds: Doubles,
+    auto_level_breaks: Doubles,
+    auto_level_values: Integers,
+    reproducible: bool,
+    convert_profile: bool,
+    metadata_backup: &str,
+    overwrite: bool,
+    state: &str,
+    checkpoint_file: &str,
+    skip_checkpointed: bool,
+    skiplist: &str,
+    retry_failed: bool,
+    checksums: &str,
+    force_color_type: &str,
+    group_by_dir: i32,
+    sample_files: i32,
+    seed: f64,
+    lossy_speed: i32,
+    threads: i32,
+    dry_run: bool,
+    no_grow: bool,
+    stop_on_error: bool,
+    progress: Robj,
+    strip: Strings,
+    zopfli: i32,
+    fix_errors: bool,
+    interlace: &str,
+    filters: Strings,
+    reduce_bit_depth: bool,
+    reduce_color_type: bool,
+    reduce_palette: bool,
+    scale_16: bool,
+    force: bool,
+    timeout: f64,
+    colors: i32,
+    dither: &str,
+) -> Result<List> {
+    if threads < 0 {
+        return Err(TinyImgRError::argument("The number of threads must be a non-negative integer").into());
+    }
+    if !(0..=256).contains(&colors) {
+        return Err(TinyImgRError::argument("The number of colors must be between 1 and 256 (or 0)").into());
+    }
+    let zopfli = u8::try_from(zopfli).map_err(|_| TinyImgRError::argument(
+        "The number of Zopfli iterations must be an integer from 0 (no Zopfli) to 255"
+    ))?;
+    // A dry run writes nothing, not even the bookkeeping files of the batch.
+    let (state, checkpoint_file, skiplist, checksums) = if dry_run { ("", "", "", "") } else {
+        (state, checkpoint_file, skiplist, checksums)
+    };
+    let mut inputs: Vec<String>  = input.iter().map(|s| s.to_string()).collect();
+    let mut outputs: Vec<String> = output.iter().map(|s| s.to_string()).collect();
+    // Only process a random sample of the batch, and remember the total input
+    // size of the batch to extrapolate the savings of the sample to it.
+    let sample = if sample_files <= 0 { None } else {
+        if inputs.len() != outputs.len() {
+            return Err(TinyImgRError::argument("Input and output vectors must have the same length").into());
+        }
+        let mut input_size = 0;
+        for s in &inputs {
+            input_size += match std::fs::metadata(s) {
+                Ok(m) => m.len(),
+                Err(_) if !stop_on_error => 0,
+                Err(_) => return Err(TinyImgRError::io(format!("Input file does not exist: {}", s)).into()),
+            };
+        }
+        let idx = random_sample(inputs.len(), sample_files as usize, seed as u64);
+        let files = inputs.len();
+        inputs = idx.iter().map(|&i| inputs[i].clone()).collect();
+        outputs = idx.iter().map(|&i| outputs[i].clone()).collect();
+        Some((idx, files, input_size))
+    };
+    // Without `stop_on_error`, missing inputs fail one by one in the batch.
+    if stop_on_error {
+        validate_inputs(&inputs, &outputs)?;
+    } else if inputs.len() != outputs.len() {
+        return Err(TinyImgRError::argument("Input and output vectors must have the same length").into());
+    }
+    if !dry_run {
+        let existing: Vec<&String> = inputs.iter().zip(&outputs).filter(|(i, _)| Path::new(i).exists()).map(|(_, o)| o).collect();
+        create_output_dirs(&existing)?;
+    }
+
+    let (verbose, list_removed) = (verbose > 0, verbose > 1);
+    let channels = [lossy_l, lossy_a, lossy_b];
+    let background = parse_background(lossy_background)?;
+    let auto = parse_auto_thresholds(&auto_thresholds)?;
+    if colors > 0 && (lossy > 0.0 || channels.iter().any(|&t| t > 0.0) || auto.is_some()) {
+        return Err(TinyImgRError::argument("The number of colors cannot be used with a positive lossy threshold").into());
+    }
+    let auto_levels = parse_auto_levels(&auto_level_breaks, &auto_level_values)?;
+    let level_key = auto_levels.as_ref().map(|levels| format!("|levels{:?}", levels));
+    let color_type = if force_color_type.is_empty() { None } else { Some(ColorType::from_name(force_color_type)?) };
+    let dither = if dither.is_empty() { None } else { Some(Dither::from_name(dither)?) };
+    let strip_names: Vec<&str> = strip.iter().map(|s| s.as_str()).collect();
+    let filter_names: Vec<&str> = filters.iter().map(|s| s.as_str()).collect();
+    let settings = PngSettings::new(level, alpha, preserve, lossy, channels, white_point)?
+        .with_background(background)
+        .with_auto_thresholds(auto)
+        .with_auto_levels(auto_levels)
+        .with_reproducible(reproducible)
+        .with_profile_conversion(convert_profile)
+        .with_metadata_backup((!metadata_backup.is_empty()).then(|| metadata_backup.to_string()))
+        .with_color_type(color_type)
+        .with_lossy_speed(LossySpeed::new(lossy_speed)?)
+        .with_dry_run(dry_run)
+        .with_checksum(!checksums.is_empty())
+        .with_classify(verbose.then_some(PHOTO_HINT_SAVING))
+        .with_no_grow(no_grow)
+        .with_strip(parse_strip(&strip_names)?)
+        .with_zopfli(std::num::NonZeroU8::new(zopfli))
+        .with_fix_errors(fix_errors)
+        .with_interlace(parse_interlace(interlace)?)
+        .with_filters(parse_filters(&filter_names)?)
+        .with_reductions(reduce_bit_depth, reduce_color_type, reduce_palette)
+        .with_scale_16(scale_16)
+        .with_force(force)
+        .with_timeout((timeout > 0.0).then(|| std::time::Duration::from_secs_f64(timeout)))
+        .with_colors(colors as usize)
+        .with_dither(dither);
+
+    // Skip the files that a previous run with the same settings has finished.
+    let (mut state, reset) = if state.is_empty() { (None, false) } else {
+        let mut key = format!("{}|{}|{}|{}|{:?}|{}", level, alpha, preserve, lossy, channels, white_point);
+        // Keep the keys of state files written without a background valid.
+        if let Some(bg) = background {
+            key.push_str(&format!("|{:?}", bg));
+        }
+        if let Some(t) = auto {
+            key.push_str(&format!("|auto{:?}", [t.graphic, t.mixed, t.photo]));
+        }
+        if convert_profile {
+            key.push_str("|convert");
+        }
+        if let Some(levels) = &level_key {
+            key.push_str(levels);
+        }
+        if let Some(color_type) = color_type {
+            key.push_str(&format!("|{}", color_type.name()));
+        }
+        if lossy_speed != DEFAULT_LOSSY_SPEED as i32 {
+            key.push_str(&format!("|speed{}", lossy_speed));
+        }
+        if !no_grow {
+            key.push_str("|grow");
+        }
+        if strip_names != ["all"] {
+            key.push_str(&format!("|strip{:?}", strip_names));
+        }
+        if zopfli > 0 {
+            key.push_str(&format!("|zopfli{}", zopfli));
+        }
+        if interlace != "remove" {
+            key.push_str(&format!("|interlace{}", interlace));
+        }
+        if !filter_names.is_empty() {
+            key.push_str(&format!("|filters{:?}", filter_names));
+        }
+        if !(reduce_bit_depth && reduce_color_type && reduce_palette) {
+            key.push_str(&format!("|reduce{}{}{}", reduce_bit_depth as u8, reduce_color_type as u8, reduce_palette as u8));
+        }
+        if scale_16 {
+            key.push_str("|scale16");
+        }
+        if force {
+            key.push_str("|force");
+        }
+        if timeout > 0.0 {
+            key.push_str(&format!("|timeout{}", timeout));
+        }
+        if colors > 0 {
+            key.push_str(&format!("|colors{}", colors));
+        }
+        if let Some(dither) = dither {
+            key.push_str(&format!("|dither-{}", dither.name()));
+        }
+        if reproducible {
+            key.push_str("|reproducible");
+        }
+        if !metadata_backup.is_empty() {
+            key.push_str(&format!("|backup{}", metadata_backup));
+        }
+        if fix_errors {
+            key.push_str("|fix");
+        }
+        let (state, reset) = StateFile::open(state, &format!("{:08x}", crc32fast::hash(key.as_bytes())))?;
+        let trunc = if verbose { find_truncate_index(&inputs) } else { 0 };
+        let (mut todo_in, mut todo_out) = (Vec::new(), Vec::new());
+        for (i, o) in inputs.into_iter().zip(outputs) {
+            if !state.is_done(&i, &o) {
+                todo_in.push(i);
+                todo_out.push(o);
+            } else if verbose {
+                console_println(&format!("{} | resumed-skip", truncate_path(&i, trunc)));
+            }
+        }
+        (inputs, outputs) = (todo_in, todo_out);
+        (Some(state), reset)
+    };
+    // Skip the files whose outputs are listed in the checkpoint file.
+    let mut checkpoint = if checkpoint_file.is_empty() { None } else {
+        let checkpoint = CheckpointFile::open(checkpoint_file, skip_checkpointed)?;
+        let trunc = if verbose { find_truncate_index(&inputs) } else { 0 };
+        let (mut todo_in, mut todo_out) = (Vec::new(), Vec::new());
+        for (i, o) in inputs.into_iter().zip(outputs) {
+            if !checkpoint.done.contains(&o) {
+                todo_in.push(i);
+                todo_out.push(o);
+            } else if verbose {
+                console_println(&format!("{} | checkpoint-skip", truncate_path(&i, trunc)));
+            }
+        }
+        (inputs, outputs) = (todo_in, todo_out);
+        Some(checkpoint)
+    };
+    // Skip the files that failed before, unless their content has changed.
+    let mut skiplist = if skiplist.is_empty() { None } else {
+        let list = SkipList::open(skiplist);
+        if !retry_failed {
+            let trunc = if verbose { find_truncate_index(&inputs) } else { 0 };
+            let (mut todo_in, mut todo_out) = (Vec::new(), Vec::new());
+            for (i, o) in inputs.into_iter().zip(outputs) {
+                if !list.contains(&i)? {
+                    todo_in.push(i);
+                    todo_out.push(o);
+                } else if verbose {
+                    console_println(&format!("{} | skiplisted", truncate_path(&i, trunc)));
+                }
+            }
+            (inputs, outputs) = (todo_in, todo_out);
+        }
+        Some(list)
+    };
+    // Check before processing any file, so that nothing is written on error.
+    if !overwrite {
+        check_overwrite(&outputs)?;
+    }
+
+    if verbose && zopfli > 0 && !inputs.is_empty() {
+        console_println(&format!(
+            "Compressing with Zopfli ({} iterations) instead of the default deflater, which is \
+             often 10 to 100 times slower (fewer iterations and lower levels are faster)",
+            zopfli
+        ));
+    }
+    let mut progress = BatchProgress::new(progress, &inputs);
+    let (input_trunc, output_trunc) = if verbose {
+        (find_truncate_index(&inputs), find_truncate_index(&outputs))
+    } else { (0, 0) };
+    let files = inputs.iter().zip(&outputs).map(|(i, o)| (PathBuf::from(i), PathBuf::from(o))).collect();
+    // With a skiplist, failed files are recorded and the batch goes on.
+    let keep_going = skiplist.is_some() || !stop_on_error;
+    let batch = BatchOptions { settings, files, keep_going, threads: threads as usize };
+    // In verbose mode, a hint is shown (once) if a photograph barely shrinks.
+    let mut photo_hint: Option<PathBuf> = None;
+    let mut warnings = Vec::new();
+    // The SHA-256 hashes of the written outputs for the checksum manifest.
+    let mut hashes = Vec::new();
+    // The sizes of the optimized files to total by directory.
+    let mut sizes = Vec::new();
+    let results = optimize_batch(&batch, |res| {
+        let (input, output) = (res.input.to_string_lossy(), res.output.to_string_lossy());
+        let &(input_size, output_size) = match res.sizes.as_ref() {
+            Ok(sizes) => sizes,
+            Err(e) => {
+                if let Some(list) = &mut skiplist {
+                    list.add(&input, &e.to_string())?;
+                }
+                if verbose && keep_going {
+                    progress.clear();
+                    console_println(&format!("{} | failed", truncate_path(&input, input_trunc)));
+                }
+                return progress.update(&input, None);
+            }
+        };
+        warnings.extend(res.warning.clone());
+        if let Some(list) = &mut skiplist {
+            list.remove(&input)?;
+        }
+        if let Some(hash) = &res.sha256 {
+            hashes.push((res.output.clone(), hash.clone()));
+        }
+        if let Some(state) = &mut state {
+            state.record(&input, &output)?;
+        }
+        if let Some(checkpoint) = &mut checkpoint {
+            checkpoint.record(&output)?;
+        }
+        if group_by_dir > 0 || sample.is_some() {
+            sizes.push((input.to_string(), input_size, output_size));
+        }
+        if verbose {
+            progress.clear();
+            VerboseMessage::new(&input, &output, (input_size, output_size))
+                .with_auto(res.auto)
+                .with_level(res.level)
+                .with_colors(res.colors)
+                .with_timed_out(res.timed_out)
+                .with_removed(if list_removed { &res.removed } else { &[] })
+                .print(input_trunc, output_trunc);
+        }
+        if verbose && photo_hint.is_none()
+            && (output_size as f64) > input_size as f64 * (1.0 - PHOTO_HINT_SAVING)
+            && res.class == Some(ImageClass::Photo) {
+            photo_hint = Some(res.input.clone());
+        }
+        progress.update(&input, Some(input_size as f64 - output_size as f64))
+    });
+    progress.finish();
+    if !checksums.is_empty() {
+        checksum::write_checksums(Path::new(checksums), &hashes)?;
+    }
+    // The sizes of the processed files (`NA` for failed ones) for R.
+    let file_sizes: Vec<Option<(u64, u64)>> = results.iter().map(|res| res.sizes.as_ref().ok().copied()).collect();
+    let file_column = |f: fn(u64, u64) -> f64| Doubles::from_values(
+        file_sizes.iter().map(|s| s.map_or(Rfloat::na(), |(i, o)| Rfloat::from(f(i, o))))
+    );
+    let files = list!(
+        input = results.iter().map(|res| res.input.to_string_lossy().into_owned()).collect::<Vec<_>>(),
+        output = results.iter().map(|res| res.output.to_string_lossy().into_owned()).collect::<Vec<_>>(),
+        input_size = file_column(|i, _| i as f64),
+        output_size = file_column(|_, o| o as f64),
+        reduction = file_column(|i, o| if i == 0 { 0.0 } else { (1.0 - o as f64 / i as f64) * 100.0 }),
+        copied = Logicals::from_values(results.iter().map(|res| match &res.sizes {
+            Ok(_) => Rbool::from(res.copied),
+            Err(_) => Rbool::na(),
+        })),
+        timed_out = Logicals::from_values(results.iter().map(|res| match &res.sizes {
+            Ok(_) => Rbool::from(res.timed_out),
+            Err(_) => Rbool::na(),
+        })),
+        colors = Integers::from_values(results.iter().map(|res| match res.colors {
+            Some(n) => Rint::from(n as i32),
+            None => Rint::na(),
+        })),
+        error = Strings::from_values(results.iter().map(|res| match &res.sizes {
+            Ok(_) => Rstr::na(),
+            Err(e) => Rstr::from(e.to_string()),
+        }))
+    );
+    for res in results {
+        match res.sizes {
+            // Failures are recorded in the skiplist, so they are only warnings.
+            Err(e) if skiplist.is_some() => warnings.push(format!("{} (added to the skiplist)", e)),
+            // Other failures are listed from the `error` column by R.
+            Err(_) if keep_going => {}
+            Err(e) => return Err(e.into()),
+            Ok(_) => {}
+        }
+    }
+    if let Some(path) = photo_hint {
+        console_println(&format!(
+            "Hint: {} looks like a photograph, which PNG cannot compress well; \
+             consider a lossy format like JPEG or WebP (see png_classify()).",
+            path.display()
+        ));
+    }
+    let groups = (group_by_dir > 0).then(|| group_sizes_by_dir(&sizes, find_truncate_index(&inputs), group_by_dir as usize));
+    if let (true, Some(groups)) = (verbose, &groups) {
+        for line in format_dir_groups(groups) {
+            console_println(&line);
+        }
+    }
+    let groups: Robj = groups.map_or(NULL.into(), |groups| list!(
+        dir = groups.iter().map(|g| g.dir.clone()).collect::<Vec<_>>(),
+        files = groups.iter().map(|g| g.files as i32).collect::<Vec<_>>(),
+        input_size = groups.iter().map(|g| g.input_size as f64).collect::<Vec<_>>(),
+        output_size = groups.iter().map(|g| g.output_size as f64).collect::<Vec<_>>()
+    ).into());
+    let (sampled, estimate): (Robj, Robj) = match sample {
+        None => (NULL.into(), NULL.into()),
+        Some((idx, files, input_size)) => {
+            let estimate = SampleEstimate {
+                files, sampled: sizes.len(), input_size,
+                sample_input_size: sizes.iter().map(|s| s.1).sum(),
+                sample_output_size: sizes.iter().map(|s| s.2).sum(),
+            };
+            if verbose {
+                console_println(&estimate.summary());
+            }
+            let sampled: Vec<i32> = idx.iter().map(|&i| i as i32 + 1).collect();
+            (sampled.into(), list!(
+                files = files as i32, sampled = estimate.sampled as i32, input_size = input_size as f64,
+                sample_input_size = estimate.sample_input_size as f64,
+                sample_output_size = estimate.sample_output_size as f64,
+                output_size = estimate.output_size() as f64
+            ).into())
+        }
+    };
+    Ok(list!(
+        reset = reset, warnings = warnings, groups = groups, sampled = sampled, estimate = estimate, files = files
+    ))
+}
+
+/// Longest side of the thumbnail used to estimate PNG compressibility.
+const SIMULATE_SIZE: usize = 64;
+
+/// Estimate how well a PNG file compresses: decode it, shrink it to fit in a
+/// `SIMULATE_SIZE` square by nearest-neighbour sampling (which, unlike
+/// averaging, keeps noise and texture that affect compression), optimize the
+/// thumbnail, and return its compressed size relative to its raw RGBA size.
+fn simulate_png_compression(input: &PathBuf, opts: &Options) -> Result<f64> {
+    let image = lodepng::decode32_file(input)
+        .map_err(|e| TinyImgRError::decode(format!("Failed to read PNG {}: {}", input.display(), e)))?;
+    let (w, h) = (image.width, image.height);
+    let scale = (w.max(h) as f64 / SIMULATE_SIZE as f64).max(1.0);
+    let tw = ((w as f64 / scale).round() as usize).max(1);
+    let th = ((h as f64 / scale).round() as usize).max(1);
+    let mut thumb = Vec::with_capacity(tw * th);
+    for y in 0..th {
+        let sy = (y * h / th).min(h - 1);
+        for x in 0..tw {
+            thumb.push(image.buffer[sy * w + (x * w / tw).min(w - 1)]);
+        }
+    }
+    let png = lodepng::encode32(&thumb, tw, th)
+        .map_err(|e| TinyImgRError::encode(format!("Failed to encode thumbnail of {}: {}", input.display(), e)))?;
+    let optimized = oxipng::optimize_from_memory(&png, opts)
+        .map_err(|e| TinyImgRError::encode(format!("Failed to optimize thumbnail of {}: {}", input.display(), e)))?;
+    Ok(optimized.len() as f64 / (tw * th * 4) as f64)
+}
+
+/// Estimate the compressibility of PNG files from thumbnails
+///
+/// @param input Vector of input PNG file paths
+/// @param level Optimization level (0-6)
+/// @return Ratios of compressed to uncompressed thumbnail sizes
+/// @export
+#[extendr]
+fn tinypng_simulate_compression_impl(input: Strings, level: i32) -> Result<Doubles> {
+    let opts = png_options(check_level(level)?);
+    let mut ratios = Vec::with_capacity(input.len());
+    for s in input.iter() {
+        let path = PathBuf::from(s.as_str());
+        if !path.exists() {
+            return Err(TinyImgRError::io(format!("Input file does not exist: {}", s.as_str())).into());
+        }
+        ratios.push(simulate_png_compression(&path, &opts)?);
+    }
+    Ok(Doubles::from_values(ratios))
+}
+
+/// Substitute `{level}` and `{lossy}` in an output path template.
+fn fill_output_template(template: &str, level: u8, lossy: f64) -> String {
+    template
+        .replace("{level}", &level.to_string())
+        .replace("{lossy}", &lossy.to_string())
+}
+
+/// Join scoped worker threads, turning a panic into an error.
+fn join_all<T>(
+    handles: Vec<std::thread::ScopedJoinHandle<'_, std::result::Result<T, TinyImgRError>>>,
+) -> std::result::Result<Vec<T>, TinyImgRError> {
+    handles
+        .into_iter()
+        .map(|h| h.join().unwrap_or_else(|_| Err(TinyImgRError::encode("A worker thread panicked"))))
+        .collect()
+}
+
+/// Run `f` on the items in scoped worker threads, at most `jobs` at a time, and
+/// return the results in the order of the items.
+fn parallel_map<'a, I: Sync, T: Send>(
+    items: &'a [I], jobs: usize, f: impl Fn(&'a I) -> std::result::Result<T, TinyImgRError> + Sync,
+) -> std::result::Result<Vec<T>, TinyImgRError> {
+    let f = &f;
+    let mut results = Vec::with_capacity(items.len());
+    for chunk in items.chunks(jobs.max(1)) {
+        results.extend(std::thread::scope(|s| join_all(chunk.iter().map(|item| s.spawn(move || f(item))).collect()))?);
+    }
+    Ok(results)
+}
+
+/// Optimize a PNG file at several levels and lossy thresholds
+///
+/// @param input Path to the input PNG file
+/// @param output_template Output path containing `{level}` and/or `{lossy}`
+/// @param levels Optimization levels (0-6)
+/// @param lossy_values Maximum CIE76 Delta E thresholds (`<= 0` for lossless)
+/// @param jobs Maximum number of worker threads (`<= 0` for the number of
+///   available CPUs)
+/// @return Output paths for all combinations of levels and lossy thresholds
+///   (the lossy thresholds vary fastest)
+/// @export
+#[extendr]
+fn tinypng_multi_level_output_impl(
+    input: &str, output_template: &str, levels: Integers, lossy_values: Doubles, jobs: i32,
+) -> Result<Strings> {
+    let input_path = PathBuf::from(input);
+    if !input_path.exists() {
+        return Err(TinyImgRError::io(format!("Input file does not exist: {}", input)).into());
+    }
+    let levels = levels.iter().map(|l| check_level(l.inner())).collect::<Result<Vec<u8>>>()?;
+    let lossy_values: Vec<f64> = lossy_values.iter().map(|l| l.inner()).collect();
+    let combos: Vec<(u8, usize)> = levels
+        .iter()
+        .flat_map(|&l| (0..lossy_values.len()).map(move |j| (l, j)))
+        .collect();
+    let outputs: Vec<String> = combos
+        .iter()
+        .map(|&(l, j)| fill_output_template(output_template, l, lossy_values[j]))
+        .collect();
+    if outputs.iter().collect::<HashSet<_>>().len() < outputs.len() {
+        return Err(TinyImgRError::argument(format!(
+            "Output paths are not unique; the template '{}' needs {{level}} and {{lossy}} placeholders",
+            output_template
+        )).into());
+    }
+
+    // Decode once and share the pixels and the L*a*b* samples across all
+    // lossy thresholds.
+    let (pixels, width, height) = read_png_colors(&input_path)?;
+    let lab = LabConverter::new("d65").expect("d65 is a known white point");
+    let samples = LabSamples::new(&pixels, &lab);
+    let jobs = if jobs > 0 { jobs as usize } else { cpus::detect_cpus() };
+
+    // Reduce the palette once per lossy threshold, then optimize the encoded
+    // PNG at each level.
+    let encoded = parallel_map(&lossy_values, jobs, |&lossy| {
+        if lossy > 0.0 {
+            let q = quantize_pixels(&pixels, width, &samples, LossyThreshold::DeltaE(lossy), &lab);
+            encode_png_colors(&q, width, height).map_err(TinyImgRError::encode)
+        } else {
+            encode_png_colors(&pixels, width, height).map_err(TinyImgRError::encode)
+        }
+    })?;
+    let tasks: Vec<_> = combos.iter().zip(&outputs).collect();
+    parallel_map(&tasks, jobs, |&(&(level, j), output)| {
+        let optimized = oxipng::optimize_from_memory(&encoded[j], &png_options(level))
+            .map_err(|e| TinyImgRError::encode(format!("Failed to optimize {}: {}", output, e)))?;
+        std::fs::write(output, optimized)
+            .map_err(|e| TinyImgRError::io(format!("Failed to write {}: {}", output, e)))
+    })?;
+    Ok(Strings::from_values(outputs))
+}
+
+/// Benchmark PNG optimization levels and lossy thresholds
+///
+/// Every combination is optimized in memory (nothing is written), one after
+/// another so that the timings do not compete for the CPU.  Each file is
+/// decoded once for all lossy thresholds, and its palette is reduced once per
+/// threshold for all levels.
+///
+/// @param input Vector of PNG file paths
+/// @param levels Optimization levels (0-6)
+/// @param lossy_values Maximum CIE76 Delta E thresholds (`<= 0` for lossless)
+/// @return A list of the file paths, levels, lossy thresholds, input sizes,
+///   optimized sizes, and elapsed milliseconds (the palette reduction and the
+///   optimization), with one element per file and combination (the levels
+///   vary fastest)
+/// @export
+#[extendr]
+fn tinybench_impl(input: Strings, levels: Integers, lossy_values: Doubles) -> Result<List> {
+    let levels = levels.iter().map(|l| check_level(l.inner())).collect::<Result<Vec<u8>>>()?;
+    let lossy_values: Vec<f64> = lossy_values.iter().map(|l| l.inner()).collect();
+    let lab = LabConverter::new("d65").expect("d65 is a known white point");
+    let (mut files, mut level_col, mut lossy_col) = (Vec::new(), Vec::new(), Vec::new());
+    let (mut input_sizes, mut sizes, mut elapsed_ms) = (Vec::new(), Vec::new(), Vec::new());
+    for s in input.iter() {
+        let path = PathBuf::from(s.as_str());
+        let data = std::fs::read(&path)
+            .map_err(|e| TinyImgRError::io(format!("Failed to read {}: {}", path.display(), e)))?;
+        // The pixels and their L*a*b* samples are shared by all thresholds.
+        let decoded = if lossy_values.iter().any(|&l| l > 0.0) {
+            let (pixels, width, height) = decode_png_colors(&data, &path)?;
+            let samples = LabSamples::new(&pixels, &lab);
+            Some((pixels, width, height, samples))
+        } else {
+            None
+        };
+        for &lossy in &lossy_values {
+            let start = std::time::Instant::now();
+            let png = match &decoded {
+                Some((pixels, width, height, samples)) if lossy > 0.0 => {
+                    let q = quantize_pixels(pixels, *width, samples, LossyThreshold::DeltaE(lossy), &lab);
+                    std::borrow::Cow::Owned(encode_png_colors(&q, *width, *height).map_err(TinyImgRError::encode)?)
+                }
+                _ => std::borrow::Cow::Borrowed(&data),
+            };
+            let reduction = start.elapsed();
+            for &level in &levels {
+                let start = std::time::Instant::now();
+                let optimized = oxipng::optimize_from_memory(&png, &png_options(level))
+                    .map_err(|e| optimize_error(&path, e))?;
+                let elapsed = reduction + start.elapsed();
+                files.push(s.to_string());
+                level_col.push(i32::from(level));
+                lossy_col.push(lossy.max(0.0));
+                input_sizes.push(data.len() as f64);
+                sizes.push(optimized.len() as f64);
+                elapsed_ms.push(elapsed.as_secs_f64() * 1000.0);
+            }
+        }
+    }
+    Ok(list!(
+        file = files, level = level_col, lossy = lossy_col, input_size = input_sizes,
+        size = sizes, elapsed_ms = elapsed_ms
+    ))
+}
+
+/// Largest lossy threshold tried by `tinypng_recompress_impl()`, and the step
+/// by which the threshold is increased.
+const RECOMPRESS_MAX_LOSSY: f64 = 20.0;
+const RECOMPRESS_LOSSY_STEP: f64 = 0.5;
+
+/// Optimize a PNG file in place so that it fits in `max_bytes`, increasing
+/// the lossy threshold step by step if lossless optimization is not enough.
+/// If no threshold works, the losslessly optimized file is kept (when it is
+/// smaller than the original).  Returns whether the file fits.
+fn recompress_png(input: &PathBuf, max_bytes: usize, opts: &Options) -> Result<bool> {
+    let original = std::fs::read(input)
+        .map_err(|e| TinyImgRError::io(format!("Failed to read {}: {}", input.display(), e)))?;
+    let write = |data: &[u8]| -> Result<()> {
+        std::fs::write(input, data)
+            .map_err(|e| TinyImgRError::io(format!("Failed to write {}: {}", input.display(), e)).into())
+    };
+    let optimize = |png: &[u8]| -> Result<Vec<u8>> {
+        oxipng::optimize_from_memory(png, opts)
+            .map_err(|e| optimize_error(input, e).into())
+    };
+    let lossless = optimize(&original)?;
+    if lossless.len() <= max_bytes {
+        write(&lossless)?;
+        return Ok(true);
+    }
+
+    let (pixels, width, height) = decode_png_colors(&original, input)?;
+    let lab = LabConverter::new("d65").expect("d65 is a known white point");
+    let samples = LabSamples::new(&pixels, &lab);
+    let mut prev: Option<Vec<Color>> = None;
+    let steps = (RECOMPRESS_MAX_LOSSY / RECOMPRESS_LOSSY_STEP).round() as usize;
+    for i in 1..=steps {
+        let lossy = i as f64 * RECOMPRESS_LOSSY_STEP;
+        let q = quantize_pixels(&pixels, width, &samples, LossyThreshold::DeltaE(lossy), &lab);
+        // Neighbouring thresholds often select the same palette; skip them.
+        if prev.as_ref() == Some(&q) {
+            continue;
+        }
+        let png = encode_png_colors(&q, width, height).map_err(TinyImgRError::encode)?;
+        let data = optimize(&png)?;
+        if data.len() <= max_bytes {
+            write(&data)?;
+            return Ok(true);
+        }
+        prev = Some(q);
+    }
+    if lossless.len() < original.len() {
+        write(&lossless)?;
+    }
+    Ok(false)
+}
+
+/// Optimize PNG files in place to fit in a size limit
+///
+/// @param input Vector of PNG file paths
+/// @param max_bytes Maximum file size in bytes
+/// @param level Optimization level (0-6)
+/// @return Whether each file fits in `max_bytes`
+/// @export
+#[extendr]
+fn tinypng_recompress_impl(input: Strings, max_bytes: i32, level: i32) -> Result<Logicals> {
+    if max_bytes <= 0 {
+        return Err(TinyImgRError::argument("The size limit must be positive").into());
+    }
+    let opts = png_options(check_level(level)?);
+    let mut fits = Vec::with_capacity(input.len());
+    for s in input.iter() {
+        let path = PathBuf::from(s.as_str());
+        if !path.exists() {
+            return Err(TinyImgRError::io(format!("Input file does not exist: {}", s.as_str())).into());
+        }
+        fits.push(Rbool::from(recompress_png(&path, max_bytes as usize, &opts)?));
+    }
+    Ok(Logicals::from_values(fits))
+}
+
+/// Reduce a PNG image to a palette with adaptive dithering
+///
+/// @param input Path to the input PNG file
+/// @param output Path to the output PNG file
+/// @param n_colors Number of palette colors (1-256)
+/// @param level Optimization level (0-6)
+/// @return The output path
+/// @export
+#[extendr]
+fn tinypng_dither_adaptive_impl(input: &str, output: &str, n_colors: i32, level: i32) -> Result<String> {
+    if !(1..=256).contains(&n_colors) {
+        return Err(TinyImgRError::argument("The number of colors must be between 1 and 256").into());
+    }
+    let level = check_level(level)?;
+    let (pixels, width, height) = read_png_colors(&PathBuf::from(input))?;
+    let dithered = KMeansAdaptive.quantize(&pixels, width, n_colors as usize);
+    drop(pixels);
+    let png = encode_png_colors(&dithered, width, height).map_err(TinyImgRError::encode)?;
+    let optimized = oxipng::optimize_from_memory(&png, &png_options(level))
+        .map_err(|e| TinyImgRError::encode(format!("Failed to optimize {}: {}", output, e)))?;
+    std::fs::write(output, optimized)
+        .map_err(|e| TinyImgRError::io(format!("Failed to write {}: {}", output, e)))?;
+    Ok(output.to_string())
+}
+
+/// Reduce a PNG image to a palette without optimizing it
+///
+/// @param input Path to the input PNG file
+/// @param output Path to the output PNG file, or `""` to return the PNG data
+/// @param lossy Maximum CIE76 Delta E threshold
+/// @param colors Number of palette colors (0 to find the smallest number
+///   within `lossy`)
+/// @param dither Whether to dither the quantized pixels (`NA` for the
+///   setting of `lossy_speed`)
+/// @param white_point Reference white for L*a*b*
+/// @param lossy_speed The speed from `1` to `10` (see `LossySpeed`)
+/// @return A list of the output path or the PNG data as a raw vector
+///   (`output`), the hex codes of the palette colors in PLTE order
+///   (`palette`), the palette size passed to the quantizer (`n_colors`), and
+///   the 95th percentile Delta E of the quantized pixels (`p95_de`)
+/// @export
+#[extendr]
+fn png_quantize_impl(
+    input: &str, output: &str, lossy: f64, colors: i32, dither: Rbool, white_point: &str, lossy_speed: i32,
+) -> Result<List> {
+    if !(0..=256).contains(&colors) {
+        return Err(TinyImgRError::argument("The number of colors must be between 1 and 256 (or 0)").into());
+    }
+    if colors == 0 && !(lossy > 0.0 && lossy.is_finite()) {
+        return Err(TinyImgRError::argument("Either the number of colors or a positive lossy threshold is required").into());
+    }
+    let speed = LossySpeed::new(lossy_speed)?;
+    let dither = if dither.is_na() {
+        speed.ditherer()
+    } else if dither.is_true() {
+        Dither::Ordered
+    } else {
+        Dither::None
+    };
+    let lab = LabConverter::new(white_point).map_err(TinyImgRError::argument)?;
+    let (pixels, width, height) = read_png_colors(&PathBuf::from(input))?;
+    let threshold = LossyThreshold::DeltaE(lossy.max(0.0));
+    let quantized = quantize_stage(&pixels, width, threshold, colors as usize, dither, &lab, &speed);
+    drop(pixels);
+    let (png, palette) = encode_palette_png(&quantized.pixels, width, height).map_err(TinyImgRError::encode)?;
+    let output: Robj = if output.is_empty() {
+        Raw::from_bytes(&png).into()
+    } else {
+        std::fs::write(output, &png)
+            .map_err(|e| TinyImgRError::io(format!("Failed to write {}: {}", output, e)))?;
+        output.into()
+    };
+    let palette: Vec<String> = palette.iter().map(|&c| swatch::hex_code(c)).collect();
+    Ok(list!(
+        output = output,
+        palette = palette,
+        n_colors = quantized.n_colors as i32,
+        p95_de = quantized.metric[0]
+    ))
+}
+
+/// Losslessly optimize a PNG file and, if `verify`, check that the optimized
+/// image decodes to exactly the same pixels as the original before writing
+/// it.  Returns whether the output was written.
+fn safe_optimize_png(input: &PathBuf, output: &PathBuf, verify: bool, opts: &Options) -> Result<bool> {
+    let original = std::fs::read(input)
+        .map_err(|e| TinyImgRError::io(format!("Failed to read {}: {}", input.display(), e)))?;
+    let optimized = oxipng::optimize_from_memory(&original, opts)
+        .map_err(|e| optimize_error(input, e))?;
+    if verify {
+        let decode = |png: &[u8]| lodepng::decode32(png)
+            .map(|img| (img.width, img.height, img.buffer))
+            .map_err(|e| TinyImgRError::decode(format!("Failed to decode {}: {}", input.display(), e)));
+        if decode(&original)? != decode(&optimized)? {
+            return Ok(false);
+        }
+    }
+    std::fs::write(output, optimized)
+        .map_err(|e| TinyImgRError::io(format!("Failed to write {}: {}", output.display(), e)))?;
+    Ok(true)
+}
+
+/// Losslessly optimize PNG files with pixel-by-pixel verification
+///
+/// @param input Vector of input PNG file paths
+/// @param output Vector of output PNG file paths (same length as input)
+/// @param verify Compare the pixels of the original and optimized images, and
+///   only write outputs that are identical
+/// @param level Optimization level (0-6)
+/// @return Whether each file passed the verification (and was written)
+/// @export
+#[extendr]
+fn tinypng_safe_optimize_impl(input: Strings, output: Strings, verify: bool, level: i32) -> Result<Logicals> {
+    let inputs: Vec<String>  = input.iter().map(|s| s.to_string()).collect();
+    let outputs: Vec<String> = output.iter().map(|s| s.to_string()).collect();
+    validate_io(&inputs, &outputs)?;
+    // Only lossless reductions: transparent pixels must keep their colors.
+    let mut opts = png_options(check_level(level)?);
+    opts.optimize_alpha = false;
+    let mut passed = Vec::with_capacity(inputs.len());
+    for (input, output) in inputs.iter().zip(&outputs) {
+        let ok = safe_optimize_png(&PathBuf::from(input), &PathBuf::from(output), verify, &opts)?;
+        passed.push(Rbool::from(ok));
+    }
+    Ok(Logicals::from_values(passed))
+}
+
+/// Optimize the same PNG file from several threads at once
+///
+/// @param input Path to the input PNG file
+/// @param n_threads Number of threads
+/// @param n_iterations Number of times each thread optimizes the file
+/// @return Whether all outputs were identical
+/// @export
+#[extendr]
+fn tinypng_thread_stress_impl(input: &str, n_threads: i32, n_iterations: i32) -> Result<Rbool> {
+    if n_threads < 1 || n_iterations < 1 {
+        return Err(TinyImgRError::argument("The numbers of threads and iterations must be positive").into());
+    }
+    let input = PathBuf::from(input);
+    let opts = png_options(DEFAULT_PNG_LEVEL);
+    // Each thread writes to its own file and records the CRC-32 and size of
+    // every output it produced.
+    let dir = std::env::temp_dir();
+    let results = std::thread::scope(|s| {
+        let handles = (0..n_threads).map(|i| {
+            let (input, opts) = (&input, &opts);
+            let output = dir.join(format!("tinyimg-stress-{}-{}.png", std::process::id(), i));
+            s.spawn(move || {
+                let mut hashes = Vec::with_capacity(n_iterations as usize);
+                for _ in 0..n_iterations {
+                    let out_file = OutFile::Path { path: Some(output.clone()), preserve_attrs: false };
+                    let res = oxipng::optimize(&InFile::Path(input.clone()), &out_file, opts)
+                        .map_err(|e| optimize_error(input, e))
+                        .and_then(|_| std::fs::read(&output).map_err(|e| {
+                            TinyImgRError::io(format!("Failed to read {}: {}", output.display(), e))
+                        }));
+                    match res {
+                        Ok(data) => hashes.push((crc32fast::hash(&data), data.len())),
+                        Err(e) => {
+                            let _ = std::fs::remove_file(&output);
+                            return Err(e);
+                        }
+                    }
+                }
+                let _ = std::fs::remove_file(&output);
+                Ok(hashes)
+            })
+        }).collect();
+        join_all(handles)
+    })?;
+    let first = results[0][0];
+    Ok(Rbool::from(results.iter().flatten().all(|h| *h == first)))
+}
+
+// ---------------------------------------------------------------------------
+// Live preview servers
+// ---------------------------------------------------------------------------
+
+/// The preview servers started by `tinypng_watch_and_serve_impl()`, which
+/// keep running in the background until they are stopped.
+static SERVERS: std::sync::Mutex<Vec<serve::Server>> = std::sync::Mutex::new(Vec::new());
+
+/// Lock the list of preview servers.
+fn servers() -> std::result::Result<std::sync::MutexGuard<'static, Vec<serve::Server>>, TinyImgRError> {
+    SERVERS.lock().map_err(|e| TinyImgRError::io(format!("Failed to access the preview servers: {}", e)))
+}
+
+/// Start a preview server for a directory
+///
+/// Serve the files under a directory at `http://127.0.0.1:<port>/` from a
+/// background thread, with an HTML listing of the files at the root path.
+/// Files are read on each request, so files optimized again after a change
+/// are served in their new versions.
+///
+/// @param input_dir Path to the directory to serve
+/// @param port The port to listen on (0 to choose a free port)
+/// @return The port the server listens on
+/// @export
+#[extendr]
+fn tinypng_watch_and_serve_impl(input_dir: &str, port: i32) -> Result<i32> {
+    let port = u16::try_from(port)
+        .map_err(|_| TinyImgRError::argument(format!("The port must be between 0 and 65535: {}", port)))?;
+    if !Path::new(input_dir).is_dir() {
+        return Err(TinyImgRError::io(format!("The directory does not exist: {}", input_dir)).into());
+    }
+    let server = serve::Server::start(Path::new(input_dir), port)
+        .map_err(|e| TinyImgRError::io(format!("Failed to listen on port {}: {}", port, e)))?;
+    let port = server.port();
+    servers()?.push(server);
+    Ok(port as i32)
+}
+
+/// Stop a preview server
+///
+/// @param port The port of a server started by `tinypng_watch_and_serve_impl()`
+/// @return Whether a server was stopped
+/// @export
+#[extendr]
+fn tinypng_serve_stop_impl(port: i32) -> Result<bool> {
+    let mut servers = servers()?;
+    let n = servers.len();
+    servers.retain(|s| s.port() as i32 != port);
+    Ok(servers.len() < n)
+}
+
+// ---------------------------------------------------------------------------
+// JPEG optimisation
+// ---------------------------------------------------------------------------
+
+/// Returns the sizes of the input and output data. The input is copied as is
+/// if re-encoding would not make it smaller.
+fn optimize_jpeg(input: &PathBuf, output: &PathBuf, quality: f32, progressive: bool) -> Result<(u64, u64)> {
+    if quality >= 100.0 {
+        let size = keep_jpeg(input, output)?;
+        return Ok((size, size));
+    }
+    let src_data = std::fs::read(input)
+        .map_err(|e| TinyImgRError::io(format!("Failed to read {}: {}", input.display(), e)))?;
+
+    let d = Decompress::with_markers(mozjpeg::ALL_MARKERS)
+        .from_mem(&src_data)
+        .map_err(|e| TinyImgRError::decode(format!("Failed to decompress {}: {}", input.display(), e)))?;
+    let width  = d.width();
+    let height = d.height();
+
+    let (flat_pixels, colorspace) = if d.color_space() == ColorSpace::JCS_GRAYSCALE {
+        let mut img = d.grayscale()
+            .map_err(|e| TinyImgRError::decode(format!("Failed to convert colorspace for {}: {}", input.display(), e)))?;
+        let pixels: Vec<[u8; 1]> = img.read_scanlines()
+            .map_err(|e| TinyImgRError::decode(format!("Failed to read scanlines from {}: {}", input.display(), e)))?;
+        let _ = img.finish();
+        let flat: Vec<u8> = pixels.iter().flatten().copied().collect();
+        (flat, ColorSpace::JCS_GRAYSCALE)
+    } else {
+        let mut img = d.rgb()
+            .map_err(|e| TinyImgRError::decode(format!("Failed to convert colorspace for {}: {}", input.display(), e)))?;
+        let pixels: Vec<[u8; 3]> = img.read_scanlines()
+            .map_err(|e| TinyImgRError::decode(format!("Failed to read scanlines from {}: {}", input.display(), e)))?;
+        let _ = img.finish();
+        let flat: Vec<u8> = pixels.iter().flatten().copied().collect();
+        (flat, ColorSpace::JCS_RGB)
+    };
+
+    let data = encode_jpeg(&flat_pixels, width, height, colorspace, quality, progressive, output)?;
+    if data.len() >= src_data.len() {
+        let size = keep_jpeg(input, output)?;
+        return Ok((size, size));
+    }
+    std::fs::write(output, &data)
+        .map_err(|e| TinyImgRError::io(format!("Failed to write {}: {}", output.display(), e)))?;
+    Ok((src_data.len() as u64, data.len() as u64))
+}
+
+/// Copy a JPEG file to its output path unchanged (unless they are the same
+/// file) and return its size.
+fn keep_jpeg(input: &PathBuf, output: &PathBuf) -> Result<u64> {
+    if input != output {
+        std::fs::copy(input, output)
+            .map_err(|e| TinyImgRError::io(format!("Failed to copy {}: {}", input.display(), e)).into())
+    } else {
+        std::fs::metadata(input).map(|m| m.len())
+            .map_err(|e| TinyImgRError::io(format!("Failed to read {}: {}", input.display(), e)).into())
+    }
+}
+
+/// Encode RGB or grayscale pixels as a JPEG with optimized Huffman coding,
+/// either progressive (mozjpeg's default) or baseline (`output` is only used
+/// in error messages).
+fn encode_jpeg(
+    pixels: &[u8], width: usize, height: usize, colorspace: ColorSpace, quality: f32,
+    progressive: bool, output: &Path,
+) -> Result<Vec<u8>> {
+    let mut comp = Compress::new(colorspace);
+    comp.set_size(width, height);
+    comp.set_quality(quality);
+    comp.set_optimize_coding(true);
+    if !progressive {
+        // Without a scan script, a single sequential scan is written.
+        comp.set_optimize_scans(false);
+    }
+    let mut comp = comp.start_compress(Vec::new())
+        .map_err(|e| TinyImgRError::encode(format!("Failed to start JPEG compression for {}: {}", output.display(), e)))?;
+    comp.write_scanlines(pixels)
+        .map_err(|e| TinyImgRError::encode(format!("Failed to write JPEG scanlines to {}: {}", output.display(), e)))?;
+    comp.finish()
+        .map_err(|e| TinyImgRError::encode(format!("Failed to finish JPEG compression for {}: {}", output.display(), e)).into())
+}
+
+/// Optimize JPEG files using mozjpeg
+///
+/// @param input Vector of input JPEG file paths
+/// @param output Vector of output JPEG file paths (same length as input)
+/// @param quality Quality level (0-100); higher means better quality and larger files
+/// @param progressive Write progressive (`TRUE`) or baseline JPEGs
+/// @param verbose Print file size reduction info
+/// @export
+#[extendr]
+fn tinyjpg_impl(
+    input: Strings,
+    output: Strings,
+    quality: f64,
+    progressive: bool,
+    verbose: bool,
+) -> Result<()> {
+    let inputs: Vec<String>  = input.iter().map(|s| s.to_string()).collect();
+    let outputs: Vec<String> = output.iter().map(|s| s.to_string()).collect();
+    validate_io(&inputs, &outputs)?;
+    process_files(&inputs, &outputs, verbose, |input_path, output_path| {
+        optimize_jpeg(input_path, output_path, quality as f32, progressive)
+    })
+}
+
+/// Decode a PNG file into RGBA pixels (internal helper for tests)
+///
+/// @param input Path to a PNG file
+/// @return A list with `width`, `height`, and `rgba` (a raw vector of
+///   `width * height * 4` bytes in row-major order)
+#[extendr]
+fn png_pixels_impl(input: &str) -> Result<List> {
+    let (bytes, width, height) = read_png_rgba(Path::new(input))?;
+    Ok(list!(
+        width = width as i32,
+        height = height as i32,
+        rgba = Raw::from_bytes(&bytes)
+    ))
+}
+
+// Macro to generate exports
+extendr_module! {
+    mod tinyimg;
+    fn tinypng_impl;
+    fn tinyjpg_impl;
+    fn png_pixels_impl;
+    fn tinypng_simulate_compression_impl;
+    fn tinypng_multi_level_output_impl;
+    fn tinybench_impl;
+    fn tinypng_recompress_impl;
+    fn tinypng_dither_adaptive_impl;
+    fn png_quantize_impl;
+    fn tinypng_safe_optimize_impl;
+    fn tinypng_thread_stress_impl;
+    fn tinypng_watch_and_serve_impl;
+    fn tinypng_serve_stop_impl;
+    use anim;
+    use audit;
+    use checksum;
+    use chunk;
+    use classify;
+    use colorspace;
+    use convert;
+    use edit;
+    use ico;
+    use manifest;
+    use opts;
+    use quality;
+    use svg;
+    use swatch;
+}
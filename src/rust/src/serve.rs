@@ -0,0 +1,221 @@
+use std::io::{BufRead, BufReader, Write};
+use std::net::{TcpListener, TcpStream};
+use std::path::{Component, Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread::JoinHandle;
+use std::time::Duration;
+
+// ---------------------------------------------------------------------------
+// Live preview server
+// ---------------------------------------------------------------------------
+//
+// A tiny HTTP/1.0 server for previewing a directory of images in a browser
+// while it is being watched: `GET /` returns an HTML page that lists the
+// images (and reloads itself every few seconds), and `GET /<path>` returns
+// the file under the directory.  Files are read on every request, so a file
+// optimized again after a change is served in its new version.  The server
+// only listens on the loopback interface and runs in a background thread, so
+// it does not block (or touch) the R session.
+
+/// How often the listing page reloads itself, in seconds.
+const RELOAD_SECONDS: u32 = 2;
+
+/// How long the server thread sleeps when there is no connection to accept.
+const IDLE: Duration = Duration::from_millis(50);
+
+/// A preview server running in a background thread.
+pub struct Server {
+    port: u16,
+    stop: Arc<AtomicBool>,
+    handle: Option<JoinHandle<()>>,
+}
+
+impl Server {
+    /// Serve the files under `dir` at `http://127.0.0.1:<port>/`.  With port
+    /// 0, a free port is chosen by the system (see `port()`).
+    pub fn start(dir: &Path, port: u16) -> std::io::Result<Server> {
+        let listener = TcpListener::bind(("127.0.0.1", port))?;
+        listener.set_nonblocking(true)?;
+        let port = listener.local_addr()?.port();
+        let stop = Arc::new(AtomicBool::new(false));
+        let (dir, flag) = (dir.to_path_buf(), stop.clone());
+        let handle = std::thread::spawn(move || {
+            while !flag.load(Ordering::Relaxed) {
+                match listener.accept() {
+                    Ok((stream, _)) => {
+                        let _ = handle_connection(stream, &dir);
+                    }
+                    Err(_) => std::thread::sleep(IDLE),
+                }
+            }
+        });
+        Ok(Server { port, stop, handle: Some(handle) })
+    }
+
+    /// The port the server listens on.
+    pub fn port(&self) -> u16 {
+        self.port
+    }
+}
+
+impl Drop for Server {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::Relaxed);
+        if let Some(handle) = self.handle.take() {
+            let _ = handle.join();
+        }
+    }
+}
+
+/// Read a request and write the response.
+fn handle_connection(stream: TcpStream, dir: &Path) -> std::io::Result<()> {
+    stream.set_nonblocking(false)?;
+    stream.set_read_timeout(Some(Duration::from_secs(5)))?;
+    let mut reader = BufReader::new(&stream);
+    let mut request = String::new();
+    reader.read_line(&mut request)?;
+    // Skip the headers.
+    let mut line = String::new();
+    while reader.read_line(&mut line)? > 2 {
+        line.clear();
+    }
+    let (status, content_type, body) = respond(&request, dir);
+    let head = request.starts_with("HEAD ");
+    let mut out = &stream;
+    write!(
+        out,
+        "HTTP/1.0 {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nCache-Control: no-store\r\nConnection: close\r\n\r\n",
+        status, content_type, body.len()
+    )?;
+    if !head {
+        out.write_all(&body)?;
+    }
+    out.flush()
+}
+
+/// The status, content type, and body of the response to a request line.
+pub fn respond(request: &str, dir: &Path) -> (&'static str, &'static str, Vec<u8>) {
+    let mut fields = request.split_whitespace();
+    let (Some(method), Some(target)) = (fields.next(), fields.next()) else {
+        return ("400 Bad Request", "text/plain", b"Bad request\n".to_vec());
+    };
+    if method != "GET" && method != "HEAD" {
+        return ("405 Method Not Allowed", "text/plain", b"Only GET and HEAD are supported\n".to_vec());
+    }
+    let path = percent_decode(target.split(['?', '#']).next().unwrap_or("/"));
+    let Some(rel) = safe_path(&path) else {
+        return ("403 Forbidden", "text/plain", b"Forbidden\n".to_vec());
+    };
+    if rel.as_os_str().is_empty() {
+        return ("200 OK", "text/html; charset=utf-8", listing(dir).into_bytes());
+    }
+    match std::fs::read(dir.join(&rel)) {
+        Ok(bytes) => ("200 OK", content_type(&rel), bytes),
+        Err(_) => ("404 Not Found", "text/plain", format!("Not found: {}\n", path).into_bytes()),
+    }
+}
+
+/// Decode `%XX` escapes in a URL path.
+fn percent_decode(s: &str) -> String {
+    let bytes = s.as_bytes();
+    let mut out = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let hex = bytes.get(i + 1..i + 3).and_then(|h| std::str::from_utf8(h).ok())
+            .and_then(|h| u8::from_str_radix(h, 16).ok());
+        match (bytes[i], hex) {
+            (b'%', Some(b)) => {
+                out.push(b);
+                i += 3;
+            }
+            (b, _) => {
+                out.push(b);
+                i += 1;
+            }
+        }
+    }
+    String::from_utf8_lossy(&out).into_owned()
+}
+
+/// The relative path of a URL path, or `None` if it would leave the served
+/// directory (e.g., with `..`).
+fn safe_path(path: &str) -> Option<PathBuf> {
+    let mut rel = PathBuf::new();
+    for c in Path::new(path.trim_start_matches('/')).components() {
+        match c {
+            Component::Normal(s) => rel.push(s),
+            Component::CurDir => {}
+            _ => return None,
+        }
+    }
+    Some(rel)
+}
+
+fn content_type(path: &Path) -> &'static str {
+    let ext = path.extension().and_then(|e| e.to_str()).unwrap_or("").to_ascii_lowercase();
+    match ext.as_str() {
+        "png" => "image/png",
+        "jpg" | "jpeg" => "image/jpeg",
+        "gif" => "image/gif",
+        "webp" => "image/webp",
+        "svg" => "image/svg+xml",
+        "html" | "htm" => "text/html; charset=utf-8",
+        "txt" => "text/plain; charset=utf-8",
+        _ => "application/octet-stream",
+    }
+}
+
+/// The files under a directory (recursively), relative to it, sorted.
+fn list_files(dir: &Path) -> Vec<PathBuf> {
+    let mut files = Vec::new();
+    let mut dirs = vec![PathBuf::new()];
+    while let Some(sub) = dirs.pop() {
+        let Ok(entries) = std::fs::read_dir(dir.join(&sub)) else { continue };
+        for entry in entries.flatten() {
+            let rel = sub.join(entry.file_name());
+            match entry.file_type() {
+                Ok(t) if t.is_dir() => dirs.push(rel),
+                Ok(_) => files.push(rel),
+                Err(_) => {}
+            }
+        }
+    }
+    files.sort();
+    files
+}
+
+fn escape_html(s: &str) -> String {
+    s.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;").replace('"', "&quot;")
+}
+
+/// Escape a relative path for a URL (byte by byte, except for unreserved
+/// characters and `/`).
+fn url_path(path: &Path) -> String {
+    let s = path.to_string_lossy().replace('\\', "/");
+    s.bytes().map(|b| match b {
+        b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' | b'/' => (b as char).to_string(),
+        _ => format!("%{:02X}", b),
+    }).collect()
+}
+
+/// The HTML page listing the files under a directory, with previews of PNG
+/// files and their sizes.
+fn listing(dir: &Path) -> String {
+    let mut html = format!(
+        "<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n\
+         <meta http-equiv=\"refresh\" content=\"{}\">\n<title>{}</title>\n</head>\n<body>\n<h1>{}</h1>\n<ul>\n",
+        RELOAD_SECONDS, escape_html(&dir.display().to_string()), escape_html(&dir.display().to_string())
+    );
+    for file in list_files(dir) {
+        let name = escape_html(&file.to_string_lossy());
+        let (url, size) = (url_path(&file), std::fs::metadata(dir.join(&file)).map_or(0, |m| m.len()));
+        html.push_str(&format!("<li><a href=\"/{}\">{}</a> ({} bytes)", url, name, size));
+        if content_type(&file) == "image/png" {
+            html.push_str(&format!("<br><img src=\"/{}\" alt=\"{}\" style=\"max-width: 100%;\">", url, name));
+        }
+        html.push_str("</li>\n");
+    }
+    html.push_str("</ul>\n</body>\n</html>\n");
+    html
+}
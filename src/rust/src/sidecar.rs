@@ -0,0 +1,183 @@
+use std::path::{Path, PathBuf};
+
+use crate::error::TinyImgRError;
+
+// ---------------------------------------------------------------------------
+// Metadata sidecar files
+// ---------------------------------------------------------------------------
+//
+// The ancillary chunks stripped from a PNG can be saved to a sidecar file and
+// put back later.  A sidecar file is:
+//
+//   magic     "TINYMETA" (8 bytes)
+//   version   1 byte (`SIDECAR_VERSION`)
+//   hash      4 bytes: CRC-32 of the dimensions and RGBA pixels of the image
+//             the chunks were stripped from (after optimization)
+//   count     4 bytes: number of chunks
+//   chunks    per chunk, 1 byte for its position (0 before the image data,
+//             1 after it) and its complete bytes (length, type, data, CRC)
+//
+// All integers are big-endian, as in PNG.  Hashing the pixels instead of the
+// bytes lets the chunks be restored onto a file that was optimized again.
+
+const SIDECAR_MAGIC: &[u8; 8] = b"TINYMETA";
+const SIDECAR_VERSION: u8 = 1;
+
+/// Chunks that must come before PLTE (and the image data).
+const BEFORE_PLTE: [[u8; 4]; 8] = [
+    *b"iCCP", *b"sRGB", *b"gAMA", *b"cHRM", *b"sBIT", *b"cICP", *b"mDCV", *b"cLLI",
+];
+
+/// Chunks that may appear more than once in a PNG file.
+const MULTIPLE: [[u8; 4]; 4] = [*b"tEXt", *b"zTXt", *b"iTXt", *b"sPLT"];
+
+/// An ancillary chunk saved from a PNG file.
+pub(crate) struct SavedChunk {
+    pub name: [u8; 4],
+    /// Whether the chunk came after the image data (IDAT).
+    pub after_idat: bool,
+    /// The complete chunk bytes (length, type, data, and CRC).
+    pub raw: Vec<u8>,
+}
+
+/// The ancillary chunks of a PNG file (those with a lowercase first letter),
+/// in their order.
+pub(crate) fn ancillary_chunks_raw(png: &[u8]) -> Vec<SavedChunk> {
+    let mut chunks = Vec::new();
+    let (mut pos, mut after_idat) = (8, false);  // after the signature
+    while pos + 12 <= png.len() {
+        let len = u32::from_be_bytes(png[pos..pos + 4].try_into().unwrap()) as usize;
+        let end = pos.saturating_add(12 + len);
+        let name: [u8; 4] = png[pos + 4..pos + 8].try_into().unwrap();
+        if end > png.len() {
+            break;
+        }
+        if &name == b"IDAT" {
+            after_idat = true;
+        } else if name[0].is_ascii_lowercase() {
+            chunks.push(SavedChunk { name, after_idat, raw: png[pos..end].to_vec() });
+        }
+        pos = end;
+    }
+    chunks
+}
+
+/// The content hash of a PNG image: CRC-32 of its dimensions and RGBA pixels.
+pub(crate) fn pixel_hash(png: &[u8], path: &Path) -> Result<u32, TinyImgRError> {
+    let image = lodepng::decode32(png)
+        .map_err(|e| TinyImgRError::decode(format!("Failed to read PNG {}: {}", path.display(), e)))?;
+    let mut hasher = crc32fast::Hasher::new();
+    hasher.update(&(image.width as u32).to_be_bytes());
+    hasher.update(&(image.height as u32).to_be_bytes());
+    for p in &image.buffer {
+        hasher.update(&[p.r, p.g, p.b, p.a]);
+    }
+    Ok(hasher.finalize())
+}
+
+/// The sidecar path of an output file: a `backup` starting with `.` is a
+/// suffix appended to the output path (e.g., `plot.png.meta`), and anything
+/// else is a directory for `<output file name>.meta`.
+pub(crate) fn sidecar_path(output: &Path, backup: &str) -> PathBuf {
+    if backup.starts_with('.') {
+        let mut path = output.as_os_str().to_owned();
+        path.push(backup);
+        PathBuf::from(path)
+    } else {
+        let mut name = output.file_name().unwrap_or_default().to_owned();
+        name.push(".meta");
+        Path::new(backup).join(name)
+    }
+}
+
+/// Write chunks to a sidecar file.
+pub(crate) fn write_sidecar(path: &Path, hash: u32, chunks: &[SavedChunk]) -> Result<(), TinyImgRError> {
+    let mut bytes = Vec::with_capacity(17 + chunks.iter().map(|c| c.raw.len() + 1).sum::<usize>());
+    bytes.extend_from_slice(SIDECAR_MAGIC);
+    bytes.push(SIDECAR_VERSION);
+    bytes.extend_from_slice(&hash.to_be_bytes());
+    bytes.extend_from_slice(&(chunks.len() as u32).to_be_bytes());
+    for chunk in chunks {
+        bytes.push(chunk.after_idat as u8);
+        bytes.extend_from_slice(&chunk.raw);
+    }
+    if let Some(dir) = path.parent().filter(|p| !p.as_os_str().is_empty()) {
+        std::fs::create_dir_all(dir)
+            .map_err(|e| TinyImgRError::io(format!("Failed to create directory {}: {}", dir.display(), e)))?;
+    }
+    std::fs::write(path, bytes)
+        .map_err(|e| TinyImgRError::io(format!("Failed to write {}: {}", path.display(), e)))
+}
+
+/// Read the content hash and the chunks of a sidecar file.
+#[cfg(feature = "r")]
+pub(crate) fn read_sidecar(bytes: &[u8]) -> Result<(u32, Vec<SavedChunk>), String> {
+    if bytes.len() < 17 || &bytes[..8] != SIDECAR_MAGIC {
+        return Err("not a metadata sidecar file".to_string());
+    }
+    if bytes[8] != SIDECAR_VERSION {
+        return Err(format!("unsupported sidecar version {} (expected {})", bytes[8], SIDECAR_VERSION));
+    }
+    let u32_at = |pos: usize| u32::from_be_bytes(bytes[pos..pos + 4].try_into().unwrap());
+    let (hash, count) = (u32_at(9), u32_at(13) as usize);
+    let mut chunks = Vec::new();
+    let mut pos = 17;
+    for _ in 0..count {
+        if pos + 13 > bytes.len() {
+            return Err("the sidecar file is truncated".to_string());
+        }
+        let end = (pos + 13).saturating_add(u32_at(pos + 1) as usize);
+        if end > bytes.len() {
+            return Err("the sidecar file is truncated".to_string());
+        }
+        let name: [u8; 4] = bytes[pos + 5..pos + 9].try_into().unwrap();
+        chunks.push(SavedChunk { name, after_idat: bytes[pos] == 1, raw: bytes[pos + 1..end].to_vec() });
+        pos = end;
+    }
+    Ok((hash, chunks))
+}
+
+/// Insert saved chunks into a PNG file at valid positions: the chunks that
+/// must precede PLTE right after IHDR, other chunks that came before the image
+/// data right before it, and the rest before IEND.  Chunks already in the file
+/// (or of a type that may appear only once and is already present) are
+/// skipped.  Returns the new file and the number of inserted chunks.
+pub(crate) fn restore_chunks(png: &[u8], saved: &[SavedChunk]) -> Result<(Vec<u8>, usize), String> {
+    // The positions of the chunks in the file and their types.
+    let mut chunks = Vec::new();
+    let mut pos = 8;
+    while pos + 12 <= png.len() {
+        let len = u32::from_be_bytes(png[pos..pos + 4].try_into().unwrap()) as usize;
+        let end = pos.saturating_add(12 + len);
+        if end > png.len() {
+            return Err("the PNG file is truncated".to_string());
+        }
+        chunks.push((<[u8; 4]>::try_from(&png[pos + 4..pos + 8]).unwrap(), pos, end));
+        pos = end;
+    }
+    let find = |name: &[u8; 4]| chunks.iter().find(|c| &c.0 == name).map(|c| c.1);
+    let (Some(idat), Some(iend)) = (find(b"IDAT"), find(b"IEND")) else {
+        return Err("the PNG file has no image data".to_string());
+    };
+    let ihdr_end = chunks.first().filter(|c| &c.0 == b"IHDR").map(|c| c.2)
+        .ok_or("the PNG file does not start with IHDR")?;
+    let present = |s: &SavedChunk| chunks.iter().any(|&(name, start, end)| {
+        name == s.name && (!MULTIPLE.contains(&name) || png[start..end] == s.raw[..])
+    });
+    let (mut head, mut body, mut tail, mut n) = (Vec::new(), Vec::new(), Vec::new(), 0);
+    for s in saved.iter().filter(|s| !present(s)) {
+        let group = if s.after_idat {
+            &mut tail
+        } else if BEFORE_PLTE.contains(&s.name) {
+            &mut head
+        } else {
+            &mut body
+        };
+        group.extend_from_slice(&s.raw);
+        n += 1;
+    }
+    let out = [
+        &png[..ihdr_end], &head, &png[ihdr_end..idat], &body, &png[idat..iend], &tail, &png[iend..],
+    ].concat();
+    Ok((out, n))
+}
@@ -0,0 +1,733 @@
+use extendr_api::prelude::*;
+use std::collections::{HashMap, HashSet};
+use std::fmt::Write;
+use std::path::{Path, PathBuf};
+
+use crate::error::TinyImgRError;
+use crate::quantize::{Ditherer, NoDither};
+use crate::optimize::read_png_colors;
+use crate::{process_files, validate_io};
+
+// ---------------------------------------------------------------------------
+// Tracing PNG images as SVG
+// ---------------------------------------------------------------------------
+//
+// The image is quantized to a few colors, and the outline of each color region
+// is traced with marching squares on the grid of pixel centers.  Points are
+// stored in half-pixel units, so that all coordinates are integers and the
+// endpoints of segments from neighboring cells match exactly.
+
+/// A point in half-pixel units.
+type Point = (i32, i32);
+
+/// Edge midpoints of a marching-squares cell whose top-left corner is the
+/// center of pixel `(x, y)`: top, right, bottom, and left.
+fn midpoints(x: i32, y: i32) -> [Point; 4] {
+    // The center of pixel x is at x + 0.5, i.e., 2x + 1 in half-pixel units.
+    let (cx, cy) = (2 * x + 1, 2 * y + 1);
+    [(cx + 1, cy), (cx + 2, cy + 1), (cx + 1, cy + 2), (cx, cy + 1)]
+}
+
+/// The segments of a marching-squares cell (as pairs of indices into
+/// `midpoints()`) for the corner pattern `case` (top-left = 8, top-right = 4,
+/// bottom-right = 2, bottom-left = 1).  Saddles are resolved by separating
+/// the two diagonal corners.
+fn cell_segments(case: u8) -> &'static [(usize, usize)] {
+    const T: usize = 0;
+    const R: usize = 1;
+    const B: usize = 2;
+    const L: usize = 3;
+    match case {
+        1 | 14 => &[(L, B)],
+        2 | 13 => &[(B, R)],
+        3 | 12 => &[(L, R)],
+        4 | 11 => &[(T, R)],
+        5 => &[(T, R), (L, B)],
+        6 | 9 => &[(T, B)],
+        7 | 8 => &[(T, L)],
+        10 => &[(T, L), (B, R)],
+        _ => &[],
+    }
+}
+
+/// Trace the outlines of the pixels where `mask` is true as closed polygons.
+///
+/// The mask is extended by one pixel on each side by repeating its edge
+/// pixels, and then padded with false values, so that all outlines are closed.
+/// The outlines are clipped to the image, so regions touching the edges of the
+/// image are not chamfered there.
+fn trace_mask(mask: &[bool], width: usize, height: usize) -> Vec<Vec<Point>> {
+    let (w, h) = (width as i32, height as i32);
+    let at = |x: i32, y: i32| {
+        x >= -1 && y >= -1 && x <= w && y <= h
+            && mask[(y.clamp(0, h - 1) * w + x.clamp(0, w - 1)) as usize]
+    };
+    let clip = |(x, y): Point| (x.clamp(0, 2 * w), y.clamp(0, 2 * h));
+    // Every midpoint belongs to exactly two cells, so it has two neighbors.
+    let mut links: HashMap<Point, Vec<Point>> = HashMap::new();
+    for y in -2..=h {
+        for x in -2..=w {
+            let case = (at(x, y) as u8) << 3 | (at(x + 1, y) as u8) << 2
+                | (at(x + 1, y + 1) as u8) << 1 | at(x, y + 1) as u8;
+            let mids = midpoints(x, y);
+            for &(a, b) in cell_segments(case) {
+                links.entry(mids[a]).or_default().push(mids[b]);
+                links.entry(mids[b]).or_default().push(mids[a]);
+            }
+        }
+    }
+    let mut polygons = Vec::new();
+    while let Some(&start) = links.keys().next() {
+        let mut polygon = vec![start];
+        let (mut prev, mut cur) = (start, links[&start][0]);
+        links.remove(&start);
+        while cur != start {
+            polygon.push(cur);
+            let next = match links.remove(&cur) {
+                Some(n) if n[0] != prev => n[0],
+                Some(n) => n[1],
+                None => break,
+            };
+            (prev, cur) = (cur, next);
+        }
+        polygon.iter_mut().for_each(|p| *p = clip(*p));
+        polygon.dedup();
+        polygons.push(simplify(polygon));
+    }
+    polygons
+}
+
+/// Drop the points of a closed polygon that lie on the straight line between
+/// their neighbors.
+fn simplify(points: Vec<Point>) -> Vec<Point> {
+    let n = points.len();
+    if n < 3 {
+        return points;
+    }
+    (0..n)
+        .filter(|&i| {
+            let (p, q, r) = (points[(i + n - 1) % n], points[i], points[(i + 1) % n]);
+            (q.0 - p.0) * (r.1 - q.1) != (q.1 - p.1) * (r.0 - q.0)
+        })
+        .map(|i| points[i])
+        .collect()
+}
+
+/// Format a coordinate in half-pixel units as pixels.
+fn coord(v: i32) -> String {
+    if v % 2 == 0 { (v / 2).to_string() } else { format!("{:.1}", v as f64 / 2.0) }
+}
+
+/// Build the SVG path data of polygons.
+fn path_data(polygons: &[Vec<Point>]) -> String {
+    let mut d = String::new();
+    for polygon in polygons {
+        for (i, &(x, y)) in polygon.iter().enumerate() {
+            let _ = write!(d, "{}{} {}", if i == 0 { "M" } else { "L" }, coord(x), coord(y));
+        }
+        d.push('Z');
+    }
+    d
+}
+
+/// Trace a PNG image as an SVG file of colored outlines
+///
+/// @param input Path to the input PNG file
+/// @param output Path to the output SVG file
+/// @param n_colors Number of colors to quantize the image to (1-256)
+/// @export
+#[extendr]
+fn tinypng_png_to_svg_trace_impl(input: &str, output: &str, n_colors: i32) -> Result<()> {
+    if !(1..=256).contains(&n_colors) {
+        return Err(TinyImgRError::argument("The number of colors must be between 1 and 256").into());
+    }
+    let (pixels, width, height) = read_png_colors(&PathBuf::from(input))?;
+    let (palette, indices) = NoDither.to_indexed(&pixels, width, n_colors as usize);
+    // Fill each region with the average of its source pixels (the palette
+    // colors from k-means may be slightly off, e.g., alpha 254 for opaque
+    // images).
+    let mut counts = vec![0usize; palette.len()];
+    let mut sums = vec![[0u64; 4]; palette.len()];
+    for (&i, p) in indices.iter().zip(&pixels) {
+        counts[i as usize] += 1;
+        for (s, v) in sums[i as usize].iter_mut().zip([p.r, p.g, p.b, p.a]) {
+            *s += v as u64;
+        }
+    }
+    let fills: Vec<[u8; 4]> = sums.iter().zip(&counts)
+        .map(|(s, &n)| s.map(|v| if n > 0 { ((v as f64) / n as f64).round() as u8 } else { 0 }))
+        .collect();
+    // Draw larger regions first, so that small details end up on top.
+    let mut order: Vec<usize> = (0..palette.len()).filter(|&i| counts[i] > 0).collect();
+    order.sort_by_key(|&i| std::cmp::Reverse(counts[i]));
+
+    let mut svg = format!(
+        "<svg xmlns=\"http://www.w3.org/2000/svg\" width=\"{w}\" height=\"{h}\" viewBox=\"0 0 {w} {h}\">\n",
+        w = width, h = height
+    );
+    for i in order {
+        let [r, g, b, a] = fills[i];
+        if a == 0 {
+            continue;
+        }
+        let mask: Vec<bool> = indices.iter().map(|&j| j as usize == i).collect();
+        let d = path_data(&trace_mask(&mask, width, height));
+        let opacity = if a < 255 { format!(" fill-opacity=\"{:.3}\"", a as f64 / 255.0) } else { String::new() };
+        let _ = writeln!(
+            svg, "<path fill=\"#{:02x}{:02x}{:02x}\"{} fill-rule=\"evenodd\" d=\"{}\"/>",
+            r, g, b, opacity, d
+        );
+    }
+    svg.push_str("</svg>\n");
+    std::fs::write(Path::new(output), svg)
+        .map_err(|e| TinyImgRError::io(format!("Failed to write {}: {}", output, e)).into())
+}
+
+// ---------------------------------------------------------------------------
+// Minifying SVG files
+// ---------------------------------------------------------------------------
+//
+// SVG files are read by a small XML tokenizer that only accepts well-formed
+// documents (matched tags, quoted attributes, valid entity references, and a
+// single `<svg>` root element), so that a file that cannot be parsed is never
+// rewritten.  Comments, `<metadata>`, and the elements and attributes of
+// editors (e.g., Inkscape) are dropped, numbers in coordinates are rounded,
+// and whitespace is collapsed where it does not affect rendering.
+
+/// A token of an XML document.
+enum Token<'a> {
+    /// An XML declaration, processing instruction, DOCTYPE, or CDATA section
+    /// (kept verbatim).
+    Raw(&'a str),
+    Comment,
+    Text(&'a str),
+    /// A start tag with its attributes (name, value, and quote character).
+    Start { name: &'a str, attrs: Vec<(&'a str, &'a str, char)>, empty: bool },
+    End(&'a str),
+}
+
+/// Namespace prefixes of editors, whose elements and attributes do not
+/// affect rendering.
+const EDITOR_PREFIXES: [&str; 4] = ["inkscape", "sodipodi", "sketch", "serif"];
+
+/// Namespace prefixes used in `<metadata>`, declared on the root element.
+const METADATA_PREFIXES: [&str; 3] = ["rdf", "cc", "dc"];
+
+/// Elements whose text is rendered (so whitespace between words matters).
+const TEXT_ELEMENTS: [&str; 6] = ["text", "tspan", "textPath", "title", "desc", "a"];
+
+/// Attributes with lengths or coordinates whose numbers are rounded.
+const COORD_ATTRS: [&str; 19] = [
+    "x", "y", "x1", "y1", "x2", "y2", "cx", "cy", "r", "rx", "ry", "width", "height", "dx", "dy",
+    "stroke-width", "font-size", "points", "viewBox",
+];
+
+fn is_name_char(c: char, first: bool) -> bool {
+    c.is_alphabetic() || c == '_' || c == ':' || !c.is_ascii() ||
+        (!first && (c.is_ascii_digit() || c == '-' || c == '.'))
+}
+
+fn valid_name(name: &str) -> bool {
+    let mut chars = name.chars();
+    chars.next().is_some_and(|c| is_name_char(c, true)) && chars.all(|c| is_name_char(c, false))
+}
+
+/// Check that every `&` starts an entity or character reference.
+fn check_refs(s: &str) -> std::result::Result<(), String> {
+    for (i, _) in s.match_indices('&') {
+        let end = s[i..].find(';').map(|j| i + j);
+        let ok = end.is_some_and(|end| {
+            let r = &s[i + 1..end];
+            match r.strip_prefix('#') {
+                Some(n) => match n.strip_prefix('x') {
+                    Some(h) => !h.is_empty() && h.chars().all(|c| c.is_ascii_hexdigit()),
+                    None => !n.is_empty() && n.chars().all(|c| c.is_ascii_digit()),
+                },
+                None => valid_name(r),
+            }
+        });
+        if !ok {
+            return Err(format!("invalid reference at '{}'", s[i..].chars().take(10).collect::<String>()));
+        }
+    }
+    Ok(())
+}
+
+/// Split an XML document into tokens.
+fn tokenize(s: &str) -> std::result::Result<Vec<Token<'_>>, String> {
+    let find = |from: usize, pat: &str, what: &str| {
+        s[from..].find(pat).map(|j| from + j + pat.len()).ok_or(format!("unclosed {}", what))
+    };
+    let mut tokens = Vec::new();
+    let mut i = 0;
+    while i < s.len() {
+        let rest = &s[i..];
+        if !rest.starts_with('<') {
+            let end = rest.find('<').map_or(s.len(), |j| i + j);
+            check_refs(&s[i..end])?;
+            tokens.push(Token::Text(&s[i..end]));
+            i = end;
+        } else if rest.starts_with("<!--") {
+            i = find(i + 4, "-->", "comment")?;
+            tokens.push(Token::Comment);
+        } else if rest.starts_with("<![CDATA[") {
+            let end = find(i, "]]>", "CDATA section")?;
+            tokens.push(Token::Raw(&s[i..end]));
+            i = end;
+        } else if rest.starts_with("<?") {
+            let end = find(i, "?>", "processing instruction")?;
+            tokens.push(Token::Raw(&s[i..end]));
+            i = end;
+        } else if rest.starts_with("<!DOCTYPE") {
+            // Skip the internal subset in brackets and quoted strings.
+            let (mut depth, mut quote, mut end) = (0, None, None);
+            for (j, c) in rest.char_indices() {
+                match (quote, c) {
+                    (Some(q), c) if c == q => quote = None,
+                    (Some(_), _) => {}
+                    (None, '"' | '\'') => quote = Some(c),
+                    (None, '[') => depth += 1,
+                    (None, ']') => depth -= 1,
+                    (None, '>') if depth == 0 => { end = Some(i + j + 1); break; }
+                    _ => {}
+                }
+            }
+            let end = end.ok_or("unclosed DOCTYPE")?;
+            tokens.push(Token::Raw(&s[i..end]));
+            i = end;
+        } else if let Some(tag) = rest.strip_prefix("</") {
+            let end = tag.find('>').ok_or("unclosed end tag")?;
+            let name = tag[..end].trim_end();
+            if !valid_name(name) {
+                return Err(format!("invalid end tag </{}>", &tag[..end]));
+            }
+            tokens.push(Token::End(name));
+            i += 2 + end + 1;
+        } else {
+            let tag = &rest[1..];
+            let name_end = tag.find(|c: char| c.is_whitespace() || c == '/' || c == '>').unwrap_or(tag.len());
+            let name = &tag[..name_end];
+            if !valid_name(name) {
+                return Err(format!("invalid tag <{}", name));
+            }
+            let mut j = name_end;
+            let mut attrs: Vec<(&str, &str, char)> = Vec::new();
+            let empty = loop {
+                let after = tag[j..].trim_start();
+                let spaced = after.len() < tag[j..].len();
+                j = tag.len() - after.len();
+                if after.starts_with("/>") {
+                    j += 2;
+                    break true;
+                } else if after.starts_with('>') {
+                    j += 1;
+                    break false;
+                } else if after.is_empty() {
+                    return Err(format!("unclosed tag <{}>", name));
+                } else if !spaced {
+                    return Err(format!("missing space between attributes in <{}>", name));
+                }
+                let n_end = after.find(|c: char| c.is_whitespace() || c == '=' || c == '/' || c == '>')
+                    .unwrap_or(after.len());
+                let attr = &after[..n_end];
+                let value = after[n_end..].trim_start().strip_prefix('=').map(str::trim_start);
+                let quote = value.and_then(|v| v.chars().next()).filter(|&q| q == '"' || q == '\'');
+                let (Some(value), Some(q), true) = (value, quote, valid_name(attr)) else {
+                    return Err(format!("invalid attribute '{}' in <{}>", attr, name));
+                };
+                let v_end = value[1..].find(q).ok_or(format!("unclosed attribute value in <{}>", name))? + 1;
+                let v = &value[1..v_end];
+                if v.contains('<') {
+                    return Err(format!("'<' in the value of attribute '{}'", attr));
+                }
+                check_refs(v)?;
+                if attrs.iter().any(|a| a.0 == attr) {
+                    return Err(format!("duplicate attribute '{}' in <{}>", attr, name));
+                }
+                attrs.push((attr, v, q));
+                j = tag.len() - value[v_end + 1..].len();
+            };
+            tokens.push(Token::Start { name, attrs, empty });
+            i += 1 + j;
+        }
+    }
+    Ok(tokens)
+}
+
+/// The prefix and local name of a qualified name.
+fn split_name(name: &str) -> (Option<&str>, &str) {
+    match name.split_once(':') {
+        Some((p, l)) => (Some(p), l),
+        None => (None, name),
+    }
+}
+
+/// Format a number with at most `precision` decimal places (trailing zeros
+/// dropped).
+fn format_number(v: f64, precision: usize) -> String {
+    let s = format!("{:.*}", precision, v);
+    let s = if s.contains('.') { s.trim_end_matches('0').trim_end_matches('.') } else { &s };
+    if s == "-0" { "0".to_string() } else { s.to_string() }
+}
+
+/// The length of the number at the start of `s` (0 if there is none).
+fn number_len(s: &str) -> usize {
+    let b = s.as_bytes();
+    let digits = |mut i: usize| { while i < b.len() && b[i].is_ascii_digit() { i += 1; } i };
+    let mut i = if matches!(b.first(), Some(b'+' | b'-')) { 1 } else { 0 };
+    let int_end = digits(i);
+    let mut end = int_end;
+    if b.get(end) == Some(&b'.') {
+        end = digits(end + 1);
+    }
+    if end == i || (end == i + 1 && int_end == i) {
+        return 0;  // no digits
+    }
+    i = end;
+    if matches!(b.get(i), Some(b'e' | b'E')) {
+        let j = if matches!(b.get(i + 1), Some(b'+' | b'-')) { i + 2 } else { i + 1 };
+        if digits(j) > j {
+            end = digits(j);
+        }
+    }
+    end
+}
+
+/// Round the numbers in an attribute value and collapse its whitespace.
+fn round_numbers(value: &str, precision: Option<usize>) -> String {
+    let mut out = String::with_capacity(value.len());
+    let mut rest = value.trim();
+    while let Some(c) = rest.chars().next() {
+        let n = number_len(rest);
+        // Digits after letters (e.g., in `h1`) are not numbers.
+        let after_word = out.chars().last().is_some_and(|c| c.is_alphabetic());
+        if n > 0 && !after_word {
+            let num = &rest[..n];
+            match (precision, num.parse::<f64>()) {
+                (Some(p), Ok(v)) => out.push_str(&format_number(v, p)),
+                _ => out.push_str(num),
+            }
+            rest = &rest[n..];
+        } else if c.is_whitespace() {
+            out.push(' ');
+            rest = rest.trim_start();
+        } else {
+            out.push(c);
+            rest = &rest[c.len_utf8()..];
+        }
+    }
+    out
+}
+
+/// Minify path data: round coordinates and drop redundant separators.  The
+/// coordinates of relative commands are rounded relative to the rounded
+/// current point, so rounding errors do not accumulate along the path.
+/// Returns `None` for invalid path data (which is then left alone).
+fn minify_path(d: &str, precision: Option<usize>) -> Option<String> {
+    // Parameter kinds of each command: x, y, a non-coordinate number, or a
+    // flag.
+    #[derive(Clone, Copy, PartialEq)]
+    enum Kind { X, Y, Num, Flag }
+    use Kind::*;
+    let kinds = |cmd: char| -> &'static [Kind] {
+        match cmd.to_ascii_uppercase() {
+            'M' | 'L' | 'T' => &[X, Y],
+            'H' => &[X],
+            'V' => &[Y],
+            'C' => &[X, Y, X, Y, X, Y],
+            'S' | 'Q' => &[X, Y, X, Y],
+            'A' => &[Num, Num, Num, Flag, Flag, X, Y],
+            _ => &[],
+        }
+    };
+    let mut out = String::with_capacity(d.len());
+    // The exact and the rounded current point and subpath start.
+    let (mut cur, mut cur_r, mut start, mut start_r) = ([0.0f64; 2], [0.0f64; 2], [0.0f64; 2], [0.0f64; 2]);
+    let mut cmd: Option<char> = None;
+    let mut params: Vec<(Kind, &str)> = Vec::new();
+    let mut rest = d;
+    loop {
+        rest = rest.trim_start_matches(|c: char| c.is_whitespace() || c == ',');
+        let next = rest.chars().next();
+        let is_cmd = next.is_some_and(|c| "MmZzLlHhVvCcSsQqTtAa".contains(c));
+        let Some(c) = cmd else {
+            match next {
+                None => return Some(out),
+                Some(c) if is_cmd && c.eq_ignore_ascii_case(&'m') => {}
+                _ => return None,
+            }
+            cmd = next;
+            out.push(next.unwrap());
+            rest = &rest[1..];
+            continue;
+        };
+        let ks = kinds(c);
+        if c.eq_ignore_ascii_case(&'z') {
+            (cur, cur_r) = (start, start_r);
+        } else if !is_cmd && next.is_some() {
+            let kind = ks[params.len()];
+            let n = if kind == Flag { usize::from(rest.starts_with(['0', '1'])) } else { number_len(rest) };
+            if n == 0 {
+                return None;
+            }
+            params.push((kind, &rest[..n]));
+            rest = &rest[n..];
+            if params.len() < ks.len() {
+                continue;
+            }
+            // A complete set of parameters: write them.
+            let relative = c.is_ascii_lowercase();
+            let mut end = cur;
+            let mut end_r = cur_r;
+            for &(kind, text) in &params {
+                let v: f64 = text.parse().ok()?;
+                let axis = usize::from(kind == Y);
+                let s = match (kind, precision) {
+                    (Flag, _) | (_, None) => text.to_string(),
+                    (Num, Some(p)) => format_number(v, p),
+                    (_, Some(p)) => {
+                        let exact = if relative { cur[axis] + v } else { v };
+                        let target = if relative { exact - cur_r[axis] } else { v };
+                        format_number(target, p)
+                    }
+                };
+                if matches!(kind, X | Y) {
+                    let r: f64 = s.parse().ok()?;
+                    end[axis] = if relative { cur[axis] + v } else { v };
+                    end_r[axis] = if relative { cur_r[axis] + r } else { r };
+                }
+                // Numbers are separated by a space, unless they follow a
+                // command or start with a minus sign.
+                if !out.ends_with(|c: char| c.is_ascii_alphabetic()) && !s.starts_with('-') {
+                    out.push(' ');
+                }
+                out.push_str(&s);
+            }
+            // The last point of a segment (or the current point on the
+            // other axis for `H` and `V`) is the new current point.
+            (cur, cur_r) = (end, end_r);
+            if c.eq_ignore_ascii_case(&'m') {
+                (start, start_r) = (cur, cur_r);
+                // Further coordinate pairs after a moveto are linetos.
+                cmd = Some(if relative { 'l' } else { 'L' });
+            }
+            params.clear();
+            continue;
+        }
+        if !params.is_empty() {
+            return None;  // incomplete parameters
+        }
+        match next {
+            None => return Some(out),
+            Some(n) if is_cmd => {
+                // A repeated command is implied (except for closepath).
+                if cmd != Some(n) || n.eq_ignore_ascii_case(&'z') {
+                    out.push(n);
+                }
+                cmd = Some(n);
+                rest = &rest[1..];
+            }
+            _ => return None,
+        }
+    }
+}
+
+/// How whitespace in the text of an element is handled.
+#[derive(Clone, Copy, PartialEq)]
+enum Space {
+    /// Whitespace-only text is dropped (e.g., indentation between elements).
+    Drop,
+    /// Whitespace is collapsed to single spaces, as it is rendered.
+    Collapse,
+    /// Text is kept verbatim (`<style>`, `<script>`, and `xml:space`).
+    Preserve,
+}
+
+/// Collapse runs of whitespace into single spaces.
+fn collapse_space(s: &str) -> String {
+    let mut out = String::with_capacity(s.len());
+    for (i, word) in s.split(|c: char| c.is_ascii_whitespace()).enumerate() {
+        if i > 0 && !out.ends_with(' ') {
+            out.push(' ');
+        }
+        out.push_str(word);
+    }
+    out
+}
+
+/// Minify an SVG document, or return why it cannot be parsed.
+fn minify_svg(svg: &str, precision: Option<usize>) -> std::result::Result<String, String> {
+    let tokens = tokenize(svg)?;
+    let dropped_element = |name: &str| {
+        let (prefix, local) = split_name(name);
+        local == "metadata" || prefix.is_some_and(|p| EDITOR_PREFIXES.contains(&p))
+    };
+
+    // Check the structure, and mark the tokens to keep and the namespace
+    // prefixes still used.
+    let mut keep = vec![true; tokens.len()];
+    let mut used = HashSet::new();
+    let mut stack: Vec<&str> = Vec::new();
+    let (mut root_done, mut skip_depth) = (false, None);
+    for (i, token) in tokens.iter().enumerate() {
+        match token {
+            Token::Start { name, attrs, empty } => {
+                if stack.is_empty() {
+                    if root_done {
+                        return Err("more than one root element".into());
+                    }
+                    if split_name(name).1 != "svg" {
+                        return Err(format!("the root element is <{}> instead of <svg>", name));
+                    }
+                }
+                if skip_depth.is_none() && dropped_element(name) {
+                    skip_depth = Some(stack.len());
+                }
+                if skip_depth.is_some() {
+                    keep[i] = false;
+                } else {
+                    let names = attrs.iter().map(|a| a.0).filter(|a| !a.starts_with("xmlns"));
+                    used.extend(std::iter::once(*name).chain(names).filter_map(|n| split_name(n).0));
+                }
+                if !empty {
+                    stack.push(name);
+                } else if skip_depth == Some(stack.len()) {
+                    skip_depth = None;
+                }
+                root_done |= stack.is_empty();
+            }
+            Token::End(name) => {
+                match stack.pop() {
+                    Some(open) if open == *name => {}
+                    Some(open) => return Err(format!("</{}> does not match <{}>", name, open)),
+                    None => return Err(format!("unexpected </{}>", name)),
+                }
+                if skip_depth.is_some() {
+                    keep[i] = false;
+                    if skip_depth == Some(stack.len()) {
+                        skip_depth = None;
+                    }
+                }
+                root_done |= stack.is_empty();
+            }
+            Token::Comment => keep[i] = false,
+            Token::Text(text) => {
+                if stack.is_empty() && !text.trim().is_empty() {
+                    return Err("text outside the root element".into());
+                }
+                keep[i] = skip_depth.is_none() && !stack.is_empty();
+            }
+            Token::Raw(_) => keep[i] = skip_depth.is_none(),
+        }
+    }
+    if let Some(open) = stack.last() {
+        return Err(format!("<{}> is not closed", open));
+    }
+    if !root_done {
+        return Err("no <svg> element".into());
+    }
+
+    let dropped_attr = |name: &str| match split_name(name) {
+        (Some("xmlns"), p) => EDITOR_PREFIXES.contains(&p) || (METADATA_PREFIXES.contains(&p) && !used.contains(p)),
+        (Some(p), _) => EDITOR_PREFIXES.contains(&p),
+        _ => false,
+    };
+    let mut out = String::with_capacity(svg.len());
+    let mut spaces: Vec<Space> = Vec::new();
+    // Whether the last thing written is a start tag (so that an end tag can
+    // turn it into an empty-element tag).
+    let mut open_tag = false;
+    for (token, _) in tokens.iter().zip(&keep).filter(|(_, &k)| k) {
+        match token {
+            Token::Start { name, attrs, empty } => {
+                let local = split_name(name).1;
+                let parent = spaces.last().copied().unwrap_or(Space::Drop);
+                let space = if local == "style" || local == "script" || parent == Space::Preserve ||
+                    attrs.iter().any(|a| a.0 == "xml:space" && a.1 == "preserve") {
+                    Space::Preserve
+                } else if parent == Space::Collapse || TEXT_ELEMENTS.contains(&local) {
+                    Space::Collapse
+                } else {
+                    Space::Drop
+                };
+                out.push('<');
+                out.push_str(name);
+                for &(attr, value, quote) in attrs.iter().filter(|a| !dropped_attr(a.0)) {
+                    let value = if value.contains('&') {
+                        value.to_string()
+                    } else if attr == "d" {
+                        minify_path(value, precision).unwrap_or_else(|| value.to_string())
+                    } else if COORD_ATTRS.contains(&attr) {
+                        round_numbers(value, precision)
+                    } else {
+                        value.to_string()
+                    };
+                    let _ = write!(out, " {}={}{}{}", attr, quote, value, quote);
+                }
+                if *empty {
+                    out.push_str("/>");
+                } else {
+                    out.push('>');
+                    spaces.push(space);
+                }
+                open_tag = !empty;
+                continue;
+            }
+            Token::End(name) => {
+                spaces.pop();
+                if open_tag {
+                    out.pop();
+                    out.push_str("/>");
+                } else {
+                    let _ = write!(out, "</{}>", name);
+                }
+            }
+            Token::Text(text) => match spaces.last().copied().unwrap_or(Space::Drop) {
+                Space::Preserve => out.push_str(text),
+                Space::Collapse => out.push_str(&collapse_space(text)),
+                Space::Drop => {
+                    if !text.trim().is_empty() {
+                        out.push_str(&collapse_space(text));
+                    }
+                }
+            },
+            Token::Raw(raw) => out.push_str(raw),
+            Token::Comment => {}
+        }
+        open_tag = false;
+    }
+    Ok(out)
+}
+
+/// Minify SVG files
+///
+/// Files that cannot be parsed are not written, and an error is signaled.
+///
+/// @param input Vector of input SVG file paths
+/// @param output Vector of output SVG file paths (same length as input)
+/// @param precision The number of decimal places of coordinates (`-1` to
+///   keep the numbers as they are)
+/// @param verbose Print file size reduction info
+/// @export
+#[extendr]
+fn tinysvg_impl(input: Strings, output: Strings, precision: i32, verbose: bool) -> Result<()> {
+    let precision = usize::try_from(precision).ok();
+    let inputs: Vec<String>  = input.iter().map(|s| s.to_string()).collect();
+    let outputs: Vec<String> = output.iter().map(|s| s.to_string()).collect();
+    validate_io(&inputs, &outputs)?;
+    process_files(&inputs, &outputs, verbose, |input_path, output_path| {
+        let svg = std::fs::read_to_string(input_path)
+            .map_err(|e| TinyImgRError::io(format!("Failed to read {}: {}", input_path.display(), e)))?;
+        let min = minify_svg(&svg, precision)
+            .map_err(|e| TinyImgRError::decode(format!("Failed to parse SVG {}: {}", input_path.display(), e)))?;
+        std::fs::write(output_path, &min)
+            .map_err(|e| TinyImgRError::io(format!("Failed to write {}: {}", output_path.display(), e)))?;
+        Ok((svg.len() as u64, min.len() as u64))
+    })
+}
+
+extendr_module! {
+    mod svg;
+    fn tinypng_png_to_svg_trace_impl;
+    fn tinysvg_impl;
+}
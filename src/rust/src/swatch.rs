@@ -0,0 +1,136 @@
+use extendr_api::prelude::*;
+use std::path::PathBuf;
+
+use crate::error::TinyImgRError;
+use crate::optimize::read_png_colors;
+use crate::quantize::{Color, Ditherer, NoDither};
+use crate::write_png_rgba;
+
+// ---------------------------------------------------------------------------
+// Palette swatches
+// ---------------------------------------------------------------------------
+//
+// A swatch image shows the palette of an image as a row of solid-color
+// rectangles, each labelled with its hex code in a tiny built-in bitmap font
+// (only the characters of hex codes are needed).
+
+/// 5x7 glyphs of `#`, `0`-`9`, and `A`-`F`: one byte per row, with the
+/// leftmost pixel in bit 4.
+const GLYPHS: [(char, [u8; 7]); 17] = [
+    ('#', [0x0A, 0x0A, 0x1F, 0x0A, 0x1F, 0x0A, 0x0A]),
+    ('0', [0x0E, 0x11, 0x13, 0x15, 0x19, 0x11, 0x0E]),
+    ('1', [0x04, 0x0C, 0x04, 0x04, 0x04, 0x04, 0x0E]),
+    ('2', [0x0E, 0x11, 0x01, 0x02, 0x04, 0x08, 0x1F]),
+    ('3', [0x1F, 0x02, 0x04, 0x02, 0x01, 0x11, 0x0E]),
+    ('4', [0x02, 0x06, 0x0A, 0x12, 0x1F, 0x02, 0x02]),
+    ('5', [0x1F, 0x10, 0x1E, 0x01, 0x01, 0x11, 0x0E]),
+    ('6', [0x06, 0x08, 0x10, 0x1E, 0x11, 0x11, 0x0E]),
+    ('7', [0x1F, 0x01, 0x02, 0x04, 0x08, 0x08, 0x08]),
+    ('8', [0x0E, 0x11, 0x11, 0x0E, 0x11, 0x11, 0x0E]),
+    ('9', [0x0E, 0x11, 0x11, 0x0F, 0x01, 0x02, 0x0C]),
+    ('A', [0x0E, 0x11, 0x11, 0x1F, 0x11, 0x11, 0x11]),
+    ('B', [0x1E, 0x11, 0x11, 0x1E, 0x11, 0x11, 0x1E]),
+    ('C', [0x0E, 0x11, 0x10, 0x10, 0x10, 0x11, 0x0E]),
+    ('D', [0x1C, 0x12, 0x11, 0x11, 0x11, 0x12, 0x1C]),
+    ('E', [0x1F, 0x10, 0x10, 0x1E, 0x10, 0x10, 0x1F]),
+    ('F', [0x1F, 0x10, 0x10, 0x1E, 0x10, 0x10, 0x10]),
+];
+
+const GLYPH_WIDTH: usize = 5;
+const GLYPH_HEIGHT: usize = 7;
+
+/// Minimum margin in pixels between a label and the edges of its swatch.
+const LABEL_MARGIN: usize = 2;
+
+/// The hex code of a color (with the alpha only if it is not opaque).
+pub(crate) fn hex_code(c: Color) -> String {
+    if c.a == 255 {
+        format!("#{:02X}{:02X}{:02X}", c.r, c.g, c.b)
+    } else {
+        format!("#{:02X}{:02X}{:02X}{:02X}", c.r, c.g, c.b, c.a)
+    }
+}
+
+/// Black or white, whichever contrasts more with a color (by its WCAG
+/// relative luminance).
+fn label_color(c: Color) -> [u8; 4] {
+    let lin = |v: u8| {
+        let u = v as f64 / 255.0;
+        if u <= 0.04045 { u / 12.92 } else { ((u + 0.055) / 1.055).powf(2.4) }
+    };
+    let y = 0.2126 * lin(c.r) + 0.7152 * lin(c.g) + 0.0722 * lin(c.b);
+    // Contrast ratios with white and black are equal at this luminance.
+    if y > 0.179 { [0, 0, 0, 255] } else { [255, 255, 255, 255] }
+}
+
+/// Draw `text` centered in the rectangle at (`x0`, 0) of size `w` x `h` of an
+/// RGBA image of `width` pixels per row, at the largest integer scale that
+/// fits in the margins.  Nothing is drawn if the text does not fit at scale 1.
+fn draw_text(rgba: &mut [u8], width: usize, (x0, w, h): (usize, usize, usize), text: &str, color: [u8; 4]) {
+    let text_w = text.len() * (GLYPH_WIDTH + 1) - 1;
+    let scale = ((w.saturating_sub(2 * LABEL_MARGIN)) / text_w)
+        .min(h.saturating_sub(2 * LABEL_MARGIN) / GLYPH_HEIGHT);
+    if scale == 0 {
+        return;
+    }
+    let (left, top) = (x0 + (w - text_w * scale) / 2, (h - GLYPH_HEIGHT * scale) / 2);
+    for (k, ch) in text.chars().enumerate() {
+        let Some((_, rows)) = GLYPHS.iter().find(|(c, _)| *c == ch) else { continue };
+        for (gy, bits) in rows.iter().enumerate() {
+            for gx in 0..GLYPH_WIDTH {
+                if bits & (0x10 >> gx) == 0 {
+                    continue;
+                }
+                let x = left + (k * (GLYPH_WIDTH + 1) + gx) * scale;
+                for y in top + gy * scale..top + (gy + 1) * scale {
+                    for x in x..x + scale {
+                        let i = 4 * (y * width + x);
+                        rgba[i..i + 4].copy_from_slice(&color);
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Write the palette of a PNG image as a swatch image
+///
+/// @param input Path to the input PNG file
+/// @param n_colors Number of palette colors (1-256)
+/// @param swatch_width,swatch_height Size of each swatch in pixels
+/// @param output Path to the output PNG file
+/// @return The hex codes of the palette colors
+/// @export
+#[extendr]
+fn tinypng_export_palette_png_impl(
+    input: &str, n_colors: i32, swatch_width: i32, swatch_height: i32, output: &str,
+) -> Result<Strings> {
+    if !(1..=256).contains(&n_colors) {
+        return Err(TinyImgRError::argument("The number of colors must be between 1 and 256").into());
+    }
+    if swatch_width <= 0 || swatch_height <= 0 {
+        return Err(TinyImgRError::argument("The swatch width and height must be positive").into());
+    }
+    let (pixels, width, _) = read_png_colors(&PathBuf::from(input))?;
+    let (palette, _) = NoDither.to_indexed(&pixels, width, n_colors as usize);
+    drop(pixels);
+    let (sw, sh) = (swatch_width as usize, swatch_height as usize);
+    let total_w = sw * palette.len();
+    let mut rgba = vec![0u8; total_w * sh * 4];
+    for (k, &c) in palette.iter().enumerate() {
+        for y in 0..sh {
+            for x in k * sw..(k + 1) * sw {
+                let i = 4 * (y * total_w + x);
+                rgba[i..i + 4].copy_from_slice(&[c.r, c.g, c.b, c.a]);
+            }
+        }
+        draw_text(&mut rgba, total_w, (k * sw, sw, sh), &hex_code(c), label_color(c));
+    }
+    write_png_rgba(&PathBuf::from(output), &rgba, total_w, sh)?;
+    Ok(palette.iter().map(|&c| hex_code(c)).collect::<Strings>())
+}
+
+extendr_module! {
+    mod swatch;
+    fn tinypng_export_palette_png_impl;
+}
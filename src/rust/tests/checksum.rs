@@ -0,0 +1,28 @@
+// Tests of the SHA-256 hashes of optimized files.
+
+use tinyimg::optimize::{sha256_hex, PngSettings};
+
+#[test]
+fn hashes_known_messages() {
+    assert_eq!(sha256_hex(b""), "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855");
+    assert_eq!(sha256_hex(b"abc"), "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad");
+}
+
+#[test]
+fn hashes_output_data() {
+    let dir = std::env::temp_dir().join(format!("tinyimg-checksum-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let input = dir.join("in.png");
+    let bytes: Vec<u8> = (0..64 * 48).flat_map(|i| [(i % 64 * 4) as u8, (i / 64 * 5) as u8, 128]).collect();
+    lodepng::encode24_file(&input, &bytes, 64, 48).unwrap();
+    let settings = || PngSettings::new(2, false, false, 0.0, [0.0; 3], "d65").unwrap();
+    let output = dir.join("out.png");
+    let (_, notes) = settings().optimize_auto(&input, &output).unwrap();
+    assert_eq!(notes.sha256, None);
+    let (_, notes) = settings().with_checksum(true).optimize_auto(&input, &output).unwrap();
+    assert_eq!(notes.sha256.unwrap(), sha256_hex(&std::fs::read(&output).unwrap()));
+    // An optimal file is left untouched in place, and hashed as it is.
+    let (_, notes) = settings().with_checksum(true).optimize_auto(&output, &output).unwrap();
+    assert_eq!(notes.sha256.unwrap(), sha256_hex(&std::fs::read(&output).unwrap()));
+    std::fs::remove_dir_all(&dir).unwrap();
+}
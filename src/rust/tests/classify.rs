@@ -0,0 +1,56 @@
+// Tests of the classification of image content while optimizing.
+
+use std::path::PathBuf;
+
+use tinyimg::optimize::{ImageClass, PngSettings};
+
+/// A photo-like RGB image: smooth gradients with a little noise, which PNG
+/// cannot compress much.
+fn write_photo_png(path: &PathBuf, width: usize, height: usize) {
+    let mut seed = 42u32;
+    let mut noise = || {
+        seed = seed.wrapping_mul(1_103_515_245).wrapping_add(12_345);
+        ((seed >> 16) % 9) as usize
+    };
+    let mut bytes: Vec<u8> = Vec::with_capacity(width * height * 3);
+    for y in 0..height {
+        for x in 0..width {
+            bytes.extend([(x * 200 / width + noise()) as u8, (y * 200 / height + noise()) as u8, (100 + noise()) as u8]);
+        }
+    }
+    lodepng::encode24_file(path, &bytes, width, height).unwrap();
+}
+
+/// A graphic: a few flat rectangles.
+fn write_graphic_png(path: &PathBuf, width: usize, height: usize) {
+    let mut bytes: Vec<u8> = Vec::with_capacity(width * height * 3);
+    for y in 0..height {
+        for x in 0..width {
+            bytes.extend(if x < width / 2 { [255, 255, 255] } else if y < height / 2 { [200, 0, 0] } else { [0, 0, 200] });
+        }
+    }
+    lodepng::encode24_file(path, &bytes, width, height).unwrap();
+}
+
+#[test]
+fn classifies_images_while_optimizing() {
+    let dir = std::env::temp_dir().join(format!("tinyimg-classify-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let (photo, graphic) = (dir.join("photo.png"), dir.join("graphic.png"));
+    write_photo_png(&photo, 300, 200);
+    write_graphic_png(&graphic, 300, 200);
+    let output = dir.join("out.png");
+    let lossless = || PngSettings::new(0, false, false, 0.0, [0.0; 3], "d65").unwrap();
+    let class = |settings: &PngSettings, input: &PathBuf| settings.optimize_auto(input, &output).unwrap().1.class;
+    // Nothing is classified by default.
+    assert_eq!(class(&lossless(), &photo), None);
+    let classify = lossless().with_classify(Some(0.1));
+    // The photo is hardly made smaller, so its input data is decoded.
+    assert_eq!(class(&classify, &photo), Some(ImageClass::Photo));
+    // With no saving allowed, the reduced photo is not decoded for it.
+    assert_eq!(class(&lossless().with_classify(Some(0.0)), &photo), None);
+    // Lossy reduction decodes the pixels anyway, so they are always classified.
+    let lossy = PngSettings::new(0, false, false, 1.0, [0.0; 3], "d65").unwrap().with_classify(Some(0.1));
+    assert_eq!(class(&lossy, &graphic), Some(ImageClass::Graphic));
+    std::fs::remove_dir_all(&dir).unwrap();
+}
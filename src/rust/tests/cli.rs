@@ -0,0 +1,427 @@
+// Integration tests of the command-line tool (run with
+// `cargo test --no-default-features --features cli`).
+
+use std::path::{Path, PathBuf};
+use std::process::{Command, Output};
+
+fn tinyimg_cli(args: &[&str]) -> Output {
+    Command::new(env!("CARGO_BIN_EXE_tinyimg-cli"))
+        .args(args)
+        .output()
+        .expect("failed to run tinyimg-cli")
+}
+
+/// A fresh temporary directory for a test.
+fn temp_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("tinyimg-cli-{}-{}", name, std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+/// Write an RGBA gradient PNG (kept as RGBA, so oxipng can always shrink it).
+fn write_png(path: &Path) {
+    let (w, h) = (64, 48);
+    let mut rgba = Vec::with_capacity(w * h * 4);
+    for y in 0..h {
+        for x in 0..w {
+            rgba.extend_from_slice(&[(x * 4) as u8, (y * 5) as u8, 128, 255]);
+        }
+    }
+    let mut encoder = lodepng::Encoder::new();
+    encoder.set_auto_convert(false);
+    let png = encoder.encode(&rgba, w, h).unwrap();
+    std::fs::write(path, png).unwrap();
+}
+
+/// A PNG chunk with its length and CRC.
+fn png_chunk(name: &[u8; 4], data: &[u8]) -> Vec<u8> {
+    let mut crc = 0xFFFF_FFFF_u32;
+    for &byte in name.iter().chain(data) {
+        crc ^= byte as u32;
+        for _ in 0..8 {
+            crc = if crc & 1 == 1 { (crc >> 1) ^ 0xEDB8_8320 } else { crc >> 1 };
+        }
+    }
+    [&(data.len() as u32).to_be_bytes()[..], name, data, &(!crc).to_be_bytes()].concat()
+}
+
+fn size(path: &Path) -> u64 {
+    std::fs::metadata(path).unwrap().len()
+}
+
+#[test]
+fn invalid_arguments_exit_with_2() {
+    for args in [
+        &[][..], &["--level", "7", "a.png"], &["--bogus", "a.png"], &["-o", "x", "a.png", "b.png"],
+        &["--lossy-speed", "11", "a.png"], &["--threads", "-1", "a.png"],
+    ] {
+        let out = tinyimg_cli(args);
+        assert_eq!(out.status.code(), Some(2), "args: {:?}", args);
+        assert!(String::from_utf8_lossy(&out.stderr).contains("Usage:"));
+    }
+    assert_eq!(tinyimg_cli(&["--help"]).status.code(), Some(0));
+}
+
+#[test]
+fn optimizes_files_and_writes_json_report() {
+    let dir = temp_dir("ok");
+    let input = dir.join("a.png");
+    write_png(&input);
+    let before = size(&input);
+
+    let output = dir.join("out.png");
+    let json = dir.join("report.json");
+    let out = tinyimg_cli(&[
+        input.to_str().unwrap(), "-o", output.to_str().unwrap(), "--json", json.to_str().unwrap(),
+    ]);
+    assert_eq!(out.status.code(), Some(0), "{}", String::from_utf8_lossy(&out.stderr));
+    assert_eq!(size(&input), before, "the input should not be modified");
+    assert!(size(&output) < before);
+    let report = std::fs::read_to_string(&json).unwrap();
+    assert!(report.contains(&format!("\"input_size\": {}", before)));
+    assert!(report.contains(&format!("\"output_size\": {}", size(&output))));
+    assert!(report.contains("\"error\": null"));
+
+    // Lossy reduction writes `_l<lossy>` files next to the inputs by default.
+    let out = tinyimg_cli(&["-q", "--lossy", "2.3", dir.to_str().unwrap(), "--no-recursive"]);
+    assert_eq!(out.status.code(), Some(0));
+    assert!(out.stdout.is_empty());
+    assert!(dir.join("a_l2.3.png").exists());
+    std::fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn broken_files_exit_with_1() {
+    let dir = temp_dir("broken");
+    write_png(&dir.join("a.png"));
+    std::fs::write(dir.join("b.png"), b"not a png").unwrap();
+    write_png(&dir.join("c.png"));
+    let out_dir = dir.join("out");
+    let out = tinyimg_cli(&["--json", "-", "-o", out_dir.to_str().unwrap(), dir.to_str().unwrap()]);
+    assert_eq!(out.status.code(), Some(1));
+    // Other files are still optimized after a failure.
+    assert!(out_dir.join("a.png").exists());
+    assert!(out_dir.join("c.png").exists());
+    let report = String::from_utf8(out.stdout).unwrap();
+    assert_eq!(report.matches("\"error\": null").count(), 2);
+    assert!(report.contains("b.png\", \"input_size\": null"));
+    assert!(String::from_utf8_lossy(&out.stderr).contains("b.png"));
+    std::fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn parallel_batches_match_sequential_batches() {
+    let dir = temp_dir("threads");
+    let input = dir.join("in");
+    std::fs::create_dir_all(&input).unwrap();
+    // The first file is the largest, so it finishes last with several threads.
+    let (w, h) = (400, 300);
+    let rgba: Vec<u8> = (0..w * h).flat_map(|i| [(i * 7 % 251) as u8, (i / w) as u8, (i % w) as u8, 255]).collect();
+    std::fs::write(input.join("a0.png"), lodepng::encode32(&rgba, w, h).unwrap()).unwrap();
+    for i in 1..12 {
+        write_png(&input.join(format!("a{:02}.png", i)));
+    }
+    std::fs::write(input.join("a05.png"), b"not a png").unwrap();
+    let run = |threads: &str| {
+        let output = dir.join(format!("out{}", threads));
+        let out = tinyimg_cli(&["-j", threads, "--json", "-", "-o", output.to_str().unwrap(), input.to_str().unwrap()]);
+        assert_eq!(out.status.code(), Some(1));
+        let report = String::from_utf8(out.stdout).unwrap().replace(output.to_str().unwrap(), "out");
+        (output, report)
+    };
+    let (out1, report1) = run("1");
+    let (out4, report4) = run("4");
+    // The results are reported in the order of the files, and the broken file
+    // does not stop the others.
+    assert_eq!(report4, report1);
+    assert_eq!(report4.matches("\"error\": null").count(), 11);
+    for i in (0..12).filter(|&i| i != 5) {
+        let name = if i == 0 { "a0.png".to_string() } else { format!("a{:02}.png", i) };
+        assert_eq!(std::fs::read(out4.join(&name)).unwrap(), std::fs::read(out1.join(&name)).unwrap());
+    }
+    assert!(!out4.join("a05.png").exists());
+    std::fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn reproducible_output_has_no_time_chunks() {
+    let dir = temp_dir("reproducible");
+    let input = dir.join("a.png");
+    let (w, h) = (32, 32);
+    let rgba: Vec<u8> = (0..w * h).flat_map(|i| [(i % 251) as u8, (i / 7) as u8, 90, 255]).collect();
+    let mut encoder = lodepng::Encoder::new();
+    encoder.set_auto_convert(false);
+    encoder.info_png_mut().add_text("Creation Time", "2026-10-16 12:00:00").unwrap();
+    let png = encoder.encode(&rgba, w, h).unwrap();
+    // Insert a tIME chunk after IHDR (the 8-byte signature and 25-byte IHDR).
+    let time = png_chunk(b"tIME", &[0x07, 0xEA, 10, 16, 12, 0, 0]);
+    std::fs::write(&input, [&png[..33], &time, &png[33..]].concat()).unwrap();
+
+    let run = |name: &str, threads: &str, reproducible: bool| -> Vec<u8> {
+        let output = dir.join(name);
+        let mut args = vec!["-q", "--strip", "none", "-o", output.to_str().unwrap(), input.to_str().unwrap()];
+        if reproducible {
+            args.push("--reproducible");
+        }
+        let out = Command::new(env!("CARGO_BIN_EXE_tinyimg-cli"))
+            .args(&args)
+            .env("RAYON_NUM_THREADS", threads)
+            .output()
+            .unwrap();
+        assert_eq!(out.status.code(), Some(0), "{}", String::from_utf8_lossy(&out.stderr));
+        std::fs::read(output).unwrap()
+    };
+    let has = |png: &[u8], chunk: &[u8]| png.windows(4).any(|w| w == chunk);
+    let has_text = |png: &[u8]| [b"tEXt", b"zTXt", b"iTXt"].iter().any(|c| has(png, &c[..]));
+
+    // Without --reproducible, `--strip none` keeps the time-varying chunks.
+    let kept = run("kept.png", "1", false);
+    assert!(has(&kept, b"tIME") && has_text(&kept));
+
+    let (a, b) = (run("b.png", "1", true), run("c.png", "4", true));
+    assert_eq!(a, b, "outputs differ across runs and thread counts");
+    assert!(!has(&a, b"tIME") && !has_text(&a));
+    std::fs::remove_dir_all(dir).unwrap();
+}
+
+/// A Display P3 ICC profile (D50-adapted colorants and the sRGB tone curve).
+fn display_p3_icc(space: &[u8; 4]) -> Vec<u8> {
+    let s15 = |v: f64| ((v * 65536.0).round() as i32).to_be_bytes();
+    let xyz = |v: [f64; 3]| [&b"XYZ \0\0\0\0"[..], &s15(v[0]), &s15(v[1]), &s15(v[2])].concat();
+    let mut trc = b"para\0\0\0\0\0\x03\0\0".to_vec();
+    for v in [2.4, 1.0 / 1.055, 0.055 / 1.055, 1.0 / 12.92, 0.04045] {
+        trc.extend(s15(v));
+    }
+    let tags = [
+        (b"rXYZ", xyz([0.515121, 0.241196, -0.001053])),
+        (b"gXYZ", xyz([0.291977, 0.692245, 0.041885])),
+        (b"bXYZ", xyz([0.157104, 0.066574, 0.784073])),
+        (b"rTRC", trc.clone()), (b"gTRC", trc.clone()), (b"bTRC", trc),
+    ];
+    let mut icc = vec![0u8; 128];
+    icc[16..20].copy_from_slice(space);
+    icc[20..24].copy_from_slice(b"XYZ ");
+    icc[36..40].copy_from_slice(b"acsp");
+    icc.extend((tags.len() as u32).to_be_bytes());
+    let mut offset = 128 + 4 + 12 * tags.len();
+    for (sig, data) in &tags {
+        icc.extend([&sig[..], &(offset as u32).to_be_bytes(), &(data.len() as u32).to_be_bytes()].concat());
+        offset += data.len();
+    }
+    for (_, data) in tags {
+        icc.extend(data);
+    }
+    let len = icc.len() as u32;
+    icc[..4].copy_from_slice(&len.to_be_bytes());
+    icc
+}
+
+/// An iCCP chunk of an ICC profile, compressed as a single stored deflate block.
+fn iccp_chunk(icc: &[u8]) -> Vec<u8> {
+    let (mut a, mut b) = (1u32, 0u32);
+    for &byte in icc {
+        a = (a + byte as u32) % 65521;
+        b = (b + a) % 65521;
+    }
+    let len = icc.len() as u16;
+    let zlib = [
+        &[0x78, 0x01, 0x01][..], &len.to_le_bytes(), &(!len).to_le_bytes(), icc, &((b << 16) | a).to_be_bytes(),
+    ].concat();
+    png_chunk(b"iCCP", &[&b"P3\0\0"[..], &zlib].concat())
+}
+
+#[test]
+fn converts_color_profiles_to_srgb() {
+    let dir = temp_dir("profile");
+    let (w, h) = (16, 16);
+    let rgba: Vec<u8> = (0..w * h).flat_map(|_| [200, 50, 50, 255]).collect();
+    let mut encoder = lodepng::Encoder::new();
+    encoder.set_auto_convert(false);
+    let png = encoder.encode(&rgba, w, h).unwrap();
+    let tagged = |icc: Vec<u8>| [&png[..33], &iccp_chunk(&icc), &png[33..]].concat();
+    let (p3, cmyk) = (dir.join("p3.png"), dir.join("cmyk.png"));
+    std::fs::write(&p3, tagged(display_p3_icc(b"RGB "))).unwrap();
+    std::fs::write(&cmyk, tagged(display_p3_icc(b"CMYK"))).unwrap();
+
+    let run = |input: &Path, name: &str, convert: bool| -> (Output, Vec<u8>) {
+        let output = dir.join(name);
+        let mut args = vec!["-q", "-o", output.to_str().unwrap(), input.to_str().unwrap()];
+        if convert {
+            args.push("--convert-profile");
+        }
+        let out = tinyimg_cli(&args);
+        assert_eq!(out.status.code(), Some(0), "{}", String::from_utf8_lossy(&out.stderr));
+        (out, std::fs::read(output).unwrap())
+    };
+    let first_pixel = |png: &[u8]| lodepng::decode32(png).unwrap().buffer[0];
+    let has_iccp = |png: &[u8]| png.windows(4).any(|w| w == b"iCCP");
+
+    // The profile is only stripped by default.
+    let (_, stripped) = run(&p3, "a.png", false);
+    assert!(!has_iccp(&stripped));
+    assert_eq!(first_pixel(&stripped), lodepng::RGBA::new(200, 50, 50, 255));
+    // Display P3 (200, 50, 50) is about (218, 24, 40) in sRGB.
+    let (_, converted) = run(&p3, "b.png", true);
+    assert!(!has_iccp(&converted));
+    let c = first_pixel(&converted);
+    for (v, expected) in [(c.r, 218), (c.g, 24), (c.b, 40)] {
+        assert!((v as i32 - expected).abs() <= 1, "{:?}", c);
+    }
+    // Unsupported profiles are kept, with a warning.
+    let (out, kept) = run(&cmyk, "c.png", true);
+    assert!(has_iccp(&kept));
+    assert_eq!(first_pixel(&kept), lodepng::RGBA::new(200, 50, 50, 255));
+    assert!(String::from_utf8_lossy(&out.stderr).contains("warning: Kept the color profile"));
+    std::fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn explains_optimization_failures() {
+    let dir = temp_dir("errors");
+    let input = dir.join("a.png");
+    write_png(&input);
+    let png = std::fs::read(&input).unwrap();
+    // An invalid interlace method (the last byte of IHDR), with the CRC fixed.
+    let mut ihdr = png[16..29].to_vec();
+    ihdr[12] = 2;
+    let interlace = [&png[..8], &png_chunk(b"IHDR", &ihdr), &png[33..]].concat();
+    std::fs::write(dir.join("interlace.png"), interlace).unwrap();
+    // A file cut off in the middle of the image data.
+    std::fs::write(dir.join("truncated.png"), &png[..png.len() / 2]).unwrap();
+    std::fs::write(dir.join("text.png"), b"not a png").unwrap();
+
+    let stderr = |name: &str| {
+        let out = tinyimg_cli(&["-q", "-o", dir.join("out.png").to_str().unwrap(), dir.join(name).to_str().unwrap()]);
+        assert_eq!(out.status.code(), Some(1));
+        String::from_utf8_lossy(&out.stderr).into_owned()
+    };
+    assert!(stderr("interlace.png").contains("invalid interlace method"));
+    assert!(stderr("truncated.png").contains("the file is incomplete"));
+    assert!(stderr("text.png").contains("not a PNG file"));
+    std::fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn reports_removed_chunks() {
+    let dir = temp_dir("removed");
+    let input = dir.join("a.png");
+    write_png(&input);
+    let png = std::fs::read(&input).unwrap();
+    let chunks = [
+        png_chunk(b"tEXt", b"Author\0Jane Doe"),
+        png_chunk(b"pHYs", &[0, 0, 0x0B, 0x13, 0, 0, 0x0B, 0x13, 1]),
+        png_chunk(b"eXIf", b"MM\0\x2a\0\0\0\x08\0\0"),
+    ].concat();
+    std::fs::write(&input, [&png[..33], &chunks, &png[33..]].concat()).unwrap();
+
+    let report = |strip: &str| -> String {
+        let output = dir.join(format!("{}.png", strip));
+        let out = tinyimg_cli(&[
+            "--strip", strip, "--json", "-", "-o", output.to_str().unwrap(), input.to_str().unwrap(),
+        ]);
+        assert_eq!(out.status.code(), Some(0), "{}", String::from_utf8_lossy(&out.stderr));
+        String::from_utf8(out.stdout).unwrap()
+    };
+    assert!(report("all").contains("\"removed\": [\"tEXt\", \"pHYs\", \"eXIf\"]"));
+    // The physical dimensions are safe to keep.
+    assert!(report("safe").contains("\"removed\": [\"tEXt\", \"eXIf\"]"));
+    assert!(report("none").contains("\"removed\": []"));
+
+    // The removed chunks can be backed up to sidecar files.
+    let output = dir.join("out.png");
+    let meta_dir = dir.join("meta");
+    for (backup, sidecar) in [(".meta", dir.join("out.png.meta")), (meta_dir.to_str().unwrap(), meta_dir.join("out.png.meta"))] {
+        let out = tinyimg_cli(&[
+            "-q", "--metadata-backup", backup, "-o", output.to_str().unwrap(), input.to_str().unwrap(),
+        ]);
+        assert_eq!(out.status.code(), Some(0), "{}", String::from_utf8_lossy(&out.stderr));
+        let meta = std::fs::read(sidecar).unwrap();
+        assert!(meta.starts_with(b"TINYMETA"));
+        assert_eq!(meta.len(), 17 + 3 + chunks.len());
+    }
+    std::fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn forces_color_types() {
+    let dir = temp_dir("color-type");
+    let color = dir.join("color.png");
+    write_png(&color);
+    // An opaque gray gradient, stored as RGBA.
+    let gray = dir.join("gray.png");
+    let rgba: Vec<u8> = (0..64 * 48).flat_map(|i| [(i % 64 * 4) as u8; 3].into_iter().chain([255])).collect();
+    let mut encoder = lodepng::Encoder::new();
+    encoder.set_auto_convert(false);
+    std::fs::write(&gray, encoder.encode(&rgba, 64, 48).unwrap()).unwrap();
+
+    let run = |input: &Path, color_type: &str, lossy: &str| -> (Output, PathBuf) {
+        let output = dir.join(format!("{}-{}-{}.png", input.file_stem().unwrap().to_str().unwrap(), color_type, lossy));
+        let out = tinyimg_cli(&[
+            "-q", "--color-type", color_type, "--lossy", lossy, "-o", output.to_str().unwrap(), input.to_str().unwrap(),
+        ]);
+        (out, output)
+    };
+    // The color type in the IHDR chunk (byte 25 of the file).
+    let ihdr_type = |path: &Path| std::fs::read(path).unwrap()[25];
+    for (color_type, code) in [("rgba", 6), ("rgb", 2), ("palette", 3), ("gray", 0), ("gray_alpha", 4)] {
+        let (out, output) = run(&gray, color_type, "0");
+        assert_eq!(out.status.code(), Some(0), "{}", String::from_utf8_lossy(&out.stderr));
+        assert_eq!(ihdr_type(&output), code, "{}", color_type);
+    }
+    // More than 256 colors need a lossy threshold for a palette, and colors
+    // cannot be written as gray.
+    let (out, _) = run(&color, "palette", "0");
+    assert_eq!(out.status.code(), Some(1));
+    assert!(String::from_utf8_lossy(&out.stderr).contains("more than 256 colors"));
+    let (out, output) = run(&color, "palette", "5");
+    assert_eq!(out.status.code(), Some(0), "{}", String::from_utf8_lossy(&out.stderr));
+    assert_eq!(ihdr_type(&output), 3);
+    let (out, _) = run(&color, "gray", "0");
+    assert!(String::from_utf8_lossy(&out.stderr).contains("colored"));
+    assert_eq!(tinyimg_cli(&["--color-type", "cmyk", color.to_str().unwrap()]).status.code(), Some(2));
+    std::fs::remove_dir_all(dir).unwrap();
+}
+
+/// Write a GIF-style indexed PNG: a transparent background (palette entry 0)
+/// with stripes of 32 similar shades of blue.
+fn write_indexed_png(path: &Path) -> Vec<u8> {
+    let (w, h) = (96, 64);
+    let mut mode = lodepng::ColorMode::new();
+    mode.colortype = lodepng::ColorType::PALETTE;
+    mode.set_bitdepth(8);
+    mode.palette_add(lodepng::RGBA::new(0, 0, 0, 0)).unwrap();
+    for i in 0..32u8 {
+        mode.palette_add(lodepng::RGBA::new(20 + i / 4, 40 + i / 2, 200 + i, 255)).unwrap();
+    }
+    let idx: Vec<u8> = (0..w * h).map(|i| {
+        let (x, y) = (i % w, i / w);
+        if (x / 8 + y / 8) % 3 == 0 { 0 } else { 1 + ((x * 7 + y * 3) % 32) as u8 }
+    }).collect();
+    let mut encoder = lodepng::Encoder::new();
+    encoder.set_auto_convert(false);
+    *encoder.info_raw_mut() = mode.clone();
+    encoder.info_png_mut().color = mode;
+    std::fs::write(path, encoder.encode(&idx, w, h).unwrap()).unwrap();
+    idx
+}
+
+#[test]
+fn keeps_palette_transparency_in_lossy_mode() {
+    let dir = temp_dir("trns");
+    let input = dir.join("a.png");
+    let idx = write_indexed_png(&input);
+    for lossy in ["1", "5", "20"] {
+        let output = dir.join(format!("a_{}.png", lossy));
+        let out = tinyimg_cli(&[input.to_str().unwrap(), "-o", output.to_str().unwrap(), "--lossy", lossy]);
+        assert_eq!(out.status.code(), Some(0), "{}", String::from_utf8_lossy(&out.stderr));
+        assert!(size(&output) <= size(&input), "lossy = {}: {} > {}", lossy, size(&output), size(&input));
+        let png = std::fs::read(&output).unwrap();
+        assert_eq!(png[25], 3, "the output should be indexed");
+        // The same pixels are transparent, and the others are opaque.
+        let image = lodepng::decode32(&png).unwrap();
+        for (px, &i) in image.buffer.iter().zip(&idx) {
+            assert_eq!(px.a, if i == 0 { 0 } else { 255 });
+        }
+    }
+}
@@ -0,0 +1,66 @@
+// Tests of the CPU limit detection against sample cgroup and /proc files.
+
+use std::path::PathBuf;
+
+use tinyimg::cpus::{detect_cpus_in, parse_affinity, parse_cfs_quota, parse_cpu_list, parse_cpu_max};
+
+/// A fake cgroup root with the given files.
+fn cgroup_root(name: &str, files: &[(&str, &str)]) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("tinyimg-cgroup-{}-{}", name, std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    for (file, content) in files {
+        let path = dir.join(file);
+        std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+        std::fs::write(path, content).unwrap();
+    }
+    std::fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+#[test]
+fn parses_cgroup_v2_quotas() {
+    assert_eq!(parse_cpu_max("200000 100000\n"), Some(2.0));
+    assert_eq!(parse_cpu_max("150000 100000"), Some(1.5));
+    assert_eq!(parse_cpu_max("50000"), Some(0.5));
+    assert_eq!(parse_cpu_max("max 100000\n"), None);
+    assert_eq!(parse_cpu_max(""), None);
+}
+
+#[test]
+fn parses_cgroup_v1_quotas() {
+    assert_eq!(parse_cfs_quota("200000\n", "100000\n"), Some(2.0));
+    assert_eq!(parse_cfs_quota("-1\n", "100000\n"), None);
+    assert_eq!(parse_cfs_quota("100000", "0"), None);
+    assert_eq!(parse_cfs_quota("garbage", "100000"), None);
+}
+
+#[test]
+fn parses_cpu_lists() {
+    assert_eq!(parse_cpu_list("0-3"), Some(4));
+    assert_eq!(parse_cpu_list("0-3,8,10-11\n"), Some(7));
+    assert_eq!(parse_cpu_list("5"), Some(1));
+    assert_eq!(parse_cpu_list("3-1"), None);
+    assert_eq!(parse_cpu_list(""), None);
+    let status = "Name:\tR\nCpus_allowed:\tff\nCpus_allowed_list:\t0-1\nMems_allowed_list:\t0\n";
+    assert_eq!(parse_affinity(status), Some(2));
+    assert_eq!(parse_affinity("Name:\tR\n"), None);
+}
+
+#[test]
+fn limits_cpus_by_cgroup_and_affinity() {
+    let all = std::thread::available_parallelism().map_or(1, |n| n.get());
+    // No limits.
+    let root = cgroup_root("none", &[]);
+    assert_eq!(detect_cpus_in(&root, None), all);
+    let root = cgroup_root("max", &[("cpu.max", "max 100000\n")]);
+    assert_eq!(detect_cpus_in(&root, None), all);
+    // A quota below one CPU still allows one thread.
+    let root = cgroup_root("v2", &[("cpu.max", "50000 100000\n")]);
+    assert_eq!(detect_cpus_in(&root, None), 1);
+    let root = cgroup_root("v1", &[("cpu/cpu.cfs_quota_us", "50000\n"), ("cpu/cpu.cfs_period_us", "100000\n")]);
+    assert_eq!(detect_cpus_in(&root, None), 1);
+    // The affinity mask limits the CPUs, too.
+    let root = cgroup_root("affinity", &[]);
+    assert_eq!(detect_cpus_in(&root, Some("Cpus_allowed_list:\t0\n")), 1);
+    assert_eq!(detect_cpus_in(&root, Some("Cpus_allowed_list:\t0-1023\n")), all);
+}
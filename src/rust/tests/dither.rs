@@ -0,0 +1,62 @@
+// Tests of the dithering algorithms of lossy palette reduction.
+
+use std::path::PathBuf;
+
+use tinyimg::optimize::{Dither, PngSettings};
+
+#[test]
+fn parses_dither_names() {
+    for dither in Dither::ALL {
+        assert_eq!(Dither::from_name(dither.name()).unwrap(), dither);
+    }
+    let msg = Dither::from_name("atkinson").unwrap_err().to_string();
+    assert!(msg.contains("floyd-steinberg"), "{}", msg);
+}
+
+/// An RGB image of smooth gradients, with more than 256 colors.
+fn write_gradient_png(path: &PathBuf, width: usize, height: usize) {
+    let mut bytes = Vec::with_capacity(width * height * 3);
+    for y in 0..height {
+        for x in 0..width {
+            bytes.extend([(x * 255 / width) as u8, (y * 255 / height) as u8, ((x + y) * 127 / (width + height)) as u8]);
+        }
+    }
+    lodepng::encode24_file(path, &bytes, width, height).unwrap();
+}
+
+#[test]
+fn dithers_with_each_algorithm() {
+    let dir = std::env::temp_dir().join(format!("tinyimg-dither-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let input = dir.join("gradient.png");
+    write_gradient_png(&input, 256, 192);
+    let lossy = 5.0;
+    let colors = default_colors(&input, lossy);
+    let outputs: Vec<Vec<u8>> = Dither::ALL.iter().map(|&dither| {
+        let settings = PngSettings::new(0, false, false, lossy, [0.0; 3], "d65").unwrap()
+            .with_dither(Some(dither))
+            .with_no_grow(false)
+            .with_quality_report(10_000);
+        let output = dir.join(format!("{}.png", dither.name()));
+        let (_, notes) = settings.optimize_auto(&input, &output).unwrap();
+        // The palette size is chosen without dithering, so it is the same.
+        assert_eq!(notes.colors, Some(colors), "{}", dither.name());
+        // Dithering trades the error of single pixels for the error of areas,
+        // so only the typical pixel is within the threshold, as documented in
+        // `with_dither()`; the 95th percentile may exceed it.
+        let median = notes.quality.unwrap().quantiles[0];
+        assert!(median <= lossy, "{}: median Delta E {}", dither.name(), median);
+        lodepng::decode24_file(&output).unwrap().buffer.into_iter().flat_map(|p| [p.r, p.g, p.b]).collect()
+    }).collect();
+    for (i, j) in [(0, 1), (0, 2), (1, 2)] {
+        assert!(outputs[i] != outputs[j], "{} and {} are the same", Dither::ALL[i].name(), Dither::ALL[j].name());
+    }
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+/// The palette size of an image reduced with the default dithering.
+fn default_colors(input: &PathBuf, lossy: f64) -> usize {
+    let settings = PngSettings::new(0, false, false, lossy, [0.0; 3], "d65").unwrap().with_no_grow(false);
+    let (_, notes) = settings.optimize_auto(input, &input.with_file_name("default.png")).unwrap();
+    notes.colors.unwrap()
+}
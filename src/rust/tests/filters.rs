@@ -0,0 +1,64 @@
+// Tests of the row filters tried by PNG optimization.
+
+use std::path::PathBuf;
+use std::time::{Duration, Instant};
+
+use oxipng::RowFilter;
+use tinyimg::optimize::{parse_filters, PngSettings};
+
+#[test]
+fn parses_filters() {
+    assert_eq!(parse_filters(&[]).unwrap(), None);
+    let filters = parse_filters(&["none", "PAETH", "MinSum", "9"]).unwrap().unwrap();
+    assert_eq!(
+        filters.into_iter().collect::<Vec<_>>(),
+        [RowFilter::None, RowFilter::Paeth, RowFilter::MinSum, RowFilter::Brute]
+    );
+    // Duplicates are tried once.
+    assert_eq!(parse_filters(&["Up", "2"]).unwrap().unwrap().len(), 1);
+    for bad in ["Median", "10", "-1", ""] {
+        let msg = parse_filters(&[bad]).unwrap_err().to_string();
+        assert!(msg.contains("\"Paeth\"") && msg.contains("0 to 9"), "{}", msg);
+    }
+}
+
+/// An RGB image of gradients with some noise.
+fn write_gradient_png(path: &PathBuf, width: usize, height: usize) {
+    let mut state = 1u32;
+    let mut bytes = Vec::with_capacity(width * height * 3);
+    for y in 0..height {
+        for x in 0..width {
+            state = state.wrapping_mul(1_664_525).wrapping_add(1_013_904_223);
+            let noise = (state >> 30) as usize;
+            bytes.extend([(x * 255 / width + noise) as u8, (y * 255 / height + noise) as u8, 128]);
+        }
+    }
+    lodepng::encode24_file(path, &bytes, width, height).unwrap();
+}
+
+/// The time to optimize a PNG file at level 4 with the given row filters, and
+/// the output.
+fn time_filters(input: &PathBuf, filters: &[&str]) -> (Duration, PathBuf) {
+    let settings = PngSettings::new(4, false, false, 0.0, [0.0; 3], "d65").unwrap()
+        .with_filters(parse_filters(filters).unwrap());
+    let output = input.with_file_name(format!("filters{}.png", filters.join("-")));
+    let start = Instant::now();
+    settings.optimize(input, &output).unwrap();
+    (start.elapsed(), output)
+}
+
+#[test]
+fn fewer_filters_are_faster() {
+    let dir = std::env::temp_dir().join(format!("tinyimg-filters-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let input = dir.join("gradient.png");
+    write_gradient_png(&input, 800, 600);
+    let pixels = lodepng::decode24_file(&input).unwrap().buffer;
+    let (preset, _) = time_filters(&input, &[]);
+    let (none, output) = time_filters(&input, &["None"]);
+    assert!(none * 2 < preset, "filter None took {:?} and the preset {:?}", none, preset);
+    assert_eq!(lodepng::decode24_file(&output).unwrap().buffer, pixels);
+    let (_, output) = time_filters(&input, &["MinSum", "Paeth"]);
+    assert_eq!(lodepng::decode24_file(&output).unwrap().buffer, pixels);
+    std::fs::remove_dir_all(&dir).unwrap();
+}
@@ -0,0 +1,197 @@
+// Tests of the peak memory usage of lossy palette reduction, measured by a
+// global allocator that counts the heap bytes in use by this test binary.
+
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicUsize, Ordering::SeqCst};
+use std::sync::Mutex;
+
+use tinyimg::optimize::{LossySpeed, PngSettings};
+
+/// The system allocator, counting the bytes in use and the blocks of at least
+/// `LARGE` bytes (the pixel buffers of an image).
+struct Counting;
+
+static LIVE: AtomicUsize = AtomicUsize::new(0);
+static PEAK: AtomicUsize = AtomicUsize::new(0);
+static LARGE: AtomicUsize = AtomicUsize::new(usize::MAX);
+static LARGE_LIVE: AtomicUsize = AtomicUsize::new(0);
+static LARGE_MAX: AtomicUsize = AtomicUsize::new(0);
+static LARGE_TOTAL: AtomicUsize = AtomicUsize::new(0);
+static TAIL_PEAK: AtomicUsize = AtomicUsize::new(0);
+
+impl Counting {
+    fn grow(size: usize) {
+        let live = LIVE.fetch_add(size, SeqCst) + size;
+        PEAK.fetch_max(live, SeqCst);
+        TAIL_PEAK.fetch_max(live, SeqCst);
+        if size >= LARGE.load(SeqCst) {
+            LARGE_MAX.fetch_max(LARGE_LIVE.fetch_add(1, SeqCst) + 1, SeqCst);
+            LARGE_TOTAL.fetch_add(1, SeqCst);
+        }
+    }
+
+    fn shrink(size: usize) {
+        let live = LIVE.fetch_sub(size, SeqCst) - size;
+        if size >= LARGE.load(SeqCst) && LARGE_LIVE.fetch_sub(1, SeqCst) == 1 {
+            TAIL_PEAK.store(live, SeqCst);
+        }
+    }
+}
+
+unsafe impl GlobalAlloc for Counting {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let ptr = System.alloc(layout);
+        if !ptr.is_null() { Self::grow(layout.size()) }
+        ptr
+    }
+
+    unsafe fn alloc_zeroed(&self, layout: Layout) -> *mut u8 {
+        let ptr = System.alloc_zeroed(layout);
+        if !ptr.is_null() { Self::grow(layout.size()) }
+        ptr
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout);
+        Self::shrink(layout.size());
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        let new = System.realloc(ptr, layout, new_size);
+        if !new.is_null() {
+            Self::shrink(layout.size());
+            Self::grow(new_size);
+        }
+        new
+    }
+}
+
+#[global_allocator]
+static ALLOCATOR: Counting = Counting;
+
+/// The memory used by a task, in bytes above the usage before it started.
+#[derive(Debug)]
+struct Usage {
+    /// The peak usage.
+    peak: usize,
+    /// The number of pixel buffers allocated, and the most alive at once.
+    buffers: usize,
+    max_buffers: usize,
+    /// The peak usage after the last pixel buffer was freed.
+    tail_peak: usize,
+}
+
+/// Tests run in parallel threads, which share the counters, so each test
+/// holds this lock while it allocates anything.
+static SERIAL: Mutex<()> = Mutex::new(());
+
+/// Measure the memory used by `task`, counting the blocks of at least
+/// `pixel_bytes` bytes as pixel buffers.
+fn measure(pixel_bytes: usize, task: impl FnOnce()) -> Usage {
+    LARGE.store(pixel_bytes, SeqCst);
+    for counter in [&LARGE_LIVE, &LARGE_MAX, &LARGE_TOTAL] {
+        counter.store(0, SeqCst);
+    }
+    let base = LIVE.load(SeqCst);
+    PEAK.store(base, SeqCst);
+    TAIL_PEAK.store(base, SeqCst);
+    task();
+    LARGE.store(usize::MAX, SeqCst);
+    Usage {
+        peak: PEAK.load(SeqCst) - base,
+        buffers: LARGE_TOTAL.load(SeqCst),
+        max_buffers: LARGE_MAX.load(SeqCst),
+        tail_peak: TAIL_PEAK.load(SeqCst).saturating_sub(base),
+    }
+}
+
+/// A large RGB image of gradients with some noise, which has far more than
+/// 256 colors.
+fn write_gradient_png(path: &PathBuf, width: usize, height: usize) {
+    let mut state = 1u32;
+    let mut bytes = Vec::with_capacity(width * height * 3);
+    for y in 0..height {
+        for x in 0..width {
+            state = state.wrapping_mul(1_664_525).wrapping_add(1_013_904_223);
+            let noise = (state >> 30) as usize;
+            bytes.extend([(x * 255 / width + noise) as u8, (y * 255 / height + noise) as u8, 128]);
+        }
+    }
+    lodepng::encode24_file(path, &bytes, width, height).unwrap();
+}
+
+/// The memory used by lossy palette reduction, and by optimizing its output
+/// losslessly (mostly by oxipng).
+struct LossyUsage {
+    lossy: Usage,
+    lossless: Usage,
+    input_size: usize,
+    width: usize,
+    height: usize,
+}
+
+/// Optimize a large gradient (lossy, at the fastest lossy speed, so that
+/// quantization takes little memory besides the pixels) in a temporary
+/// directory, and then optimize the output again without lossy reduction.
+fn lossy_usage(name: &str, colors: usize) -> LossyUsage {
+    let _lock = SERIAL.lock().unwrap_or_else(|e| e.into_inner());
+    let dir = std::env::temp_dir().join(format!("tinyimg-memory-{}-{}", name, std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let input = dir.join("gradient.png");
+    let (width, height) = (1500, 1000);
+    write_gradient_png(&input, width, height);
+    let rgba = width * height * 4;
+    let settings = PngSettings::new(2, false, false, 3.0, [0.0; 3], "d65").unwrap()
+        .with_lossy_speed(LossySpeed::new(10).unwrap())
+        .with_colors(colors)
+        .with_no_grow(false);
+    let output = dir.join("out.png");
+    let lossy = measure(rgba, || { settings.optimize_auto(&input, &output).unwrap(); });
+    let settings = PngSettings::new(2, false, false, 0.0, [0.0; 3], "d65").unwrap().with_no_grow(false);
+    let lossless = measure(rgba, || { settings.optimize_auto(&output, &dir.join("lossless.png")).unwrap(); });
+    let input_size = std::fs::metadata(&input).unwrap().len() as usize;
+    std::fs::remove_dir_all(&dir).unwrap();
+    LossyUsage { lossy, lossless, input_size, width, height }
+}
+
+#[test]
+fn quantizes_pixels_without_copies() {
+    let LossyUsage { lossy: usage, input_size, width, height, .. } = lossy_usage("copies", 0);
+    let rgba = width * height * 4;
+    // lodepng decodes the pixels into bytes before turning them into RGBA
+    // pixels, which are quantized as they are; the only other pixel buffer is
+    // the quantized image, which is encoded as it is.  A copy of the pixels
+    // (to exoquant colors, or of the quantized colors to bytes) would be a
+    // fourth.
+    assert_eq!(usage.buffers, 3, "{:?}", usage);
+    assert_eq!(usage.max_buffers, 2, "{:?}", usage);
+    // The source and quantized pixels (and the input data, which is kept to
+    // be compared with the output) make up most of the peak.
+    assert!(usage.peak < rgba * 12 / 5 + input_size, "{:?}", usage);
+}
+
+#[test]
+fn frees_quantized_pixels_before_oxipng() {
+    let LossyUsage { lossy, lossless, width, height, .. } = lossy_usage("quantized", 64);
+    // oxipng holds at least a byte per pixel of the indexed image, so it runs
+    // after the quantized pixels are freed (and only the intermediate PNG is
+    // left from them).
+    assert!(lossy.tail_peak >= width * height, "{:?}", lossy);
+    // Then it takes about as much memory as when optimizing the output alone,
+    // instead of a whole RGBA buffer more.
+    assert!(lossy.tail_peak < lossless.peak + width * height * 2, "{:?} vs {:?}", lossy, lossless);
+}
+
+#[test]
+fn frees_source_pixels_before_oxipng() {
+    let LossyUsage { lossy, lossless, width, height, .. } = lossy_usage("source", 0);
+    let rgba = width * height * 4;
+    // The Lab samples are freed once the palette size is chosen, and the
+    // source pixels once they are quantized, so none of them are left when
+    // oxipng runs.
+    assert!(lossy.tail_peak >= width * height, "{:?}", lossy);
+    // Holding the source pixels would add a whole RGBA buffer to the peak of
+    // the oxipng phase.
+    assert!(lossy.tail_peak + rgba / 2 < lossless.peak + rgba, "{:?} vs {:?}", lossy, lossless);
+}
@@ -0,0 +1,25 @@
+// Tests of preserving the timestamps of optimized files.
+
+use std::time::{Duration, SystemTime};
+
+use tinyimg::optimize::PngSettings;
+
+#[test]
+fn preserves_timestamps_of_lossy_outputs() {
+    let dir = std::env::temp_dir().join(format!("tinyimg-preserve-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let input = dir.join("in.png");
+    let bytes: Vec<u8> = (0..64 * 48).flat_map(|i| [(i % 64 * 4) as u8, (i / 64 * 5) as u8, 128]).collect();
+    lodepng::encode24_file(&input, &bytes, 64, 48).unwrap();
+    let time = SystemTime::UNIX_EPOCH + Duration::from_secs(1_000_000_000);
+    std::fs::File::options().write(true).open(&input).unwrap().set_modified(time).unwrap();
+    let modified = |path: &std::path::Path| std::fs::metadata(path).unwrap().modified().unwrap();
+    // Lossless, lossy, and a fixed number of colors.
+    for (lossy, colors, preserve) in [(0.0, 0, true), (2.0, 0, true), (0.0, 16, true), (2.0, 0, false)] {
+        let output = dir.join(format!("out-{}-{}-{}.png", lossy, colors, preserve));
+        PngSettings::new(2, false, preserve, lossy, [0.0; 3], "d65").unwrap().with_colors(colors)
+            .optimize_auto(&input, &output).unwrap();
+        assert_eq!(modified(&output) == time, preserve, "lossy = {}, colors = {}, preserve = {}", lossy, colors, preserve);
+    }
+    std::fs::remove_dir_all(&dir).unwrap();
+}
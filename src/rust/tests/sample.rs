@@ -0,0 +1,37 @@
+// Tests of the random sampling of batches and the estimates from samples.
+
+use tinyimg::optimize::{random_sample, SampleEstimate};
+
+#[test]
+fn samples_reproducibly() {
+    let x = random_sample(5000, 50, 42);
+    assert_eq!(x.len(), 50);
+    assert!(x.windows(2).all(|w| w[0] < w[1]) && x[49] < 5000);
+    assert_eq!(random_sample(5000, 50, 42), x);
+    assert_ne!(random_sample(5000, 50, 43), x);
+    // Samples as large as the batch have all files.
+    assert_eq!(random_sample(3, 5, 1), vec![0, 1, 2]);
+    assert_eq!(random_sample(0, 5, 1), Vec::<usize>::new());
+}
+
+#[test]
+fn samples_uniformly() {
+    // Each of 10 files is chosen about 300 times in 1000 samples of 3.
+    let mut counts = [0; 10];
+    for seed in 0..1000 {
+        for i in random_sample(10, 3, seed) {
+            counts[i] += 1;
+        }
+    }
+    assert!(counts.iter().all(|&n| (240..360).contains(&n)), "{:?}", counts);
+}
+
+#[test]
+fn extrapolates_sizes() {
+    let est = SampleEstimate { files: 100, sampled: 10, input_size: 10240, sample_input_size: 1024, sample_output_size: 768 };
+    assert_eq!(est.output_size(), 7680);
+    assert_eq!(est.summary(), "Estimated for all 100 files (from 10 sampled): 10.0 KB -> 7.5 KB (-25.0%, saving about 2.5 KB)");
+    // Without successfully processed samples, nothing is saved.
+    let est = SampleEstimate { sampled: 0, sample_input_size: 0, sample_output_size: 0, ..est };
+    assert_eq!(est.output_size(), 10240);
+}
@@ -0,0 +1,64 @@
+// Tests of the live preview server.
+
+use std::io::{Read, Write};
+use std::net::TcpStream;
+use std::path::PathBuf;
+
+use tinyimg::serve::{respond, Server};
+
+/// A directory with a PNG file and a subdirectory.
+fn serve_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("tinyimg-serve-{}-{}", name, std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(dir.join("sub dir")).unwrap();
+    std::fs::write(dir.join("a.png"), b"not really a png").unwrap();
+    std::fs::write(dir.join("sub dir/b.png"), b"b").unwrap();
+    dir
+}
+
+/// Send a request to the server and return the response.
+fn fetch(port: u16, request: &str) -> String {
+    let mut stream = TcpStream::connect(("127.0.0.1", port)).unwrap();
+    stream.write_all(request.as_bytes()).unwrap();
+    let mut response = String::new();
+    stream.read_to_string(&mut response).unwrap();
+    response
+}
+
+#[test]
+fn lists_and_serves_files() {
+    let dir = serve_dir("respond");
+    let (status, content_type, body) = respond("GET / HTTP/1.1\r\n", &dir);
+    let html = String::from_utf8(body).unwrap();
+    assert_eq!((status, content_type), ("200 OK", "text/html; charset=utf-8"));
+    assert!(html.contains("<img src=\"/a.png\""));
+    assert!(html.contains("href=\"/sub%20dir/b.png\""));
+    assert_eq!(respond("GET /a.png HTTP/1.1", &dir), ("200 OK", "image/png", b"not really a png".to_vec()));
+    assert_eq!(respond("GET /sub%20dir/b.png?t=1 HTTP/1.1", &dir).2, b"b");
+    assert_eq!(respond("GET /c.png HTTP/1.1", &dir).0, "404 Not Found");
+    assert_eq!(respond("POST / HTTP/1.1", &dir).0, "405 Method Not Allowed");
+    assert_eq!(respond("", &dir).0, "400 Bad Request");
+}
+
+#[test]
+fn refuses_paths_outside_the_directory() {
+    let dir = serve_dir("outside");
+    assert_eq!(respond("GET /../a.png HTTP/1.1", &dir).0, "403 Forbidden");
+    assert_eq!(respond("GET /sub%20dir/%2e%2e/%2e%2e/a.png HTTP/1.1", &dir).0, "403 Forbidden");
+}
+
+#[test]
+fn serves_new_versions_of_files() {
+    let dir = serve_dir("server");
+    let server = Server::start(&dir, 0).unwrap();
+    let port = server.port();
+    let response = fetch(port, "GET /a.png HTTP/1.0\r\n\r\n");
+    assert!(response.starts_with("HTTP/1.0 200 OK\r\n"));
+    assert!(response.ends_with("\r\n\r\nnot really a png"));
+    std::fs::write(dir.join("a.png"), b"optimized").unwrap();
+    assert!(fetch(port, "GET /a.png HTTP/1.0\r\n\r\n").ends_with("\r\n\r\noptimized"));
+    // HEAD requests only get the headers.
+    assert!(fetch(port, "HEAD /a.png HTTP/1.0\r\n\r\n").ends_with("Content-Length: 9\r\nCache-Control: no-store\r\nConnection: close\r\n\r\n"));
+    drop(server);
+    assert!(TcpStream::connect(("127.0.0.1", port)).is_err());
+}
@@ -0,0 +1,75 @@
+// Tests of the speeds of lossy palette reduction.
+
+use std::path::PathBuf;
+use std::time::{Duration, Instant};
+
+use tinyimg::optimize::{LossySpeed, PngSettings, DEFAULT_LOSSY_SPEED, LOSSY_SPEEDS};
+
+#[test]
+fn maps_speeds_to_settings() {
+    // (speed, palette samples, samples, tolerance, K-means iterations, dither)
+    let all = usize::MAX;
+    let table = [
+        (1, all, all, 0, 16, true),
+        (2, all, 200_000, 0, 12, true),
+        (3, all, 100_000, 0, 10, true),
+        (4, all, 50_000, 0, 8, true),
+        (5, 1_000_000, 40_000, 1, 6, true),
+        (6, 500_000, 30_000, 2, 4, true),
+        (7, 250_000, 20_000, 2, 3, true),
+        (8, 100_000, 10_000, 4, 2, true),
+        (9, 50_000, 5_000, 6, 1, true),
+        (10, 20_000, 2_000, 8, 0, false),
+    ];
+    for (speed, palette_samples, samples, tolerance, kmeans_iterations, dither) in table {
+        let expected = LossySpeed { palette_samples, samples, tolerance, kmeans_iterations, dither };
+        assert_eq!(LossySpeed::new(speed).unwrap(), expected, "speed {}", speed);
+        assert_eq!(LOSSY_SPEEDS[speed as usize - 1], expected);
+    }
+    // The default speed has the settings from before speeds were introduced.
+    assert_eq!(LossySpeed::default(), LossySpeed::new(DEFAULT_LOSSY_SPEED as i32).unwrap());
+    assert_eq!(LossySpeed::default(), LossySpeed {
+        palette_samples: all, samples: 50_000, tolerance: 0, kmeans_iterations: 8, dither: true,
+    });
+    for speed in [0, 11, -1] {
+        assert!(LossySpeed::new(speed).is_err(), "speed {}", speed);
+    }
+}
+
+/// A large RGB image of smooth gradients with some noise, which has far more
+/// than 256 colors.
+fn write_gradient_png(path: &PathBuf, width: usize, height: usize) {
+    let mut state = 1u32;
+    let mut bytes = Vec::with_capacity(width * height * 3);
+    for y in 0..height {
+        for x in 0..width {
+            state = state.wrapping_mul(1_664_525).wrapping_add(1_013_904_223);
+            let noise = (state >> 30) as usize;
+            bytes.extend([(96 + x * 64 / width + noise) as u8, (96 + y * 64 / height + noise) as u8, 128]);
+        }
+    }
+    lodepng::encode24_file(path, &bytes, width, height).unwrap();
+}
+
+/// The time to optimize a PNG file at a lossy speed, and the output size.
+fn time_speed(input: &PathBuf, speed: i32) -> (Duration, u64) {
+    let settings = PngSettings::new(0, false, false, 5.0, [0.0; 3], "d65").unwrap()
+        .with_lossy_speed(LossySpeed::new(speed).unwrap());
+    let output = input.with_file_name(format!("speed{}.png", speed));
+    let start = Instant::now();
+    let (_, output_size) = settings.optimize(input, &output).unwrap();
+    (start.elapsed(), output_size)
+}
+
+#[test]
+fn fastest_speed_is_faster() {
+    let dir = std::env::temp_dir().join(format!("tinyimg-speed-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let input = dir.join("gradient.png");
+    write_gradient_png(&input, 1024, 768);
+    let input_size = std::fs::metadata(&input).unwrap().len();
+    let (slow, slow_size) = time_speed(&input, 1);
+    let (fast, fast_size) = time_speed(&input, 10);
+    assert!(fast * 2 < slow, "speed 10 took {:?} and speed 1 {:?}", fast, slow);
+    assert!(slow_size < input_size && fast_size < input_size);
+}
@@ -0,0 +1,77 @@
+// Tests of the time limit of optimizing a PNG file.
+
+use std::path::PathBuf;
+use std::time::{Duration, Instant};
+
+use tinyimg::optimize::PngSettings;
+
+/// A large RGB image of gradients with some noise.
+fn write_gradient_png(path: &PathBuf, width: usize, height: usize) {
+    let mut state = 1u32;
+    let mut bytes = Vec::with_capacity(width * height * 3);
+    for y in 0..height {
+        for x in 0..width {
+            state = state.wrapping_mul(1_664_525).wrapping_add(1_013_904_223);
+            let noise = (state >> 30) as usize;
+            bytes.extend([(x * 255 / width + noise) as u8, (y * 255 / height + noise) as u8, 128]);
+        }
+    }
+    lodepng::encode24_file(path, &bytes, width, height).unwrap();
+}
+
+#[test]
+fn keeps_best_result_after_timeout() {
+    let dir = std::env::temp_dir().join(format!("tinyimg-timeout-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let input = dir.join("gradient.png");
+    write_gradient_png(&input, 1024, 768);
+    let pixels = lodepng::decode24_file(&input).unwrap().buffer;
+    let output = dir.join("out.png");
+    for lossy in [0.0, 2.0] {
+        let settings = |timeout| PngSettings::new(6, false, false, lossy, [0.0; 3], "d65").unwrap()
+            .with_timeout(timeout);
+        let start = Instant::now();
+        let (_, notes) = settings(None).optimize_auto(&input, &output).unwrap();
+        let unlimited = start.elapsed();
+        assert!(!notes.timed_out);
+        let start = Instant::now();
+        let (_, notes) = settings(Some(Duration::from_millis(1))).optimize_auto(&input, &output).unwrap();
+        assert!(notes.timed_out, "lossy = {}", lossy);
+        assert!(start.elapsed() * 2 < unlimited, "lossy = {}: {:?} vs {:?}", lossy, start.elapsed(), unlimited);
+        // The output is still a valid PNG (with the same pixels if lossless).
+        let decoded = lodepng::decode24_file(&output).unwrap().buffer;
+        if lossy == 0.0 {
+            assert_eq!(decoded, pixels);
+        }
+    }
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn reproducible_ignores_timeout() {
+    let dir = std::env::temp_dir().join(format!("tinyimg-timeout-repro-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let input = dir.join("gradient.png");
+    write_gradient_png(&input, 256, 192);
+    let output = dir.join("out.png");
+    let timeout = Some(Duration::from_millis(1));
+    // The limit is dropped whether it is set before or after the reproducibility.
+    let settings = [
+        PngSettings::new(2, false, false, 2.0, [0.0; 3], "d65").unwrap()
+            .with_timeout(timeout).with_reproducible(true),
+        PngSettings::new(2, false, false, 2.0, [0.0; 3], "d65").unwrap()
+            .with_reproducible(true).with_timeout(timeout),
+    ];
+    let mut outputs = Vec::new();
+    for settings in settings {
+        let (_, notes) = settings.optimize_auto(&input, &output).unwrap();
+        assert!(!notes.timed_out);
+        outputs.push(std::fs::read(&output).unwrap());
+    }
+    let (_, notes) = PngSettings::new(2, false, false, 2.0, [0.0; 3], "d65").unwrap()
+        .with_reproducible(true).optimize_auto(&input, &output).unwrap();
+    assert!(!notes.timed_out);
+    assert_eq!(outputs[0], outputs[1]);
+    assert_eq!(outputs[0], std::fs::read(&output).unwrap());
+    std::fs::remove_dir_all(&dir).unwrap();
+}
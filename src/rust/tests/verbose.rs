@@ -0,0 +1,106 @@
+// Tests of the paths shown in verbose messages, in particular paths with
+// multibyte characters (the byte offsets must stay on character boundaries),
+// and of the totals by directory.
+
+use tinyimg::optimize::{
+    find_truncate_index, format_dir_groups, group_sizes_by_dir, truncate_path, DirGroup, VerboseMessage,
+};
+
+fn paths(x: &[&str]) -> Vec<String> {
+    x.iter().map(|s| s.to_string()).collect()
+}
+
+#[test]
+fn truncates_ascii_paths() {
+    assert_eq!(find_truncate_index(&[]), 0);
+    assert_eq!(find_truncate_index(&paths(&["a.png"])), 0);
+    assert_eq!(find_truncate_index(&paths(&["fig/a.png"])), 4);
+    assert_eq!(find_truncate_index(&paths(&["fig/a/x.png", "fig/b/y.png"])), 4);
+    assert_eq!(find_truncate_index(&paths(&["fig\\a.png", "fig\\b.png"])), 4);
+    assert_eq!(find_truncate_index(&paths(&["a/x.png", "b/y.png"])), 0);
+    assert_eq!(truncate_path("fig/a.png", 4), "a.png");
+    assert_eq!(truncate_path("fig/a.png", 0), "fig/a.png");
+}
+
+#[test]
+fn truncates_multibyte_paths() {
+    let x = paths(&["fig/日本語/plot.png", "fig/日本語/plot2.png"]);
+    let i = find_truncate_index(&x);
+    assert_eq!(i, "fig/日本語/".len());
+    assert_eq!(truncate_path(&x[0], i), "plot.png");
+    // Paths that differ inside a multibyte character share only `fig/`.
+    let x = paths(&["fig/日本/a.png", "fig/日語/a.png", "fig/é/a.png"]);
+    assert_eq!(find_truncate_index(&x), 4);
+    assert_eq!(find_truncate_index(&paths(&["données/é.png"])), "données/".len());
+    // An index that is not at a character boundary keeps the whole path.
+    assert_eq!(truncate_path("日本語/a.png", 1), "日本語/a.png");
+}
+
+#[test]
+fn summarizes_multibyte_paths() {
+    let msg = VerboseMessage::new("fig/日本語/plot.png", "fig/日本語/plot.png", (2048, 1024));
+    let i = find_truncate_index(&paths(&["fig/日本語/plot.png", "fig/日本語/plot2.png"]));
+    assert_eq!(msg.summary(i, i).unwrap(), "plot.png | 2.0 KB -> 1.0 KB (-50.0%)");
+    assert_eq!(msg.summary(4, 4).unwrap(), "日本語/plot.png | 2.0 KB -> 1.0 KB (-50.0%)");
+}
+
+#[test]
+fn summarizes_unchanged_sizes() {
+    let msg = VerboseMessage::new("a.png", "b.png", (2048, 2048));
+    assert_eq!(msg.summary(0, 0).unwrap(), "a.png -> b.png | 2.0 KB -> 2.0 KB (0.0%)");
+    let msg = VerboseMessage::new("a.png", "b.png", (1024, 2048));
+    assert_eq!(msg.summary(0, 0).unwrap(), "a.png -> b.png | 1.0 KB -> 2.0 KB (+100.0%)");
+}
+
+#[test]
+fn notes_timeouts() {
+    let msg = VerboseMessage::new("a.png", "a.png", (2048, 1024)).with_level(Some(6)).with_timed_out(true);
+    assert_eq!(msg.summary(0, 0).unwrap(), "a.png | 2.0 KB -> 1.0 KB (-50.0%) | level = 6 | timed out");
+}
+
+#[test]
+fn reports_palette_sizes() {
+    let msg = VerboseMessage::new("a.png", "b.png", (2048, 1024)).with_colors(Some(64));
+    assert_eq!(msg.summary(0, 0).unwrap(), "a.png -> b.png | 2.0 KB -> 1.0 KB (-50.0%) | colors = 64");
+}
+
+fn sizes(x: &[(&str, u64, u64)]) -> Vec<(String, u64, u64)> {
+    x.iter().map(|&(p, a, b)| (p.to_string(), a, b)).collect()
+}
+
+fn group(dir: &str, files: usize, input_size: u64, output_size: u64) -> DirGroup {
+    DirGroup { dir: dir.to_string(), files, input_size, output_size }
+}
+
+#[test]
+fn groups_sizes_by_directory() {
+    let x = sizes(&[
+        ("docs/reference/figures/a.png", 100, 60),
+        ("docs/articles/b.png", 200, 100),
+        ("docs/reference/c.png", 300, 240),
+        ("docs/logo.png", 50, 50),
+    ]);
+    let i = find_truncate_index(&x.iter().map(|s| s.0.clone()).collect::<Vec<_>>());
+    assert_eq!(group_sizes_by_dir(&x, i, 1), vec![
+        group(".", 1, 50, 50), group("articles", 1, 200, 100), group("reference", 2, 400, 300),
+    ]);
+    assert_eq!(group_sizes_by_dir(&x, i, 2), vec![
+        group(".", 1, 50, 50), group("articles", 1, 200, 100),
+        group("reference", 1, 300, 240), group("reference/figures", 1, 100, 60),
+    ]);
+    // Without a common prefix, all files are in one group.
+    assert_eq!(group_sizes_by_dir(&sizes(&[("a/x.png", 1, 1), ("b\\y.png", 2, 1)]), 0, 1), vec![group(".", 2, 3, 2)]);
+}
+
+#[test]
+fn formats_directory_groups() {
+    let lines = format_dir_groups(&[group("articles", 1, 2048, 1024), group("reference", 12, 4096, 4096)]);
+    assert_eq!(lines, vec![
+        "directory  files  before   after  change",
+        "articles       1  2.0 KB  1.0 KB  -50.0%",
+        "reference     12  4.0 KB  4.0 KB   +0.0%",
+        "(total)       13  6.0 KB  5.0 KB  -16.7%",
+    ]);
+    // No total row for a single group.
+    assert_eq!(format_dir_groups(&[group(".", 1, 10, 10)]).len(), 2);
+}
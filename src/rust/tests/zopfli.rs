@@ -0,0 +1,35 @@
+// Tests of compressing the image data with Zopfli.
+
+use std::num::NonZeroU8;
+
+use tinyimg::optimize::{AutoLevels, PngSettings};
+
+#[test]
+fn compresses_once_with_zopfli() {
+    let dir = std::env::temp_dir().join(format!("tinyimg-zopfli-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let input = dir.join("bands.png");
+    let (width, height) = (200, 150);
+    let bytes: Vec<u8> = (0..width * height).flat_map(|i| {
+        let (x, y) = ((i % width) as f64, (i / width) as f64);
+        let v = ((x / 17.0).sin() * 40.0 + (y / 11.0).cos() * 40.0 + 100.0) as u8 / 8 * 8;
+        [v, 255 - v, 128]
+    }).collect();
+    lodepng::encode24_file(&input, &bytes, width, height).unwrap();
+    let zopfli = NonZeroU8::new(5);
+    let optimize = |settings: PngSettings, name: &str| {
+        let output = dir.join(name);
+        settings.optimize(&input, &output).unwrap();
+        std::fs::read(output).unwrap()
+    };
+    let settings = || PngSettings::new(2, false, false, 0.0, [0.0; 3], "d65").unwrap();
+    let deflated = optimize(settings(), "deflate.png");
+    let zopflied = optimize(settings().with_zopfli(zopfli), "zopfli.png");
+    // Zopfli replaces the deflater (so the data differs), and the pixels are kept.
+    assert_ne!(zopflied, deflated);
+    assert_eq!(lodepng::decode24(&zopflied).unwrap().buffer, lodepng::decode24(&deflated).unwrap().buffer);
+    // Automatic levels only choose the row filters, not the deflater.
+    let auto = AutoLevels::new(vec![], vec![2]).unwrap();
+    assert_eq!(optimize(settings().with_zopfli(zopfli).with_auto_levels(Some(auto)), "auto.png"), zopflied);
+    std::fs::remove_dir_all(&dir).unwrap();
+}